@@ -0,0 +1,61 @@
+#version 150
+
+#define PI 3.14159265359
+#define SAMPLE_COUNT 1024u
+
+in vec2 TCoord;
+
+out vec2 out_color;
+
+// Gets the ith point of the Hammersley sequence of n points.
+vec2 hammersley(uint i, uint n) {
+    uint bits = i;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return vec2(float(i) / float(n), float(bits) * 2.3283064365386963e-10);
+}
+
+// Gets a halfway vector around the +Z normal distributed according to the GGX distribution.
+vec3 importance_sample_ggx(vec2 xi, float roughness) {
+    float alpha = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+}
+
+// Gets the Schlick-GGX geometry term for a single direction using the remapping of roughness for
+// image based lighting.
+float geometry_schlick_ggx(float cos_n, float roughness) {
+    float k = roughness * roughness / 2.0;
+    return cos_n / (cos_n * (1.0 - k) + k);
+}
+
+void main() {
+    float cos_nv = max(TCoord.x, 0.0001);
+    float roughness = TCoord.y;
+    vec3 view = vec3(sqrt(1.0 - cos_nv * cos_nv), 0.0, cos_nv);
+
+    // Integrate the scale and bias to the Fresnel term at normal incidence.
+    float scale = 0.0;
+    float bias = 0.0;
+    for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+        vec3 halfway = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), roughness);
+        vec3 light = normalize(2.0 * dot(view, halfway) * halfway - view);
+        float cos_nl = max(light.z, 0.0);
+        float cos_nh = max(halfway.z, 0.0);
+        float cos_hv = max(dot(view, halfway), 0.0);
+        if (cos_nl > 0.0) {
+            float geometry = geometry_schlick_ggx(cos_nv, roughness) *
+                    geometry_schlick_ggx(cos_nl, roughness);
+            float visibility = geometry * cos_hv / (cos_nh * cos_nv);
+            float fresnel = pow(1.0 - cos_hv, 5.0);
+            scale += (1.0 - fresnel) * visibility;
+            bias += fresnel * visibility;
+        }
+    }
+    out_color = vec2(scale, bias) / float(SAMPLE_COUNT);
+}
@@ -0,0 +1,12 @@
+#version 150
+
+in vec3 position;
+
+out vec3 Direction;
+
+uniform mat4 transform;
+
+void main() {
+    Direction = position;
+    gl_Position = transform * vec4(position, 1.0);
+}
@@ -0,0 +1,18 @@
+#version 150
+
+#define PI 3.14159265359
+
+in vec3 Direction;
+
+out vec4 out_color;
+
+uniform sampler2D equirect_map;
+
+void main() {
+    // The world is Z up, so longitude is measured around the Z axis and latitude from the XY plane.
+    // The first row of the image is the top of the sphere.
+    vec3 direction = normalize(Direction);
+    vec2 uv = vec2(atan(direction.y, direction.x) / (2.0 * PI) + 0.5,
+            0.5 - asin(clamp(direction.z, -1.0, 1.0)) / PI);
+    out_color = vec4(texture(equirect_map, uv).rgb, 1.0);
+}
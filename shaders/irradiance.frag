@@ -0,0 +1,38 @@
+#version 150
+
+#define PI 3.14159265359
+#define SAMPLE_DELTA 0.025
+
+in vec3 Direction;
+
+out vec4 out_color;
+
+uniform samplerCube environment_map;
+uniform float environment_resolution;
+
+void main() {
+    // Build a tangent frame around the normal.
+    vec3 normal = normalize(Direction);
+    vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 right = normalize(cross(up, normal));
+    up = cross(normal, right);
+
+    // Sample from a mip level whose texels are about as far apart as the samples to avoid
+    // aliasing.
+    float lod = max(log2(environment_resolution * SAMPLE_DELTA * 2.0 / PI), 0.0);
+
+    // Integrate the cosine weighted radiance over the hemisphere.
+    vec3 irradiance = vec3(0.0);
+    float samples = 0.0;
+    for (float phi = 0.0; phi < 2.0 * PI; phi += SAMPLE_DELTA) {
+        for (float theta = 0.0; theta < 0.5 * PI; theta += SAMPLE_DELTA) {
+            vec3 tangent_sample = vec3(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            vec3 direction = tangent_sample.x * right + tangent_sample.y * up +
+                    tangent_sample.z * normal;
+            irradiance += textureLod(environment_map, direction, lod).rgb *
+                    cos(theta) * sin(theta);
+            samples += 1.0;
+        }
+    }
+    out_color = vec4(PI * irradiance / samples, 1.0);
+}
@@ -0,0 +1,12 @@
+#version 150
+
+in vec3 WorldPosition;
+
+uniform vec3 light_position;
+uniform float radius;
+
+// Depth-only pass for point light shadow cube maps. The distance to the light normalized by the
+// shadow radius is stored instead of the projected depth so it can be compared directly.
+void main() {
+    gl_FragDepth = distance(WorldPosition, light_position) / radius;
+}
@@ -0,0 +1,73 @@
+#version 150
+
+#define PI 3.14159265359
+#define SAMPLE_COUNT 512u
+
+in vec3 Direction;
+
+out vec4 out_color;
+
+uniform samplerCube environment_map;
+uniform float environment_resolution;
+uniform float roughness;
+
+// Gets the ith point of the Hammersley sequence of n points.
+vec2 hammersley(uint i, uint n) {
+    uint bits = i;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return vec2(float(i) / float(n), float(bits) * 2.3283064365386963e-10);
+}
+
+// Gets a halfway vector around the normal distributed according to the GGX distribution.
+vec3 importance_sample_ggx(vec2 xi, vec3 normal, float roughness) {
+    float alpha = roughness * roughness;
+    float phi = 2.0 * PI * xi.x;
+    float cos_theta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    float sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    vec3 halfway = vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+    vec3 up = abs(normal.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, normal));
+    vec3 bitangent = cross(normal, tangent);
+    return normalize(tangent * halfway.x + bitangent * halfway.y + normal * halfway.z);
+}
+
+// Gets the GGX normal distribution for the cosine between the normal and halfway vector.
+float distribution_ggx(float cos_nh, float roughness) {
+    float alpha = roughness * roughness;
+    float alpha2 = alpha * alpha;
+    float denom = cos_nh * cos_nh * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * denom * denom);
+}
+
+void main() {
+    // Assume the view direction is the same as the normal and reflection direction.
+    vec3 normal = normalize(Direction);
+    vec3 view = normal;
+
+    vec3 color = vec3(0.0);
+    float weight = 0.0;
+    float texel_solid_angle = 4.0 * PI / (6.0 * environment_resolution * environment_resolution);
+    for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+        vec3 halfway = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), normal, roughness);
+        vec3 light = normalize(2.0 * dot(view, halfway) * halfway - view);
+        float cos_nl = max(dot(normal, light), 0.0);
+        if (cos_nl > 0.0) {
+            // Sample from a mip level based on the solid angle covered by the sample to reduce
+            // noise from bright spots in the environment.
+            float cos_nh = max(dot(normal, halfway), 0.0);
+            float cos_hv = max(dot(halfway, view), 0.0);
+            float pdf = distribution_ggx(cos_nh, roughness) * cos_nh / (4.0 * cos_hv) + 0.0001;
+            float sample_solid_angle = 1.0 / (float(SAMPLE_COUNT) * pdf + 0.0001);
+            float lod = roughness == 0.0 ? 0.0 :
+                    0.5 * log2(sample_solid_angle / texel_solid_angle);
+            color += textureLod(environment_map, light, lod).rgb * cos_nl;
+            weight += cos_nl;
+        }
+    }
+    out_color = vec4(color / weight, 1.0);
+}
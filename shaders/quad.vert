@@ -0,0 +1,10 @@
+#version 150
+
+in vec3 position;
+
+out vec2 TCoord;
+
+void main() {
+    TCoord = position.xy * 0.5 + 0.5;
+    gl_Position = vec4(position, 1.0);
+}
@@ -0,0 +1,5 @@
+#version 150
+
+// Depth-only pass for shadow maps. The depth is written implicitly.
+void main() {
+}
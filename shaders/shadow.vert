@@ -0,0 +1,45 @@
+#version 150
+
+#define MAX_ACTIVE_MORPH_TARGETS 8
+
+in vec3 position;
+in vec4 joints;
+in vec4 weights;
+
+uniform mat4 transform;
+
+uniform bool use_skinning;
+uniform samplerBuffer joint_data;
+
+uniform int morph_count;
+uniform int morph_targets[MAX_ACTIVE_MORPH_TARGETS];
+uniform float morph_weights[MAX_ACTIVE_MORPH_TARGETS];
+uniform int morph_vertex_count;
+uniform int morph_base_vertex;
+uniform samplerBuffer morph_data;
+
+// Fetches the skinning matrix of a joint stored as four consecutive columns in the joint buffer.
+mat4 fetch_joint(float joint) {
+    int offset = int(joint) * 4;
+    return mat4(texelFetch(joint_data, offset), texelFetch(joint_data, offset + 1),
+            texelFetch(joint_data, offset + 2), texelFetch(joint_data, offset + 3));
+}
+
+// Morphs and skins the vertex position in the same way as std.vert so that shadows follow the
+// pose.
+vec4 get_skinned_position() {
+    vec4 morphed = vec4(position, 1.0);
+    for (int i = 0; i < morph_count; i++) {
+        int offset = (morph_targets[i] * morph_vertex_count + gl_VertexID - morph_base_vertex) * 2;
+        morphed.xyz += morph_weights[i] * texelFetch(morph_data, offset).xyz;
+    }
+    float total = weights.x + weights.y + weights.z + weights.w;
+    if (!use_skinning || total <= 0.0) { return morphed; }
+    mat4 skin = weights.x * fetch_joint(joints.x) + weights.y * fetch_joint(joints.y) +
+            weights.z * fetch_joint(joints.z) + weights.w * fetch_joint(joints.w);
+    return skin / total * morphed;
+}
+
+void main() {
+    gl_Position = transform * get_skinned_position();
+}
@@ -0,0 +1,13 @@
+#version 150
+
+in vec3 Direction;
+
+out vec4 out_color;
+
+uniform samplerCube skybox;
+uniform float gamma;
+
+void main() {
+    vec3 color = clamp(texture(skybox, Direction).rgb, 0.0, 1.0);
+    out_color = vec4(pow(color, vec3(1.0 / gamma)), 1.0);
+}
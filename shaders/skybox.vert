@@ -0,0 +1,13 @@
+#version 150
+
+in vec3 position;
+
+out vec3 Direction;
+
+uniform mat4 transform;
+
+void main() {
+    Direction = position;
+    // Force the depth to the far plane so the skybox is only drawn where there is no geometry.
+    gl_Position = (transform * vec4(position, 1.0)).xyww;
+}
@@ -1,24 +1,47 @@
 #version 150
 
-#define MAX_LIGHTS 8
-#define EMPTY_LIGHT 0u
-#define POINT_LIGHT 1u
-#define DIRECTIONAL_LIGHT 2u
-#define SPOT_LIGHT 3u
-
-// TODO: Add materials so these can be a uniforms.
-#define AMBIENT_COEFF 0.03
-#define SPECULAR_COLOR 1.0
+#define MAX_LIGHTS 128
+#define EMPTY_LIGHT 0
+#define POINT_LIGHT 1
+#define DIRECTIONAL_LIGHT 2
+#define SPOT_LIGHT 3
+#define MAX_SHADOW_MAPS 4
+#define MAX_CUBE_SHADOW_MAPS 4
+#define NO_SHADOW -1
+#define BLINN_PHONG 0
+#define METALLIC_ROUGHNESS 1
+#define PI 3.14159265359
+#define DIELECTRIC_F0 0.04
+#define MIN_ROUGHNESS 0.04
 
+// The normal and position are in world space.
 in vec3 Normal;
 in mat3 TBN;
 in vec3 Vert;
 in vec2 TCoord;
+in vec4 VertexColor;
 
 out vec4 out_color;
 
-uniform struct Light {
-    uint type;
+// A light as laid out in the light uniform buffer. This mirrors PackedLight in light.rs.
+struct PackedLight {
+    vec4 intensity;
+    vec4 position;
+    vec4 direction;
+    vec4 attenuation;
+    vec4 spot;
+    vec4 shadow;
+    ivec4 info;
+};
+
+layout(std140) uniform LightBlock {
+    int light_count;
+    PackedLight packed_lights[MAX_LIGHTS];
+};
+
+// An unpacked light with named fields.
+struct Light {
+    int type;
     vec3 intensity;
     vec3 position;
     vec3 direction;
@@ -27,23 +50,249 @@ uniform struct Light {
     float quad_attn;
     float cutoff;
     float dropoff;
-} lights[MAX_LIGHTS];
+    int shadow_index;
+    float shadow_bias;
+    float shadow_radius;
+};
+
+// Per-cluster (offset, count) pairs into cluster_lights, which holds the indices of the global
+// lights followed by the indices of the lights binned into each cluster.
+uniform bool use_clusters;
+uniform usamplerBuffer cluster_map;
+uniform usamplerBuffer cluster_lights;
+uniform int global_light_count;
+uniform mat4 view;
+uniform ivec3 cluster_grid;
+uniform float cluster_near;
+uniform float cluster_far;
+uniform vec2 cluster_viewport;
+
+uniform sampler2DShadow shadow_maps[MAX_SHADOW_MAPS];
+uniform mat4 shadow_matrices[MAX_SHADOW_MAPS];
+uniform float shadow_texel_sizes[MAX_SHADOW_MAPS];
+uniform samplerCubeShadow cube_shadow_maps[MAX_CUBE_SHADOW_MAPS];
+
+// Directions used to offset samples when filtering cube shadow maps.
+const vec3 CUBE_PCF_OFFSETS[8] = vec3[](
+    vec3(1, 1, 1), vec3(1, -1, 1), vec3(-1, -1, 1), vec3(-1, 1, 1),
+    vec3(1, 1, -1), vec3(1, -1, -1), vec3(-1, -1, -1), vec3(-1, 1, -1));
+#define CUBE_PCF_RADIUS 0.01
 
 uniform vec3 camera;
 uniform vec4 color;
-uniform mat4 model;
-uniform mat4 normal_matrix;
 uniform float specular_coeff;
 uniform float gamma;
 uniform sampler2D diffuse_map;
 uniform sampler2D specular_map;
 uniform sampler2D normal_map;
 uniform bool use_normal_map;
+uniform bool use_alpha_test;
+uniform float alpha_cutoff;
+uniform int shading_model;
+uniform float metallic;
+uniform float roughness;
+uniform sampler2D metallic_roughness_map;
+uniform sampler2D occlusion_map;
+uniform sampler2D emissive_map;
+uniform vec3 emissive_color;
+uniform vec3 ambient_color;
+uniform vec3 specular_color;
+uniform bool use_environment;
+uniform samplerCube irradiance_map;
+uniform samplerCube prefiltered_map;
+uniform sampler2D brdf_lut;
+uniform float prefiltered_max_lod;
 
-void main() {
-    // Ambient light.
-    vec4 total_color = vec4(AMBIENT_COEFF * color.rgb * texture(diffuse_map, TCoord).rgb, 0.0);
+// The properties of the surface being shaded by the metallic-roughness model.
+struct Surface {
+    vec3 position;
+    vec3 normal;
+    vec3 albedo;
+    float metallic;
+    float roughness;
+};
+
+// Unpacks a light from the light uniform buffer given its index.
+Light get_light(int i) {
+    PackedLight packed = packed_lights[i];
+    return Light(packed.info.x, packed.intensity.rgb, packed.position.xyz, packed.direction.xyz,
+            packed.attenuation.x, packed.attenuation.y, packed.attenuation.z, packed.spot.x,
+            packed.spot.y, packed.info.y, packed.shadow.x, packed.shadow.y);
+}
+
+// Samples a shadow map by index. GLSL 1.50 only allows constant indices into sampler arrays, so
+// this branches to the correct sampler.
+float sample_shadow_map(int index, vec3 coord) {
+    if (index == 0) return texture(shadow_maps[0], coord);
+    if (index == 1) return texture(shadow_maps[1], coord);
+    if (index == 2) return texture(shadow_maps[2], coord);
+    return texture(shadow_maps[3], coord);
+}
+
+// Samples a cube shadow map by index. Like sample_shadow_map, this branches to a constant index.
+float sample_cube_shadow_map(int index, vec4 coord) {
+    if (index == 0) return texture(cube_shadow_maps[0], coord);
+    if (index == 1) return texture(cube_shadow_maps[1], coord);
+    if (index == 2) return texture(cube_shadow_maps[2], coord);
+    return texture(cube_shadow_maps[3], coord);
+}
+
+// Gets the fraction of a point light that reaches a world space position by comparing the
+// normalized distance to the light against the cube shadow map at several nearby directions.
+float get_point_shadow(Light light, vec3 position) {
+    vec3 light_to_surface = position - light.position;
+    float dist = length(light_to_surface);
+    float depth = dist / light.shadow_radius;
+    if (depth > 1.0) return 1.0;
+    float lit = sample_cube_shadow_map(light.shadow_index,
+            vec4(light_to_surface, depth - light.shadow_bias));
+    for (int i = 0; i < 8; i++) {
+        vec3 offset = CUBE_PCF_OFFSETS[i] * CUBE_PCF_RADIUS * dist;
+        lit += sample_cube_shadow_map(light.shadow_index,
+                vec4(light_to_surface + offset, depth - light.shadow_bias));
+    }
+    return lit / 9.0;
+}
+
+// Gets the fraction of the light that reaches a world space position using 3x3 percentage closer
+// filtering on the light's shadow map.
+float get_shadow(Light light, vec3 position) {
+    if (light.shadow_index == NO_SHADOW) return 1.0;
+    if (light.type == POINT_LIGHT) return get_point_shadow(light, position);
+    vec4 coord = shadow_matrices[light.shadow_index] * vec4(position, 1.0);
+    coord.xyz = (coord.xyz / coord.w) * 0.5 + 0.5;
+    if (coord.z > 1.0) return 1.0;
+    float texel = shadow_texel_sizes[light.shadow_index];
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            vec2 offset = vec2(x, y) * texel;
+            lit += sample_shadow_map(light.shadow_index,
+                    vec3(coord.xy + offset, coord.z - light.shadow_bias));
+        }
+    }
+    return lit / 9.0;
+}
+
+// Gets the index of the cluster containing a world space position or -1 if it lies outside of the
+// clustered depth range. The depth slices mirror ClusterGrid::get_slice in cluster.rs.
+int get_cluster(vec3 position) {
+    float depth = -(view * vec4(position, 1.0)).z;
+    if (depth < cluster_near || depth > cluster_far) return -1;
+    float t;
+    if (cluster_near > 0.0) {
+        t = log(depth / cluster_near) / log(cluster_far / cluster_near);
+    } else {
+        t = (depth - cluster_near) / (cluster_far - cluster_near);
+    }
+    int slice = min(int(t * float(cluster_grid.z)), cluster_grid.z - 1);
+    ivec2 tile = clamp(ivec2(gl_FragCoord.xy / cluster_viewport * vec2(cluster_grid.xy)),
+            ivec2(0), cluster_grid.xy - 1);
+    return (slice * cluster_grid.y + tile.y) * cluster_grid.x + tile.x;
+}
+
+// Gets the intensity of a light that reaches a world space position after attenuation, spot
+// falloff, and shadowing along with the direction from the position to the light.
+vec3 get_light_intensity(Light light, vec3 position, out vec3 surface_to_light) {
+    vec3 intensity;
+    if (light.type == DIRECTIONAL_LIGHT) {
+        surface_to_light = -normalize(light.direction);
+        intensity = light.intensity;
+    } else {
+        // Point light.
+        surface_to_light = normalize(light.position - position);
+        float dist = distance(position, light.position);
+        intensity = light.intensity /
+            (light.const_attn + light.linear_attn * dist + light.quad_attn * (dist * dist));
+        if (light.type == SPOT_LIGHT) {
+            float cos_dv = dot(normalize(light.direction), -surface_to_light);
+            if (cos_dv > cos(light.cutoff)) {
+                intensity *= pow(cos_dv, light.dropoff);
+            } else {
+                intensity = vec3(0, 0, 0);
+            }
+        }
+    }
+    return intensity * get_shadow(light, position);
+}
+
+// Gets the Blinn-Phong diffuse and specular contribution of a single light. The alpha is 0 since
+// the surface's alpha comes from the ambient term.
+vec4 shade_blinn_phong(Light light, Surface surface) {
+    vec3 surface_to_light;
+    vec3 intensity = get_light_intensity(light, surface.position, surface_to_light);
+
+    // Get diffuse lighting.
+    float cos_nl = max(dot(surface_to_light, surface.normal), 0.0);
+    vec4 diffuse = vec4(cos_nl * intensity * color.rgb * texture(diffuse_map, TCoord).rgb, 0.0);
+
+    // Get specular lighting.
+    vec4 specular = vec4(0, 0, 0, 0);
+    if (cos_nl > 0.0) {
+        vec3 surface_to_camera = normalize(camera - surface.position);
+        vec3 halfway = normalize(surface_to_light + surface_to_camera);
+        float cos_nha = pow(max(dot(surface.normal, halfway), 0.0), specular_coeff);
+        specular = vec4(cos_nha * specular_color * texture(specular_map, TCoord).rgb * intensity,
+                0.0);
+    }
+    return diffuse + specular;
+}
+
+// Gets the contribution of a single light using a Cook-Torrance BRDF with a GGX distribution, a
+// Schlick-GGX geometry term, and a Schlick Fresnel approximation. Light intensities are scaled by
+// PI so that a white dielectric receives the same diffuse lighting as under Blinn-Phong.
+vec4 shade_cook_torrance(Light light, Surface surface) {
+    vec3 surface_to_light;
+    vec3 intensity = get_light_intensity(light, surface.position, surface_to_light);
+    float cos_nl = max(dot(surface.normal, surface_to_light), 0.0);
+    if (cos_nl <= 0.0) return vec4(0, 0, 0, 0);
+
+    vec3 surface_to_camera = normalize(camera - surface.position);
+    vec3 halfway = normalize(surface_to_light + surface_to_camera);
+    float cos_nv = max(dot(surface.normal, surface_to_camera), 0.0001);
+    float cos_nh = max(dot(surface.normal, halfway), 0.0);
+    float cos_hv = max(dot(halfway, surface_to_camera), 0.0);
+
+    float alpha = surface.roughness * surface.roughness;
+    float alpha2 = alpha * alpha;
+    float denom = cos_nh * cos_nh * (alpha2 - 1.0) + 1.0;
+    float distribution = alpha2 / (PI * denom * denom);
+
+    float k = (surface.roughness + 1.0) * (surface.roughness + 1.0) / 8.0;
+    float geometry = (cos_nv / (cos_nv * (1.0 - k) + k)) * (cos_nl / (cos_nl * (1.0 - k) + k));
+
+    vec3 f0 = mix(vec3(DIELECTRIC_F0), surface.albedo, surface.metallic);
+    vec3 fresnel = f0 + (1.0 - f0) * pow(1.0 - cos_hv, 5.0);
+
+    vec3 specular = distribution * geometry * fresnel / (4.0 * cos_nv * cos_nl);
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - surface.metallic) * surface.albedo / PI;
+    return vec4(PI * (diffuse + specular) * intensity * cos_nl, 0.0);
+}
+
+// Gets the ambient light reflected by a metallic-roughness surface from the environment using the
+// irradiance map for diffuse and the prefiltered map with the BRDF lookup table for specular.
+vec3 get_environment_light(Surface surface) {
+    vec3 surface_to_camera = normalize(camera - surface.position);
+    float cos_nv = max(dot(surface.normal, surface_to_camera), 0.0);
+    vec3 f0 = mix(vec3(DIELECTRIC_F0), surface.albedo, surface.metallic);
+    vec3 fresnel = f0 + (max(vec3(1.0 - surface.roughness), f0) - f0) * pow(1.0 - cos_nv, 5.0);
+    vec3 irradiance = texture(irradiance_map, surface.normal).rgb;
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - surface.metallic) * irradiance * surface.albedo;
+
+    vec3 reflected = reflect(-surface_to_camera, surface.normal);
+    vec3 prefiltered = textureLod(prefiltered_map, reflected,
+            surface.roughness * prefiltered_max_lod).rgb;
+    vec2 brdf = texture(brdf_lut, vec2(cos_nv, surface.roughness)).rg;
+    return diffuse + prefiltered * (fresnel * brdf.x + brdf.y);
+}
 
+// Gets the contribution of a single light using the shading model of the material.
+vec4 shade_light(Light light, Surface surface) {
+    if (shading_model == METALLIC_ROUGHNESS) return shade_cook_torrance(light, surface);
+    return shade_blinn_phong(light, surface);
+}
+
+void main() {
     // Transform normal map to world space.
     vec3 world_normal;
     if (use_normal_map) {
@@ -51,96 +300,58 @@ void main() {
         world_normal = normalize(world_normal * 2.0 - 1.0);
         world_normal = normalize(TBN * world_normal);
     } else {
-        world_normal = normalize(mat3(normal_matrix) * Normal);
+        world_normal = normalize(Normal);
     }
     // world_normal = normalize(mat3(normal_matrix) * (texture(normal_map, TCoord).rgb - 0.5) * 2);
 
-    Light light = lights[7];
-    if (light.type != EMPTY_LIGHT) {
-        vec3 position = vec3(model * vec4(Vert, 1.0));
-        vec3 surface_to_light;
-        vec3 intensity;
-
-        if (light.type == DIRECTIONAL_LIGHT) {
-            surface_to_light = -normalize(light.direction);
-            intensity = light.intensity;
-        } else {
-            // Point light.
-            surface_to_light = normalize(light.position - position);
-            float dist = distance(position, light.position);
-            intensity = light.intensity /
-                (light.const_attn + light.linear_attn * dist + light.quad_attn * (dist * dist));
-            if (light.type == SPOT_LIGHT) {
-                // bla = 1.0;
-                float cos_dv = dot(normalize(light.direction), -surface_to_light);
-                if (cos_dv > cos(light.cutoff)) {
-                    intensity *= pow(cos_dv, light.dropoff);
-                } else {
-                    intensity = vec3(0, 0, 0);
-                }
-            }
-        }
-
-        // Get diffuse lighting.
-        float cos_nl = max(dot(surface_to_light, world_normal), 0.0);
-        vec4 diffuse = vec4(cos_nl * intensity * color.rgb *
-                texture(diffuse_map, TCoord).rgb, color.a);
-
-        // Get specular lighting.
-        vec4 specular = vec4(0, 0, 0, 0);
-        if (cos_nl > 0.0) {
-            vec3 surface_to_camera = normalize(camera - position);
-            vec3 halfway = normalize(surface_to_light + surface_to_camera);
-            float cos_nha = pow(max(dot(world_normal, halfway), 0.0), specular_coeff);
-            specular = vec4(cos_nha * texture(specular_map, TCoord).rgb * intensity, 0.0);
-        }
-
-        // Get total lighting.
-        total_color += diffuse + specular;
+    Surface surface;
+    surface.position = Vert;
+    surface.normal = world_normal;
+    vec4 base_color = color * VertexColor * texture(diffuse_map, TCoord);
+    if (use_alpha_test && base_color.a < alpha_cutoff) {
+        discard;
+    }
+    surface.albedo = base_color.rgb;
+    vec3 emissive = emissive_color * texture(emissive_map, TCoord).rgb;
+    vec4 total_color;
+    if (shading_model == METALLIC_ROUGHNESS) {
+        // Roughness and metallic are stored in the green and blue channels respectively.
+        vec3 metallic_roughness = texture(metallic_roughness_map, TCoord).rgb;
+        surface.metallic = clamp(metallic * metallic_roughness.b, 0.0, 1.0);
+        surface.roughness = clamp(roughness * metallic_roughness.g, MIN_ROUGHNESS, 1.0);
+        float occlusion = texture(occlusion_map, TCoord).r;
+        vec3 ambient = use_environment ? get_environment_light(surface) :
+                ambient_color * surface.albedo;
+        total_color = vec4(ambient * occlusion + emissive, base_color.a);
+    } else {
+        // Ambient light.
+        vec3 ambient = use_environment ? texture(irradiance_map, world_normal).rgb :
+                ambient_color;
+        total_color = vec4(ambient * surface.albedo + emissive, base_color.a);
     }
 
-    light = lights[6];
-    if (light.type != EMPTY_LIGHT) {
-        vec3 position = vec3(model * vec4(Vert, 1.0));
-        vec3 surface_to_light;
-        vec3 intensity;
-
-        if (light.type == DIRECTIONAL_LIGHT) {
-            surface_to_light = -normalize(light.direction);
-            intensity = light.intensity;
-        } else {
-            // Point light.
-            surface_to_light = normalize(light.position - position);
-            float dist = distance(position, light.position);
-            intensity = light.intensity /
-                (light.const_attn + light.linear_attn * dist + light.quad_attn * (dist * dist));
-            if (light.type == SPOT_LIGHT) {
-                // bla = 1.0;
-                float cos_dv = dot(normalize(light.direction), -surface_to_light);
-                if (cos_dv > cos(light.cutoff)) {
-                    intensity *= pow(cos_dv, light.dropoff);
-                } else {
-                    intensity = vec3(0, 0, 0);
-                }
+    if (use_clusters) {
+        // Only shade the global lights and the lights binned into this fragment's cluster.
+        for (int i = 0; i < global_light_count; i++) {
+            int index = int(texelFetch(cluster_lights, i).r);
+            total_color += shade_light(get_light(index), surface);
+        }
+        int cluster = get_cluster(surface.position);
+        if (cluster != -1) {
+            uvec2 entry = texelFetch(cluster_map, cluster).rg;
+            int offset = global_light_count + int(entry.x);
+            for (int i = 0; i < int(entry.y); i++) {
+                int index = int(texelFetch(cluster_lights, offset + i).r);
+                total_color += shade_light(get_light(index), surface);
             }
         }
-
-        // Get diffuse lighting.
-        float cos_nl = max(dot(surface_to_light, world_normal), 0.0);
-        vec4 diffuse = vec4(cos_nl * intensity * color.rgb *
-                texture(diffuse_map, TCoord).rgb, color.a);
-
-        // Get specular lighting.
-        vec4 specular = vec4(0, 0, 0, 0);
-        if (cos_nl > 0.0) {
-            vec3 surface_to_camera = normalize(camera - position);
-            vec3 halfway = normalize(surface_to_light + surface_to_camera);
-            float cos_nha = pow(max(dot(world_normal, halfway), 0.0), specular_coeff);
-            specular = vec4(cos_nha * texture(specular_map, TCoord).rgb * intensity, 0.0);
+    } else {
+        for (int i = 0; i < light_count; i++) {
+            Light light = get_light(i);
+            if (light.type != EMPTY_LIGHT) {
+                total_color += shade_light(light, surface);
+            }
         }
-
-        // Get total lighting.
-        total_color += diffuse + specular;
     }
 
     vec4 final_color = clamp(total_color, 0.0, 1.0);
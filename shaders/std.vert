@@ -1,27 +1,102 @@
 #version 150
 
+#define INSTANCE_TEXELS 8
+#define MAX_ACTIVE_MORPH_TARGETS 8
+
 in vec3 position;
 in vec3 normal;
 in vec3 tangent;
 in vec3 bitangent;
 in vec2 tcoord;
+in vec4 vertex_color;
+in vec4 joints;
+in vec4 weights;
 
 out vec3 Normal;
 out mat3 TBN;
 out vec3 Vert;
 out vec2 TCoord;
+out vec4 VertexColor;
 
+uniform mat4 model;
 uniform mat4 normal_matrix;
 uniform mat4 transform;
 
+// Instanced draws read the model and normal matrices of each instance from the instance buffer
+// instead of the uniforms above.
+uniform bool use_instancing;
+uniform mat4 view_projection;
+uniform samplerBuffer instance_data;
+
+// Skinned draws blend the vertex between the skinning matrices of up to four joints.
+uniform bool use_skinning;
+uniform samplerBuffer joint_data;
+
+// Morphed draws add the weighted position and normal deltas of their active morph targets. The
+// deltas of each target are stored as a position and normal texel for every vertex of the model,
+// and gl_VertexID is offset by the base vertex of the model in its VBO.
+uniform int morph_count;
+uniform int morph_targets[MAX_ACTIVE_MORPH_TARGETS];
+uniform float morph_weights[MAX_ACTIVE_MORPH_TARGETS];
+uniform int morph_vertex_count;
+uniform int morph_base_vertex;
+uniform samplerBuffer morph_data;
+
+// Fetches a matrix stored as four consecutive columns in a buffer texture.
+mat4 fetch_matrix(samplerBuffer data, int offset) {
+    return mat4(texelFetch(data, offset), texelFetch(data, offset + 1),
+            texelFetch(data, offset + 2), texelFetch(data, offset + 3));
+}
+
+// Gets the weighted sum of the skinning matrices influencing the vertex. Vertices without any
+// weight are left in place.
+mat4 get_skin_matrix() {
+    float total = weights.x + weights.y + weights.z + weights.w;
+    if (!use_skinning || total <= 0.0) { return mat4(1.0); }
+    mat4 skin = mat4(0.0);
+    for (int i = 0; i < 4; i++) {
+        skin += weights[i] * fetch_matrix(joint_data, int(joints[i]) * 4);
+    }
+    return skin / total;
+}
+
 void main() {
+    mat4 model_matrix = model;
+    mat4 normal_model_matrix = normal_matrix;
+    mat4 transform_matrix = transform;
+    if (use_instancing) {
+        int offset = gl_InstanceID * INSTANCE_TEXELS;
+        model_matrix = fetch_matrix(instance_data, offset);
+        normal_model_matrix = fetch_matrix(instance_data, offset + 4);
+        transform_matrix = view_projection * model_matrix;
+    }
+
+    // Morph targets are applied in bind space before skinning.
+    vec3 morphed_position = position;
+    vec3 morphed_normal = normal;
+    int vertex = gl_VertexID - morph_base_vertex;
+    for (int i = 0; i < morph_count; i++) {
+        int offset = (morph_targets[i] * morph_vertex_count + vertex) * 2;
+        morphed_position += morph_weights[i] * texelFetch(morph_data, offset).xyz;
+        morphed_normal += morph_weights[i] * texelFetch(morph_data, offset + 1).xyz;
+    }
+
+    // Skinning happens in model space before the model matrix is applied. The skinned normals
+    // assume that joints are not scaled non-uniformly.
+    mat4 skin = get_skin_matrix();
+    vec4 skinned_position = skin * vec4(morphed_position, 1.0);
+    vec3 skinned_normal = mat3(skin) * morphed_normal;
+    vec3 skinned_tangent = mat3(skin) * tangent;
+    vec3 skinned_bitangent = mat3(skin) * bitangent;
+
     // TODO: Orthognalize TBN.
-    Normal = normal;
-    vec3 T = normalize(vec3(normal_matrix * vec4(tangent, 0.0)));
-    vec3 B = normalize(vec3(normal_matrix * vec4(bitangent, 0.0)));
-    vec3 N = normalize(vec3(normal_matrix * vec4(normal, 0.0)));
+    Normal = mat3(normal_model_matrix) * skinned_normal;
+    vec3 T = normalize(vec3(normal_model_matrix * vec4(skinned_tangent, 0.0)));
+    vec3 B = normalize(vec3(normal_model_matrix * vec4(skinned_bitangent, 0.0)));
+    vec3 N = normalize(vec3(normal_model_matrix * vec4(skinned_normal, 0.0)));
     TBN = mat3(T, B, N);
     TCoord = tcoord;
-    Vert = position;
-    gl_Position = transform * vec4(position, 1.0);
+    VertexColor = vertex_color;
+    Vert = vec3(model_matrix * skinned_position);
+    gl_Position = transform_matrix * skinned_position;
 }
@@ -0,0 +1,187 @@
+// Defines the built-in components that bridge the ECS and the renderer. A Transform places an
+// entity in the world, and a MeshRenderer, Camera, or Light on the same entity is moved with it by
+// the RenderSystem. Cameras and lights are handed to the GameWindow the first time the
+// RenderSystem sees them and are removed from it once their component or entity is gone.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use self::cgmath::Rotation;
+use gfx::animation;
+use gfx::camera;
+use gfx::game_window::GameWindow;
+use gfx::handle::{CameraHandle, DirectionalLightHandle, PointLightHandle, SpotLightHandle};
+use gfx::light;
+use gfx::model;
+use gfx::types::*;
+use std::rc::Rc;
+
+// The position, rotation, and scale of an entity in world space.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub pos: Vector3D,
+    pub rot: Quaternion,
+    pub scale: f32,
+}
+
+impl Transform {
+    // Creates a Transform at a position without any rotation or scaling.
+    pub fn new(pos: Vector3D) -> Transform {
+        Transform { pos: pos, rot: Quaternion::new(1.0, 0.0, 0.0, 0.0), scale: 1.0 }
+    }
+
+    // Gets the matrix that transforms the entity's space into world space.
+    pub fn get_matrix(&self) -> cgmath::Matrix4<GLfloat> {
+        cgmath::Matrix4::from(cgmath::Decomposed {
+                scale: self.scale, rot: self.rot, disp: self.pos })
+    }
+
+    // Rotates a direction in the entity's space into world space.
+    pub fn rotate(&self, direction: Vector3D) -> Vector3D { self.rot.rotate_vector(direction) }
+}
+
+// Draws a ModelInfo at the entity's Transform. The instance is private so that its position,
+// rotation, and scale are always derived from the Transform, but it can still be posed or morphed.
+pub struct MeshRenderer {
+    instance: model::ModelInstance,
+    pub visible: bool,
+    pub casts_shadows: bool,
+}
+
+impl MeshRenderer {
+    // Creates a visible shadow casting MeshRenderer from a reference counted ModelInfo.
+    pub fn new(info: Rc<model::ModelInfo>) -> MeshRenderer {
+        MeshRenderer { instance: model::ModelInstance::from(info), visible: true,
+                casts_shadows: true }
+    }
+
+    // Gets the ModelInfo that is drawn.
+    pub fn get_info(&self) -> &Rc<model::ModelInfo> { &self.instance.info }
+
+    // Gets the instance that is drawn.
+    pub fn get_instance(&self) -> &model::ModelInstance { &self.instance }
+
+    // Moves the instance to a Transform. The model and normal matrices are only recomputed if the
+    // Transform changed. The RenderSystem does this automatically every frame.
+    pub fn set_transform(&mut self, transform: &Transform) {
+        let instance = &mut self.instance;
+        if instance.pos != transform.pos || instance.rot != transform.rot ||
+                instance.scale != transform.scale {
+            instance.pos = transform.pos;
+            instance.rot = transform.rot;
+            instance.scale = transform.scale;
+            instance.update();
+        }
+    }
+
+    // Poses the instance's skeleton. Returns Err if the ModelInfo is not skinned or if the Pose
+    // does not match its skeleton.
+    pub fn set_pose(&mut self, pose: &animation::Pose) -> Result<(), String> {
+        self.instance.set_pose(pose)
+    }
+
+    // Sets the weight of a morph target by name. Returns Err if the ModelInfo does not have a
+    // morph target with that name.
+    pub fn set_morph_weight(&mut self, name: &str, weight: GLfloat) -> Result<(), String> {
+        self.instance.set_morph_weight(name, weight)
+    }
+}
+
+// Views the scene from the entity's Transform. The camera looks along its direction rotated by
+// the Transform. The active Camera component is made the GameWindow's active camera, and if more
+// than one is active, the one on the entity with the highest index wins.
+pub struct Camera {
+    camera: Option<Box<camera::Camera>>,
+    handle: Option<CameraHandle>,
+    pub direction: Vector3D,
+    pub active: bool,
+}
+
+impl Camera {
+    // Creates a Camera component from a camera whose direction from its position to its target is
+    // kept relative to the Transform. The camera's own position is replaced by the Transform's.
+    pub fn new<T: camera::Camera + 'static>(camera: T, active: bool) -> Camera {
+        let direction = camera.get_target() - camera.get_pos();
+        Camera { camera: Some(Box::new(camera)), handle: None, direction: direction,
+                active: active }
+    }
+
+    // Attaches the camera to a GameWindow if it has not been attached yet and returns its handle.
+    // The RenderSystem does this automatically.
+    pub fn attach(&mut self, window: &mut GameWindow) -> CameraHandle {
+        if let Some(camera) = self.camera.take() {
+            self.handle = Some(window.attach_boxed_camera(camera));
+        }
+        self.handle.unwrap()
+    }
+
+    // Gets the handle of the camera in the GameWindow, which can be used to change its projection.
+    // Returns None if the camera has not been attached yet.
+    pub fn get_handle(&self) -> Option<CameraHandle> { self.handle }
+}
+
+// The light of a Light component before it is attached to the GameWindow.
+enum LightSource {
+    Point(light::PointLight),
+    Directional(light::DirectionalLight),
+    Spot(light::SpotLight),
+}
+
+// The handle of a Light component's light in the GameWindow.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LightHandle {
+    Point(PointLightHandle),
+    Directional(DirectionalLightHandle),
+    Spot(SpotLightHandle),
+}
+
+// Lights the scene from the entity's Transform. PointLights and SpotLights are placed at the
+// Transform's position, and DirectionalLights and SpotLights shine along their direction rotated by
+// the Transform.
+pub struct Light {
+    source: Option<LightSource>,
+    handle: Option<LightHandle>,
+    pub direction: Vector3D,
+}
+
+impl Light {
+    // Creates a Light component from a PointLight.
+    pub fn point(light: light::PointLight) -> Light {
+        Light { source: Some(LightSource::Point(light)), handle: None,
+                direction: Vector3D::new(0.0, 0.0, 0.0) }
+    }
+
+    // Creates a Light component from a DirectionalLight whose direction is relative to the
+    // Transform.
+    pub fn directional(light: light::DirectionalLight) -> Light {
+        let direction = light.direction;
+        Light { source: Some(LightSource::Directional(light)), handle: None, direction: direction }
+    }
+
+    // Creates a Light component from a SpotLight whose direction is relative to the Transform.
+    pub fn spot(light: light::SpotLight) -> Light {
+        let direction = light.direction;
+        Light { source: Some(LightSource::Spot(light)), handle: None, direction: direction }
+    }
+
+    // Attaches the light to a GameWindow if it has not been attached yet and returns its handle.
+    // The RenderSystem does this automatically. Returns Err if the GameWindow already has the
+    // maximum number of lights, in which case the light is dropped and cannot be attached again.
+    pub fn attach(&mut self, window: &mut GameWindow) -> Result<LightHandle, String> {
+        if let Some(source) = self.source.take() {
+            self.handle = Some(match source {
+                LightSource::Point(l) => LightHandle::Point(try!(window.attach_point_light(l))),
+                LightSource::Directional(l) =>
+                        LightHandle::Directional(try!(window.attach_directional_light(l))),
+                LightSource::Spot(l) => LightHandle::Spot(try!(window.attach_spot_light(l))),
+            });
+        }
+        self.handle.ok_or("Light component failed to attach its light.".to_string())
+    }
+
+    // Gets the handle of the light in the GameWindow, which can be used to change its intensity
+    // and other attributes. Returns None if the light has not been attached yet.
+    pub fn get_handle(&self) -> Option<LightHandle> { self.handle }
+}
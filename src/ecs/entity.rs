@@ -0,0 +1,116 @@
+// Defines the Entity ID and the EntityAllocator that hands them out. An Entity is an index along
+// with the generation of that index. Indices are reused after an entity is destroyed, but the
+// generation is bumped so that stale IDs of destroyed entities can be detected.
+//
+// Brian Ho
+// brian@brkho.com
+
+// A generational ID of an entity.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Entity {
+    index: usize,
+    generation: u32,
+}
+
+impl Entity {
+    // Gets the index of the entity, which is only unique among living entities.
+    pub fn get_index(&self) -> usize { self.index }
+
+    // Gets the generation of the entity's index.
+    pub fn get_generation(&self) -> u32 { self.generation }
+}
+
+// Allocates and frees entity IDs.
+pub struct EntityAllocator {
+    generations: Vec<u32>,
+    alive: Vec<bool>,
+    free: Vec<usize>,
+}
+
+impl EntityAllocator {
+    // Default constructor for an allocator without any entities.
+    pub fn new() -> EntityAllocator {
+        EntityAllocator { generations: Vec::new(), alive: Vec::new(), free: Vec::new() }
+    }
+
+    // Creates a new entity, reusing the index of a destroyed entity if there is one.
+    pub fn create(&mut self) -> Entity {
+        match self.free.pop() {
+            Some(index) => {
+                self.alive[index] = true;
+                Entity { index: index, generation: self.generations[index] }
+            },
+            None => {
+                self.generations.push(0);
+                self.alive.push(true);
+                Entity { index: self.generations.len() - 1, generation: 0 }
+            },
+        }
+    }
+
+    // Destroys an entity so that its index can be reused. Returns Err if the entity is not alive.
+    pub fn destroy(&mut self, entity: Entity) -> Result<(), String> {
+        if !self.is_alive(entity) {
+            return Err(format!("Entity {:?} is not alive.", entity));
+        }
+        self.alive[entity.index] = false;
+        self.generations[entity.index] = self.generations[entity.index].wrapping_add(1);
+        self.free.push(entity.index);
+        Ok(())
+    }
+
+    // Whether an entity has been created and not yet destroyed.
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        entity.index < self.alive.len() && self.alive[entity.index] &&
+                self.generations[entity.index] == entity.generation
+    }
+
+    // Gets the number of living entities.
+    pub fn len(&self) -> usize { self.alive.len() - self.free.len() }
+
+    // Whether there are no living entities.
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    // Gets every living entity in order of their indices.
+    pub fn get_entities(&self) -> Vec<Entity> {
+        (0..self.alive.len()).filter(|&i| self.alive[i]).map(|i|
+                Entity { index: i, generation: self.generations[i] }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EntityAllocator;
+
+    #[test]
+    fn reuse_destroyed_index() {
+        let mut allocator = EntityAllocator::new();
+        let first = allocator.create();
+        let second = allocator.create();
+        allocator.destroy(first).unwrap();
+        assert!(!allocator.is_alive(first));
+        assert!(allocator.destroy(first).is_err());
+
+        // The index is reused with a new generation, so the stale ID stays dead.
+        let third = allocator.create();
+        assert_eq!(third.get_index(), first.get_index());
+        assert_eq!(third.get_generation(), first.get_generation() + 1);
+        assert!(allocator.is_alive(third) && allocator.is_alive(second));
+        assert!(!allocator.is_alive(first));
+        assert!(allocator.destroy(first).is_err());
+        assert_eq!(allocator.len(), 2);
+        assert_eq!(allocator.get_entities(), vec![third, second]);
+
+        let fourth = allocator.create();
+        assert_eq!((fourth.get_index(), fourth.get_generation()), (2, 0));
+    }
+
+    #[test]
+    fn destroy_every_entity() {
+        let mut allocator = EntityAllocator::new();
+        let entities: Vec<_> = (0..4).map(|_| allocator.create()).collect();
+        for &entity in entities.iter() { allocator.destroy(entity).unwrap(); }
+        assert!(allocator.is_empty());
+        assert!(allocator.get_entities().is_empty());
+    }
+}
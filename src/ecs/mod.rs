@@ -0,0 +1,15 @@
+// An entity-component-system for game code. Entities are generational IDs handed out by the
+// World, components are plain structs stored in a typed Storage per component type, and systems
+// are run in order by a Scheduler. Components of several types can be iterated over together with
+// the Join trait. The built-in Transform, MeshRenderer, Camera, and Light components are drawn
+// by the RenderSystem.
+//
+// Brian Ho
+// brian@brkho.com
+
+pub mod components;
+pub mod entity;
+pub mod render;
+pub mod storage;
+pub mod system;
+pub mod world;
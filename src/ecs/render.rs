@@ -0,0 +1,180 @@
+// Defines the RenderSystem which draws a World through a GameWindow. The GameWindow must be added
+// to the World as a resource. Every frame, the RenderSystem attaches new Camera and Light
+// components to the GameWindow, removes the cameras and lights of components that are gone, moves
+// cameras, lights, and MeshRenderers to their entities' Transforms, and then renders shadows and
+// draws every visible MeshRenderer. The RenderStats of the frame are stored as a resource.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use self::cgmath::EuclideanVector;
+use ecs::components::{Camera, Light, LightHandle, MeshRenderer, Transform};
+use ecs::entity::Entity;
+use ecs::storage::Join;
+use ecs::system::System;
+use ecs::world::World;
+use gfx::game_window::GameWindow;
+use gfx::handle::CameraHandle;
+use gfx::model;
+use gfx::render_queue;
+use std::collections::HashMap;
+
+// A System that renders the World. This should be the last system that is run each frame since
+// it swaps the GameWindow's buffers.
+pub struct RenderSystem {
+    cameras: HashMap<Entity, CameraHandle>,
+    lights: HashMap<Entity, LightHandle>,
+}
+
+impl RenderSystem {
+    // Default constructor for a RenderSystem that has not attached anything yet.
+    pub fn new() -> RenderSystem {
+        RenderSystem { cameras: HashMap::new(), lights: HashMap::new() }
+    }
+
+    // Helper method that removes the cameras and lights whose components or entities are gone
+    // from the GameWindow.
+    fn remove_stale(&mut self, world: &World, window: &mut GameWindow) -> Result<(), String> {
+        let cameras = try!(world.read::<Camera>());
+        let stale: Vec<Entity> =
+                self.cameras.keys().filter(|&&e| !cameras.contains(e)).cloned().collect();
+        for entity in stale {
+            try!(window.detach_camera(self.cameras.remove(&entity).unwrap()));
+        }
+        let lights = try!(world.read::<Light>());
+        let stale: Vec<Entity> =
+                self.lights.keys().filter(|&&e| !lights.contains(e)).cloned().collect();
+        for entity in stale {
+            try!(RenderSystem::remove_light(window, self.lights.remove(&entity).unwrap()));
+        }
+        Ok(())
+    }
+
+    // Helper function that removes a light from the GameWindow given its handle.
+    fn remove_light(window: &mut GameWindow, handle: LightHandle) -> Result<(), String> {
+        match handle {
+            LightHandle::Point(h) => { try!(window.remove_point_light(h)); },
+            LightHandle::Directional(h) => { try!(window.remove_directional_light(h)); },
+            LightHandle::Spot(h) => { try!(window.remove_spot_light(h)); },
+        }
+        Ok(())
+    }
+
+    // Helper method that attaches new cameras, moves every camera to its Transform, and sets the
+    // active camera.
+    fn update_cameras(&mut self, world: &World, window: &mut GameWindow) -> Result<(), String> {
+        let transforms = try!(world.read::<Transform>());
+        let mut cameras = try!(world.write::<Camera>());
+        let mut active = None;
+        for (entity, camera) in cameras.iter_mut() {
+            let handle = camera.attach(window);
+            if let Some(previous) = self.cameras.insert(entity, handle) {
+                if previous != handle { try!(window.detach_camera(previous)); }
+            }
+            if let Some(transform) = transforms.get(entity) {
+                let target = transform.pos + transform.rotate(camera.direction);
+                let view = try!(window.get_camera_mut(handle));
+                view.set_pos(transform.pos);
+                view.set_target(target);
+                view.update_view();
+            }
+            if camera.active { active = Some(handle); }
+        }
+        if let Some(handle) = active { try!(window.set_active_camera(handle)); }
+        window.update_active_camera();
+        Ok(())
+    }
+
+    // Helper method that attaches new lights and moves every light to its Transform. Lights are
+    // only updated if they moved so that their shadows are not re-rendered needlessly.
+    fn update_lights(&mut self, world: &World, window: &mut GameWindow) -> Result<(), String> {
+        let transforms = try!(world.read::<Transform>());
+        let mut lights = try!(world.write::<Light>());
+        for (entity, light) in lights.iter_mut() {
+            let handle = try!(light.attach(window));
+            if let Some(previous) = self.lights.insert(entity, handle) {
+                if previous != handle { try!(RenderSystem::remove_light(window, previous)); }
+            }
+            let transform = match transforms.get(entity) {
+                Some(transform) => transform,
+                None => { continue; },
+            };
+            let direction = transform.rotate(light.direction);
+            let direction = if direction.length2() > 0.0 { direction.normalize() }
+                    else { direction };
+            match handle {
+                LightHandle::Point(h) => {
+                    if try!(window.get_point_light(h)).position == transform.pos { continue; }
+                    try!(window.get_point_light_mut(h)).position = transform.pos;
+                    try!(window.update_point_light(h));
+                },
+                LightHandle::Directional(h) => {
+                    if try!(window.get_directional_light(h)).direction == direction { continue; }
+                    try!(window.get_directional_light_mut(h)).direction = direction;
+                    try!(window.update_directional_light(h));
+                },
+                LightHandle::Spot(h) => {
+                    {
+                        let spot = try!(window.get_spot_light(h));
+                        if spot.position == transform.pos && spot.direction == direction {
+                            continue;
+                        }
+                    }
+                    {
+                        let spot = try!(window.get_spot_light_mut(h));
+                        spot.position = transform.pos;
+                        spot.direction = direction;
+                    }
+                    try!(window.update_spot_light(h));
+                },
+            }
+        }
+        Ok(())
+    }
+
+    // Helper method that moves every MeshRenderer to its Transform, renders the shadows of the
+    // shadow casters, and draws every visible MeshRenderer.
+    fn draw(&mut self, world: &World, window: &mut GameWindow) ->
+            Result<render_queue::RenderStats, String> {
+        let transforms = try!(world.read::<Transform>());
+        let mut renderers = try!(world.write::<MeshRenderer>());
+        for (_, (transform, renderer)) in (&*transforms, &mut *renderers).join() {
+            renderer.set_transform(transform);
+        }
+
+        let casters: Vec<&model::ModelInstance> = renderers.iter()
+                .filter(|&(_, r)| r.visible && r.casts_shadows).map(|(_, r)| r.get_instance())
+                .collect();
+        window.render_shadows(&casters);
+        try!(window.update_light_clusters());
+        window.clear();
+        for (_, renderer) in renderers.iter() {
+            if renderer.visible { window.submit(renderer.get_instance()); }
+        }
+        let stats = window.flush();
+        window.swap_buffers();
+        Ok(stats)
+    }
+}
+
+impl System for RenderSystem {
+    // Renders a frame. Returns Err if the World does not have a GameWindow resource or if there is
+    // no active camera.
+    fn run(&mut self, world: &mut World, _: f32) -> Result<(), String> {
+        world.register::<Transform>();
+        world.register::<MeshRenderer>();
+        world.register::<Camera>();
+        world.register::<Light>();
+        let stats = {
+            let mut window = try!(world.write_resource::<GameWindow>());
+            try!(self.remove_stale(world, &mut window));
+            try!(self.update_cameras(world, &mut window));
+            try!(self.update_lights(world, &mut window));
+            try!(self.draw(world, &mut window))
+        };
+        world.insert_resource(stats);
+        Ok(())
+    }
+}
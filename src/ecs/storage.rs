@@ -0,0 +1,337 @@
+// Defines the Storage that holds every component of a single type and the Join trait for iterating
+// over entities that have components of several types. A Storage is a vector indexed by entity
+// index, so joining storages is a matter of walking them in lockstep. Joins can mix immutable
+// (&Storage) and mutable (&mut Storage) access, which is checked by the borrow checker.
+//
+// Brian Ho
+// brian@brkho.com
+
+use ecs::entity::Entity;
+use std::any::Any;
+use std::slice;
+
+// The components of a single type indexed by entity index. Each component is stored with the
+// entity that owns it so that stale entities can be detected.
+pub struct Storage<T> {
+    components: Vec<Option<(Entity, T)>>,
+    count: usize,
+}
+
+impl<T> Storage<T> {
+    // Default constructor for an empty Storage.
+    pub fn new() -> Storage<T> {
+        Storage { components: Vec::new(), count: 0 }
+    }
+
+    // Adds a component to an entity and returns the component it replaced if there was one.
+    pub fn insert(&mut self, entity: Entity, component: T) -> Option<T> {
+        let index = entity.get_index();
+        while self.components.len() <= index { self.components.push(None); }
+        let previous = self.remove_index(index).and_then(|(e, c)|
+                if e == entity { Some(c) } else { None });
+        self.components[index] = Some((entity, component));
+        self.count += 1;
+        previous
+    }
+
+    // Removes the component of an entity and returns it if there was one.
+    pub fn remove(&mut self, entity: Entity) -> Option<T> {
+        if !self.contains(entity) { return None; }
+        self.remove_index(entity.get_index()).map(|(_, c)| c)
+    }
+
+    // Helper method that removes whichever component is stored at an entity index.
+    fn remove_index(&mut self, index: usize) -> Option<(Entity, T)> {
+        let removed = match self.components.get_mut(index) {
+            Some(slot) => slot.take(),
+            None => None,
+        };
+        if removed.is_some() { self.count -= 1; }
+        removed
+    }
+
+    // Whether an entity has a component in the Storage.
+    pub fn contains(&self, entity: Entity) -> bool { self.get(entity).is_some() }
+
+    // Gets an immutable reference to the component of an entity.
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        match self.components.get(entity.get_index()) {
+            Some(&Some((e, ref c))) if e == entity => Some(c),
+            _ => None,
+        }
+    }
+
+    // Gets a mutable reference to the component of an entity.
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        match self.components.get_mut(entity.get_index()) {
+            Some(&mut Some((e, ref mut c))) if e == entity => Some(c),
+            _ => None,
+        }
+    }
+
+    // Gets the number of components in the Storage.
+    pub fn len(&self) -> usize { self.count }
+
+    // Whether the Storage has no components.
+    pub fn is_empty(&self) -> bool { self.count == 0 }
+
+    // Iterates over the (entity, component) pairs in order of entity index.
+    pub fn iter(&self) -> JoinIter<Slots<T>> { self.join() }
+
+    // Iterates over the (entity, component) pairs in order of entity index with mutable access to
+    // the components.
+    pub fn iter_mut(&mut self) -> JoinIter<SlotsMut<T>> { self.join() }
+}
+
+// A Storage of any component type. This lets the World remove the components of a destroyed
+// entity without knowing their types.
+pub trait AnyStorage {
+    // Removes the component of an entity if there is one.
+    fn remove_entity(&mut self, entity: Entity);
+
+    // Gets the Storage as Any so that it can be downcast to its component type.
+    fn as_any(&self) -> &Any;
+
+    // Gets the Storage as mutable Any so that it can be downcast to its component type.
+    fn as_any_mut(&mut self) -> &mut Any;
+}
+
+impl<T: 'static> AnyStorage for Storage<T> {
+    fn remove_entity(&mut self, entity: Entity) { self.remove(entity); }
+    fn as_any(&self) -> &Any { self }
+    fn as_any_mut(&mut self) -> &mut Any { self }
+}
+
+// An iterator over the slots of a Join by entity index. Each slot is the (entity, item) pair at
+// that index, or None if the index has nothing to join.
+pub trait SlotIter {
+    type Item;
+
+    // Gets the next slot or None if there are no more slots.
+    fn next_slot(&mut self) -> Option<Option<(Entity, Self::Item)>>;
+}
+
+// Something that can be iterated over by entity index, such as a borrowed Storage or a tuple of
+// Joins.
+pub trait Join: Sized {
+    type Item;
+    type Slots: SlotIter<Item = Self::Item>;
+
+    // Gets an iterator over every slot.
+    fn slots(self) -> Self::Slots;
+
+    // Iterates over the (entity, item) pairs of the filled slots.
+    fn join(self) -> JoinIter<Self::Slots> { JoinIter { slots: self.slots() } }
+}
+
+// An iterator over the filled slots of a Join.
+pub struct JoinIter<S> {
+    slots: S,
+}
+
+impl<S: SlotIter> Iterator for JoinIter<S> {
+    type Item = (Entity, S::Item);
+
+    fn next(&mut self) -> Option<(Entity, S::Item)> {
+        loop {
+            match self.slots.next_slot() {
+                None => { return None; },
+                Some(Some(pair)) => { return Some(pair); },
+                Some(None) => (),
+            }
+        }
+    }
+}
+
+// The slots of an immutably borrowed Storage.
+pub struct Slots<'a, T: 'a> {
+    iter: slice::Iter<'a, Option<(Entity, T)>>,
+}
+
+impl<'a, T> SlotIter for Slots<'a, T> {
+    type Item = &'a T;
+
+    fn next_slot(&mut self) -> Option<Option<(Entity, &'a T)>> {
+        self.iter.next().map(|slot| slot.as_ref().map(|&(e, ref c)| (e, c)))
+    }
+}
+
+impl<'a, T> Join for &'a Storage<T> {
+    type Item = &'a T;
+    type Slots = Slots<'a, T>;
+    fn slots(self) -> Slots<'a, T> { Slots { iter: self.components.iter() } }
+}
+
+// The slots of a mutably borrowed Storage.
+pub struct SlotsMut<'a, T: 'a> {
+    iter: slice::IterMut<'a, Option<(Entity, T)>>,
+}
+
+impl<'a, T> SlotIter for SlotsMut<'a, T> {
+    type Item = &'a mut T;
+
+    fn next_slot(&mut self) -> Option<Option<(Entity, &'a mut T)>> {
+        self.iter.next().map(|slot| slot.as_mut().map(|&mut (e, ref mut c)| (e, c)))
+    }
+}
+
+impl<'a, T> Join for &'a mut Storage<T> {
+    type Item = &'a mut T;
+    type Slots = SlotsMut<'a, T>;
+    fn slots(self) -> SlotsMut<'a, T> { SlotsMut { iter: self.components.iter_mut() } }
+}
+
+// Implements Join for a tuple of Joins by walking their slots in lockstep. A slot is only filled
+// if every member has the same entity at that index. Iteration stops at the end of the shortest
+// member since the rest of the indices cannot be filled in it.
+macro_rules! impl_join_tuple { ($slots:ident, $($name:ident),+) => (
+    // The slots of a tuple of Joins.
+    pub struct $slots<$($name),+> {
+        slots: ($($name,)+),
+    }
+
+    #[allow(non_snake_case)]
+    impl<$($name: Join),+> Join for ($($name,)+) {
+        type Item = ($($name::Item,)+);
+        type Slots = $slots<$($name::Slots),+>;
+
+        fn slots(self) -> Self::Slots {
+            let ($($name,)+) = self;
+            $slots { slots: ($($name.slots(),)+) }
+        }
+    }
+
+    #[allow(non_snake_case)]
+    impl<$($name: SlotIter),+> SlotIter for $slots<$($name),+> {
+        type Item = ($($name::Item,)+);
+
+        fn next_slot(&mut self) -> Option<Option<(Entity, Self::Item)>> {
+            let ($(ref mut $name,)+) = self.slots;
+            $(let $name = match $name.next_slot() {
+                Some(slot) => slot,
+                None => { return None; },
+            };)+
+            let mut entity = None;
+            $(let $name = match $name {
+                Some((e, item)) if entity.map_or(true, |other| other == e) => {
+                    entity = Some(e);
+                    item
+                },
+                _ => { return Some(None); },
+            };)+
+            Some(Some((entity.unwrap(), ($($name,)+))))
+        }
+    }
+)}
+
+impl_join_tuple!(Slots2, A, B);
+impl_join_tuple!(Slots3, A, B, C);
+impl_join_tuple!(Slots4, A, B, C, D);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ecs::entity::EntityAllocator;
+
+    // Creates a number of entities.
+    fn create_entities(count: usize) -> (EntityAllocator, Vec<Entity>) {
+        let mut allocator = EntityAllocator::new();
+        let entities = (0..count).map(|_| allocator.create()).collect();
+        (allocator, entities)
+    }
+
+    #[test]
+    fn insert_replaces_component() {
+        let (_, entities) = create_entities(3);
+        let mut storage = Storage::new();
+        assert_eq!(storage.insert(entities[2], 'a'), None);
+        assert_eq!(storage.insert(entities[0], 'b'), None);
+        assert_eq!(storage.insert(entities[2], 'c'), Some('a'));
+        assert_eq!(storage.len(), 2);
+        assert_eq!(storage.get(entities[2]), Some(&'c'));
+        assert_eq!(storage.get(entities[1]), None);
+        assert_eq!(storage.remove(entities[2]), Some('c'));
+        assert_eq!(storage.remove(entities[2]), None);
+        assert_eq!(storage.len(), 1);
+    }
+
+    #[test]
+    fn stale_entity() {
+        let (mut allocator, entities) = create_entities(2);
+        let mut storage = Storage::new();
+        storage.insert(entities[0], 1);
+        allocator.destroy(entities[0]).unwrap();
+        let reused = allocator.create();
+        assert_eq!(reused.get_index(), entities[0].get_index());
+
+        // The old generation's component is not visible to the new entity and vice versa.
+        assert_eq!(storage.get(reused), None);
+        assert!(!storage.contains(reused));
+        assert_eq!(storage.insert(reused, 2), None);
+        assert_eq!(storage.len(), 1);
+        assert_eq!(storage.get(entities[0]), None);
+        assert_eq!(storage.get_mut(entities[0]), None);
+        assert_eq!(storage.remove(entities[0]), None);
+        assert_eq!(storage.get(reused), Some(&2));
+    }
+
+    #[test]
+    fn join_pairs() {
+        // The storages have different lengths and each skips some entities.
+        let (_, entities) = create_entities(6);
+        let mut numbers = Storage::new();
+        let mut letters = Storage::new();
+        for &i in [0, 1, 3, 4, 5].iter() { numbers.insert(entities[i], i); }
+        for &i in [1, 2, 3].iter() { letters.insert(entities[i], (b'a' + i as u8) as char); }
+        let joined: Vec<_> = (&numbers, &letters).join().collect();
+        assert_eq!(joined, vec![(entities[1], (&1, &'b')), (entities[3], (&3, &'d'))]);
+        let joined: Vec<_> = (&letters, &numbers).join().map(|(e, _)| e).collect();
+        assert_eq!(joined, vec![entities[1], entities[3]]);
+
+        for (_, (number, letter)) in (&mut numbers, &letters).join() {
+            *number += *letter as usize;
+        }
+        assert_eq!(numbers.get(entities[1]), Some(&(1 + 'b' as usize)));
+        assert_eq!(numbers.get(entities[0]), Some(&0));
+        assert_eq!(numbers.iter().count(), 5);
+    }
+
+    #[test]
+    fn join_three_and_four() {
+        let (_, entities) = create_entities(8);
+        let mut a = Storage::new();
+        let mut b = Storage::new();
+        let mut c = Storage::new();
+        let mut d = Storage::new();
+        for i in 0..8 { a.insert(entities[i], i); }
+        for i in (0..8).filter(|i| i % 2 == 0) { b.insert(entities[i], i * 10); }
+        for i in (0..7).filter(|i| i % 3 != 1) { c.insert(entities[i], i * 100); }
+        for i in 0..5 { d.insert(entities[i], i * 1000); }
+
+        let joined: Vec<_> = (&a, &b, &c).join().map(|(e, (x, y, z))| (e, x + y + z)).collect();
+        assert_eq!(joined, vec![(entities[0], 0), (entities[2], 222), (entities[6], 666)]);
+        let joined: Vec<_> = (&a, &b, &mut c, &d).join().map(|(e, _)| e).collect();
+        assert_eq!(joined, vec![entities[0], entities[2]]);
+        assert_eq!((&d, &c, &b, &a).join().count(), 2);
+        assert_eq!((&a, &b, &c, &Storage::<u8>::new()).join().count(), 0);
+    }
+
+    #[test]
+    fn join_skips_stale_entities() {
+        let (mut allocator, entities) = create_entities(2);
+        let mut positions = Storage::new();
+        let mut names = Storage::new();
+        positions.insert(entities[0], 1.0);
+        names.insert(entities[0], "old");
+        positions.insert(entities[1], 2.0);
+        names.insert(entities[1], "kept");
+
+        // Only one storage has a component for the entity that reused index 0.
+        allocator.destroy(entities[0]).unwrap();
+        let reused = allocator.create();
+        positions.remove(entities[0]);
+        positions.insert(reused, 3.0);
+        let joined: Vec<_> = (&positions, &names).join().collect();
+        assert_eq!(joined, vec![(entities[1], (&2.0, &"kept"))]);
+    }
+}
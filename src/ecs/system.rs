@@ -0,0 +1,62 @@
+// Defines the System trait for game logic that runs over the World each frame and the Scheduler
+// that runs a list of named systems in order.
+//
+// Brian Ho
+// brian@brkho.com
+
+use ecs::world::World;
+
+// Game logic that is run over the World once per frame.
+pub trait System {
+    // Runs the system given the elapsed time in seconds since the last frame.
+    fn run(&mut self, world: &mut World, dt: f32) -> Result<(), String>;
+}
+
+impl<F> System for F where F: FnMut(&mut World, f32) -> Result<(), String> {
+    fn run(&mut self, world: &mut World, dt: f32) -> Result<(), String> { self(world, dt) }
+}
+
+// Runs systems in the order they were added.
+pub struct Scheduler {
+    systems: Vec<(String, Box<System>)>,
+}
+
+impl Scheduler {
+    // Default constructor for a Scheduler without any systems.
+    pub fn new() -> Scheduler {
+        Scheduler { systems: Vec::new() }
+    }
+
+    // Adds a system to the end of the schedule. Returns Err if a system with the same name has
+    // already been added.
+    pub fn add(&mut self, name: &str, system: Box<System>) -> Result<(), String> {
+        if self.systems.iter().any(|&(ref n, _)| n == name) {
+            return Err(format!("System {} has already been added.", name));
+        }
+        self.systems.push((name.to_string(), system));
+        Ok(())
+    }
+
+    // Removes a system from the schedule and returns it. Returns Err if there is no system with
+    // the name.
+    pub fn remove(&mut self, name: &str) -> Result<Box<System>, String> {
+        match self.systems.iter().position(|&(ref n, _)| n == name) {
+            Some(i) => Ok(self.systems.remove(i).1),
+            None => Err(format!("System {} does not exist.", name)),
+        }
+    }
+
+    // Gets the names of the systems in the order that they are run.
+    pub fn get_names(&self) -> Vec<&str> {
+        self.systems.iter().map(|&(ref n, _)| n.as_str()).collect()
+    }
+
+    // Runs every system in order. Stops at the first system that returns Err and returns its error
+    // prefixed with the system's name.
+    pub fn run(&mut self, world: &mut World, dt: f32) -> Result<(), String> {
+        for &mut (ref name, ref mut system) in self.systems.iter_mut() {
+            try!(system.run(world, dt).map_err(|e| format!("System {} failed: {}", name, e)));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+// Defines the World which owns every entity, the Storage of every component type, and resources
+// (singletons such as the frame time that are not attached to an entity). Storages and resources
+// are borrowed at runtime through read() and write() so that a system can borrow several of them
+// at once. Conflicting borrows return Err instead of panicking.
+//
+// Brian Ho
+// brian@brkho.com
+
+use ecs::entity::{Entity, EntityAllocator};
+use ecs::storage::{AnyStorage, Storage};
+use std::any::{self, Any, TypeId};
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::HashMap;
+
+// A container for every entity, component, and resource in the game.
+pub struct World {
+    entities: EntityAllocator,
+    storages: HashMap<TypeId, RefCell<Box<AnyStorage>>>,
+    resources: HashMap<TypeId, RefCell<Box<Any>>>,
+}
+
+impl World {
+    // Default constructor for an empty World.
+    pub fn new() -> World {
+        World { entities: EntityAllocator::new(), storages: HashMap::new(),
+                resources: HashMap::new() }
+    }
+
+    // Creates a new entity without any components.
+    pub fn create_entity(&mut self) -> Entity { self.entities.create() }
+
+    // Destroys an entity and removes all of its components. Returns Err if the entity is not
+    // alive.
+    pub fn destroy_entity(&mut self, entity: Entity) -> Result<(), String> {
+        try!(self.entities.destroy(entity));
+        for storage in self.storages.values_mut() {
+            storage.get_mut().remove_entity(entity);
+        }
+        Ok(())
+    }
+
+    // Whether an entity has been created and not yet destroyed.
+    pub fn is_alive(&self, entity: Entity) -> bool { self.entities.is_alive(entity) }
+
+    // Gets every living entity.
+    pub fn get_entities(&self) -> Vec<Entity> { self.entities.get_entities() }
+
+    // Registers a component type by creating an empty Storage for it. This does nothing if the
+    // type is already registered. Component types are also registered by add_component().
+    pub fn register<T: 'static>(&mut self) {
+        let storage: Box<AnyStorage> = Box::new(Storage::<T>::new());
+        self.storages.entry(TypeId::of::<T>()).or_insert(RefCell::new(storage));
+    }
+
+    // Adds a component to an entity and returns the component it replaced if there was one.
+    // Returns Err if the entity is not alive.
+    pub fn add_component<T: 'static>(&mut self, entity: Entity, component: T) ->
+            Result<Option<T>, String> {
+        if !self.is_alive(entity) { return Err(format!("Entity {:?} is not alive.", entity)); }
+        self.register::<T>();
+        let storage = self.storages.get_mut(&TypeId::of::<T>()).unwrap().get_mut();
+        Ok(storage.as_any_mut().downcast_mut::<Storage<T>>().unwrap().insert(entity, component))
+    }
+
+    // Removes a component from an entity and returns it if there was one.
+    pub fn remove_component<T: 'static>(&mut self, entity: Entity) -> Option<T> {
+        match self.storages.get_mut(&TypeId::of::<T>()) {
+            Some(storage) => storage.get_mut().as_any_mut().downcast_mut::<Storage<T>>()
+                    .unwrap().remove(entity),
+            None => None,
+        }
+    }
+
+    // Immutably borrows the Storage of a component type. Returns Err if the type is not
+    // registered or if the Storage is mutably borrowed.
+    pub fn read<T: 'static>(&self) -> Result<Ref<Storage<T>>, String> {
+        let storage = try!(self.get_storage::<T>());
+        let storage = try!(storage.try_borrow().map_err(|_|
+                format!("Storage of {} is mutably borrowed.", World::get_type_name::<T>())));
+        Ok(Ref::map(storage, |s| s.as_any().downcast_ref::<Storage<T>>().unwrap()))
+    }
+
+    // Mutably borrows the Storage of a component type. Returns Err if the type is not registered
+    // or if the Storage is already borrowed.
+    pub fn write<T: 'static>(&self) -> Result<RefMut<Storage<T>>, String> {
+        let storage = try!(self.get_storage::<T>());
+        let storage = try!(storage.try_borrow_mut().map_err(|_|
+                format!("Storage of {} is already borrowed.", World::get_type_name::<T>())));
+        Ok(RefMut::map(storage, |s| s.as_any_mut().downcast_mut::<Storage<T>>().unwrap()))
+    }
+
+    // Helper method that gets the RefCell of a component type's Storage.
+    fn get_storage<T: 'static>(&self) -> Result<&RefCell<Box<AnyStorage>>, String> {
+        self.storages.get(&TypeId::of::<T>()).ok_or(
+                format!("Component {} is not registered.", World::get_type_name::<T>()))
+    }
+
+    // Adds a resource and returns the resource of the same type that it replaced if there was
+    // one.
+    pub fn insert_resource<T: 'static>(&mut self, resource: T) -> Option<T> {
+        let previous = self.resources.insert(TypeId::of::<T>(), RefCell::new(Box::new(resource)));
+        previous.map(|r| *r.into_inner().downcast::<T>().unwrap())
+    }
+
+    // Removes a resource and returns it if there was one.
+    pub fn remove_resource<T: 'static>(&mut self) -> Option<T> {
+        self.resources.remove(&TypeId::of::<T>()).map(|r|
+                *r.into_inner().downcast::<T>().unwrap())
+    }
+
+    // Immutably borrows a resource. Returns Err if there is no resource of the type or if it is
+    // mutably borrowed.
+    pub fn read_resource<T: 'static>(&self) -> Result<Ref<T>, String> {
+        let resource = try!(self.get_resource::<T>());
+        let resource = try!(resource.try_borrow().map_err(|_|
+                format!("Resource {} is mutably borrowed.", World::get_type_name::<T>())));
+        Ok(Ref::map(resource, |r| r.downcast_ref::<T>().unwrap()))
+    }
+
+    // Mutably borrows a resource. Returns Err if there is no resource of the type or if it is
+    // already borrowed.
+    pub fn write_resource<T: 'static>(&self) -> Result<RefMut<T>, String> {
+        let resource = try!(self.get_resource::<T>());
+        let resource = try!(resource.try_borrow_mut().map_err(|_|
+                format!("Resource {} is already borrowed.", World::get_type_name::<T>())));
+        Ok(RefMut::map(resource, |r| r.downcast_mut::<T>().unwrap()))
+    }
+
+    // Helper method that gets the RefCell of a resource.
+    fn get_resource<T: 'static>(&self) -> Result<&RefCell<Box<Any>>, String> {
+        self.resources.get(&TypeId::of::<T>()).ok_or(
+                format!("Resource {} does not exist.", World::get_type_name::<T>()))
+    }
+
+    // Helper function that gets the name of a type (including its module path) for error messages.
+    fn get_type_name<T: 'static>() -> &'static str { any::type_name::<T>() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::World;
+
+    struct Health(u32);
+
+    #[test]
+    fn destroy_entity_removes_components() {
+        let mut world = World::new();
+        let first = world.create_entity();
+        let second = world.create_entity();
+        world.add_component(first, Health(1)).unwrap();
+        world.add_component(first, 'a').unwrap();
+        world.add_component(second, Health(2)).unwrap();
+        world.add_component(second, 0.5f32).unwrap();
+        world.register::<u8>();
+
+        world.destroy_entity(first).unwrap();
+        assert!(!world.is_alive(first));
+        assert!(world.destroy_entity(first).is_err());
+        assert!(world.add_component(first, 'b').is_err());
+        assert!(world.read::<Health>().unwrap().get(first).is_none());
+        assert_eq!(world.read::<Health>().unwrap().len(), 1);
+        assert!(world.read::<char>().unwrap().is_empty());
+        assert_eq!(world.read::<f32>().unwrap().get(second), Some(&0.5));
+
+        // A new entity that reuses the index does not see the old components.
+        let third = world.create_entity();
+        assert_eq!(third.get_index(), first.get_index());
+        assert!(world.read::<char>().unwrap().get(third).is_none());
+        assert_eq!(world.get_entities(), vec![third, second]);
+    }
+
+    #[test]
+    fn errors_name_types() {
+        let mut world = World::new();
+        let error = world.read::<Health>().err().unwrap();
+        assert_eq!(error, "Component mmo::ecs::world::tests::Health is not registered.");
+        world.insert_resource(Health(10));
+        let health = world.write_resource::<Health>().unwrap();
+        assert_eq!(health.0, 10);
+        let error = world.read_resource::<Health>().err().unwrap();
+        assert_eq!(error, "Resource mmo::ecs::world::tests::Health is mutably borrowed.");
+        assert!(world.read_resource::<u32>().err().unwrap().contains(" u32 "));
+    }
+}
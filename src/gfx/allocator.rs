@@ -0,0 +1,223 @@
+// Defines the RangeAllocator used to manage the space of the engine's VBOs and EBOs. Each buffer
+// has a fixed capacity and the allocator hands out ranges of it using a first fit free list. Freed
+// ranges are merged with their neighbors so that the holes left behind by unmapped ModelInfos can
+// be reused. When the free space is too fragmented to fit an allocation, the allocator can compact
+// every live range to the front of the buffer and report how each range moved. This only does the
+// bookkeeping and does not touch any GL state, so the GameWindow is responsible for moving the
+// buffer contents.
+//
+// Brian Ho
+// brian@brkho.com
+
+use std::collections::BTreeMap;
+
+// A live range that was moved by compaction. The contents of size units starting at from must be
+// copied to to. Moves are reported in increasing order and never move a range towards the back of
+// the buffer, so they can be applied one after another.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RangeMove {
+    pub from: usize,
+    pub to: usize,
+    pub size: usize,
+}
+
+// Allocates ranges of a buffer with a fixed capacity. Units are whatever the caller chooses (such
+// as vertices or elements).
+pub struct RangeAllocator {
+    capacity: usize,
+    free: Vec<(usize, usize)>,              // (start, size) sorted by start
+    allocated: BTreeMap<usize, usize>,      // start -> size
+}
+
+impl RangeAllocator {
+    // Default constructor for an empty allocator with a capacity.
+    pub fn new(capacity: usize) -> RangeAllocator {
+        let free = if capacity > 0 { vec![(0, capacity)] } else { Vec::new() };
+        RangeAllocator { capacity: capacity, free: free, allocated: BTreeMap::new() }
+    }
+
+    // Gets the total capacity of the buffer.
+    pub fn get_capacity(&self) -> usize { self.capacity }
+
+    // Gets the amount of space that is currently allocated.
+    pub fn get_used(&self) -> usize { self.capacity - self.get_free() }
+
+    // Gets the total amount of free space, which may be split into several holes.
+    pub fn get_free(&self) -> usize { self.free.iter().map(|&(_, size)| size).sum() }
+
+    // Gets the size of the largest hole, which is the largest allocation that can currently
+    // succeed without compacting.
+    pub fn get_largest_free(&self) -> usize {
+        self.free.iter().map(|&(_, size)| size).max().unwrap_or(0)
+    }
+
+    // Gets the number of live allocations.
+    pub fn get_allocation_count(&self) -> usize { self.allocated.len() }
+
+    // Allocates a range of a given size in the first hole it fits in and returns its start.
+    // Returns None if no single hole is large enough. Empty ranges always succeed and do not need
+    // to be freed.
+    pub fn allocate(&mut self, size: usize) -> Option<usize> {
+        if size == 0 { return Some(0); }
+        let index = match self.free.iter().position(|&(_, free_size)| free_size >= size) {
+            Some(i) => i,
+            None => return None,
+        };
+        let (start, free_size) = self.free[index];
+        if free_size == size {
+            self.free.remove(index);
+        } else {
+            self.free[index] = (start + size, free_size - size);
+        }
+        self.allocated.insert(start, size);
+        Some(start)
+    }
+
+    // Frees a range given the start returned by allocate(). The hole is merged with any adjacent
+    // holes. Returns Err if there is no live range starting there.
+    pub fn free(&mut self, start: usize) -> Result<(), String> {
+        let size = try!(self.allocated.remove(&start).ok_or(
+                format!("No allocated range starts at {}.", start)));
+        let index = match self.free.iter().position(|&(free_start, _)| free_start > start) {
+            Some(i) => i,
+            None => self.free.len(),
+        };
+        self.free.insert(index, (start, size));
+
+        // Merge with the following hole and then the preceding hole.
+        if index + 1 < self.free.len() && start + size == self.free[index + 1].0 {
+            self.free[index].1 += self.free[index + 1].1;
+            self.free.remove(index + 1);
+        }
+        if index > 0 && self.free[index - 1].0 + self.free[index - 1].1 == start {
+            self.free[index - 1].1 += self.free[index].1;
+            self.free.remove(index);
+        }
+        Ok(())
+    }
+
+    // Frees every range.
+    pub fn clear(&mut self) {
+        *self = RangeAllocator::new(self.capacity);
+    }
+
+    // Moves every live range to the front of the buffer, preserving their order, so that all of
+    // the free space forms a single hole at the back. Returns the ranges that moved.
+    pub fn compact(&mut self) -> Vec<RangeMove> {
+        let mut moves = Vec::new();
+        let mut allocated = BTreeMap::new();
+        let mut cursor = 0;
+        for (&start, &size) in self.allocated.iter() {
+            if start != cursor {
+                moves.push(RangeMove { from: start, to: cursor, size: size });
+            }
+            allocated.insert(cursor, size);
+            cursor += size;
+        }
+        self.allocated = allocated;
+        self.free.clear();
+        if cursor < self.capacity {
+            self.free.push((cursor, self.capacity - cursor));
+        }
+        moves
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_fit() {
+        let mut allocator = RangeAllocator::new(100);
+        assert_eq!(allocator.allocate(10), Some(0));
+        assert_eq!(allocator.allocate(20), Some(10));
+        assert_eq!(allocator.allocate(30), Some(30));
+        allocator.free(0).unwrap();
+        // The hole at the front is too small, so the allocation goes after the last range.
+        assert_eq!(allocator.allocate(15), Some(60));
+        // This fits in the hole at the front even though there is room at the back.
+        assert_eq!(allocator.allocate(5), Some(0));
+        assert_eq!(allocator.allocate(5), Some(5));
+        assert_eq!(allocator.get_used(), 75);
+        assert_eq!(allocator.get_allocation_count(), 5);
+        assert_eq!(allocator.allocate(0), Some(0));
+        assert_eq!(allocator.get_allocation_count(), 5);
+    }
+
+    #[test]
+    fn free_coalesces() {
+        let mut allocator = RangeAllocator::new(40);
+        for i in 0..4 { assert_eq!(allocator.allocate(10), Some(i * 10)); }
+
+        // Merging with the following hole.
+        allocator.free(20).unwrap();
+        allocator.free(10).unwrap();
+        assert_eq!(allocator.get_largest_free(), 20);
+
+        // Merging with the preceding hole.
+        allocator.free(30).unwrap();
+        assert_eq!(allocator.get_largest_free(), 30);
+        assert_eq!(allocator.allocate(10), Some(10));
+        assert_eq!(allocator.allocate(20), Some(20));
+
+        // Merging with both holes leaves a single hole covering the buffer.
+        allocator.free(0).unwrap();
+        allocator.free(20).unwrap();
+        assert_eq!(allocator.get_largest_free(), 20);
+        allocator.free(10).unwrap();
+        assert_eq!(allocator.get_largest_free(), 40);
+        assert_eq!(allocator.get_free(), 40);
+        assert_eq!(allocator.get_allocation_count(), 0);
+        assert_eq!(allocator.allocate(40), Some(0));
+    }
+
+    #[test]
+    fn allocation_failure() {
+        let mut allocator = RangeAllocator::new(30);
+        assert_eq!(allocator.allocate(31), None);
+        for i in 0..3 { assert_eq!(allocator.allocate(10), Some(i * 10)); }
+        assert_eq!(allocator.allocate(1), None);
+        allocator.free(0).unwrap();
+        allocator.free(20).unwrap();
+        // There are 20 units free but no hole is larger than 10.
+        assert_eq!(allocator.get_free(), 20);
+        assert_eq!(allocator.allocate(11), None);
+        assert!(allocator.free(0).is_err());
+        assert!(allocator.free(5).is_err());
+        assert_eq!(RangeAllocator::new(0).allocate(1), None);
+    }
+
+    #[test]
+    fn compact_moves() {
+        let mut allocator = RangeAllocator::new(33);
+        let mut buffer = vec![0; 33];
+        let mut ranges = Vec::new();
+        for (i, &size) in [5, 10, 3, 7, 8].iter().enumerate() {
+            let start = allocator.allocate(size).unwrap();
+            for unit in &mut buffer[start..(start + size)] { *unit = i + 1; }
+            ranges.push((i + 1, size));
+        }
+        for &start in [0, 15, 25].iter() { allocator.free(start).unwrap(); }
+        ranges.retain(|&(i, _)| i % 2 == 0);
+        // There are 16 units free but no hole is larger than 8.
+        assert_eq!(allocator.allocate(10), None);
+
+        let moves = allocator.compact();
+        for pair in moves.windows(2) { assert!(pair[0].from < pair[1].from); }
+        for m in moves.iter() {
+            assert!(m.to < m.from);
+            for unit in 0..m.size { buffer[m.to + unit] = buffer[m.from + unit]; }
+        }
+
+        // The live ranges are packed at the front in their original order.
+        let mut cursor = 0;
+        for &(i, size) in ranges.iter() {
+            assert!(buffer[cursor..(cursor + size)].iter().all(|&unit| unit == i));
+            cursor += size;
+        }
+        assert_eq!(allocator.get_largest_free(), 33 - cursor);
+        assert_eq!(allocator.allocate(33 - cursor), Some(cursor));
+        assert!(allocator.compact().is_empty());
+    }
+}
@@ -0,0 +1,582 @@
+// Defines the data structures used for skeletal animation. A Skeleton is a hierarchy of joints
+// where each joint stores its rest transform relative to its parent and the inverse bind matrix
+// that brings a vertex from model space into the joint's space. An AnimationClip stores keyframed
+// translation, rotation, and scale tracks for some of the joints which are sampled on the CPU into
+// a Pose. Clips can also have tracks for the weights of a ModelInfo's morph targets. An
+// AnimationSampler plays several clips at once and blends their poses and morph weights by weight.
+// The final Pose is turned into the skinning matrices of a ModelInstance which std.vert uses to
+// blend up to four joints per vertex.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use gfx::types::*;
+use std::rc::Rc;
+use util::rmod;
+
+// The maximum number of joints that can influence a single vertex.
+pub const MAX_JOINT_INFLUENCES: usize = 4;
+
+// A value that can be linearly interpolated between two keyframes.
+pub trait Interpolate: Copy {
+    // Interpolates from a to b where t is in the range [0, 1].
+    fn interpolate(a: Self, b: Self, t: GLfloat) -> Self;
+}
+
+impl Interpolate for GLfloat {
+    fn interpolate(a: GLfloat, b: GLfloat, t: GLfloat) -> GLfloat { a + (b - a) * t }
+}
+
+impl Interpolate for Vector3D {
+    fn interpolate(a: Vector3D, b: Vector3D, t: GLfloat) -> Vector3D { a + (b - a) * t }
+}
+
+// Rotations are normalized linearly interpolated along the shortest path. This is much cheaper
+// than a slerp and is indistinguishable for keyframes that are close together.
+impl Interpolate for Quaternion {
+    fn interpolate(a: Quaternion, b: Quaternion, t: GLfloat) -> Quaternion {
+        let b = if a.dot(b) < 0.0 { b * -1.0 } else { b };
+        a.nlerp(b, t)
+    }
+}
+
+// A list of keyframes sorted by time.
+#[derive(Clone)]
+pub struct Track<T: Interpolate> {
+    times: Vec<GLfloat>,
+    values: Vec<T>,
+}
+
+impl<T: Interpolate> Track<T> {
+    // Creates a Track from the times of the keyframes and their values. Returns Err if the number
+    // of times and values do not match or if the times are not increasing.
+    pub fn new(times: Vec<GLfloat>, values: Vec<T>) -> Result<Track<T>, String> {
+        if times.len() != values.len() {
+            return Err(format!("Track has {} times but {} values.", times.len(), values.len()));
+        }
+        if times.windows(2).any(|w| !(w[0] < w[1])) {
+            return Err("Track keyframe times must be increasing.".to_string());
+        }
+        Ok(Track { times: times, values: values })
+    }
+
+    // Creates a Track from a list of (time, value) keyframes.
+    pub fn from_keyframes(keyframes: &[(GLfloat, T)]) -> Result<Track<T>, String> {
+        Track::new(keyframes.iter().map(|k| k.0).collect(), keyframes.iter().map(|k| k.1).collect())
+    }
+
+    // Creates a Track without any keyframes.
+    pub fn empty() -> Track<T> { Track { times: Vec::new(), values: Vec::new() } }
+
+    // Gets the keyframe times.
+    pub fn get_times(&self) -> &[GLfloat] { &self.times }
+
+    // Gets the keyframe values.
+    pub fn get_values(&self) -> &[T] { &self.values }
+
+    // Whether the Track has no keyframes.
+    pub fn is_empty(&self) -> bool { self.times.is_empty() }
+
+    // Gets the time of the last keyframe or 0 if there are none.
+    pub fn get_duration(&self) -> GLfloat { self.times.last().cloned().unwrap_or(0.0) }
+
+    // Samples the Track at a time by interpolating the surrounding keyframes. Times outside of the
+    // keyframes are clamped to the first or last keyframe. Returns None if there are no keyframes.
+    pub fn sample(&self, time: GLfloat) -> Option<T> {
+        if self.times.is_empty() { return None; }
+        let next = self.times.iter().position(|&t| t > time).unwrap_or(self.times.len());
+        if next == 0 { return Some(self.values[0]); }
+        if next == self.times.len() { return Some(self.values[next - 1]); }
+        let (start, end) = (self.times[next - 1], self.times[next]);
+        let t = (time - start) / (end - start);
+        Some(T::interpolate(self.values[next - 1], self.values[next], t))
+    }
+}
+
+// The translation, rotation, and scale of a joint relative to its parent.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct JointTransform {
+    pub translation: Vector3D,
+    pub rotation: Quaternion,
+    pub scale: Vector3D,
+}
+
+impl JointTransform {
+    // The transform that leaves a joint at its parent's origin.
+    pub fn identity() -> JointTransform {
+        JointTransform { translation: Vector3D::new(0.0, 0.0, 0.0),
+                rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0), scale: Vector3D::new(1.0, 1.0, 1.0) }
+    }
+
+    // Gets the matrix that scales, then rotates, and then translates.
+    pub fn to_matrix(&self) -> cgmath::Matrix4<GLfloat> {
+        cgmath::Matrix4::from_translation(self.translation) *
+                cgmath::Matrix4::from(self.rotation) *
+                cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+impl Interpolate for JointTransform {
+    fn interpolate(a: JointTransform, b: JointTransform, t: GLfloat) -> JointTransform {
+        JointTransform { translation: Vector3D::interpolate(a.translation, b.translation, t),
+                rotation: Quaternion::interpolate(a.rotation, b.rotation, t),
+                scale: Vector3D::interpolate(a.scale, b.scale, t) }
+    }
+}
+
+// A single joint of a Skeleton. Parents always come before their children in the Skeleton.
+#[derive(Clone)]
+pub struct Joint {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub inverse_bind: cgmath::Matrix4<GLfloat>,
+    pub rest: JointTransform,
+}
+
+// A hierarchy of joints that the vertices of a ModelInfo are bound to.
+#[derive(Clone)]
+pub struct Skeleton {
+    joints: Vec<Joint>,
+}
+
+impl Skeleton {
+    // Creates a Skeleton from a list of joints. Returns Err if a joint's parent does not come
+    // before it in the list.
+    pub fn new(joints: Vec<Joint>) -> Result<Skeleton, String> {
+        for (i, joint) in joints.iter().enumerate() {
+            if let Some(parent) = joint.parent {
+                if parent >= i {
+                    return Err(format!("Joint {} must come after its parent {}.", i, parent));
+                }
+            }
+        }
+        Ok(Skeleton { joints: joints })
+    }
+
+    // Creates a Skeleton from the skin section of a RMOD decoding.
+    pub fn from_rmod(skin: &rmod::DecodedSkin) -> Result<Skeleton, String> {
+        Skeleton::new(skin.joints.iter().map(|j| Joint { name: j.name.clone(), parent: j.parent,
+                inverse_bind: j.inverse_bind, rest: JointTransform { translation: j.translation,
+                rotation: j.rotation, scale: j.scale } }).collect())
+    }
+
+    // Gets the joints of the Skeleton.
+    pub fn get_joints(&self) -> &[Joint] { &self.joints }
+
+    // Gets the number of joints in the Skeleton.
+    pub fn get_joint_count(&self) -> usize { self.joints.len() }
+
+    // Finds the index of a joint by name.
+    pub fn find_joint(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|j| j.name == name)
+    }
+
+    // Gets the Pose where every joint is at its rest transform.
+    pub fn get_rest_pose(&self) -> Pose {
+        Pose { transforms: self.joints.iter().map(|j| j.rest).collect() }
+    }
+
+    // Computes the skinning matrix of each joint for a Pose. Each matrix transforms a vertex in
+    // model space at bind time to its posed position in model space. Returns Err if the Pose does
+    // not have a transform for every joint.
+    pub fn get_skinning_matrices(&self, pose: &Pose) ->
+            Result<Vec<cgmath::Matrix4<GLfloat>>, String> {
+        if pose.transforms.len() != self.joints.len() {
+            return Err(format!("Pose has {} transforms but the skeleton has {} joints.",
+                    pose.transforms.len(), self.joints.len()));
+        }
+        let mut globals: Vec<cgmath::Matrix4<GLfloat>> = Vec::with_capacity(self.joints.len());
+        for (joint, transform) in self.joints.iter().zip(pose.transforms.iter()) {
+            let local = transform.to_matrix();
+            let global = match joint.parent {
+                Some(parent) => globals[parent] * local,
+                None => local,
+            };
+            globals.push(global);
+        }
+        Ok(globals.iter().zip(self.joints.iter()).map(|(g, j)| g * j.inverse_bind).collect())
+    }
+}
+
+// The local transform of every joint of a Skeleton at a point in time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pose {
+    pub transforms: Vec<JointTransform>,
+}
+
+impl Pose {
+    // Blends this Pose towards another by a weight in the range [0, 1]. Both poses must be of the
+    // same Skeleton.
+    pub fn blend(&self, other: &Pose, weight: GLfloat) -> Pose {
+        Pose { transforms: self.transforms.iter().zip(other.transforms.iter()).map(|(a, b)|
+                JointTransform::interpolate(*a, *b, weight)).collect() }
+    }
+}
+
+// The keyframes that animate a single joint. Empty tracks leave that part of the joint's
+// transform untouched.
+#[derive(Clone)]
+pub struct JointTrack {
+    pub joint: usize,
+    pub translations: Track<Vector3D>,
+    pub rotations: Track<Quaternion>,
+    pub scales: Track<Vector3D>,
+}
+
+// The keyframes that animate the weight of a single morph target.
+#[derive(Clone)]
+pub struct MorphTrack {
+    pub target: usize,
+    pub weights: Track<GLfloat>,
+}
+
+// A named animation made up of tracks for some of the joints of a Skeleton and some of the morph
+// targets of a ModelInfo.
+#[derive(Clone)]
+pub struct AnimationClip {
+    pub name: String,
+    pub duration: GLfloat,
+    pub tracks: Vec<JointTrack>,
+    pub morph_tracks: Vec<MorphTrack>,
+}
+
+impl AnimationClip {
+    // Creates an AnimationClip whose duration is the time of its last keyframe.
+    pub fn new(name: &str, tracks: Vec<JointTrack>, morph_tracks: Vec<MorphTrack>) ->
+            AnimationClip {
+        let duration = tracks.iter().map(|t| t.translations.get_duration()
+                .max(t.rotations.get_duration()).max(t.scales.get_duration()))
+                .chain(morph_tracks.iter().map(|t| t.weights.get_duration()))
+                .fold(0.0, GLfloat::max);
+        AnimationClip { name: name.to_string(), duration: duration, tracks: tracks,
+                morph_tracks: morph_tracks }
+    }
+
+    // Creates an AnimationClip from an animation of a RMOD decoding. The stored duration is kept
+    // if it is longer than the keyframes so that clips can hold their last pose.
+    pub fn from_rmod(animation: &rmod::DecodedAnimation) -> Result<AnimationClip, String> {
+        let mut tracks = Vec::new();
+        for track in animation.tracks.iter() {
+            tracks.push(JointTrack { joint: track.joint,
+                    translations: try!(Track::from_keyframes(&track.translations)),
+                    rotations: try!(Track::from_keyframes(&track.rotations)),
+                    scales: try!(Track::from_keyframes(&track.scales)) });
+        }
+        let mut clip = AnimationClip::new(&animation.name, tracks, Vec::new());
+        clip.duration = clip.duration.max(animation.duration);
+        Ok(clip)
+    }
+
+    // Samples the clip at a time and returns the Pose of a Skeleton. Joints without tracks are
+    // left at their rest transforms.
+    pub fn sample(&self, skeleton: &Skeleton, time: GLfloat) -> Pose {
+        let mut pose = skeleton.get_rest_pose();
+        for track in self.tracks.iter() {
+            let transform = match pose.transforms.get_mut(track.joint) {
+                Some(t) => t,
+                None => continue,
+            };
+            if let Some(t) = track.translations.sample(time) { transform.translation = t; }
+            if let Some(r) = track.rotations.sample(time) { transform.rotation = r; }
+            if let Some(s) = track.scales.sample(time) { transform.scale = s; }
+        }
+        pose
+    }
+
+    // Samples the weight of every morph target of a ModelInfo with a number of targets at a time.
+    // Targets without tracks have a weight of 0.
+    pub fn sample_morph_weights(&self, target_count: usize, time: GLfloat) -> Vec<GLfloat> {
+        let mut weights = vec![0.0; target_count];
+        for track in self.morph_tracks.iter().filter(|t| t.target < target_count) {
+            if let Some(w) = track.weights.sample(time) { weights[track.target] = w; }
+        }
+        weights
+    }
+}
+
+// A clip being played by an AnimationSampler.
+pub struct AnimationLayer {
+    pub clip: Rc<AnimationClip>,
+    pub time: GLfloat,
+    pub speed: GLfloat,
+    pub weight: GLfloat,
+    pub looping: bool,
+}
+
+impl AnimationLayer {
+    // Default constructor for a looping layer that starts at the beginning of a clip.
+    pub fn new(clip: Rc<AnimationClip>, weight: GLfloat) -> AnimationLayer {
+        AnimationLayer { clip: clip, time: 0.0, speed: 1.0, weight: weight, looping: true }
+    }
+
+    // Advances the layer by a number of seconds. Looping layers wrap around to the start of the
+    // clip and other layers stop at its end.
+    pub fn update(&mut self, dt: GLfloat) {
+        let duration = self.clip.duration;
+        self.time += dt * self.speed;
+        if duration <= 0.0 {
+            self.time = 0.0;
+        } else if self.looping {
+            self.time = ((self.time % duration) + duration) % duration;
+        } else {
+            self.time = self.time.max(0.0).min(duration);
+        }
+    }
+
+    // Whether a non-looping layer has reached the end of its clip.
+    pub fn is_finished(&self) -> bool {
+        !self.looping && self.time >= self.clip.duration
+    }
+}
+
+// Plays any number of clips at once and blends them together by weight.
+pub struct AnimationSampler {
+    pub layers: Vec<AnimationLayer>,
+}
+
+impl AnimationSampler {
+    // Default constructor for a sampler that is not playing anything.
+    pub fn new() -> AnimationSampler {
+        AnimationSampler { layers: Vec::new() }
+    }
+
+    // Starts playing a clip with a weight and returns the index of its layer.
+    pub fn play(&mut self, clip: Rc<AnimationClip>, weight: GLfloat) -> usize {
+        self.layers.push(AnimationLayer::new(clip, weight));
+        self.layers.len() - 1
+    }
+
+    // Advances every layer by a number of seconds.
+    pub fn update(&mut self, dt: GLfloat) {
+        for layer in self.layers.iter_mut() { layer.update(dt); }
+    }
+
+    // Samples every layer with a positive weight and blends them into a single Pose where each
+    // layer contributes in proportion to its weight. Returns the rest pose if there is nothing to
+    // sample.
+    pub fn sample(&self, skeleton: &Skeleton) -> Pose {
+        let mut result: Option<Pose> = None;
+        let mut total = 0.0;
+        for layer in self.layers.iter().filter(|l| l.weight > 0.0) {
+            let pose = layer.clip.sample(skeleton, layer.time);
+            total += layer.weight;
+            result = Some(match result {
+                Some(blended) => blended.blend(&pose, layer.weight / total),
+                None => pose,
+            });
+        }
+        result.unwrap_or(skeleton.get_rest_pose())
+    }
+
+    // Samples the morph weights of every layer with a positive weight and blends them in the same
+    // way as sample(). Returns weights of 0 if there is nothing to sample.
+    pub fn sample_morph_weights(&self, target_count: usize) -> Vec<GLfloat> {
+        let mut result = vec![0.0; target_count];
+        let mut total = 0.0;
+        for layer in self.layers.iter().filter(|l| l.weight > 0.0) {
+            let weights = layer.clip.sample_morph_weights(target_count, layer.time);
+            total += layer.weight;
+            let t = layer.weight / total;
+            for (blended, &w) in result.iter_mut().zip(weights.iter()) {
+                *blended = GLfloat::interpolate(*blended, w, t);
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Asserts that two lists of floats are equal to within rounding error.
+    fn assert_close(a: &[GLfloat], b: &[GLfloat]) {
+        assert!(a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 1e-5),
+                "{:?} is not {:?}.", a, b);
+    }
+
+    // Gets the rotation about the z axis by an angle in radians.
+    fn rotation_z(angle: GLfloat) -> Quaternion {
+        Quaternion::new((angle / 2.0).cos(), 0.0, 0.0, (angle / 2.0).sin())
+    }
+
+    // Creates a JointTrack that only animates the translation of a joint.
+    fn translation_track(joint: usize, keyframes: &[(GLfloat, Vector3D)]) -> JointTrack {
+        JointTrack { joint: joint, translations: Track::from_keyframes(keyframes).unwrap(),
+                rotations: Track::empty(), scales: Track::empty() }
+    }
+
+    // Creates a Skeleton with a single joint at the origin.
+    fn create_single_joint() -> Skeleton {
+        Skeleton::new(vec![Joint { name: "root".to_string(), parent: None,
+                inverse_bind: cgmath::Matrix4::from_scale(1.0),
+                rest: JointTransform::identity() }]).unwrap()
+    }
+
+    #[test]
+    fn track_sample() {
+        let track = Track::from_keyframes(&[(1.0, 2.0), (3.0, 6.0), (4.0, 0.0)]).unwrap();
+        assert_eq!(track.get_duration(), 4.0);
+        assert_close(&[track.sample(1.0).unwrap(), track.sample(2.0).unwrap(),
+                track.sample(3.5).unwrap()], &[2.0, 4.0, 3.0]);
+
+        // Times outside of the keyframes are clamped.
+        assert_eq!(track.sample(-1.0), Some(2.0));
+        assert_eq!(track.sample(10.0), Some(0.0));
+        assert_eq!(Track::<GLfloat>::empty().sample(1.0), None);
+    }
+
+    #[test]
+    fn track_rejects_invalid_keyframes() {
+        assert!(Track::new(vec![0.0, 1.0], vec![0.0]).is_err());
+        assert!(Track::new(vec![0.0, 1.0, 1.0], vec![0.0, 1.0, 2.0]).is_err());
+        assert!(Track::new(vec![1.0, 0.0], vec![0.0, 1.0]).is_err());
+    }
+
+    #[test]
+    fn quaternion_interpolation() {
+        // Halfway between no rotation and a quarter turn is an eighth turn.
+        let (a, b) = (rotation_z(0.0), rotation_z(::std::f32::consts::PI / 2.0));
+        let expected = rotation_z(::std::f32::consts::PI / 4.0);
+        let halfway = Quaternion::interpolate(a, b, 0.5);
+        assert_close(&[halfway.s, halfway.v.x, halfway.v.y, halfway.v.z],
+                &[expected.s, expected.v.x, expected.v.y, expected.v.z]);
+
+        // The negated quaternion is the same rotation, so it takes the same shortest path.
+        let negated = Quaternion::interpolate(a, b * -1.0, 0.5);
+        assert_close(&[negated.s, negated.v.x, negated.v.y, negated.v.z],
+                &[expected.s, expected.v.x, expected.v.y, expected.v.z]);
+        let end = Quaternion::interpolate(a, b, 1.0);
+        assert_close(&[end.s, end.v.z], &[b.s, b.v.z]);
+    }
+
+    #[test]
+    fn clip_sample() {
+        // Joints without tracks keep their rest transforms.
+        let skeleton = Skeleton::new(vec![
+                Joint { name: "root".to_string(), parent: None,
+                        inverse_bind: cgmath::Matrix4::from_scale(1.0),
+                        rest: JointTransform::identity() },
+                Joint { name: "tip".to_string(), parent: Some(0),
+                        inverse_bind: cgmath::Matrix4::from_scale(1.0),
+                        rest: JointTransform { translation: Vector3D::new(0.0, 1.0, 0.0),
+                                ..JointTransform::identity() } }]).unwrap();
+        let clip = AnimationClip::new("move", vec![translation_track(0, &[
+                (0.0, Vector3D::new(0.0, 0.0, 0.0)), (2.0, Vector3D::new(4.0, 0.0, 0.0))])],
+                Vec::new());
+        assert_eq!(clip.duration, 2.0);
+        let pose = clip.sample(&skeleton, 0.5);
+        assert_eq!(pose.transforms[0].translation, Vector3D::new(1.0, 0.0, 0.0));
+        assert_eq!(pose.transforms[0].rotation, JointTransform::identity().rotation);
+        assert_eq!(pose.transforms[1], skeleton.get_joints()[1].rest);
+        assert_eq!(clip.sample(&skeleton, 5.0).transforms[0].translation,
+                Vector3D::new(4.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn layer_update() {
+        let clip = Rc::new(AnimationClip::new("move", vec![translation_track(0, &[
+                (0.0, Vector3D::new(0.0, 0.0, 0.0)), (1.0, Vector3D::new(1.0, 0.0, 0.0))])],
+                Vec::new()));
+
+        // Looping layers wrap around in both directions.
+        let mut layer = AnimationLayer::new(clip.clone(), 1.0);
+        layer.update(1.25);
+        assert_close(&[layer.time], &[0.25]);
+        layer.speed = -1.0;
+        layer.update(0.5);
+        assert_close(&[layer.time], &[0.75]);
+
+        // Other layers stop at the end of the clip.
+        let mut layer = AnimationLayer::new(clip, 1.0);
+        layer.looping = false;
+        layer.update(0.5);
+        assert!(!layer.is_finished());
+        layer.update(3.0);
+        assert_eq!(layer.time, 1.0);
+        assert!(layer.is_finished());
+    }
+
+    #[test]
+    fn sampler_blends_by_weight() {
+        let skeleton = create_single_joint();
+        let walk = Rc::new(AnimationClip::new("walk", vec![translation_track(0, &[
+                (0.0, Vector3D::new(0.0, 0.0, 0.0)), (1.0, Vector3D::new(2.0, 0.0, 0.0))])],
+                Vec::new()));
+        let wave = Rc::new(AnimationClip::new("wave", vec![translation_track(0, &[
+                (0.0, Vector3D::new(0.0, 4.0, 0.0))])], vec![MorphTrack { target: 0,
+                weights: Track::from_keyframes(&[(0.0, 1.0)]).unwrap() }]));
+        let mut sampler = AnimationSampler::new();
+        assert_eq!(sampler.sample(&skeleton), skeleton.get_rest_pose());
+
+        // Each layer contributes in proportion to its weight.
+        sampler.play(walk, 1.0);
+        let wave_layer = sampler.play(wave, 3.0);
+        let translation = sampler.sample(&skeleton).transforms[0].translation;
+        assert_close(&[translation.x, translation.y, translation.z], &[0.0, 3.0, 0.0]);
+        assert_close(&sampler.sample_morph_weights(2), &[0.75, 0.0]);
+        sampler.update(0.5);
+        let translation = sampler.sample(&skeleton).transforms[0].translation;
+        assert_close(&[translation.x, translation.y, translation.z], &[0.25, 3.0, 0.0]);
+
+        // Layers without weight are ignored.
+        sampler.layers[wave_layer].weight = 0.0;
+        let translation = sampler.sample(&skeleton).transforms[0].translation;
+        assert_close(&[translation.x, translation.y, translation.z], &[1.0, 0.0, 0.0]);
+        assert_close(&sampler.sample_morph_weights(2), &[0.0, 0.0]);
+    }
+
+    #[test]
+    fn skinning_matrices() {
+        // A root at (0, 1, 0) with a child 2 units above it that is turned a quarter turn.
+        let quarter = ::std::f32::consts::PI / 2.0;
+        let child_bind = cgmath::Matrix4::from_translation(Vector3D::new(0.0, 3.0, 0.0)) *
+                cgmath::Matrix4::from(rotation_z(quarter));
+        let child_inverse_bind = cgmath::Matrix4::from(rotation_z(-quarter)) *
+                cgmath::Matrix4::from_translation(Vector3D::new(0.0, -3.0, 0.0));
+        let skeleton = Skeleton::new(vec![
+                Joint { name: "root".to_string(), parent: None,
+                        inverse_bind: cgmath::Matrix4::from_translation(
+                                Vector3D::new(0.0, -1.0, 0.0)),
+                        rest: JointTransform { translation: Vector3D::new(0.0, 1.0, 0.0),
+                                ..JointTransform::identity() } },
+                Joint { name: "child".to_string(), parent: Some(0),
+                        inverse_bind: child_inverse_bind,
+                        rest: JointTransform { translation: Vector3D::new(0.0, 2.0, 0.0),
+                                rotation: rotation_z(quarter),
+                                scale: Vector3D::new(1.0, 1.0, 1.0) } }]).unwrap();
+        let point = child_bind * cgmath::Vector4::new(1.0, 0.0, 0.0, 1.0);
+        assert_close(&[point.x, point.y, point.z], &[0.0, 4.0, 0.0]);
+
+        // The rest pose leaves every vertex where it was bound.
+        let matrices = skeleton.get_skinning_matrices(&skeleton.get_rest_pose()).unwrap();
+        for matrix in matrices.iter() {
+            let moved = matrix * point;
+            assert_close(&[moved.x, moved.y, moved.z, moved.w], &[0.0, 4.0, 0.0, 1.0]);
+        }
+
+        // Turning the root carries the child with it, so the child's vertex swings around the
+        // root to the left.
+        let mut pose = skeleton.get_rest_pose();
+        pose.transforms[0].rotation = rotation_z(quarter);
+        let matrices = skeleton.get_skinning_matrices(&pose).unwrap();
+        let moved = matrices[1] * point;
+        assert_close(&[moved.x, moved.y, moved.z], &[-3.0, 1.0, 0.0]);
+
+        // The root's own vertices only turn about the root.
+        let moved = matrices[0] * cgmath::Vector4::new(0.0, 2.0, 0.0, 1.0);
+        assert_close(&[moved.x, moved.y, moved.z], &[-1.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn skeleton_rejects_invalid_joints() {
+        let joint = Joint { name: "joint".to_string(), parent: Some(1),
+                inverse_bind: cgmath::Matrix4::from_scale(1.0), rest: JointTransform::identity() };
+        assert!(Skeleton::new(vec![joint.clone(), joint]).is_err());
+        let mut pose = create_single_joint().get_rest_pose();
+        pose.transforms.push(JointTransform::identity());
+        assert!(create_single_joint().get_skinning_matrices(&pose).is_err());
+    }
+}
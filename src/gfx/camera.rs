@@ -1,7 +1,7 @@
 // Defines various cameras for use in rendering. The base Camera trait specifies methods for
-// getting the matrices needed for rendering. This also defines a PerspectiveCamera with associated
-// methods. Future support is planned for adding an OrthographicCamera, but it is not currently
-// implemented.
+// getting the matrices needed for rendering. This also defines a PerspectiveCamera and an
+// OrthographicCamera with associated methods. Both store a position, a target, and an up vector
+// which are used to build the view matrix when the GameWindow updates the camera.
 //
 // Brian Ho
 // brian@brkho.com
@@ -12,14 +12,38 @@ extern crate gl;
 pub use self::cgmath::EuclideanVector;
 
 use gfx::types::*;
-use self::cgmath::SquareMatrix;
+use self::cgmath::{Point, SquareMatrix};
 
-// Specifies methods for getting the view and projection matrices.
+// Specifies methods for getting the view and projection matrices. The GameWindow stores cameras as
+// Camera trait objects, so the positional attributes are also exposed through the trait.
 pub trait Camera {
     fn get_view_matrix(&self) -> cgmath::Matrix4<GLfloat>;
     fn get_projection_matrix(&self) -> cgmath::Matrix4<GLfloat>;
-    fn get_fwd(&self) -> Vector3D;
-    fn get_right(&self) -> Vector3D;
+    fn get_pos(&self) -> Vector3D;
+    fn set_pos(&mut self, pos: Vector3D);
+    fn get_target(&self) -> Vector3D;
+    fn set_target(&mut self, target: Vector3D);
+
+    // Recomputes the view matrix from the position, target, and up vector.
+    fn update_view(&mut self);
+
+    // Gets the forward vector from the view matrix.
+    fn get_fwd(&self) -> Vector3D {
+        let mat = self.get_view_matrix();
+        Vector3D::new(mat.x[2], mat.y[2], mat.z[2]).normalize()
+    }
+
+    // Gets the right vector from the view matrix.
+    fn get_right(&self) -> Vector3D {
+        let mat = self.get_view_matrix();
+        Vector3D::new(mat.x[0], mat.y[0], mat.z[0]).normalize()
+    }
+}
+
+// Helper function that builds a view matrix shared by all of the camera types.
+fn look_at(pos: Vector3D, target: Vector3D, up: Vector3D) -> cgmath::Matrix4<GLfloat> {
+    cgmath::Matrix4::look_at(
+            cgmath::Point3::from_vec(pos), cgmath::Point3::from_vec(target), up)
 }
 
 // A representation of a camera with a perspective projection. This implements the Camera trait, so
@@ -37,20 +61,16 @@ impl Camera for PerspectiveCamera {
     // Return the precomputed view matrix.
     fn get_view_matrix(&self) -> cgmath::Matrix4<GLfloat> { self.view }
 
-    // Gets the forward vector from the view matrix.
-    fn get_fwd(&self) -> Vector3D {
-        let mat = self.get_view_matrix();
-        Vector3D::new(mat.x[2], mat.y[2], mat.z[2]).normalize()
-    }
-
-    // Gets the right vector from the view matrix.
-    fn get_right(&self) -> Vector3D {
-        let mat = self.get_view_matrix();
-        Vector3D::new(mat.x[0], mat.y[0], mat.z[0]).normalize()
-    }
-
     // Return the precomputed projection matrix.
     fn get_projection_matrix(&self) -> cgmath::Matrix4<GLfloat> { self.proj }
+
+    fn get_pos(&self) -> Vector3D { self.pos }
+    fn set_pos(&mut self, pos: Vector3D) { self.pos = pos; }
+    fn get_target(&self) -> Vector3D { self.target }
+    fn set_target(&mut self, target: Vector3D) { self.target = target; }
+
+    // Recomputes the view matrix from the position, target, and up vector.
+    fn update_view(&mut self) { self.view = look_at(self.pos, self.target, self.up); }
 }
 
 // Implementation of PerspectiveCamera methods.
@@ -79,5 +99,105 @@ impl PerspectiveCamera {
     }
 }
 
-// TODO: Write the OrthographicCamera.
-// pub struct OrthographicCamera { }
+// A representation of a camera with an orthographic projection. The bounds of the view volume are
+// specified in world units relative to the camera, and the zoom factor divides these bounds so a
+// zoom of 2.0 shows half as much of the world in each direction.
+pub struct OrthographicCamera {
+    pub pos: Vector3D,
+    pub target: Vector3D,
+    pub view: cgmath::Matrix4<GLfloat>,
+    pub up: Vector3D,
+    left: f32,
+    right: f32,
+    bottom: f32,
+    top: f32,
+    near: f32,
+    far: f32,
+    zoom: f32,
+    proj: cgmath::Matrix4<GLfloat>,
+}
+
+// Implementation of the Camera methods for OrthographicCamera.
+impl Camera for OrthographicCamera {
+    // Return the precomputed view matrix.
+    fn get_view_matrix(&self) -> cgmath::Matrix4<GLfloat> { self.view }
+
+    // Return the precomputed projection matrix.
+    fn get_projection_matrix(&self) -> cgmath::Matrix4<GLfloat> { self.proj }
+
+    fn get_pos(&self) -> Vector3D { self.pos }
+    fn set_pos(&mut self, pos: Vector3D) { self.pos = pos; }
+    fn get_target(&self) -> Vector3D { self.target }
+    fn set_target(&mut self, target: Vector3D) { self.target = target; }
+
+    // Recomputes the view matrix from the position, target, and up vector.
+    fn update_view(&mut self) { self.view = look_at(self.pos, self.target, self.up); }
+}
+
+// Implementation of OrthographicCamera methods.
+impl OrthographicCamera {
+    // Constructor to initialize the fields and set up the Projection matrix given the bounds of
+    // the view volume in world units.
+    pub fn new(pos: Vector3D, target: Vector3D, left: f32, right: f32, bottom: f32, top: f32,
+            near: f32, far: f32) -> OrthographicCamera {
+        let up = Vector3D::new(0.0, 0.0, 1.0);
+        OrthographicCamera::new_with_up(pos, target, up, left, right, bottom, top, near, far)
+    }
+
+    // Alternative constructor that centers the view volume on the target given the visible width
+    // in world units and the aspect ratio of the window.
+    pub fn new_centered(pos: Vector3D, target: Vector3D, width: f32, aspect: f32, near: f32,
+            far: f32) -> OrthographicCamera {
+        let half_width = width / 2.0;
+        let half_height = half_width / aspect;
+        OrthographicCamera::new(
+                pos, target, -half_width, half_width, -half_height, half_height, near, far)
+    }
+
+    // Constructor to initialize the fields and set up the Projection matrix with a specified up
+    // vector. Like the PerspectiveCamera, the view matrix is set when the camera is attached.
+    pub fn new_with_up(pos: Vector3D, target: Vector3D, up: Vector3D, left: f32, right: f32,
+            bottom: f32, top: f32, near: f32, far: f32) -> OrthographicCamera {
+        let mut camera = OrthographicCamera { pos: pos, target: target, up: up,
+                view: cgmath::Matrix4::identity(), left: left, right: right, bottom: bottom,
+                top: top, near: near, far: far, zoom: 1.0, proj: cgmath::Matrix4::identity() };
+        camera.update_projection();
+        camera
+    }
+
+    // Sets the zoom factor of the camera and recomputes the projection matrix. Zooms less than or
+    // equal to zero are invalid and return an Err.
+    pub fn set_zoom(&mut self, zoom: f32) -> Result<(), String> {
+        if zoom <= 0.0 { return Err("Zoom must be positive.".to_string()); }
+        self.zoom = zoom;
+        self.update_projection();
+        Ok(())
+    }
+
+    // Gets the current zoom factor of the camera.
+    pub fn get_zoom(&self) -> f32 { self.zoom }
+
+    // Sets the bounds of the view volume in world units and recomputes the projection matrix.
+    pub fn set_bounds(&mut self, left: f32, right: f32, bottom: f32, top: f32, near: f32,
+            far: f32) {
+        self.left = left;
+        self.right = right;
+        self.bottom = bottom;
+        self.top = top;
+        self.near = near;
+        self.far = far;
+        self.update_projection();
+    }
+
+    // Gets the bounds of the view volume as (left, right, bottom, top, near, far) before zoom is
+    // applied.
+    pub fn get_bounds(&self) -> (f32, f32, f32, f32, f32, f32) {
+        (self.left, self.right, self.bottom, self.top, self.near, self.far)
+    }
+
+    // Helper method that rebuilds the projection matrix from the bounds and zoom factor.
+    fn update_projection(&mut self) {
+        self.proj = cgmath::ortho(self.left / self.zoom, self.right / self.zoom,
+                self.bottom / self.zoom, self.top / self.zoom, self.near, self.far);
+    }
+}
@@ -0,0 +1,356 @@
+// Defines the ClusterGrid used for clustered forward shading. The view frustum of the active camera
+// is divided into a grid of clusters, with screen space tiles along X and Y and depth slices
+// (exponentially spaced for perspective cameras) along Z. Every frame, the bounding spheres of the
+// lights in the scene are binned into the clusters they overlap so the fragment shader only has to
+// shade the lights in the cluster it falls into. Lights without a finite range (such as
+// DirectionalLights) are kept in a separate global list that every fragment shades. This does not
+// touch any GL state, so the GameWindow is responsible for uploading the results.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use self::cgmath::SquareMatrix;
+use gfx::types::*;
+use std::cmp;
+use std::f32;
+
+// The default dimensions of the cluster grid.
+pub const DEFAULT_TILES_X: usize = 16;
+pub const DEFAULT_TILES_Y: usize = 9;
+pub const DEFAULT_SLICES: usize = 24;
+
+// The bounding sphere of a light to be binned given its index in the light uniform buffer. A
+// radius of infinity marks a global light which affects every cluster.
+#[derive(Copy, Clone)]
+pub struct LightVolume {
+    pub light_index: usize,
+    pub center: Vector3D,
+    pub radius: f32,
+}
+
+impl LightVolume {
+    // Default constructor for a LightVolume with a world space bounding sphere.
+    pub fn new(light_index: usize, center: Vector3D, radius: f32) -> LightVolume {
+        LightVolume { light_index: light_index, center: center, radius: radius }
+    }
+
+    // Constructor for a LightVolume that affects every cluster.
+    pub fn global(light_index: usize) -> LightVolume {
+        LightVolume::new(light_index, Vector3D::new(0.0, 0.0, 0.0), f32::INFINITY)
+    }
+}
+
+// A view space axis aligned bounding box of a single cluster.
+#[derive(Copy, Clone)]
+struct ClusterBounds {
+    min: Vector3D,
+    max: Vector3D,
+}
+
+// A grid of clusters covering the view frustum of a camera along with the lights binned into each
+// cluster. The lights of cluster i are indices[offsets[i].0..offsets[i].0 + offsets[i].1] where
+// each index is the position of the light in the light uniform buffer.
+pub struct ClusterGrid {
+    pub tiles_x: usize,
+    pub tiles_y: usize,
+    pub slices: usize,
+    pub near: f32,
+    pub far: f32,
+    pub offsets: Vec<(u32, u32)>,      // (offset into indices, light count)
+    pub indices: Vec<u32>,
+    pub global_indices: Vec<u32>,
+    projection: Option<cgmath::Matrix4<GLfloat>>,
+    bounds: Vec<ClusterBounds>,
+}
+
+impl ClusterGrid {
+    // Default constructor for a ClusterGrid with the given number of tiles and depth slices. The
+    // grid has no bounds until a projection matrix is set.
+    pub fn new(tiles_x: usize, tiles_y: usize, slices: usize) -> ClusterGrid {
+        ClusterGrid { tiles_x: cmp::max(tiles_x, 1), tiles_y: cmp::max(tiles_y, 1),
+                slices: cmp::max(slices, 1), near: 0.0, far: 0.0, offsets: Vec::new(),
+                indices: Vec::new(), global_indices: Vec::new(), projection: None,
+                bounds: Vec::new() }
+    }
+
+    // Gets the total number of clusters in the grid.
+    pub fn get_cluster_count(&self) -> usize { self.tiles_x * self.tiles_y * self.slices }
+
+    // Gets the index of a cluster given its tile coordinates and depth slice. Tiles are numbered
+    // from the bottom left of the screen.
+    pub fn get_cluster_index(&self, x: usize, y: usize, slice: usize) -> usize {
+        (slice * self.tiles_y + y) * self.tiles_x + x
+    }
+
+    // Gets the lights in the light uniform buffer that overlap a cluster given its index.
+    pub fn get_lights(&self, cluster: usize) -> &[u32] {
+        let (offset, count) = self.offsets[cluster];
+        &self.indices[offset as usize..(offset + count) as usize]
+    }
+
+    // Sets the projection matrix of the camera that the grid covers. The bounds of the clusters are
+    // only rebuilt if the projection changed. Returns Err if the matrix cannot be inverted or
+    // describes an empty depth range.
+    pub fn set_projection(&mut self, projection: cgmath::Matrix4<GLfloat>) -> Result<(), String> {
+        if self.projection == Some(projection) { return Ok(()); }
+        let inverse = try!(projection.invert().ok_or("Projection is not invertible.".to_string()));
+
+        // Each corner of a tile unprojects to a line running from the near to the far plane.
+        let mut lines = Vec::with_capacity((self.tiles_x + 1) * (self.tiles_y + 1));
+        for y in 0..(self.tiles_y + 1) {
+            for x in 0..(self.tiles_x + 1) {
+                let ndc_x = x as f32 / self.tiles_x as f32 * 2.0 - 1.0;
+                let ndc_y = y as f32 / self.tiles_y as f32 * 2.0 - 1.0;
+                lines.push((unproject(&inverse, ndc_x, ndc_y, -1.0),
+                        unproject(&inverse, ndc_x, ndc_y, 1.0)));
+            }
+        }
+        let (near_point, far_point) = lines[0];
+        let (near, far) = (-near_point.z, -far_point.z);
+        if !(far > near) { return Err("Projection has an empty depth range.".to_string()); }
+        self.near = near;
+        self.far = far;
+
+        self.bounds.clear();
+        for slice in 0..self.slices {
+            let (slice_near, slice_far) =
+                    (self.get_slice_depth(slice), self.get_slice_depth(slice + 1));
+            for y in 0..self.tiles_y {
+                for x in 0..self.tiles_x {
+                    let mut min = Vector3D::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+                    let mut max = -min;
+                    let corners = [y * (self.tiles_x + 1) + x, y * (self.tiles_x + 1) + x + 1,
+                            (y + 1) * (self.tiles_x + 1) + x, (y + 1) * (self.tiles_x + 1) + x + 1];
+                    for &corner in corners.iter() {
+                        let (line_near, line_far) = lines[corner];
+                        for &depth in [slice_near, slice_far].iter() {
+                            let point = intersect_depth(line_near, line_far, depth);
+                            min = Vector3D::new(
+                                    min.x.min(point.x), min.y.min(point.y), min.z.min(point.z));
+                            max = Vector3D::new(
+                                    max.x.max(point.x), max.y.max(point.y), max.z.max(point.z));
+                        }
+                    }
+                    self.bounds.push(ClusterBounds { min: min, max: max });
+                }
+            }
+        }
+        self.projection = Some(projection);
+        Ok(())
+    }
+
+    // Gets the depth slice containing a positive view space depth. Returns None if the depth lies
+    // outside of the near and far planes.
+    pub fn get_slice(&self, depth: f32) -> Option<usize> {
+        if depth < self.near || depth > self.far { return None; }
+        let t = if self.near > 0.0 {
+            (depth / self.near).ln() / (self.far / self.near).ln()
+        } else {
+            (depth - self.near) / (self.far - self.near)
+        };
+        Some(cmp::min((t * self.slices as f32) as usize, self.slices - 1))
+    }
+
+    // Bins a set of lights into the clusters given the view matrix of the camera. The projection
+    // must be set before this is called.
+    pub fn bin_lights(&mut self, view: cgmath::Matrix4<GLfloat>, lights: &[LightVolume]) {
+        let cluster_count = self.get_cluster_count();
+        self.global_indices.clear();
+        self.indices.clear();
+        self.offsets.clear();
+        self.offsets.resize(cluster_count, (0, 0));
+        if self.bounds.len() != cluster_count { return; }
+
+        // Gather (cluster, light) pairs and then sort them into contiguous per-cluster lists.
+        let mut pairs = Vec::new();
+        for light in lights.iter() {
+            if !light.radius.is_finite() {
+                self.global_indices.push(light.light_index as u32);
+                continue;
+            }
+            let center = (view * light.center.extend(1.0)).truncate();
+            let depth = -center.z;
+            if depth + light.radius < self.near || depth - light.radius > self.far { continue; }
+            let first = self.get_slice((depth - light.radius).max(self.near)).unwrap();
+            let last = self.get_slice((depth + light.radius).min(self.far)).unwrap();
+            for slice in first..(last + 1) {
+                let start = self.get_cluster_index(0, 0, slice);
+                for cluster in start..(start + self.tiles_x * self.tiles_y) {
+                    if sphere_intersects_bounds(center, light.radius, &self.bounds[cluster]) {
+                        pairs.push((cluster, light.light_index as u32));
+                    }
+                }
+            }
+        }
+        for &(cluster, _) in pairs.iter() {
+            self.offsets[cluster].1 += 1;
+        }
+        let mut offset = 0;
+        for entry in self.offsets.iter_mut() {
+            entry.0 = offset;
+            offset += entry.1;
+        }
+        self.indices.resize(pairs.len(), 0);
+        let mut filled = vec![0; cluster_count];
+        for &(cluster, light_index) in pairs.iter() {
+            self.indices[(self.offsets[cluster].0 + filled[cluster]) as usize] = light_index;
+            filled[cluster] += 1;
+        }
+    }
+
+    // Helper method that gets the view space depth of the near boundary of a slice.
+    fn get_slice_depth(&self, slice: usize) -> f32 {
+        let t = slice as f32 / self.slices as f32;
+        if self.near > 0.0 {
+            self.near * (self.far / self.near).powf(t)
+        } else {
+            self.near + (self.far - self.near) * t
+        }
+    }
+}
+
+// Helper function that transforms a point in normalized device coordinates into view space given
+// the inverse of a projection matrix.
+fn unproject(inverse: &cgmath::Matrix4<GLfloat>, x: f32, y: f32, z: f32) -> Vector3D {
+    let point = *inverse * cgmath::Vector4::new(x, y, z, 1.0);
+    point.truncate() / point.w
+}
+
+// Helper function that finds the point on the line through two view space points at a positive
+// view space depth.
+fn intersect_depth(near: Vector3D, far: Vector3D, depth: f32) -> Vector3D {
+    let t = (-depth - near.z) / (far.z - near.z);
+    near + (far - near) * t
+}
+
+// Helper function that tests whether a view space sphere overlaps the bounds of a cluster.
+fn sphere_intersects_bounds(center: Vector3D, radius: f32, bounds: &ClusterBounds) -> bool {
+    let dx = (bounds.min.x - center.x).max(0.0).max(center.x - bounds.max.x);
+    let dy = (bounds.min.y - center.y).max(0.0).max(center.y - bounds.max.y);
+    let dz = (bounds.min.z - center.z).max(0.0).max(center.z - bounds.max.z);
+    dx * dx + dy * dy + dz * dz <= radius * radius
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate cgmath;
+
+    use super::*;
+
+    // Creates a 4x4x8 grid for a camera at the origin looking down -Z with a 90 degree field of
+    // view, a near plane at 1, and a far plane at 100.
+    fn create_grid() -> ClusterGrid {
+        let mut grid = ClusterGrid::new(4, 4, 8);
+        grid.set_projection(cgmath::perspective(cgmath::deg(90.0), 1.0, 1.0, 100.0)).unwrap();
+        grid
+    }
+
+    // Bins lights with the camera at the origin.
+    fn bin(grid: &mut ClusterGrid, lights: &[LightVolume]) {
+        let view = cgmath::Matrix4::new(1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0,
+                0.0, 0.0, 0.0, 1.0);
+        grid.bin_lights(view, lights);
+    }
+
+    // Gets the (x, y, slice) of every cluster that a light was binned into.
+    fn get_clusters(grid: &ClusterGrid, light_index: u32) -> Vec<(usize, usize, usize)> {
+        let mut clusters = Vec::new();
+        for slice in 0..grid.slices {
+            for y in 0..grid.tiles_y {
+                for x in 0..grid.tiles_x {
+                    let index = grid.get_cluster_index(x, y, slice);
+                    if grid.get_lights(index).contains(&light_index) {
+                        clusters.push((x, y, slice));
+                    }
+                }
+            }
+        }
+        clusters
+    }
+
+    #[test]
+    fn depth_range() {
+        let grid = create_grid();
+        assert!((grid.near - 1.0).abs() < 1e-3);
+        assert!((grid.far - 100.0).abs() < 1e-1);
+        assert_eq!(grid.get_slice(1.0), Some(0));
+        assert_eq!(grid.get_slice(99.0), Some(7));
+        assert_eq!(grid.get_slice(0.5), None);
+        assert_eq!(grid.get_slice(101.0), None);
+    }
+
+    #[test]
+    fn light_at_near_plane() {
+        let mut grid = create_grid();
+        bin(&mut grid, &[LightVolume::new(3, Vector3D::new(0.0, 0.0, -1.0), 0.25)]);
+        let clusters = get_clusters(&grid, 3);
+        assert_eq!(clusters, vec![(1, 1, 0), (2, 1, 0), (1, 2, 0), (2, 2, 0)]);
+    }
+
+    #[test]
+    fn light_at_far_plane() {
+        let mut grid = create_grid();
+        bin(&mut grid, &[LightVolume::new(3, Vector3D::new(90.0, -90.0, -99.9), 1.0)]);
+        let clusters = get_clusters(&grid, 3);
+        assert_eq!(clusters, vec![(3, 0, 7)]);
+    }
+
+    #[test]
+    fn light_straddling_clusters() {
+        let mut grid = create_grid();
+        // The light sits on the corner shared by four tiles and on the boundary of two slices.
+        let depth = grid.get_slice_depth(4);
+        bin(&mut grid, &[LightVolume::new(0, Vector3D::new(0.0, 0.0, -depth), 0.1)]);
+        let clusters = get_clusters(&grid, 0);
+        assert_eq!(clusters, vec![(1, 1, 3), (2, 1, 3), (1, 2, 3), (2, 2, 3), (1, 1, 4),
+                (2, 1, 4), (1, 2, 4), (2, 2, 4)]);
+    }
+
+    #[test]
+    fn light_behind_camera() {
+        let mut grid = create_grid();
+        bin(&mut grid, &[LightVolume::new(0, Vector3D::new(0.0, 0.0, 5.0), 2.0),
+                LightVolume::new(1, Vector3D::new(0.0, 0.0, 0.5), 2.0)]);
+        assert!(get_clusters(&grid, 0).is_empty());
+        // A light behind the camera still affects the clusters its range reaches.
+        let clusters = get_clusters(&grid, 1);
+        assert!(!clusters.is_empty());
+        assert!(clusters.iter().all(|&(_, _, slice)| slice == 0));
+        assert_eq!(grid.indices.len(), clusters.len());
+    }
+
+    #[test]
+    fn many_lights_in_cluster() {
+        let mut grid = create_grid();
+        let mut lights: Vec<LightVolume> = (0..300)
+                .map(|i| LightVolume::new(i, Vector3D::new(-20.0, -20.0, -30.0), 0.5)).collect();
+        lights.push(LightVolume::global(300));
+        lights.push(LightVolume::new(301, Vector3D::new(20.0, 20.0, -30.0), 0.5));
+        bin(&mut grid, &lights);
+
+        let cluster = grid.get_cluster_index(0, 0, grid.get_slice(30.0).unwrap());
+        let expected: Vec<u32> = (0..300).collect();
+        assert_eq!(grid.get_lights(cluster), &expected[..]);
+        assert_eq!(grid.global_indices, vec![300]);
+        assert_eq!(get_clusters(&grid, 301).len(), 1);
+
+        // Every cluster's list is contiguous and the lists cover the indices exactly once.
+        let mut offset = 0;
+        for &(start, count) in grid.offsets.iter() {
+            assert_eq!(start, offset);
+            offset += count;
+        }
+        assert_eq!(offset as usize, grid.indices.len());
+        assert_eq!(grid.indices.len(), 301);
+    }
+
+    #[test]
+    fn bin_without_projection() {
+        let mut grid = ClusterGrid::new(4, 4, 8);
+        bin(&mut grid, &[LightVolume::new(0, Vector3D::new(0.0, 0.0, -5.0), 1.0)]);
+        assert_eq!(grid.offsets.len(), grid.get_cluster_count());
+        assert!(grid.indices.is_empty());
+    }
+}
@@ -0,0 +1,292 @@
+// Defines the GPU resources used for image based lighting. An Environment holds an HDR cube map of
+// the surroundings along with the two maps precomputed from it: an irradiance cube map that stores
+// the cosine weighted diffuse lighting for each normal and a prefiltered cube map that stores the
+// specular lighting for increasing roughness in each mip level. The EnvironmentBaker owns the
+// programs and geometry used to render these maps as well as the BRDF lookup table which is
+// shared by every Environment.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+extern crate gl;
+
+use self::cgmath::{Matrix, Point};
+use gfx::shadow;
+use gfx::types::*;
+use std::ffi::CString;
+use std::mem;
+use util::common;
+
+// The resolution (in texels per side) of each of the baked maps.
+pub const ENVIRONMENT_RESOLUTION: u32 = 512;
+pub const IRRADIANCE_RESOLUTION: u32 = 32;
+pub const PREFILTERED_RESOLUTION: u32 = 128;
+pub const BRDF_LUT_RESOLUTION: u32 = 512;
+
+// The number of mip levels of the prefiltered map. Level 0 is perfectly smooth and the last level
+// has a roughness of 1.0.
+pub const PREFILTERED_LEVELS: u32 = 5;
+
+// A unit cube as 12 triangles used to render cube map faces and skyboxes.
+const CUBE_VERTICES: [GLfloat; 108] = [
+        -1.0,  1.0, -1.0, -1.0, -1.0, -1.0,  1.0, -1.0, -1.0,
+         1.0, -1.0, -1.0,  1.0,  1.0, -1.0, -1.0,  1.0, -1.0,
+        -1.0, -1.0,  1.0, -1.0, -1.0, -1.0, -1.0,  1.0, -1.0,
+        -1.0,  1.0, -1.0, -1.0,  1.0,  1.0, -1.0, -1.0,  1.0,
+         1.0, -1.0, -1.0,  1.0, -1.0,  1.0,  1.0,  1.0,  1.0,
+         1.0,  1.0,  1.0,  1.0,  1.0, -1.0,  1.0, -1.0, -1.0,
+        -1.0, -1.0,  1.0, -1.0,  1.0,  1.0,  1.0,  1.0,  1.0,
+         1.0,  1.0,  1.0,  1.0, -1.0,  1.0, -1.0, -1.0,  1.0,
+        -1.0,  1.0, -1.0,  1.0,  1.0, -1.0,  1.0,  1.0,  1.0,
+         1.0,  1.0,  1.0, -1.0,  1.0,  1.0, -1.0,  1.0, -1.0,
+        -1.0, -1.0, -1.0, -1.0, -1.0,  1.0,  1.0, -1.0, -1.0,
+         1.0, -1.0, -1.0, -1.0, -1.0,  1.0,  1.0, -1.0,  1.0];
+
+// A quad covering the screen in normalized device coordinates as 2 triangles.
+const QUAD_VERTICES: [GLfloat; 18] = [
+        -1.0, -1.0, 0.0,  1.0, -1.0, 0.0,  1.0,  1.0, 0.0,
+         1.0,  1.0, 0.0, -1.0,  1.0, 0.0, -1.0, -1.0, 0.0];
+
+// The HDR cube map of an environment and the lighting maps baked from it.
+pub struct Environment {
+    pub cube_map: GLuint,
+    pub irradiance: GLuint,
+    pub prefiltered: GLuint,
+}
+
+impl Environment {
+    // Deletes the cube maps. The Environment must not be used afterwards.
+    pub fn delete(&mut self) { unsafe {
+        gl::DeleteTextures(1, &mut self.cube_map);
+        gl::DeleteTextures(1, &mut self.irradiance);
+        gl::DeleteTextures(1, &mut self.prefiltered);
+    }}
+}
+
+// Renders cube maps from equirectangular images and bakes the lighting maps of an Environment.
+// The programs are provided by the GameWindow and must bind "position" to attribute location 0.
+pub struct EnvironmentBaker {
+    fbo: GLuint,
+    cube_vao: GLuint,
+    quad_vao: GLuint,
+    equirect_program: GLuint,
+    irradiance_program: GLuint,
+    prefilter_program: GLuint,
+    brdf_program: GLuint,
+    brdf_lut: Option<GLuint>,
+}
+
+impl EnvironmentBaker {
+    // Creates the framebuffer and geometry used for baking given the programs that convert an
+    // equirectangular image to a cube map, convolve the irradiance map, prefilter the specular
+    // map, and integrate the BRDF. This can only be called after the window context is set up.
+    pub fn new(equirect_program: GLuint, irradiance_program: GLuint, prefilter_program: GLuint,
+            brdf_program: GLuint) -> EnvironmentBaker { unsafe {
+        let mut baker = EnvironmentBaker { fbo: 0,
+                cube_vao: create_position_vao(&CUBE_VERTICES),
+                quad_vao: create_position_vao(&QUAD_VERTICES), equirect_program: equirect_program,
+                irradiance_program: irradiance_program, prefilter_program: prefilter_program,
+                brdf_program: brdf_program, brdf_lut: None };
+        gl::GenFramebuffers(1, &mut baker.fbo);
+        baker
+    }}
+
+    // Bakes an Environment from an equirectangular HDR image. This changes the bound framebuffer,
+    // viewport, program, and VAO, so the caller is responsible for restoring them.
+    pub fn bake(&mut self, image: &common::HDRImage) -> Result<Environment, String> {
+        let cube_map = try!(self.equirect_to_cube_map(image, ENVIRONMENT_RESOLUTION));
+
+        // Convolve the environment over the hemisphere around each normal for diffuse lighting.
+        let irradiance = create_cube_map(IRRADIANCE_RESOLUTION, false);
+        unsafe {
+            gl::UseProgram(self.irradiance_program);
+            uniform_int!(self.irradiance_program, "environment_map", 0);
+            uniform_float!(self.irradiance_program, "environment_resolution",
+                    ENVIRONMENT_RESOLUTION as GLfloat);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, cube_map);
+        }
+        let program = self.irradiance_program;
+        try!(self.render_cube_faces(program, irradiance, IRRADIANCE_RESOLUTION, 0));
+
+        // Prefilter the environment with increasing roughness for each mip level.
+        let prefiltered = create_cube_map(PREFILTERED_RESOLUTION, true);
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, prefiltered);
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAX_LEVEL,
+                    (PREFILTERED_LEVELS - 1) as GLint);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, cube_map);
+            gl::UseProgram(self.prefilter_program);
+            uniform_int!(self.prefilter_program, "environment_map", 0);
+            uniform_float!(self.prefilter_program, "environment_resolution",
+                    ENVIRONMENT_RESOLUTION as GLfloat);
+        }
+        for level in 0..PREFILTERED_LEVELS {
+            let roughness = level as GLfloat / (PREFILTERED_LEVELS - 1) as GLfloat;
+            unsafe { uniform_float!(self.prefilter_program, "roughness", roughness); }
+            let program = self.prefilter_program;
+            try!(self.render_cube_faces(
+                    program, prefiltered, PREFILTERED_RESOLUTION >> level, level));
+        }
+        unsafe { gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0); }
+        Ok(Environment { cube_map: cube_map, irradiance: irradiance, prefiltered: prefiltered })
+    }
+
+    // Renders an equirectangular HDR image into a new mipmapped cube map with the given resolution
+    // and returns its texture ID. This changes the same state as bake().
+    pub fn equirect_to_cube_map(&mut self, image: &common::HDRImage, resolution: u32)
+            -> Result<GLuint, String> {
+        let size = (image.width as usize).checked_mul(image.height as usize)
+                .and_then(|pixels| pixels.checked_mul(3));
+        if size != Some(image.data.len()) {
+            return Err("HDR image data does not match its dimensions.".to_string());
+        }
+        let cube_map = create_cube_map(resolution, true);
+        let mut equirect = 0;
+        unsafe {
+            gl::GenTextures(1, &mut equirect);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D, equirect);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGB32F as GLint, image.width as GLsizei,
+                    image.height as GLsizei, 0, gl::RGB, gl::FLOAT, vec_to_addr!(image.data));
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+            gl::UseProgram(self.equirect_program);
+            uniform_int!(self.equirect_program, "equirect_map", 0);
+        }
+        let program = self.equirect_program;
+        let result = self.render_cube_faces(program, cube_map, resolution, 0);
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            gl::DeleteTextures(1, &mut equirect);
+        }
+        try!(result);
+        unsafe {
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, cube_map);
+            gl::GenerateMipmap(gl::TEXTURE_CUBE_MAP);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+        }
+        Ok(cube_map)
+    }
+
+    // Gets the BRDF lookup table, integrating it on first use. The table is indexed by the cosine
+    // between the normal and view direction along X and the roughness along Y, and stores the
+    // scale and bias applied to the Fresnel term at normal incidence.
+    pub fn get_brdf_lut(&mut self) -> Result<GLuint, String> {
+        if let Some(lut) = self.brdf_lut { return Ok(lut); }
+        let mut lut = 0;
+        unsafe {
+            gl::GenTextures(1, &mut lut);
+            gl::BindTexture(gl::TEXTURE_2D, lut);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RG16F as GLint, BRDF_LUT_RESOLUTION as GLsizei,
+                    BRDF_LUT_RESOLUTION as GLsizei, 0, gl::RG, gl::FLOAT, 0 as CVoid);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::FramebufferTexture2D(
+                    gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, lut, 0);
+            gl::DrawBuffer(gl::COLOR_ATTACHMENT0);
+            if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+                gl::DeleteTextures(1, &mut lut);
+                return Err("BRDF lookup table framebuffer is incomplete.".to_string());
+            }
+            gl::Viewport(0, 0, BRDF_LUT_RESOLUTION as GLsizei, BRDF_LUT_RESOLUTION as GLsizei);
+            gl::Disable(gl::DEPTH_TEST);
+            gl::UseProgram(self.brdf_program);
+            gl::BindVertexArray(self.quad_vao);
+            gl::DrawArrays(gl::TRIANGLES, 0, (QUAD_VERTICES.len() / 3) as GLsizei);
+            gl::Enable(gl::DEPTH_TEST);
+        }
+        self.brdf_lut = Some(lut);
+        Ok(lut)
+    }
+
+    // Draws the unit cube with the currently bound program. This binds the cube VAO.
+    pub fn draw_cube(&self) { unsafe {
+        gl::BindVertexArray(self.cube_vao);
+        gl::DrawArrays(gl::TRIANGLES, 0, (CUBE_VERTICES.len() / 3) as GLsizei);
+    }}
+
+    // Helper method that renders each face of a cube map mip level with a program that expects a
+    // "transform" uniform from the unit cube to the clip space of the face. The program must
+    // already be bound with its textures.
+    fn render_cube_faces(&self, program: GLuint, cube_map: GLuint, resolution: u32, level: u32)
+            -> Result<(), String> { unsafe {
+        let proj = cgmath::perspective(cgmath::deg(90.0), 1.0, 0.1, 10.0);
+        let eye = cgmath::Point3::new(0.0, 0.0, 0.0);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+        gl::DrawBuffer(gl::COLOR_ATTACHMENT0);
+        gl::Viewport(0, 0, resolution as GLsizei, resolution as GLsizei);
+        gl::Disable(gl::DEPTH_TEST);
+        for (face, &(dir, up)) in get_cube_face_directions().iter().enumerate() {
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0,
+                    gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as GLuint, cube_map, level as GLint);
+            if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+                gl::Enable(gl::DEPTH_TEST);
+                return Err("Environment framebuffer is incomplete.".to_string());
+            }
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+            let view = cgmath::Matrix4::look_at(eye, cgmath::Point3::from_vec(dir), up);
+            uniform_mat4!(program, "transform", proj * view);
+            self.draw_cube();
+        }
+        gl::Enable(gl::DEPTH_TEST);
+        Ok(())
+    }}
+}
+
+// Gets the view direction and up vector of each face of a cube map in the order +X, -X, +Y, -Y,
+// +Z, -Z following the OpenGL cube map conventions.
+fn get_cube_face_directions() -> [(Vector3D, Vector3D); shadow::CUBE_FACES] {
+    [(Vector3D::new(1.0, 0.0, 0.0), Vector3D::new(0.0, -1.0, 0.0)),
+     (Vector3D::new(-1.0, 0.0, 0.0), Vector3D::new(0.0, -1.0, 0.0)),
+     (Vector3D::new(0.0, 1.0, 0.0), Vector3D::new(0.0, 0.0, 1.0)),
+     (Vector3D::new(0.0, -1.0, 0.0), Vector3D::new(0.0, 0.0, -1.0)),
+     (Vector3D::new(0.0, 0.0, 1.0), Vector3D::new(0.0, -1.0, 0.0)),
+     (Vector3D::new(0.0, 0.0, -1.0), Vector3D::new(0.0, -1.0, 0.0))]
+}
+
+// Helper function that creates an empty floating point cube map with the given resolution and
+// returns its texture ID. Mipmapped cube maps have storage allocated for every mip level.
+fn create_cube_map(resolution: u32, mipmapped: bool) -> GLuint { unsafe {
+    let mut cube_map = 0;
+    gl::GenTextures(1, &mut cube_map);
+    gl::BindTexture(gl::TEXTURE_CUBE_MAP, cube_map);
+    for face in 0..shadow::CUBE_FACES {
+        gl::TexImage2D(gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as GLuint, 0,
+                gl::RGB16F as GLint, resolution as GLsizei, resolution as GLsizei, 0, gl::RGB,
+                gl::FLOAT, 0 as CVoid);
+    }
+    let min_filter = if mipmapped { gl::LINEAR_MIPMAP_LINEAR } else { gl::LINEAR };
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MIN_FILTER, min_filter as GLint);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as GLint);
+    if mipmapped { gl::GenerateMipmap(gl::TEXTURE_CUBE_MAP); }
+    gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+    cube_map
+}}
+
+// Helper function that creates a VAO backed by a new VBO of tightly packed vertex positions bound
+// to attribute location 0 and returns the ID of the VAO.
+fn create_position_vao(vertices: &[GLfloat]) -> GLuint { unsafe {
+    let (mut vao, mut vbo) = (0, 0);
+    gl::GenVertexArrays(1, &mut vao);
+    gl::BindVertexArray(vao);
+    gl::GenBuffers(1, &mut vbo);
+    gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+    gl::BufferData(gl::ARRAY_BUFFER, float_size!(vertices.len(), GLsizeiptr),
+            vec_to_addr!(vertices), gl::STATIC_DRAW);
+    gl::EnableVertexAttribArray(0);
+    gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE as GLboolean, 0, 0 as CVoid);
+    gl::BindVertexArray(0);
+    vao
+}}
@@ -10,15 +10,25 @@ extern crate cgmath;
 extern crate gl;
 extern crate glutin;
 
-use self::cgmath::{Matrix, Point};
+use self::cgmath::{EuclideanVector, Matrix};
 pub use self::glutin::{ElementState, Event, VirtualKeyCode};
 
-use gfx::camera;
+use gfx::allocator;
 use gfx::camera::Camera;
+use gfx::cluster;
 use gfx::color;
+use gfx::environment;
+use gfx::handle::{CameraHandle, DirectionalLightHandle, HandleMap, PointLightHandle,
+        SpotLightHandle};
 use gfx::light;
+use gfx::material;
 use gfx::model;
+use gfx::render_queue;
+use gfx::scene;
+use gfx::shadow;
 use gfx::types::*;
+use gfx::vertex;
+use util::{common, image};
 use util::shader;
 use self::glutin::{Window, WindowBuilder};
 use std::cmp;
@@ -26,13 +36,70 @@ use std::ffi::CString;
 use std::mem;
 use std::path;
 use std::ptr;
-use std::rc::Rc;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
 
-// Number of elements in a VBO or EBO.
+// Number of floats in a VBO or elements in an EBO unless a larger buffer is needed.
 const BUFFER_SIZE: usize = 65535 * 4;
 
-// Maximum number of dynamic lights in a scene.
-const MAX_LIGHTS: usize = 8;
+// Maximum number of dynamic lights in a scene. This must match MAX_LIGHTS in std.frag and keep the
+// light uniform buffer under the minimum guaranteed uniform block size of 16KB.
+const MAX_LIGHTS: usize = 128;
+
+// The binding point of the light uniform buffer and the size of the header preceding the packed
+// lights (the active light count padded to the std140 alignment of the light array).
+const LIGHT_BLOCK_BINDING: GLuint = 0;
+const LIGHT_BLOCK_HEADER_SIZE: usize = 16;
+
+// Maximum number of DirectionalLights and SpotLights that can cast shadows at once.
+const MAX_SHADOW_MAPS: usize = 4;
+
+// Maximum number of PointLights that can cast shadows at once.
+const MAX_CUBE_SHADOW_MAPS: usize = 4;
+
+// Texture units used by materials for the diffuse (or base color), specular (or
+// metallic-roughness), normal, occlusion, and emissive maps.
+const DIFFUSE_TEXTURE_UNIT: usize = 0;
+const SPECULAR_TEXTURE_UNIT: usize = 1;
+const NORMAL_TEXTURE_UNIT: usize = 2;
+const OCCLUSION_TEXTURE_UNIT: usize = 3;
+const EMISSIVE_TEXTURE_UNIT: usize = 4;
+const MATERIAL_TEXTURE_UNITS: usize = material::MATERIAL_TEXTURE_COUNT;
+
+// The first texture unit used for shadow maps. Units before this are used by materials. Cube
+// shadow maps use the units directly after the regular shadow maps.
+const SHADOW_TEXTURE_UNIT: usize = MATERIAL_TEXTURE_UNITS;
+const CUBE_SHADOW_TEXTURE_UNIT: usize = SHADOW_TEXTURE_UNIT + MAX_SHADOW_MAPS;
+
+// Texture units of the buffer textures holding the clustered light lists. These follow the cube
+// shadow maps.
+const CLUSTER_TEXTURE_UNIT: usize = CUBE_SHADOW_TEXTURE_UNIT + MAX_CUBE_SHADOW_MAPS;
+const CLUSTER_LIGHT_TEXTURE_UNIT: usize = CLUSTER_TEXTURE_UNIT + 1;
+
+// Texture units of the image based lighting maps. These follow the clustered light lists.
+const IRRADIANCE_TEXTURE_UNIT: usize = CLUSTER_LIGHT_TEXTURE_UNIT + 1;
+const PREFILTERED_TEXTURE_UNIT: usize = IRRADIANCE_TEXTURE_UNIT + 1;
+const BRDF_LUT_TEXTURE_UNIT: usize = PREFILTERED_TEXTURE_UNIT + 1;
+
+// The texture unit of the buffer texture holding the per-instance matrices of instanced draws.
+const INSTANCE_TEXTURE_UNIT: usize = BRDF_LUT_TEXTURE_UNIT + 1;
+
+// The texture unit of the buffer texture holding the skinning matrices of a skinned draw. Each
+// joint's matrix is stored as four RGBA texels.
+const JOINT_TEXTURE_UNIT: usize = INSTANCE_TEXTURE_UNIT + 1;
+
+// The texture unit of the buffer texture holding the morph target deltas of a morphed draw.
+const MORPH_TEXTURE_UNIT: usize = JOINT_TEXTURE_UNIT + 1;
+
+// Each instance in the instance buffer is a model matrix followed by a normal matrix, stored as
+// eight RGBA texels. Batches are capped so that the buffer stays within the minimum texture buffer
+// size of 65536 texels.
+const INSTANCE_TEXELS: usize = 8;
+const MAX_INSTANCES_PER_DRAW: usize = 65536 / INSTANCE_TEXELS;
+
+// Polygon offset applied when rendering shadow maps to reduce shadow acne.
+const SHADOW_OFFSET_FACTOR: GLfloat = 2.0;
+const SHADOW_OFFSET_UNITS: GLfloat = 4.0;
 
 // The default gamma of the scene.
 const DEFAULT_GAMMA: GLfloat = 2.2;
@@ -41,37 +108,89 @@ const DEFAULT_GAMMA: GLfloat = 2.2;
 const SHADER_DIR: &'static str = "shaders";
 const VERTEX_SHADER_NAME: &'static str = "std.vert";
 const FRAGMENT_SHADER_NAME: &'static str = "std.frag";
+const SHADOW_VERTEX_SHADER_NAME: &'static str = "shadow.vert";
+const SHADOW_FRAGMENT_SHADER_NAME: &'static str = "shadow.frag";
+const POINT_SHADOW_VERTEX_SHADER_NAME: &'static str = "point_shadow.vert";
+const POINT_SHADOW_FRAGMENT_SHADER_NAME: &'static str = "point_shadow.frag";
+const CUBE_VERTEX_SHADER_NAME: &'static str = "cube.vert";
+const QUAD_VERTEX_SHADER_NAME: &'static str = "quad.vert";
+const EQUIRECT_FRAGMENT_SHADER_NAME: &'static str = "equirect.frag";
+const IRRADIANCE_FRAGMENT_SHADER_NAME: &'static str = "irradiance.frag";
+const PREFILTER_FRAGMENT_SHADER_NAME: &'static str = "prefilter.frag";
+const BRDF_FRAGMENT_SHADER_NAME: &'static str = "brdf.frag";
+const SKYBOX_VERTEX_SHADER_NAME: &'static str = "skybox.vert";
+const SKYBOX_FRAGMENT_SHADER_NAME: &'static str = "skybox.frag";
+
+// The resolution (in texels per side) of the cube map faces of a skybox made from an
+// equirectangular image.
+const SKYBOX_RESOLUTION: u32 = 1024;
+
+// The kinds of managed buffer pools. VBO space is allocated in vertices and EBO space in elements.
+#[derive(Copy, Clone, PartialEq)]
+enum PoolKind {
+    Vertex,
+    Element,
+}
 
-// Contents of a VBO.
-// [P_x  P_y  P_z  N_x  N_y  N_z  T_u  T_v]
-const VERTEX_POS_SIZE: usize = 3;
-const VERTEX_NORMAL_SIZE: usize = 3;
-const VERTEX_TANGENT_SIZE: usize = 3;
-const VERTEX_BITANGENT_SIZE: usize = 3;
-const VERTEX_TCOORD_SIZE: usize = 2;
-const VERTEX_SIZE: usize = VERTEX_POS_SIZE + VERTEX_NORMAL_SIZE + VERTEX_TANGENT_SIZE +
-        VERTEX_BITANGENT_SIZE + VERTEX_TCOORD_SIZE;
+// The ranges of the managed buffers used by a mapped ModelInfo. The ModelInfo is only weakly
+// referenced so that its ranges can be reclaimed once it is dropped.
+struct MappedModel {
+    info: Weak<model::ModelInfo>,
+    vbo: usize,
+    vertex_start: usize,
+    vertex_count: usize,
+    ebo: usize,
+    element_start: usize,
+    element_count: usize,
+    morph_buffer: GLuint,
+    morph_texture: GLuint,
+}
 
 // A window for graphics drawing that is managed by the graphics module. This is a thin wrapper
 // around the glutin Window class and will manage draws to the glutin window.
 pub struct GameWindow {
     pub bg_color: color::Color,
-    pub cameras: Vec<Option<camera::PerspectiveCamera>>,
+    cameras: HandleMap<Box<Camera>>,
     pub program: GLuint,
-    active_camera: Option<usize>,
+    shadow_program: GLuint,
+    point_shadow_program: GLuint,
+    active_camera: Option<CameraHandle>,
     gl_window: Window,
-    point_lights: Vec<Option<light::PointLight>>,               // (light_index, light)
-    directional_lights: Vec<Option<light::DirectionalLight>>,   // (light_index, light)
-    spot_lights: Vec<Option<light::SpotLight>>,                 // (light_index, light)
+    point_lights: HandleMap<light::PointLight>,
+    directional_lights: HandleMap<light::DirectionalLight>,
+    spot_lights: HandleMap<light::SpotLight>,
     light_indices: Vec<usize>,
+    light_count: usize,
+    light_buffer: GLuint,
+    shadow_maps: Vec<Option<shadow::ShadowMap>>,
+    shadow_indices: Vec<usize>,
+    cube_shadow_maps: Vec<Option<shadow::CubeShadowMap>>,
+    cube_shadow_indices: Vec<usize>,
+    clusters: cluster::ClusterGrid,
+    cluster_buffer: GLuint,
+    cluster_texture: GLuint,
+    cluster_light_buffer: GLuint,
+    cluster_light_texture: GLuint,
+    environment_baker: Option<environment::EnvironmentBaker>,
+    environment: Option<environment::Environment>,
+    skybox_program: GLuint,
+    skybox: Option<GLuint>,
+    queue: render_queue::RenderQueue,
+    instance_buffer: GLuint,
+    instance_texture: GLuint,
+    joint_buffer: GLuint,
+    joint_texture: GLuint,
     gen: usize,
     working_vao: GLuint,
     bound_vao: Option<GLuint>,
+    defaults_vao: Option<GLuint>,
     default_texture: GLuint,
     gamma: GLfloat,
     vaos: Vec<Vec<Option<GLuint>>>,
-    vbos: Vec<(GLuint, usize, usize)>, // (vbo_id, size, max_size)
-    ebos: Vec<(GLuint, usize, usize)>, // (ebo_id, size, max_size)
+    // (vbo_id, layout, allocator in vertices)
+    vbos: Vec<(GLuint, vertex::VertexLayout, allocator::RangeAllocator)>,
+    ebos: Vec<(GLuint, allocator::RangeAllocator)>, // (ebo_id, allocator in elements)
+    mapped: Vec<MappedModel>,
 }
 
 impl GameWindow {
@@ -79,9 +198,9 @@ impl GameWindow {
     // creation can fail suchas unsupported OpenGL, so it returns a Result.
     pub fn new(width: u32, height: u32, title: String) -> Result<GameWindow, String> {
         let bg_color = color::Color::new_rgb(0.0, 0.0, 0.0);
-        let pl: Vec<Option<light::PointLight>> = Vec::new();
-        let dl: Vec<Option<light::DirectionalLight>> = Vec::new();
-        let sl: Vec<Option<light::SpotLight>> = Vec::new();
+        let pl = HandleMap::new("point light");
+        let dl = HandleMap::new("directional light");
+        let sl = HandleMap::new("spot light");
 
         // TODO: Handle the actual error reporting of glutin and make this code less ugly.
         let creation_err = "Unable to create GameWindow.";
@@ -90,32 +209,67 @@ impl GameWindow {
         unsafe { try!(gl_window.make_current().map_err(|_| creation_err.to_string())) }
         gl_window.set_title(&title);
         gl::load_with(|symbol| gl_window.get_proc_address(symbol) as *const _);
-        let lights:Vec<usize> = (0..MAX_LIGHTS).collect();
+        let lights:Vec<usize> = (0..MAX_LIGHTS).rev().collect();
+        let shadows:Vec<usize> = (0..MAX_SHADOW_MAPS).collect();
+        let cube_shadows:Vec<usize> = (0..MAX_CUBE_SHADOW_MAPS).collect();
 
         let mut window = GameWindow {
-                bg_color: bg_color, cameras: Vec::new(), gl_window: gl_window,
-                program: 0, point_lights: pl, directional_lights: dl, spot_lights: sl,
-                active_camera: None, gen: 0, bound_vao: None, vbos: Vec::new(), ebos: Vec::new(),
-                vaos: Vec::new(), working_vao: 0, light_indices: lights, default_texture: 0,
-                gamma: 0.0 };
+                bg_color: bg_color, cameras: HandleMap::new("camera"), gl_window: gl_window,
+                program: 0, shadow_program: 0, point_shadow_program: 0, point_lights: pl,
+                directional_lights: dl, spot_lights: sl,
+                active_camera: None, gen: 0, bound_vao: None, defaults_vao: None,
+                vbos: Vec::new(), ebos: Vec::new(), mapped: Vec::new(),
+                vaos: Vec::new(), working_vao: 0, light_indices: lights, light_count: 0,
+                light_buffer: 0, default_texture: 0,
+                gamma: 0.0, shadow_maps: (0..MAX_SHADOW_MAPS).map(|_| None).collect(),
+                shadow_indices: shadows,
+                cube_shadow_maps: (0..MAX_CUBE_SHADOW_MAPS).map(|_| None).collect(),
+                cube_shadow_indices: cube_shadows,
+                clusters: cluster::ClusterGrid::new(cluster::DEFAULT_TILES_X,
+                        cluster::DEFAULT_TILES_Y, cluster::DEFAULT_SLICES),
+                cluster_buffer: 0, cluster_texture: 0, cluster_light_buffer: 0,
+                cluster_light_texture: 0, environment_baker: None, environment: None,
+                skybox_program: 0, skybox: None, queue: render_queue::RenderQueue::new(),
+                instance_buffer: 0, instance_texture: 0, joint_buffer: 0, joint_texture: 0 };
 
         // Begin unsafe OpenGL shenanigans. Here, we compile and link the shaders, set up the VAO
         // and VBO, and set some texture parameters.
         unsafe {
-            let mut vpath = path::PathBuf::from(SHADER_DIR);
-            vpath.push(VERTEX_SHADER_NAME);
-            let mut fpath = path::PathBuf::from(SHADER_DIR);
-            fpath.push(FRAGMENT_SHADER_NAME);
-            let vs = shader::compile_shader(vpath.to_str().unwrap(), gl::VERTEX_SHADER);
-            let fs = shader::compile_shader(fpath.to_str().unwrap(), gl::FRAGMENT_SHADER);
-            window.program = shader::link_program(vs, fs);
+            window.program = GameWindow::load_program(VERTEX_SHADER_NAME, FRAGMENT_SHADER_NAME);
+            window.shadow_program = GameWindow::load_program(
+                    SHADOW_VERTEX_SHADER_NAME, SHADOW_FRAGMENT_SHADER_NAME);
+            window.point_shadow_program = GameWindow::load_program(
+                    POINT_SHADOW_VERTEX_SHADER_NAME, POINT_SHADOW_FRAGMENT_SHADER_NAME);
             gl::GenVertexArrays(1, &mut window.working_vao);
-            window.initialize_vbo(0);
+            window.initialize_vbo(vertex::VertexLayout::standard(), 0);
             window.initialize_ebo(0);
             gl::Enable(gl::DEPTH_TEST);
             gl::UseProgram(window.program);
             gl::BindFragDataLocation(window.program, 0, gl_str!("out_color"));
             window.set_gamma(DEFAULT_GAMMA);
+            window.initialize_light_buffer();
+            for i in 0..MAX_SHADOW_MAPS {
+                uniform_int!(window.program, format!("shadow_maps[{}]", i),
+                        (SHADOW_TEXTURE_UNIT + i) as GLint);
+            }
+            for i in 0..MAX_CUBE_SHADOW_MAPS {
+                uniform_int!(window.program, format!("cube_shadow_maps[{}]", i),
+                        (CUBE_SHADOW_TEXTURE_UNIT + i) as GLint);
+            }
+            window.initialize_cluster_buffers();
+            window.initialize_instance_buffer();
+            window.initialize_joint_buffer();
+            uniform_int!(window.program, "diffuse_map", DIFFUSE_TEXTURE_UNIT as GLint);
+            uniform_int!(window.program, "specular_map", SPECULAR_TEXTURE_UNIT as GLint);
+            uniform_int!(window.program, "metallic_roughness_map", SPECULAR_TEXTURE_UNIT as GLint);
+            uniform_int!(window.program, "normal_map", NORMAL_TEXTURE_UNIT as GLint);
+            uniform_int!(window.program, "occlusion_map", OCCLUSION_TEXTURE_UNIT as GLint);
+            uniform_int!(window.program, "emissive_map", EMISSIVE_TEXTURE_UNIT as GLint);
+            uniform_int!(window.program, "irradiance_map", IRRADIANCE_TEXTURE_UNIT as GLint);
+            uniform_int!(window.program, "prefiltered_map", PREFILTERED_TEXTURE_UNIT as GLint);
+            uniform_int!(window.program, "brdf_lut", BRDF_LUT_TEXTURE_UNIT as GLint);
+            uniform_int!(window.program, "use_environment", 0);
+            gl::Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS);
 
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as GLint);
             gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as GLint);
@@ -140,6 +294,184 @@ impl GameWindow {
         Ok(window)
     }
 
+    // Initializes the light uniform buffer with every slot empty and binds it to the LightBlock
+    // uniform block of the program.
+    fn initialize_light_buffer(&mut self) { unsafe {
+        let light_size = mem::size_of::<light::PackedLight>();
+        let mut empty: Vec<u8> = vec![0; LIGHT_BLOCK_HEADER_SIZE + MAX_LIGHTS * light_size];
+        gl::GenBuffers(1, &mut self.light_buffer);
+        gl::BindBuffer(gl::UNIFORM_BUFFER, self.light_buffer);
+        gl::BufferData(gl::UNIFORM_BUFFER, empty.len() as GLsizeiptr,
+                empty.as_mut_ptr() as CVoid, gl::DYNAMIC_DRAW);
+        for i in 0..MAX_LIGHTS {
+            self.upload_light(i, &light::PackedLight::empty());
+        }
+        let block = gl::GetUniformBlockIndex(self.program, gl_str!("LightBlock"));
+        gl::UniformBlockBinding(self.program, block, LIGHT_BLOCK_BINDING);
+        gl::BindBufferBase(gl::UNIFORM_BUFFER, LIGHT_BLOCK_BINDING, self.light_buffer);
+    }}
+
+    // Helper method that writes a packed light into its slot in the light uniform buffer.
+    fn upload_light(&self, light_index: usize, packed: &light::PackedLight) { unsafe {
+        let light_size = mem::size_of::<light::PackedLight>();
+        gl::BindBuffer(gl::UNIFORM_BUFFER, self.light_buffer);
+        gl::BufferSubData(gl::UNIFORM_BUFFER,
+                (LIGHT_BLOCK_HEADER_SIZE + light_index * light_size) as GLintptr,
+                light_size as GLsizeiptr, packed as *const light::PackedLight as CVoid);
+    }}
+
+    // Helper method that reserves the lowest free slot in the light uniform buffer and grows the
+    // active light count to include it. Returns Err if every slot is taken.
+    fn allocate_light_index(&mut self) -> Result<usize, String> {
+        let index = try!(self.light_indices.pop().ok_or(
+                format!("Exceeded the maximum number of {} lights.", MAX_LIGHTS)));
+        if index >= self.light_count {
+            self.light_count = index + 1;
+            self.upload_light_count();
+        }
+        Ok(index)
+    }
+
+    // Helper method that empties a slot in the light uniform buffer and shrinks the active light
+    // count past any trailing empty slots so the shader loops over as few lights as possible.
+    fn free_light_index(&mut self, index: usize) {
+        self.upload_light(index, &light::PackedLight::empty());
+        self.light_indices.push(index);
+        self.light_indices.sort_by(|a, b| b.cmp(a));
+        while self.light_count > 0 && self.light_indices.contains(&(self.light_count - 1)) {
+            self.light_count -= 1;
+        }
+        self.upload_light_count();
+    }
+
+    // Helper method that writes the active light count into the header of the light buffer.
+    fn upload_light_count(&self) { unsafe {
+        let count = self.light_count as GLint;
+        gl::BindBuffer(gl::UNIFORM_BUFFER, self.light_buffer);
+        gl::BufferSubData(gl::UNIFORM_BUFFER, 0, mem::size_of::<GLint>() as GLsizeiptr,
+                &count as *const GLint as CVoid);
+    }}
+
+    // Initializes the buffer textures that hold the clustered light lists and binds them to their
+    // texture units. Clustering starts out disabled until update_light_clusters() is called.
+    fn initialize_cluster_buffers(&mut self) { unsafe {
+        gl::GenBuffers(1, &mut self.cluster_buffer);
+        gl::GenTextures(1, &mut self.cluster_texture);
+        gl::GenBuffers(1, &mut self.cluster_light_buffer);
+        gl::GenTextures(1, &mut self.cluster_light_texture);
+        let empty: Vec<GLuint> = vec![0, 0];
+        let textures = [(self.cluster_buffer, self.cluster_texture, CLUSTER_TEXTURE_UNIT,
+                gl::RG32UI), (self.cluster_light_buffer, self.cluster_light_texture,
+                CLUSTER_LIGHT_TEXTURE_UNIT, gl::R32UI)];
+        for &(buffer, texture, unit, format) in textures.iter() {
+            gl::BindBuffer(gl::TEXTURE_BUFFER, buffer);
+            gl::BufferData(gl::TEXTURE_BUFFER, uint_size!(empty.len(), GLsizeiptr),
+                    vec_to_addr!(empty), gl::STREAM_DRAW);
+            gl::ActiveTexture(gl::TEXTURE0 + unit as GLuint);
+            gl::BindTexture(gl::TEXTURE_BUFFER, texture);
+            gl::TexBuffer(gl::TEXTURE_BUFFER, format, buffer);
+        }
+        gl::ActiveTexture(gl::TEXTURE0);
+        uniform_int!(self.program, "cluster_map", CLUSTER_TEXTURE_UNIT as GLint);
+        uniform_int!(self.program, "cluster_lights", CLUSTER_LIGHT_TEXTURE_UNIT as GLint);
+        uniform_int!(self.program, "use_clusters", 0);
+    }}
+
+    // Initializes the buffer texture that holds the matrices of instanced draws and binds it to its
+    // texture unit. Buffer textures are used instead of instanced vertex attributes since attribute
+    // divisors are not available in OpenGL 3.2.
+    fn initialize_instance_buffer(&mut self) { unsafe {
+        gl::GenBuffers(1, &mut self.instance_buffer);
+        gl::GenTextures(1, &mut self.instance_texture);
+        let empty: Vec<GLfloat> = vec![0.0; 4];
+        gl::BindBuffer(gl::TEXTURE_BUFFER, self.instance_buffer);
+        gl::BufferData(gl::TEXTURE_BUFFER, float_size!(empty.len(), GLsizeiptr),
+                vec_to_addr!(empty), gl::STREAM_DRAW);
+        gl::BindBuffer(gl::TEXTURE_BUFFER, 0);
+        gl::ActiveTexture(gl::TEXTURE0 + INSTANCE_TEXTURE_UNIT as GLuint);
+        gl::BindTexture(gl::TEXTURE_BUFFER, self.instance_texture);
+        gl::TexBuffer(gl::TEXTURE_BUFFER, gl::RGBA32F, self.instance_buffer);
+        gl::ActiveTexture(gl::TEXTURE0);
+        uniform_int!(self.program, "instance_data", INSTANCE_TEXTURE_UNIT as GLint);
+        uniform_int!(self.program, "use_instancing", 0);
+    }}
+
+    // Initializes the buffer texture that holds the skinning matrices of skinned draws and binds it
+    // to its texture unit. Skinning is supported by the main program and both shadow programs.
+    fn initialize_joint_buffer(&mut self) { unsafe {
+        gl::GenBuffers(1, &mut self.joint_buffer);
+        gl::GenTextures(1, &mut self.joint_texture);
+        let empty: Vec<GLfloat> = vec![0.0; 4];
+        gl::BindBuffer(gl::TEXTURE_BUFFER, self.joint_buffer);
+        gl::BufferData(gl::TEXTURE_BUFFER, float_size!(empty.len(), GLsizeiptr),
+                vec_to_addr!(empty), gl::STREAM_DRAW);
+        gl::BindBuffer(gl::TEXTURE_BUFFER, 0);
+        gl::ActiveTexture(gl::TEXTURE0 + JOINT_TEXTURE_UNIT as GLuint);
+        gl::BindTexture(gl::TEXTURE_BUFFER, self.joint_texture);
+        gl::TexBuffer(gl::TEXTURE_BUFFER, gl::RGBA32F, self.joint_buffer);
+        gl::ActiveTexture(gl::TEXTURE0);
+        for &program in [self.shadow_program, self.point_shadow_program, self.program].iter() {
+            gl::UseProgram(program);
+            uniform_int!(program, "joint_data", JOINT_TEXTURE_UNIT as GLint);
+            uniform_int!(program, "use_skinning", 0);
+            uniform_int!(program, "morph_data", MORPH_TEXTURE_UNIT as GLint);
+            uniform_int!(program, "morph_count", 0);
+        }
+    }}
+
+    // Uploads the skinning matrices of a draw to the joint buffer and enables skinning in the
+    // currently bound program. Skinning is disabled instead if there are no matrices.
+    fn upload_skinning(&self, program: GLuint, matrices: &[cgmath::Matrix4<GLfloat>]) { unsafe {
+        if matrices.is_empty() {
+            uniform_int!(program, "use_skinning", 0);
+            return;
+        }
+        let mut joints: Vec<GLfloat> = Vec::with_capacity(matrices.len() * 16);
+        for matrix in matrices.iter() {
+            for column in [matrix.x, matrix.y, matrix.z, matrix.w].iter() {
+                joints.extend([column.x, column.y, column.z, column.w].iter());
+            }
+        }
+        gl::BindBuffer(gl::TEXTURE_BUFFER, self.joint_buffer);
+        gl::BufferData(gl::TEXTURE_BUFFER, float_size!(joints.len(), GLsizeiptr),
+                vec_to_addr!(joints), gl::STREAM_DRAW);
+        gl::BindBuffer(gl::TEXTURE_BUFFER, 0);
+        uniform_int!(program, "use_skinning", 1);
+    }}
+
+    // Binds the morph buffer of a draw and uploads the active morph targets to the currently bound
+    // program, or disables morphing if there are none.
+    fn upload_morphs(&self, program: GLuint, info: &model::ModelInfo,
+            targets: &[(usize, GLfloat)]) { unsafe {
+        uniform_int!(program, "morph_count", targets.len() as GLint);
+        if targets.is_empty() { return; }
+        let buffer_info = info.buffer_info.get().unwrap();
+        gl::ActiveTexture(gl::TEXTURE0 + MORPH_TEXTURE_UNIT as GLuint);
+        gl::BindTexture(gl::TEXTURE_BUFFER, buffer_info.morph_texture);
+        gl::ActiveTexture(gl::TEXTURE0);
+        uniform_int!(program, "morph_vertex_count", info.get_vertex_count() as GLint);
+        uniform_int!(program, "morph_base_vertex", buffer_info.base_vertex as GLint);
+        for (i, &(target, weight)) in targets.iter().enumerate() {
+            uniform_int!(program, format!("morph_targets[{}]", i), target as GLint);
+            uniform_float!(program, format!("morph_weights[{}]", i), weight);
+        }
+    }}
+
+    // Compiles and links a program given the names of its vertex and fragment shaders in the
+    // shader directory. Every program is linked with the same vertex attribute locations so that
+    // they can share the managed VAOs.
+    fn load_program(vertex_name: &str, fragment_name: &str) -> GLuint {
+        let mut vpath = path::PathBuf::from(SHADER_DIR);
+        vpath.push(vertex_name);
+        let mut fpath = path::PathBuf::from(SHADER_DIR);
+        fpath.push(fragment_name);
+        let vs = shader::compile_shader(vpath.to_str().unwrap(), gl::VERTEX_SHADER);
+        let fs = shader::compile_shader(fpath.to_str().unwrap(), gl::FRAGMENT_SHADER);
+        let attributes: Vec<&str> =
+                vertex::VERTEX_ATTRIBUTES.iter().map(|a| a.get_name()).collect();
+        shader::link_program_with_attribs(vs, fs, &attributes)
+    }
+
     // A helper method for binding the VAO and VBO that sets/checks the previously bound buffer.
     fn bind_vao_checked(&mut self, vao: GLuint) { unsafe {
         if match self.bound_vao {
@@ -150,20 +482,42 @@ impl GameWindow {
         }
     } }
 
-    // Initializes a managed empty VBO of size BUFFER_SIZE and adds it to the vector of VBOs. This
-    // also adds an uninitialized row to the VAOs data structure. This takes a max argument to
-    // still not fail on creation even if we create a VBO for greater than BUFFER_SIZE elems.
-    fn initialize_vbo(&mut self, max: usize) { unsafe {
-        let buffer_size = cmp::max(max, BUFFER_SIZE);
+    // A helper method for binding the VAO of a model before drawing it. The current values of the
+    // attributes missing from the VAO's layout become undefined after drawing with a VAO that
+    // enables them, so they are reset to their defaults unless the last model drawn used the same
+    // VAO.
+    fn bind_model_vao(&mut self, vao: GLuint) { unsafe {
+        self.bind_vao_checked(vao);
+        if self.defaults_vao == Some(vao) { return; }
+        self.defaults_vao = Some(vao);
+        let vbo = match self.vaos.iter().position(|row| row.contains(&Some(vao))) {
+            Some(vbo) => vbo,
+            None => { return; },
+        };
+        let layout = &self.vbos[vbo].1;
+        for attribute in vertex::VERTEX_ATTRIBUTES.iter().filter(|a| !layout.contains(**a)) {
+            let default = attribute.get_default();
+            gl::VertexAttrib4f(attribute.get_location(), default[0], default[1], default[2],
+                    default[3]);
+        }
+    }}
+
+    // Initializes a managed empty VBO of size BUFFER_SIZE for vertices of a layout and adds it to
+    // the vector of VBOs. This also adds an uninitialized row to the VAOs data structure. This
+    // takes a max argument (in vertices) to still not fail on creation even if we create a VBO for
+    // greater than BUFFER_SIZE elems.
+    fn initialize_vbo(&mut self, layout: vertex::VertexLayout, max: usize) { unsafe {
+        let stride = layout.get_stride();
+        let buffer_size = cmp::max(max, BUFFER_SIZE / stride);
         let working_vao = self.working_vao.clone();
         self.bind_vao_checked(working_vao);
         let mut vbo = 0;
         gl::GenBuffers(1, &mut vbo);
         gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
         gl::BufferData(
-                gl::ARRAY_BUFFER, float_size!(buffer_size, GLsizeiptr),
+                gl::ARRAY_BUFFER, float_size!(buffer_size * stride, GLsizeiptr),
                 0 as CVoid, gl::STATIC_DRAW);
-        self.vbos.push((vbo, 0, buffer_size));
+        self.vbos.push((vbo, layout, allocator::RangeAllocator::new(buffer_size)));
         let size = if self.vaos.is_empty() { 0 } else { self.vaos[0].len() };
         self.vaos.push(vec![None; size]);
     }}
@@ -181,14 +535,15 @@ impl GameWindow {
         gl::BufferData(
                 gl::ELEMENT_ARRAY_BUFFER, uint_size!(buffer_size, GLsizeiptr),
                 0 as CVoid, gl::STATIC_DRAW);
-        self.ebos.push((ebo, 0, buffer_size));
+        self.ebos.push((ebo, allocator::RangeAllocator::new(buffer_size)));
         for vao_vec in self.vaos.iter_mut() {
             vao_vec.push(None);
         }
     }}
 
     // Initializes a VAO if there is not already an existing one for the EBO/VBO combination and
-    // returns the corresponding VAO ID.
+    // returns the corresponding VAO ID. Attributes missing from the VBO's layout are left disabled
+    // so that they take on their default values.
     fn initialize_vao(&mut self, vbo: usize, ebo: usize) -> GLuint { unsafe {
         match self.vaos[vbo as usize][ebo as usize] {
             Some(id) => id,
@@ -199,40 +554,17 @@ impl GameWindow {
                 self.vaos[vbo][ebo] = Some(vao);
                 gl::BindBuffer(gl::ARRAY_BUFFER, self.vbos[vbo].0);
                 gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ebos[ebo].0);
-                let pos_attr = gl::GetAttribLocation(self.program, gl_str!("position"));
-                gl::EnableVertexAttribArray(pos_attr as GLuint);
-                gl::VertexAttribPointer(
-                        pos_attr as GLuint, VERTEX_POS_SIZE as i32, gl::FLOAT,
-                        gl::FALSE as GLboolean, float_size!(VERTEX_SIZE, GLsizei), ptr::null());
-                let normal_attr = gl::GetAttribLocation(self.program, gl_str!("normal"));
-                gl::EnableVertexAttribArray(normal_attr as GLuint);
-                gl::VertexAttribPointer(
-                        normal_attr as GLuint, VERTEX_NORMAL_SIZE as i32, gl::FLOAT,
-                        gl::FALSE as GLboolean, float_size!(VERTEX_SIZE, GLsizei),
-                        float_size!(VERTEX_POS_SIZE, CVoid));
-
-                let tangent_attr = gl::GetAttribLocation(self.program, gl_str!("tangent"));
-                gl::EnableVertexAttribArray(tangent_attr as GLuint);
-                gl::VertexAttribPointer(
-                        tangent_attr as GLuint, VERTEX_TANGENT_SIZE as i32, gl::FLOAT,
-                        gl::FALSE as GLboolean, float_size!(VERTEX_SIZE, GLsizei),
-                        float_size!(VERTEX_POS_SIZE + VERTEX_NORMAL_SIZE, CVoid));
-
-                let bitangent_attr = gl::GetAttribLocation(self.program, gl_str!("bitangent"));
-                gl::EnableVertexAttribArray(bitangent_attr as GLuint);
-                gl::VertexAttribPointer(
-                        bitangent_attr as GLuint, VERTEX_BITANGENT_SIZE as i32, gl::FLOAT,
-                        gl::FALSE as GLboolean, float_size!(VERTEX_SIZE, GLsizei),
-                        float_size!(VERTEX_POS_SIZE + VERTEX_NORMAL_SIZE + VERTEX_TANGENT_SIZE,
-                        CVoid));
-
-                let tcoord_attr = gl::GetAttribLocation(self.program, gl_str!("tcoord"));
-                gl::EnableVertexAttribArray(tcoord_attr as GLuint);
-                gl::VertexAttribPointer(
-                        tcoord_attr as GLuint, VERTEX_TCOORD_SIZE as i32, gl::FLOAT,
-                        gl::FALSE as GLboolean, float_size!(VERTEX_SIZE, GLsizei),
-                        float_size!(VERTEX_POS_SIZE + VERTEX_NORMAL_SIZE + VERTEX_TANGENT_SIZE
-                        + VERTEX_BITANGENT_SIZE, CVoid));
+                let layout = &self.vbos[vbo].1;
+                let stride = layout.get_stride();
+                for attribute in layout.get_attributes().iter() {
+                    let location = attribute.get_location();
+                    let offset = layout.get_offset(*attribute).unwrap();
+                    gl::EnableVertexAttribArray(location);
+                    gl::VertexAttribPointer(
+                            location, attribute.get_size() as i32, gl::FLOAT,
+                            gl::FALSE as GLboolean, float_size!(stride, GLsizei),
+                            float_size!(offset, CVoid));
+                }
                 vao
             },
         }
@@ -244,78 +576,104 @@ impl GameWindow {
         uniform_float!(self.program, "gamma", gamma);
     }}
 
-    // Adds a Camera to the engine and returns an integer handle to that camera that can be used
-    // with get_camera() and detach_camera().
-    pub fn attach_camera(&mut self, camera: camera::PerspectiveCamera) -> usize {
-        let mut index = None;
-        for (i, elem) in self.cameras.iter().enumerate() {
-            match elem {
-                &None => { index = Some(i); },
-                &Some(_) => (),
+    // Adds a Camera to the engine and returns a handle to that camera that can be used with
+    // get_camera() and detach_camera(). Any Camera implementor (such as a PerspectiveCamera or an
+    // OrthographicCamera) can be attached.
+    pub fn attach_camera<T: Camera + 'static>(&mut self, camera: T) -> CameraHandle {
+        self.attach_boxed_camera(Box::new(camera))
+    }
+
+    // Adds a Camera trait object to the engine and returns its handle like attach_camera().
+    pub fn attach_boxed_camera(&mut self, camera: Box<Camera>) -> CameraHandle {
+        let handle = self.cameras.insert(camera);
+        self.update_camera(handle).unwrap();
+        handle
+    }
+
+    // Updates the world matrices of a SceneGraph and moves every camera and light attached to a
+    // node that changed. The attached cameras and lights are then updated as if update_camera() or
+    // the matching light update had been called. Returns Err if an attachment refers to a camera
+    // or light that has been removed.
+    pub fn update_scene(&mut self, scene: &mut scene::SceneGraph) -> Result<(), String> {
+        for handle in scene.update() {
+            let attachments = try!(scene.get_node(handle)).attachments.clone();
+            for attachment in attachments.iter() {
+                let (pos, direction) = try!(scene.get_attachment_transform(handle, attachment));
+                let normalized = if direction.length2() > 0.0 { direction.normalize() }
+                        else { direction };
+                match attachment.kind {
+                    scene::AttachmentKind::Camera(c) => {
+                        {
+                            let camera = try!(self.get_camera_mut(c));
+                            camera.set_pos(pos);
+                            camera.set_target(pos + direction);
+                        }
+                        try!(self.update_camera(c));
+                    },
+                    scene::AttachmentKind::PointLight(l) => {
+                        try!(self.get_point_light_mut(l)).position = pos;
+                        try!(self.update_point_light(l));
+                    },
+                    scene::AttachmentKind::DirectionalLight(l) => {
+                        try!(self.get_directional_light_mut(l)).direction = normalized;
+                        try!(self.update_directional_light(l));
+                    },
+                    scene::AttachmentKind::SpotLight(l) => {
+                        {
+                            let light = try!(self.get_spot_light_mut(l));
+                            light.position = pos;
+                            light.direction = normalized;
+                        }
+                        try!(self.update_spot_light(l));
+                    },
+                }
             }
         }
-        let handle = match index {
-            None => {
-                self.cameras.push(Some(camera));
-                self.cameras.len() - 1
-            }
-            Some(i) => {
-                self.cameras[i] = Some(camera);
-                i
-            }
-        };
-        self.update_camera(handle);
-        handle
+        Ok(())
     }
 
     // Updates the camera view matrix and the view uniform on the GPU. This must be called after
-    // any sequence of struct field changes for the changes to appear in-world.
-    pub fn update_camera(&mut self, handle: usize) {
+    // any sequence of struct field changes for the changes to appear in-world. Returns Err if the
+    // handle is stale.
+    pub fn update_camera(&mut self, handle: CameraHandle) -> Result<(), String> {
         let program = self.program.clone();
-        let camera = self.get_camera_mut(handle).unwrap();
-        camera.view = cgmath::Matrix4::look_at(
-                cgmath::Point3::from_vec(camera.pos),
-                cgmath::Point3::from_vec(camera.target),
-                camera.up);
-        unsafe { uniform_vec3!(program, "camera", v3d_to_vec!(camera.pos)) };
+        let camera = try!(self.get_camera_mut(handle));
+        camera.update_view();
+        unsafe { uniform_vec3!(program, "camera", v3d_to_vec!(camera.get_pos())) };
+        Ok(())
     }
 
-    // Helper method that updates the active camera by calling update_camera().
+    // Helper method that updates the active camera by calling update_camera(). The active camera
+    // is cleared when it is detached, so its handle is never stale.
     pub fn update_active_camera(&mut self) {
         let active_camera = match self.active_camera {
             None => { return; },
-            Some(i) => i };
-        self.update_camera(active_camera);
+            Some(c) => c };
+        self.update_camera(active_camera).unwrap();
     }
 
-    // Removes a camera from the engine and returns a Result if it was successful.
-    pub fn detach_camera(&mut self, handle: usize) -> Result<(), String> {
-        if handle >= self.cameras.len() { return Err("Out of range.".to_string()); }
-        match self.active_camera {
-            None => (),
-            Some(c) => if handle == c { self.active_camera = None; },
-        }
-        self.cameras[handle] = None;
-        Ok(())
+    // Removes a camera from the engine and returns it. If it was the active camera, there is no
+    // active camera afterwards. Returns Err if the handle is stale.
+    pub fn detach_camera(&mut self, handle: CameraHandle) -> Result<Box<Camera>, String> {
+        let camera = try!(self.cameras.remove(handle));
+        if self.active_camera == Some(handle) { self.active_camera = None; }
+        Ok(camera)
     }
 
-    // Takes in a handle and returns a mutable reference to the corresponding camera if it is
-    // within range. Otherwise, return an Err.
-    pub fn get_camera_mut(&mut self, handle: usize)
-            -> Result<&mut camera::PerspectiveCamera, String> {
-        if handle >= self.cameras.len() { return Err("Out of range.".to_string()); }
-        Ok(self.cameras[handle].as_mut().unwrap())
+    // Takes in a handle and returns a mutable reference to the corresponding camera. Returns Err
+    // if the handle is stale.
+    pub fn get_camera_mut(&mut self, handle: CameraHandle) -> Result<&mut Camera, String> {
+        Ok(&mut **try!(self.cameras.get_mut(handle)))
     }
 
-    // Takes in a handle and returns an immutable reference to the corresponding camera if it is
-    // within range. Otherwise, return an Err.
-    pub fn get_camera(&self, handle: usize) -> Result<&camera::PerspectiveCamera, String> {
-        if handle >= self.cameras.len() { return Err("Out of range.".to_string()); }
-        Ok(self.cameras[handle].as_ref().unwrap())
+    // Takes in a handle and returns an immutable reference to the corresponding camera. Returns
+    // Err if the handle is stale.
+    pub fn get_camera(&self, handle: CameraHandle) -> Result<&Camera, String> {
+        Ok(&**try!(self.cameras.get(handle)))
     }
 
     // Gets a mutable reference to the active camera. Returns Err if no current active camera.
-    pub fn get_active_camera_mut(&mut self) -> Result<&mut camera::PerspectiveCamera, String> {
+    pub fn get_active_camera_mut(&mut self) -> Result<&mut Camera, String> {
         match self.active_camera {
             None => Err("No currently active camera.".to_string()),
             Some(c) => self.get_camera_mut(c)
@@ -323,173 +681,458 @@ impl GameWindow {
     }
 
     // Gets an immutable reference to the active camera. Returns Err if no current active camera.
-    pub fn get_active_camera(&self) -> Result<&camera::PerspectiveCamera, String> {
+    pub fn get_active_camera(&self) -> Result<&Camera, String> {
         match self.active_camera {
             None => Err("No currently active camera.".to_string()),
             Some(c) => self.get_camera(c)
         }
     }
 
-    // Sets the active camera used for rendering given a handle.
-    pub fn set_active_camera(&mut self, handle: usize) -> Result<(), String> {
-        if handle >= self.cameras.len() { return Err("Out of range.".to_string()); }
+    // Sets the active camera used for rendering given a handle. Returns Err if the handle is
+    // stale.
+    pub fn set_active_camera(&mut self, handle: CameraHandle) -> Result<(), String> {
+        try!(self.cameras.get(handle));
         self.active_camera = Some(handle);
         Ok(())
     }
 
     // Attaches and transfers ownership of a point light to the window. This then returns a handle
-    // (internally representing the index in the array) that can be used with the getter to modify
-    // light attrs.
-    pub fn attach_point_light(&mut self, mut light: light::PointLight) -> usize {
-        light.light_index = self.light_indices.pop();
-        let vec_index = GameWindow::add_light(light, &mut self.point_lights);
-        self.update_point_light(vec_index);
-        vec_index
-    }
-
-    // Updates the uniforms for a point light. This must be called after any sequence of struct
-    // field changes for the changes to appear in-world.
-    pub fn update_point_light(&self, index: usize) { unsafe {
-        let light = self.get_point_light(index);
-        let li = light.light_index.unwrap();
-        uniform_uint!(self.program, lights![li, "type"], 1);
-        let color = vec![light.intensity.r, light.intensity.g, light.intensity.b];
-        uniform_vec3!(self.program, lights![li, "intensity"], color);
-        uniform_vec3!(self.program, lights![li, "position"], v3d_to_vec!(light.position));
-        uniform_float!(self.program, lights![li, "const_attn"], light.const_attn);
-        uniform_float!(self.program, lights![li, "linear_attn"], light.linear_attn);
-        uniform_float!(self.program, lights![li, "quad_attn"], light.quad_attn);
-    }}
+    // that can be used with the getter to modify light attrs. Returns Err if the window already
+    // has the maximum number of lights.
+    pub fn attach_point_light(&mut self, mut light: light::PointLight) ->
+            Result<PointLightHandle, String> {
+        light.light_index = Some(try!(self.allocate_light_index()));
+        let handle = self.point_lights.insert(light);
+        try!(self.update_point_light(handle));
+        Ok(handle)
+    }
+
+    // Updates the uniforms and shadow cube map for a point light. This must be called after any
+    // sequence of struct field changes for the changes to appear in-world. The faces of the shadow
+    // cube map are only re-rendered if the light moved or its shadow radius or resolution changed.
+    // Returns Err if the handle is stale.
+    pub fn update_point_light(&mut self, handle: PointLightHandle) -> Result<(), String> {
+        let shadow_index = {
+            let light = try!(self.point_lights.get(handle));
+            let (casts, resolution, current) =
+                    (light.casts_shadows, light.shadow_resolution, light.shadow_index);
+            GameWindow::update_shadow_target(&mut self.cube_shadow_maps,
+                    &mut self.cube_shadow_indices, casts, resolution, current)
+        };
+        try!(self.point_lights.get_mut(handle)).shadow_index = shadow_index;
+        if let Some(si) = shadow_index {
+            let (position, radius) = {
+                let light = try!(self.point_lights.get(handle));
+                (light.position, light.shadow_radius)
+            };
+            let shadow_map = self.cube_shadow_maps[si].as_mut().unwrap();
+            if shadow_map.position != position || shadow_map.radius != radius {
+                shadow_map.position = position;
+                shadow_map.radius = radius;
+                shadow_map.dirty = true;
+            }
+        }
+        let light = try!(self.point_lights.get(handle));
+        self.upload_light(light.light_index.unwrap(), &light.get_packed());
+        Ok(())
+    }
 
     // Removes a PointLight from the scene given its handle and returns it to transfer ownership.
-    pub fn remove_point_light(&mut self, index: usize) -> light::PointLight {
-        self.point_lights.push(None);
-        let light = self.point_lights.swap_remove(index).unwrap();
-        let free_index = light.light_index.unwrap();
-        unsafe { uniform_uint!(self.program, lights![free_index, "type"], 0); };
-        self.light_indices.push(free_index);
-        light
+    // Returns Err if the handle is stale.
+    pub fn remove_point_light(&mut self, handle: PointLightHandle) ->
+            Result<light::PointLight, String> {
+        let mut light = try!(self.point_lights.remove(handle));
+        if let Some(si) = light.shadow_index.take() {
+            GameWindow::free_shadow_target(&mut self.cube_shadow_maps,
+                    &mut self.cube_shadow_indices, si);
+        }
+        let free_index = light.light_index.take().unwrap();
+        self.free_light_index(free_index);
+        Ok(light)
     }
 
-    // Gets a mutable reference to a PointLight given its handle.
-    pub fn get_point_light_mut(&mut self, index: usize) -> &mut light::PointLight {
-        (&mut self.point_lights[index]).as_mut().unwrap()
+    // Gets a mutable reference to a PointLight given its handle. Returns Err if the handle is
+    // stale.
+    pub fn get_point_light_mut(&mut self, handle: PointLightHandle) ->
+            Result<&mut light::PointLight, String> {
+        self.point_lights.get_mut(handle)
     }
 
-    // Gets an immutable reference to a PointLight given its handle.
-    pub fn get_point_light(&self, index: usize) -> &light::PointLight {
-        (&self.point_lights[index]).as_ref().unwrap()
+    // Gets an immutable reference to a PointLight given its handle. Returns Err if the handle is
+    // stale.
+    pub fn get_point_light(&self, handle: PointLightHandle) -> Result<&light::PointLight, String> {
+        self.point_lights.get(handle)
     }
 
     // Attaches and transfers ownership of a directional light to the window. This then returns a
-    // handle (internally representing the index in the array) that can be used with the getter to
-    // modify light attrs.
-    pub fn attach_directional_light(&mut self, mut light: light::DirectionalLight) -> usize {
-        light.light_index = self.light_indices.pop();
-        let vec_index = GameWindow::add_light(light, &mut self.directional_lights);
-        self.update_directional_light(vec_index);
-        vec_index
-    }
-
-    // Updates the uniforms for a directional light. This must be called after any sequence of
-    // struct field changes for the changes to appear in-world.
-    pub fn update_directional_light(&self, index: usize) { unsafe {
-        let light = self.get_directional_light(index);
-        let li = light.light_index.unwrap();
-        uniform_uint!(self.program, lights![li, "type"], 2);
-        let color = vec![light.intensity.r, light.intensity.g, light.intensity.b];
-        uniform_vec3!(self.program, lights![li, "intensity"], color);
-        uniform_vec3!(self.program, lights![li, "direction"], v3d_to_vec!(light.direction));
-    }}
+    // handle that can be used with the getter to modify light attrs. Returns Err if the window
+    // already has the maximum number of lights.
+    pub fn attach_directional_light(&mut self, mut light: light::DirectionalLight) ->
+            Result<DirectionalLightHandle, String> {
+        light.light_index = Some(try!(self.allocate_light_index()));
+        let handle = self.directional_lights.insert(light);
+        try!(self.update_directional_light(handle));
+        Ok(handle)
+    }
+
+    // Updates the uniforms and shadow map for a directional light. This must be called after any
+    // sequence of struct field changes for the changes to appear in-world. Returns Err if the
+    // handle is stale.
+    pub fn update_directional_light(&mut self, handle: DirectionalLightHandle) ->
+            Result<(), String> {
+        let shadow_index = {
+            let light = try!(self.directional_lights.get(handle));
+            let (casts, resolution, current) =
+                    (light.casts_shadows, light.shadow_resolution, light.shadow_index);
+            GameWindow::update_shadow_target(&mut self.shadow_maps, &mut self.shadow_indices,
+                    casts, resolution, current)
+        };
+        try!(self.directional_lights.get_mut(handle)).shadow_index = shadow_index;
+        let light = try!(self.directional_lights.get(handle));
+        self.upload_light(light.light_index.unwrap(), &light.get_packed());
+        Ok(())
+    }
 
     // Removes a DirectionalLight from the scene given its handle and returns it to transfer
-    // ownership.
-    pub fn remove_directional_light(&mut self, index: usize) -> light::DirectionalLight {
-        self.directional_lights.push(None);
-        let light = self.directional_lights.swap_remove(index).unwrap();
-        let free_index = light.light_index.unwrap();
-        unsafe { uniform_uint!(self.program, lights![free_index, "type"], 0); };
-        self.light_indices.push(free_index);
-        light
+    // ownership. Returns Err if the handle is stale.
+    pub fn remove_directional_light(&mut self, handle: DirectionalLightHandle) ->
+            Result<light::DirectionalLight, String> {
+        let mut light = try!(self.directional_lights.remove(handle));
+        if let Some(si) = light.shadow_index.take() {
+            GameWindow::free_shadow_target(&mut self.shadow_maps, &mut self.shadow_indices, si);
+        }
+        let free_index = light.light_index.take().unwrap();
+        self.free_light_index(free_index);
+        Ok(light)
     }
 
-    // Gets a reference to a DirectionalLight given its handle.
-    pub fn get_directional_light_mut(&mut self, index: usize) -> &mut light::DirectionalLight {
-        (&mut self.directional_lights[index]).as_mut().unwrap()
+    // Gets a reference to a DirectionalLight given its handle. Returns Err if the handle is stale.
+    pub fn get_directional_light_mut(&mut self, handle: DirectionalLightHandle) ->
+            Result<&mut light::DirectionalLight, String> {
+        self.directional_lights.get_mut(handle)
     }
 
-    // Gets an immutable reference to a DirectionalLight given its handle.
-    pub fn get_directional_light(&self, index: usize) -> &light::DirectionalLight {
-        (&self.directional_lights[index]).as_ref().unwrap()
+    // Gets an immutable reference to a DirectionalLight given its handle. Returns Err if the
+    // handle is stale.
+    pub fn get_directional_light(&self, handle: DirectionalLightHandle) ->
+            Result<&light::DirectionalLight, String> {
+        self.directional_lights.get(handle)
     }
 
     // Attaches and transfers ownership of a spot light to the window. This then returns a handle
-    // (internally representing the index in the array) that can be used with the getter to modify
-    // light attrs.
-    pub fn attach_spot_light(&mut self, mut light: light::SpotLight) -> usize {
-        light.light_index = self.light_indices.pop();
-        let vec_index = GameWindow::add_light(light, &mut self.spot_lights);
-        self.update_spot_light(vec_index);
-        vec_index
-    }
-
-    // Updates the uniforms for a spot light. This must be called after any sequence of struct
-    // field changes for the changes to appear in-world.
-    pub fn update_spot_light(&self, index: usize) { unsafe {
-        let light = self.get_spot_light(index);
-        let li = light.light_index.unwrap();
-        uniform_uint!(self.program, lights![li, "type"], 3);
-        let color = vec![light.intensity.r, light.intensity.g, light.intensity.b];
-        uniform_vec3!(self.program, lights![li, "intensity"], color);
-        uniform_vec3!(self.program, lights![li, "position"], v3d_to_vec!(light.position));
-        uniform_vec3!(self.program, lights![li, "direction"], v3d_to_vec!(light.direction));
-        uniform_float!(self.program, lights![li, "const_attn"], light.const_attn);
-        uniform_float!(self.program, lights![li, "linear_attn"], light.linear_attn);
-        uniform_float!(self.program, lights![li, "quad_attn"], light.quad_attn);
-        uniform_float!(self.program, lights![li, "cutoff"], light.cutoff);
-        uniform_float!(self.program, lights![li, "dropoff"], light.dropoff);
-    }}
+    // that can be used with the getter to modify light attrs. Returns Err if the window already
+    // has the maximum number of lights.
+    pub fn attach_spot_light(&mut self, mut light: light::SpotLight) ->
+            Result<SpotLightHandle, String> {
+        light.light_index = Some(try!(self.allocate_light_index()));
+        let handle = self.spot_lights.insert(light);
+        try!(self.update_spot_light(handle));
+        Ok(handle)
+    }
+
+    // Updates the uniforms and shadow map for a spot light. This must be called after any sequence
+    // of struct field changes for the changes to appear in-world. Returns Err if the handle is
+    // stale.
+    pub fn update_spot_light(&mut self, handle: SpotLightHandle) -> Result<(), String> {
+        let shadow_index = {
+            let light = try!(self.spot_lights.get(handle));
+            let (casts, resolution, current) =
+                    (light.casts_shadows, light.shadow_resolution, light.shadow_index);
+            GameWindow::update_shadow_target(&mut self.shadow_maps, &mut self.shadow_indices,
+                    casts, resolution, current)
+        };
+        try!(self.spot_lights.get_mut(handle)).shadow_index = shadow_index;
+        let light = try!(self.spot_lights.get(handle));
+        self.upload_light(light.light_index.unwrap(), &light.get_packed());
+        Ok(())
+    }
 
     // Removes a SpotLight from the scene given its handle and returns it to transfer ownership.
-    pub fn remove_spot_light(&mut self, index: usize) -> light::SpotLight {
-        self.spot_lights.push(None);
-        let light = self.spot_lights.swap_remove(index).unwrap();
-        let free_index = light.light_index.unwrap();
-        unsafe { uniform_uint!(self.program, lights![free_index, "type"], 0); };
-        self.light_indices.push(free_index);
-        light
-    }
-
-    // Gets a mutable reference to a SpotLight given its handle.
-    pub fn get_spot_light_mut(&mut self, index: usize) -> &mut light::SpotLight {
-        (&mut self.spot_lights[index]).as_mut().unwrap()
-    }
-
-    // Gets an immutable reference to a SpotLight given its handle.
-    pub fn get_spot_light(&self, index: usize) -> &light::SpotLight {
-        (&self.spot_lights[index]).as_ref().unwrap()
-    }
-
-    // Helper function that adds a light to a specified vector of lights. This keeps track of
-    // "holes" in the array and returns a handle to the first unused location in the array. If
-    // there are no holes, then it adds the light to the end and returns the corresponding handle.
-    fn add_light<T>(light: T, vector: &mut Vec<Option<T>>) -> usize {
-        let mut index = None;
-        for (i, elem) in vector.iter().enumerate() {
-            match elem {
-                &None => { index = Some(i); },
-                &Some(_) => (),
-            }
+    // Returns Err if the handle is stale.
+    pub fn remove_spot_light(&mut self, handle: SpotLightHandle) ->
+            Result<light::SpotLight, String> {
+        let mut light = try!(self.spot_lights.remove(handle));
+        if let Some(si) = light.shadow_index.take() {
+            GameWindow::free_shadow_target(&mut self.shadow_maps, &mut self.shadow_indices, si);
         }
-        match index {
-            None => {
-                vector.push(Some(light));
-                vector.len() - 1
-            }
-            Some(i) => {
-                vector[i] = Some(light);
-                i
+        let free_index = light.light_index.take().unwrap();
+        self.free_light_index(free_index);
+        Ok(light)
+    }
+
+    // Gets a mutable reference to a SpotLight given its handle. Returns Err if the handle is
+    // stale.
+    pub fn get_spot_light_mut(&mut self, handle: SpotLightHandle) ->
+            Result<&mut light::SpotLight, String> {
+        self.spot_lights.get_mut(handle)
+    }
+
+    // Gets an immutable reference to a SpotLight given its handle. Returns Err if the handle is
+    // stale.
+    pub fn get_spot_light(&self, handle: SpotLightHandle) -> Result<&light::SpotLight, String> {
+        self.spot_lights.get(handle)
+    }
+
+    // Bins every PointLight and SpotLight into the view frustum clusters of the active camera and
+    // uploads the per-cluster light lists so that each fragment only shades the lights that can
+    // reach it. DirectionalLights and lights without attenuation reach every fragment. This should
+    // be called once per frame after the camera and lights are updated. Returns Err and falls back
+    // to shading every light if there is no active camera or its projection is degenerate.
+    pub fn update_light_clusters(&mut self) -> Result<(), String> {
+        let result = self.bin_light_clusters();
+        if result.is_err() { unsafe { uniform_int!(self.program, "use_clusters", 0); } }
+        result
+    }
+
+    // Helper method that does the work of update_light_clusters().
+    fn bin_light_clusters(&mut self) -> Result<(), String> {
+        let (view, proj) = {
+            let camera = try!(self.get_active_camera());
+            (camera.get_view_matrix(), camera.get_projection_matrix())
+        };
+        try!(self.clusters.set_projection(proj));
+
+        let mut volumes = Vec::new();
+        for (_, l) in self.point_lights.iter() {
+            volumes.push(cluster::LightVolume::new(
+                    l.light_index.unwrap(), l.position, l.get_range()));
+        }
+        for (_, l) in self.directional_lights.iter() {
+            volumes.push(cluster::LightVolume::global(l.light_index.unwrap()));
+        }
+        for (_, l) in self.spot_lights.iter() {
+            let (center, radius) = l.get_bounding_sphere();
+            volumes.push(cluster::LightVolume::new(l.light_index.unwrap(), center, radius));
+        }
+        self.clusters.bin_lights(view, &volumes);
+
+        // The light list buffer holds the global lights followed by the lights of each cluster.
+        let mut offsets: Vec<GLuint> = Vec::with_capacity(self.clusters.offsets.len() * 2);
+        for &(offset, count) in self.clusters.offsets.iter() {
+            offsets.push(offset);
+            offsets.push(count);
+        }
+        let mut lights: Vec<GLuint> = self.clusters.global_indices.clone();
+        lights.extend(self.clusters.indices.iter());
+        if lights.is_empty() { lights.push(0); }
+        let (width, height) = self.get_size();
+        unsafe {
+            gl::BindBuffer(gl::TEXTURE_BUFFER, self.cluster_buffer);
+            gl::BufferData(gl::TEXTURE_BUFFER, uint_size!(offsets.len(), GLsizeiptr),
+                    vec_to_addr!(offsets), gl::STREAM_DRAW);
+            gl::BindBuffer(gl::TEXTURE_BUFFER, self.cluster_light_buffer);
+            gl::BufferData(gl::TEXTURE_BUFFER, uint_size!(lights.len(), GLsizeiptr),
+                    vec_to_addr!(lights), gl::STREAM_DRAW);
+            gl::BindBuffer(gl::TEXTURE_BUFFER, 0);
+
+            let grid: Vec<GLint> = vec![self.clusters.tiles_x as GLint,
+                    self.clusters.tiles_y as GLint, self.clusters.slices as GLint];
+            uniform_mat4!(self.program, "view", view);
+            uniform_ivec3!(self.program, "cluster_grid", grid);
+            uniform_float!(self.program, "cluster_near", self.clusters.near);
+            uniform_float!(self.program, "cluster_far", self.clusters.far);
+            uniform_vec2!(self.program, "cluster_viewport",
+                    vec![width as GLfloat, height as GLfloat]);
+            uniform_int!(self.program, "global_light_count",
+                    self.clusters.global_indices.len() as GLint);
+            uniform_int!(self.program, "use_clusters", 1);
+        }
+        Ok(())
+    }
+
+    // Sets the environment used for image based lighting from an equirectangular HDR image (such as
+    // one decoded with util::hdr). The irradiance and prefiltered specular maps are baked on the
+    // GPU, which can take a moment for large images. This replaces any previous environment.
+    pub fn set_environment(&mut self, image: &common::HDRImage) -> Result<(), String> {
+        let result = {
+            let baker = self.get_environment_baker();
+            baker.bake(image).and_then(|env| baker.get_brdf_lut().map(|lut| (env, lut)))
+        };
+        self.restore_render_state();
+        let (env, lut) = try!(result);
+        self.remove_environment();
+        unsafe {
+            gl::ActiveTexture(gl::TEXTURE0 + IRRADIANCE_TEXTURE_UNIT as GLuint);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, env.irradiance);
+            gl::ActiveTexture(gl::TEXTURE0 + PREFILTERED_TEXTURE_UNIT as GLuint);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, env.prefiltered);
+            gl::ActiveTexture(gl::TEXTURE0 + BRDF_LUT_TEXTURE_UNIT as GLuint);
+            gl::BindTexture(gl::TEXTURE_2D, lut);
+            gl::ActiveTexture(gl::TEXTURE0);
+            uniform_float!(self.program, "prefiltered_max_lod",
+                    (environment::PREFILTERED_LEVELS - 1) as GLfloat);
+            uniform_int!(self.program, "use_environment", 1);
+        }
+        self.environment = Some(env);
+        Ok(())
+    }
+
+    // Removes the environment used for image based lighting so that the constant ambient term is
+    // used instead.
+    pub fn remove_environment(&mut self) {
+        if let Some(mut env) = self.environment.take() {
+            env.delete();
+            unsafe { uniform_int!(self.program, "use_environment", 0); }
+        }
+    }
+
+    // Sets the skybox from six images given their paths in the order +X, -X, +Y, -Y, +Z, -Z
+    // following the OpenGL cube map conventions in world space. Every face must be a square of the
+    // same size. This replaces any previous skybox.
+    pub fn set_skybox(&mut self, face_names: [&str; 6]) -> Result<(), String> {
+        let mut faces = Vec::new();
+        for name in face_names.iter() {
+            faces.push(try!(image::decode_image(name)));
+        }
+        let size = faces[0].width;
+        if faces.iter().any(|face| face.width != size || face.height != size) {
+            return Err("Skybox faces must be squares of the same size.".to_string());
+        }
+        let mut cube_map = 0;
+        unsafe {
+            gl::GenTextures(1, &mut cube_map);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, cube_map);
+            for (i, face) in faces.iter().enumerate() {
+                let data = face.get_rgba_vec();
+                gl::TexImage2D(gl::TEXTURE_CUBE_MAP_POSITIVE_X + i as GLuint, 0,
+                        gl::SRGB_ALPHA as GLint, size as GLsizei, size as GLsizei, 0, gl::RGBA,
+                        gl::UNSIGNED_BYTE, vec_to_addr!(data));
             }
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+            gl::TexParameteri(
+                    gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexParameteri(
+                    gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+            gl::TexParameteri(
+                    gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as GLint);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+        }
+        self.replace_skybox(cube_map);
+        Ok(())
+    }
+
+    // Sets the skybox from an equirectangular image. LDR images can be converted with
+    // HDRImage::from_image(). This replaces any previous skybox.
+    pub fn set_skybox_from_equirect(&mut self, image: &common::HDRImage) -> Result<(), String> {
+        let result = self.get_environment_baker().equirect_to_cube_map(image, SKYBOX_RESOLUTION);
+        self.restore_render_state();
+        let cube_map = try!(result);
+        self.replace_skybox(cube_map);
+        Ok(())
+    }
+
+    // Removes the skybox so that only the background color is shown behind the scene.
+    pub fn remove_skybox(&mut self) {
+        if let Some(mut cube_map) = self.skybox.take() {
+            unsafe { gl::DeleteTextures(1, &mut cube_map); }
+        }
+    }
+
+    // Draws the skybox around the active camera. This should be called once per frame after every
+    // opaque instance and before any blended instance is drawn so that the skybox is only shaded
+    // where there is no geometry. draw_instances() takes care of this ordering. The
+    // skybox follows the rotation of the camera but not its translation.
+    pub fn draw_skybox(&mut self) {
+        let cube_map = match self.skybox { Some(c) => c, None => return };
+        let transform = match self.get_active_camera() {
+            Ok(camera) => {
+                let mut view = camera.get_view_matrix();
+                view.w = cgmath::Vector4::new(0.0, 0.0, 0.0, 1.0);
+                camera.get_projection_matrix() * view
+            },
+            Err(_) => return,
+        };
+        let (program, gamma) = (self.skybox_program, self.gamma);
+        unsafe {
+            gl::UseProgram(program);
+            uniform_mat4!(program, "transform", transform);
+            uniform_float!(program, "gamma", gamma);
+            uniform_int!(program, "skybox", 0);
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, cube_map);
+            gl::DepthFunc(gl::LEQUAL);
+            self.get_environment_baker().draw_cube();
+            gl::DepthFunc(gl::LESS);
+            gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+        }
+        self.restore_render_state();
+    }
+
+    // Helper method that replaces the skybox cube map, loading the skybox program and the cube
+    // geometry on first use.
+    fn replace_skybox(&mut self, cube_map: GLuint) {
+        self.remove_skybox();
+        self.get_environment_baker();
+        if self.skybox_program == 0 {
+            self.skybox_program = GameWindow::load_program(
+                    SKYBOX_VERTEX_SHADER_NAME, SKYBOX_FRAGMENT_SHADER_NAME);
+        }
+        self.skybox = Some(cube_map);
+    }
+
+    // Helper method that gets the EnvironmentBaker, loading its programs on first use.
+    fn get_environment_baker(&mut self) -> &mut environment::EnvironmentBaker {
+        if self.environment_baker.is_none() {
+            let equirect = GameWindow::load_program(
+                    CUBE_VERTEX_SHADER_NAME, EQUIRECT_FRAGMENT_SHADER_NAME);
+            let irradiance = GameWindow::load_program(
+                    CUBE_VERTEX_SHADER_NAME, IRRADIANCE_FRAGMENT_SHADER_NAME);
+            let prefilter = GameWindow::load_program(
+                    CUBE_VERTEX_SHADER_NAME, PREFILTER_FRAGMENT_SHADER_NAME);
+            let brdf = GameWindow::load_program(
+                    QUAD_VERTEX_SHADER_NAME, BRDF_FRAGMENT_SHADER_NAME);
+            self.environment_baker =
+                    Some(environment::EnvironmentBaker::new(equirect, irradiance, prefilter, brdf));
+        }
+        self.environment_baker.as_mut().unwrap()
+    }
+
+    // Helper method that restores the framebuffer, viewport, program, and VAO used for drawing
+    // instances after rendering to an offscreen target.
+    fn restore_render_state(&mut self) { unsafe {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        let (width, height) = self.get_size();
+        gl::Viewport(0, 0, width as GLsizei, height as GLsizei);
+        gl::UseProgram(self.program);
+        gl::ActiveTexture(gl::TEXTURE0);
+        self.bound_vao = None;
+        self.defaults_vao = None;
+    }}
+
+    // Helper function that allocates, resizes, or frees the shadow target of a light in a pool of
+    // targets given whether it should cast shadows, its shadow resolution, and its current index
+    // in the pool. This returns the new index. If every target in the pool is in use, the light
+    // will not cast shadows.
+    fn update_shadow_target<T: shadow::ShadowTarget>(targets: &mut Vec<Option<T>>,
+            free: &mut Vec<usize>, casts_shadows: bool, resolution: u32, current: Option<usize>)
+            -> Option<usize> {
+        match (casts_shadows, current) {
+            (true, Some(i)) => {
+                targets[i].as_mut().unwrap().resize(resolution);
+                Some(i)
+            },
+            (true, None) => {
+                let index = free.pop();
+                if let Some(i) = index {
+                    targets[i] = Some(T::create(resolution));
+                }
+                index
+            },
+            (false, Some(i)) => {
+                GameWindow::free_shadow_target(targets, free, i);
+                None
+            },
+            (false, None) => None,
+        }
+    }
+
+    // Helper function that deletes a shadow target and returns its index to the pool.
+    fn free_shadow_target<T: shadow::ShadowTarget>(targets: &mut Vec<Option<T>>,
+            free: &mut Vec<usize>, index: usize) {
+        if let Some(mut target) = targets[index].take() {
+            target.delete();
+            free.push(index);
         }
     }
 
@@ -528,67 +1171,351 @@ impl GameWindow {
     }
 
     // Maps/remaps a given Rc<ModelInfo> to VBO and EBO locations in the engine's managed buffers.
+    // Vertices are only placed in VBOs with the same layout as the ModelInfo. Space is taken from
+    // the first hole it fits in. If no hole is large enough but a buffer has enough free space in
+    // total, that buffer is defragmented first. Otherwise, a new buffer is created. The space of
+    // ModelInfos that have been dropped is reclaimed beforehand.
     pub fn map_vbo(&mut self, info: Rc<model::ModelInfo>) {
+        self.unmap_vbo(&info);
+        self.reclaim_vertex_buffers();
         let vertices = info.get_vbo_format();
-        // Find empty EBO space.
-        let ebo_index = {
-            let mut index = None;
-            for (i, ebo_pair) in self.ebos.iter().enumerate() {
-                if info.elements.len() < ebo_pair.2 - ebo_pair.1 {
-                    index = Some(i);
-                    break;
-                }
+        let vertex_count = info.get_vertex_count();
+        let (vbo_index, vertex_start) =
+                self.allocate_buffer_range(PoolKind::Vertex, &info.layout, vertex_count);
+        let (ebo_index, element_start) =
+                self.allocate_buffer_range(PoolKind::Element, &info.layout, info.elements.len());
+        let vao = self.initialize_vao(vbo_index, ebo_index);
+        let (morph_buffer, morph_texture) = self.initialize_morph_buffer(&info);
+
+        let buffer_info = model::BufferInfo { start: element_start, size: info.elements.len(),
+                base_vertex: vertex_start, gen: self.gen, vao: vao, morph_texture: morph_texture };
+        info.buffer_info.set(Some(buffer_info));
+        unsafe {
+            let working_vao = self.working_vao.clone();
+            self.bind_vao_checked(working_vao);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ebos[ebo_index].0);
+            gl::BufferSubData(
+                    gl::ELEMENT_ARRAY_BUFFER, uint_size!(element_start, GLintptr),
+                    uint_size!(info.elements.len(), GLsizeiptr), vec_to_addr!(info.elements));
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbos[vbo_index].0);
+            gl::BufferSubData(
+                    gl::ARRAY_BUFFER,
+                    float_size!(vertex_start * info.layout.get_stride(), GLintptr),
+                    float_size!(vertices.len(), GLsizeiptr), vec_to_addr!(vertices));
+        }
+        self.mapped.push(MappedModel { info: Rc::downgrade(&info), vbo: vbo_index,
+                vertex_start: vertex_start, vertex_count: vertex_count, ebo: ebo_index,
+                element_start: element_start, element_count: info.elements.len(),
+                morph_buffer: morph_buffer, morph_texture: morph_texture });
+    }
+
+    // Helper method that uploads the morph target deltas of a ModelInfo to a new buffer texture
+    // and returns the buffer and texture, or 0s if the ModelInfo does not have morph targets.
+    // Unlike vertices, the deltas are not pooled since only a few ModelInfos have morph targets.
+    fn initialize_morph_buffer(&mut self, info: &model::ModelInfo) -> (GLuint, GLuint) { unsafe {
+        if info.get_morph_targets().is_empty() { return (0, 0); }
+        let deltas = info.get_morph_format();
+        let mut buffer = 0;
+        let mut texture = 0;
+        gl::GenBuffers(1, &mut buffer);
+        gl::GenTextures(1, &mut texture);
+        gl::BindBuffer(gl::TEXTURE_BUFFER, buffer);
+        gl::BufferData(gl::TEXTURE_BUFFER, float_size!(deltas.len(), GLsizeiptr),
+                vec_to_addr!(deltas), gl::STATIC_DRAW);
+        gl::BindBuffer(gl::TEXTURE_BUFFER, 0);
+        gl::ActiveTexture(gl::TEXTURE0 + MORPH_TEXTURE_UNIT as GLuint);
+        gl::BindTexture(gl::TEXTURE_BUFFER, texture);
+        gl::TexBuffer(gl::TEXTURE_BUFFER, gl::RGBA32F, buffer);
+        gl::ActiveTexture(gl::TEXTURE0);
+        (buffer, texture)
+    }}
+
+    // Frees the VBO and EBO space of a ModelInfo so that it can be reused by other ModelInfos. The
+    // ModelInfo is mapped again the next time it is drawn. This does nothing if the ModelInfo is
+    // not mapped.
+    pub fn unmap_vbo(&mut self, info: &Rc<model::ModelInfo>) {
+        let target = &**info as *const model::ModelInfo;
+        let index = self.mapped.iter().position(|mapped| match mapped.info.upgrade() {
+            Some(i) => &*i as *const model::ModelInfo == target,
+            None => false,
+        });
+        if let Some(i) = index {
+            let mapped = self.mapped.swap_remove(i);
+            self.free_mapped_model(&mapped);
+        }
+        info.buffer_info.set(None);
+    }
+
+    // Reclaims the VBO and EBO space of every mapped ModelInfo that has since been dropped. This
+    // happens automatically whenever a ModelInfo is mapped.
+    pub fn reclaim_vertex_buffers(&mut self) {
+        let mapped = mem::replace(&mut self.mapped, Vec::new());
+        for model in mapped.into_iter() {
+            if model.info.upgrade().is_some() {
+                self.mapped.push(model);
+            } else {
+                self.free_mapped_model(&model);
+            }
+        }
+    }
+
+    // Reclaims the space of dropped ModelInfos and then compacts every VBO and EBO so that their
+    // free space forms a single hole at the back. The BufferInfo of every mapped ModelInfo is
+    // updated to its new location.
+    pub fn defragment_vertex_buffers(&mut self) {
+        self.reclaim_vertex_buffers();
+        for i in 0..self.vbos.len() {
+            self.defragment_pool(PoolKind::Vertex, i);
+        }
+        for i in 0..self.ebos.len() {
+            self.defragment_pool(PoolKind::Element, i);
+        }
+    }
+
+    // Helper method that returns the ranges of a mapped ModelInfo to their allocators and deletes
+    // its morph buffer.
+    fn free_mapped_model(&mut self, mapped: &MappedModel) {
+        if mapped.vertex_count > 0 {
+            self.vbos[mapped.vbo].2.free(mapped.vertex_start).unwrap();
+        }
+        if mapped.element_count > 0 {
+            self.ebos[mapped.ebo].1.free(mapped.element_start).unwrap();
+        }
+        GameWindow::delete_morph_buffer(mapped);
+    }
+
+    // Helper function that deletes the morph buffer and texture of a mapped ModelInfo if it has
+    // any.
+    fn delete_morph_buffer(mapped: &MappedModel) { unsafe {
+        if mapped.morph_buffer != 0 {
+            gl::DeleteTextures(1, &mapped.morph_texture);
+            gl::DeleteBuffers(1, &mapped.morph_buffer);
+        }
+    }}
+
+    // Helper method that allocates a range of a given size in a pool of managed buffers and
+    // returns the index of the buffer and the start of the range. VBOs must match the layout.
+    fn allocate_buffer_range(&mut self, kind: PoolKind, layout: &vertex::VertexLayout,
+            size: usize) -> (usize, usize) {
+        let count = if kind == PoolKind::Vertex { self.vbos.len() } else { self.ebos.len() };
+        let matching: Vec<usize> = (0..count).filter(|&i|
+                kind == PoolKind::Element || self.vbos[i].1 == *layout).collect();
+        for &i in matching.iter() {
+            if let Some(start) = self.get_pool_allocator(kind, i).allocate(size) {
+                return (i, start);
             }
-            match index {
-                None => {
-                    self.initialize_ebo(info.elements.len() + 1);
-                    self.ebos.len() - 1
-                },
-                Some(i) => i,
+        }
+        let mut fragmented = None;
+        for &i in matching.iter() {
+            if self.get_pool_allocator(kind, i).get_free() >= size {
+                fragmented = Some(i);
+                break;
             }
+        }
+        let index = match fragmented {
+            Some(i) => {
+                self.defragment_pool(kind, i);
+                i
+            },
+            None => {
+                match kind {
+                    PoolKind::Vertex => self.initialize_vbo(layout.clone(), size),
+                    PoolKind::Element => self.initialize_ebo(size),
+                };
+                count
+            },
         };
+        (index, self.get_pool_allocator(kind, index).allocate(size).unwrap())
+    }
 
-        // Find empty VBO space.
-        let vbo_index = {
-            let mut index = None;
-            for (i, vbo_pair) in self.vbos.iter().enumerate() {
-                if vertices.len() < vbo_pair.2 - vbo_pair.1 {
-                    index = Some(i);
-                    break;
-                }
+    // Helper method that gets the allocator of a managed buffer.
+    fn get_pool_allocator(&mut self, kind: PoolKind, index: usize)
+            -> &mut allocator::RangeAllocator {
+        match kind {
+            PoolKind::Vertex => &mut self.vbos[index].2,
+            PoolKind::Element => &mut self.ebos[index].1,
+        }
+    }
+
+    // Helper method that compacts a single managed buffer. The moved ranges are copied through a
+    // temporary buffer since a copy within a buffer cannot overlap itself.
+    fn defragment_pool(&mut self, kind: PoolKind, index: usize) {
+        let (buffer, moves) = match kind {
+            PoolKind::Vertex => (self.vbos[index].0, self.vbos[index].2.compact()),
+            PoolKind::Element => (self.ebos[index].0, self.ebos[index].1.compact()),
+        };
+        if moves.is_empty() { return; }
+        let unit = match kind {
+            PoolKind::Vertex => float_size!(self.vbos[index].1.get_stride(), usize),
+            PoolKind::Element => uint_size!(1, usize),
+        };
+        let largest = moves.iter().map(|m| m.size).max().unwrap();
+        unsafe {
+            let mut temp = 0;
+            gl::GenBuffers(1, &mut temp);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, temp);
+            gl::BufferData(gl::COPY_WRITE_BUFFER, (largest * unit) as GLsizeiptr, ptr::null(),
+                    gl::STREAM_COPY);
+            gl::BindBuffer(gl::COPY_READ_BUFFER, buffer);
+            for m in moves.iter() {
+                let size = (m.size * unit) as GLsizeiptr;
+                gl::CopyBufferSubData(gl::COPY_READ_BUFFER, gl::COPY_WRITE_BUFFER,
+                        (m.from * unit) as GLintptr, 0, size);
+                gl::CopyBufferSubData(gl::COPY_WRITE_BUFFER, gl::COPY_READ_BUFFER,
+                        0, (m.to * unit) as GLintptr, size);
             }
-            match index {
-                None => {
-                    self.initialize_vbo(vertices.len() + 1);
-                    self.vbos.len() - 1
-                },
-                Some(i) => i,
+            gl::BindBuffer(gl::COPY_READ_BUFFER, 0);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+            gl::DeleteBuffers(1, &mut temp);
+        }
+
+        // Point every moved ModelInfo at its new location.
+        let moved: HashMap<usize, usize> = moves.iter().map(|m| (m.from, m.to)).collect();
+        for mapped in self.mapped.iter_mut() {
+            let start = match kind {
+                PoolKind::Vertex if mapped.vbo == index => &mut mapped.vertex_start,
+                PoolKind::Element if mapped.ebo == index => &mut mapped.element_start,
+                _ => continue,
+            };
+            let to = match moved.get(start) { Some(&to) => to, None => continue };
+            *start = to;
+            if let Some(info) = mapped.info.upgrade() {
+                if let Some(mut buffer_info) = info.buffer_info.get() {
+                    match kind {
+                        PoolKind::Vertex => buffer_info.base_vertex = to,
+                        PoolKind::Element => buffer_info.start = to,
+                    };
+                    info.buffer_info.set(Some(buffer_info));
+                }
             }
-        };
+        }
+    }
 
-        let vao = self.initialize_vao(vbo_index, ebo_index);
-        let vbo_pair = self.vbos[vbo_index];
-        let ebo_pair = self.ebos[ebo_index];
+    // Helper method that maps a ModelInfo to the engine's VBO space if it has never been mapped or
+    // if its mapping is from an older generation.
+    fn map_vbo_if_needed(&mut self, info: &Rc<model::ModelInfo>) {
+        match info.buffer_info.get() {
+            None => { self.map_vbo(info.clone()); },
+            Some(i) => { if i.gen != self.gen { self.map_vbo(info.clone()) }; },
+        }
+    }
 
-        let mut elements: Vec<GLuint> = Vec::new();
-        for elem in &info.elements {
-            elements.push(elem.clone() + (vbo_pair.1 as GLuint / VERTEX_SIZE as GLuint));
+    // Renders the shadow maps of every shadow casting light with the given instances as shadow
+    // casters. This should be called once per frame before drawing the instances. DirectionalLight
+    // shadows are centered on the target of the active camera. PointLight shadow cube maps are
+    // cached and only re-rendered when their light changes or after invalidate_shadows().
+    pub fn render_shadows(&mut self, instances: &[&model::ModelInstance]) {
+        let center = match self.get_active_camera() {
+            Ok(camera) => camera.get_target(),
+            Err(_) => Vector3D::new(0.0, 0.0, 0.0),
+        };
+        let mut casters: Vec<(usize, cgmath::Matrix4<GLfloat>)> = Vec::new();
+        for (_, l) in self.directional_lights.iter() {
+            if let Some(si) = l.shadow_index {
+                casters.push((si, l.get_shadow_matrix(center)));
+            }
         }
-        let buffer_info = model::BufferInfo {
-                start: ebo_pair.1, size: elements.len(), gen: self.gen, vao: vao };
-        info.buffer_info.set(Some(buffer_info));
+        for (_, l) in self.spot_lights.iter() {
+            if let Some(si) = l.shadow_index {
+                casters.push((si, l.get_shadow_matrix()));
+            }
+        }
+        let mut cube_casters: Vec<(usize, Vec<cgmath::Matrix4<GLfloat>>)> = Vec::new();
+        for (_, l) in self.point_lights.iter() {
+            if let Some(si) = l.shadow_index {
+                if self.cube_shadow_maps[si].as_ref().unwrap().dirty {
+                    cube_casters.push((si, l.get_shadow_matrices()));
+                }
+            }
+        }
+        if casters.is_empty() && cube_casters.is_empty() { return; }
+        for instance in instances {
+            self.map_vbo_if_needed(&instance.info);
+        }
+
         unsafe {
-            let working_vao = self.working_vao.clone();
-            self.bind_vao_checked(working_vao);
-            gl::BufferSubData(
-                    gl::ELEMENT_ARRAY_BUFFER, uint_size!(ebo_pair.1, GLintptr),
-                    uint_size!(elements.len(), GLsizeiptr), vec_to_addr!(elements));
-            gl::BufferSubData(
-                    gl::ARRAY_BUFFER, float_size!(vbo_pair.1, GLintptr),
-                    float_size!(vertices.len(), GLsizeiptr), vec_to_addr!(vertices));
+            gl::Enable(gl::POLYGON_OFFSET_FILL);
+            gl::PolygonOffset(SHADOW_OFFSET_FACTOR, SHADOW_OFFSET_UNITS);
+            gl::UseProgram(self.shadow_program);
+            for &(si, matrix) in &casters {
+                let (fbo, resolution) = {
+                    let shadow_map = self.shadow_maps[si].as_mut().unwrap();
+                    shadow_map.matrix = matrix;
+                    (shadow_map.fbo, shadow_map.resolution)
+                };
+                gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+                gl::Viewport(0, 0, resolution as GLsizei, resolution as GLsizei);
+                gl::Clear(gl::DEPTH_BUFFER_BIT);
+                let program = self.shadow_program;
+                self.draw_shadow_casters(program, instances, matrix);
+            }
+
+            gl::UseProgram(self.point_shadow_program);
+            for &(si, ref matrices) in &cube_casters {
+                let (fbo, texture, resolution, position, radius) = {
+                    let shadow_map = self.cube_shadow_maps[si].as_mut().unwrap();
+                    shadow_map.dirty = false;
+                    (shadow_map.fbo, shadow_map.texture, shadow_map.resolution,
+                            shadow_map.position, shadow_map.radius)
+                };
+                uniform_vec3!(self.point_shadow_program, "light_position", v3d_to_vec!(position));
+                uniform_float!(self.point_shadow_program, "radius", radius);
+                gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+                gl::Viewport(0, 0, resolution as GLsizei, resolution as GLsizei);
+                for (face, matrix) in matrices.iter().enumerate() {
+                    gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT,
+                            gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as GLuint, texture, 0);
+                    gl::Clear(gl::DEPTH_BUFFER_BIT);
+                    let program = self.point_shadow_program;
+                    self.draw_shadow_casters(program, instances, *matrix);
+                }
+            }
+            gl::Disable(gl::POLYGON_OFFSET_FILL);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            let (width, height) = self.get_size();
+            gl::Viewport(0, 0, width as GLsizei, height as GLsizei);
+
+            // Upload the light space matrices and bind the depth textures for the main pass.
+            gl::UseProgram(self.program);
+            for &(si, matrix) in &casters {
+                let shadow_map = self.shadow_maps[si].as_ref().unwrap();
+                uniform_mat4!(self.program, format!("shadow_matrices[{}]", si), matrix);
+                uniform_float!(self.program, format!("shadow_texel_sizes[{}]", si),
+                        1.0 / shadow_map.resolution as GLfloat);
+                gl::ActiveTexture(gl::TEXTURE0 + (SHADOW_TEXTURE_UNIT + si) as GLuint);
+                gl::BindTexture(gl::TEXTURE_2D, shadow_map.texture);
+            }
+            for &(si, _) in &cube_casters {
+                let shadow_map = self.cube_shadow_maps[si].as_ref().unwrap();
+                gl::ActiveTexture(gl::TEXTURE0 + (CUBE_SHADOW_TEXTURE_UNIT + si) as GLuint);
+                gl::BindTexture(gl::TEXTURE_CUBE_MAP, shadow_map.texture);
+            }
+            gl::ActiveTexture(gl::TEXTURE0);
+        }
+    }
+
+    // Helper method that draws every shadow caster with the currently bound shadow program and
+    // framebuffer given the matrix that transforms world space into the light's clip space.
+    fn draw_shadow_casters(&mut self, program: GLuint, instances: &[&model::ModelInstance],
+            matrix: cgmath::Matrix4<GLfloat>) { unsafe {
+        for instance in instances {
+            let info = instance.info.buffer_info.get().unwrap();
+            self.bind_model_vao(info.vao);
+            self.upload_skinning(program, &instance.joint_matrices);
+            self.upload_morphs(program, &instance.info, &instance.get_active_morph_targets());
+            uniform_mat4!(program, "transform", matrix * instance.model);
+            uniform_mat4!(program, "model", instance.model);
+            gl::DrawElementsBaseVertex(gl::TRIANGLES, info.size as i32, gl::UNSIGNED_INT,
+                    uint_size!(info.start, CVoid), info.base_vertex as GLint);
+        }
+    }}
+
+    // Marks every PointLight shadow cube map as dirty so that it is re-rendered on the next call
+    // to render_shadows(). This should be called when shadow casting instances move.
+    pub fn invalidate_shadows(&mut self) {
+        for shadow_map in self.cube_shadow_maps.iter_mut() {
+            if let &mut Some(ref mut s) = shadow_map { s.dirty = true; }
         }
-        self.ebos[ebo_index] = (ebo_pair.0, ebo_pair.1 + elements.len(), ebo_pair.2);
-        self.vbos[vbo_index] = (vbo_pair.0, vbo_pair.1 + vertices.len(), vbo_pair.2);
     }
 
     // Clears the VBO/VAO/EBOs so that every ModelInfo currently mapped to the engine's VBO space
@@ -598,11 +1525,15 @@ impl GameWindow {
         self.bind_vao_checked(working_vao);
         self.gen += 1;
         for vbo_pair in self.vbos.iter_mut() {
-            *vbo_pair = (vbo_pair.0, 0, vbo_pair.2);
+            vbo_pair.2.clear();
         }
         for ebo_pair in self.ebos.iter_mut() {
-            *ebo_pair = (ebo_pair.0, 0, ebo_pair.2);
+            ebo_pair.1.clear();
+        }
+        for mapped in self.mapped.iter() {
+            GameWindow::delete_morph_buffer(mapped);
         }
+        self.mapped.clear();
         for row in self.vaos.iter_mut() {
             for column in row.iter_mut() {
                 if let Some(id) = *column {
@@ -612,6 +1543,211 @@ impl GameWindow {
                 }
             }
         }
+
+        // GL reuses deleted VAO names, so a new VAO with a different layout could otherwise skip
+        // resetting the attribute defaults.
+        self.defaults_vao = None;
+    }
+
+    // Helper method that binds a material texture to a texture unit, substituting the default white
+    // texture for a texture ID of 0.
+    fn bind_material_texture(&self, unit: usize, texture: GLuint) { unsafe {
+        let texture = if texture == 0 { self.default_texture } else { texture };
+        gl::ActiveTexture(gl::TEXTURE0 + unit as GLuint);
+        gl::BindTexture(gl::TEXTURE_2D, texture);
+    }}
+
+    // Helper method that uploads the non-texture uniforms of a material to the bound program.
+    fn upload_material(&self, mat: &material::Material) { unsafe {
+        match mat.normal {
+            Some(_) => { uniform_int!(self.program, "use_normal_map", 1); },
+            None => { uniform_int!(self.program, "use_normal_map", 0); },
+        };
+        match mat.transparency {
+            material::Transparency::AlphaTest(cutoff) => {
+                uniform_int!(self.program, "use_alpha_test", 1);
+                uniform_float!(self.program, "alpha_cutoff", cutoff); },
+            _ => { uniform_int!(self.program, "use_alpha_test", 0); },
+        };
+        uniform_int!(self.program, "shading_model", mat.shading as GLint);
+        uniform_vec4!(self.program, "color", color_to_vec!(mat.color));
+        uniform_vec3!(self.program, "ambient_color", vec![mat.ambient_color.r,
+                mat.ambient_color.g, mat.ambient_color.b]);
+        uniform_vec3!(self.program, "emissive_color", vec![mat.emissive_color.r,
+                mat.emissive_color.g, mat.emissive_color.b]);
+        match mat.shading {
+            material::ShadingModel::BlinnPhong => {
+                uniform_float!(self.program, "specular_coeff", mat.shininess);
+                uniform_vec3!(self.program, "specular_color", vec![mat.specular_color.r,
+                        mat.specular_color.g, mat.specular_color.b]);
+            },
+            material::ShadingModel::MetallicRoughness => {
+                uniform_float!(self.program, "metallic", mat.metallic);
+                uniform_float!(self.program, "roughness", mat.roughness);
+            },
+        };
+    }}
+
+    // Helper method that sets the blending and depth write state for a transparency mode.
+    fn apply_transparency(&self, transparency: material::Transparency) { unsafe {
+        match transparency {
+            material::Transparency::Opaque | material::Transparency::AlphaTest(_) => {
+                gl::Disable(gl::BLEND);
+                gl::DepthMask(gl::TRUE);
+            },
+            material::Transparency::AlphaBlend => {
+                gl::Enable(gl::BLEND);
+                gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+                gl::DepthMask(gl::FALSE);
+            },
+            material::Transparency::Additive => {
+                gl::Enable(gl::BLEND);
+                gl::BlendFunc(gl::SRC_ALPHA, gl::ONE);
+                gl::DepthMask(gl::FALSE);
+            },
+        };
+    }}
+
+    // Records a ModelInstance to be drawn on the next call to flush(). The instance can be changed
+    // or dropped after it is submitted.
+    pub fn submit(&mut self, instance: &model::ModelInstance) {
+        let program = self.program;
+        self.queue.submit(program, instance);
+    }
+
+    // Draws every ModelInstance submitted since the last flush along with the skybox and returns
+    // the number of GL state changes made and saved. Opaque and alpha tested instances are sorted
+    // by program, VAO, and material textures to minimize state changes and then front-to-back.
+    // The skybox is drawn next, followed by the blended instances sorted back-to-front by the view
+    // space depth of their positions relative to the active camera. Consecutive instances of the
+    // same ModelInfo are drawn with a single instanced draw call. Nothing is drawn if there is no
+    // active camera.
+    pub fn flush(&mut self) -> render_queue::RenderStats {
+        let mut stats = render_queue::RenderStats::default();
+        let (view, proj) = match self.get_active_camera() {
+            Ok(camera) => (camera.get_view_matrix(), camera.get_projection_matrix()),
+            Err(_) => {
+                self.queue.clear();
+                return stats;
+            },
+        };
+        let mut queue = mem::replace(&mut self.queue, render_queue::RenderQueue::new());
+        for command in queue.opaque.iter().chain(queue.blended.iter()) {
+            self.map_vbo_if_needed(&command.info);
+        }
+        queue.sort(view);
+
+        let view_proj = proj * view;
+        self.draw_commands(&queue.opaque, view_proj, &mut stats);
+        self.draw_skybox();
+        self.draw_commands(&queue.blended, view_proj, &mut stats);
+        if !queue.blended.is_empty() { self.apply_transparency(material::Transparency::Opaque); }
+        queue.clear();
+        self.queue = queue;
+        stats
+    }
+
+    // Helper method that draws a sorted list of recorded draws in batches given the camera's view
+    // projection matrix. The program and texture bindings are assumed to be unknown beforehand.
+    fn draw_commands(&mut self, commands: &[render_queue::DrawCommand],
+            view_proj: cgmath::Matrix4<GLfloat>, stats: &mut render_queue::RenderStats) {
+        let mut bound_textures = [None; MATERIAL_TEXTURE_UNITS];
+        let mut previous: Option<&render_queue::DrawCommand> = None;
+        for batch in render_queue::get_batches(commands, MAX_INSTANCES_PER_DRAW) {
+            let batch = &commands[batch];
+            self.draw_batch(batch, previous, view_proj, &mut bound_textures, stats);
+            previous = batch.last();
+        }
+    }
+
+    // Draws a set of ModelInstances and the skybox in an order that composites transparent
+    // Materials correctly. This is shorthand for submitting every instance and then flushing.
+    pub fn draw_instances(&mut self, instances: &[&model::ModelInstance]) {
+        for instance in instances {
+            self.submit(instance);
+        }
+        self.flush();
+    }
+
+    // Helper method that draws a batch of recorded draws of the same ModelInfo given the
+    // previously drawn command, the camera's view projection matrix, and the textures currently
+    // bound to the material texture units. Program, VAO, texture, material, and blending state is
+    // only changed if it differs from the previous draw, and every change (or lack thereof) is
+    // counted in the stats. A batch of a single command falls back to a regular draw call with the
+    // matrices passed as uniforms. The ModelInfo must already be mapped to the engine's VBO space.
+    fn draw_batch(&mut self, batch: &[render_queue::DrawCommand],
+            previous: Option<&render_queue::DrawCommand>, view_proj: cgmath::Matrix4<GLfloat>,
+            bound_textures: &mut [Option<GLuint>; MATERIAL_TEXTURE_UNITS],
+            stats: &mut render_queue::RenderStats) {
+        let command = &batch[0];
+        let mat = command.get_material();
+        let info = command.info.buffer_info.get().unwrap();
+        unsafe {
+            if previous.map_or(true, |p| p.program != command.program) {
+                gl::UseProgram(command.program);
+                stats.program_changes += 1;
+            } else { stats.changes_saved += 1; }
+            if self.bound_vao != Some(info.vao) {
+                stats.vao_changes += 1;
+            } else { stats.changes_saved += 1; }
+            self.bind_model_vao(info.vao);
+
+            let units = [DIFFUSE_TEXTURE_UNIT, SPECULAR_TEXTURE_UNIT, NORMAL_TEXTURE_UNIT,
+                    OCCLUSION_TEXTURE_UNIT, EMISSIVE_TEXTURE_UNIT];
+            let mut textures_changed = false;
+            for (&unit, &texture) in units.iter().zip(mat.get_textures().iter()) {
+                if bound_textures[unit] != Some(texture) {
+                    self.bind_material_texture(unit, texture);
+                    bound_textures[unit] = Some(texture);
+                    stats.texture_changes += 1;
+                    textures_changed = true;
+                } else { stats.changes_saved += 1; }
+            }
+            if textures_changed { gl::ActiveTexture(gl::TEXTURE0); }
+
+            if previous.map_or(true, |p| !p.shares_material(command)) {
+                self.upload_material(mat);
+                stats.material_changes += 1;
+            } else { stats.changes_saved += 1; }
+            if mat.transparency.is_blended() &&
+                    previous.map_or(true, |p| p.get_material().transparency != mat.transparency) {
+                self.apply_transparency(mat.transparency);
+            }
+
+            if batch.len() == 1 {
+                uniform_int!(command.program, "use_instancing", 0);
+                self.upload_skinning(command.program, &command.joint_matrices);
+                self.upload_morphs(command.program, &command.info, &command.morph_targets);
+                uniform_mat4!(command.program, "transform", view_proj * command.model);
+                uniform_mat4!(command.program, "model", command.model);
+                uniform_mat4!(command.program, "normal_matrix", command.normal);
+                gl::DrawElementsBaseVertex(gl::TRIANGLES, info.size as i32, gl::UNSIGNED_INT,
+                        uint_size!(info.start, CVoid), info.base_vertex as GLint);
+            } else {
+                let mut instances: Vec<GLfloat> = Vec::with_capacity(batch.len() * 32);
+                for instanced in batch.iter() {
+                    for matrix in [instanced.model, instanced.normal].iter() {
+                        for column in [matrix.x, matrix.y, matrix.z, matrix.w].iter() {
+                            instances.extend([column.x, column.y, column.z, column.w].iter());
+                        }
+                    }
+                }
+                gl::BindBuffer(gl::TEXTURE_BUFFER, self.instance_buffer);
+                gl::BufferData(gl::TEXTURE_BUFFER, float_size!(instances.len(), GLsizeiptr),
+                        vec_to_addr!(instances), gl::STREAM_DRAW);
+                gl::BindBuffer(gl::TEXTURE_BUFFER, 0);
+                uniform_int!(command.program, "use_instancing", 1);
+                uniform_int!(command.program, "use_skinning", 0);
+                uniform_int!(command.program, "morph_count", 0);
+                uniform_mat4!(command.program, "view_projection", view_proj);
+                gl::DrawElementsInstancedBaseVertex(gl::TRIANGLES, info.size as i32,
+                        gl::UNSIGNED_INT, uint_size!(info.start, CVoid), batch.len() as GLsizei,
+                        info.base_vertex as GLint);
+                stats.instanced_draw_calls += 1;
+            }
+            stats.draw_calls += 1;
+            stats.instances += batch.len();
+        }
     }
 
     // Draw a ModelInstance to the window using a camera, position, vertices, and materials.
@@ -621,51 +1757,25 @@ impl GameWindow {
     // any of the managed VBOs, we create a new VBO and assign it there instead. There is also a
     // generation field in both the BufferInfo and the Engine. On clear_vertex_buffers(), we
     // increment this generation count in the engine. If the generation count on the ModelInfo does
-    // not match the count of the Engine, we remap.
+    // not match the count of the Engine, we remap. This draws immediately and rebinds all of the
+    // instance's state, so prefer submit() and flush() for scenes with many or transparent
+    // instances.
     pub fn draw_instance(&mut self, instance: &model::ModelInstance) {
-        match instance.info.buffer_info.get() {
-            None => { self.map_vbo(instance.info.clone()); },
-            Some(i) => { if i.gen != self.gen { self.map_vbo(instance.info.clone()) }; },
-        }
+        self.map_vbo_if_needed(&instance.info);
 
-        let transform = {
-            let camera = match self.active_camera {
-                None => { return; },
-                Some(c) => self.cameras[c].as_ref().unwrap(),
+        let view_proj = {
+            let camera = match self.get_active_camera() {
+                Ok(camera) => camera,
+                Err(_) => { return; },
             };
-            let view = camera.get_view_matrix();
-            let proj = camera.get_projection_matrix();
-            proj * view * instance.model
+            camera.get_projection_matrix() * camera.get_view_matrix()
         };
-
-        unsafe {
-            let mat = &instance.info.mat;
-            let info = instance.info.buffer_info.get().unwrap();
-            self.bind_vao_checked(info.vao);
-            uniform_mat4!(self.program, "transform", transform);
-            uniform_mat4!(self.program, "model", instance.model);
-            uniform_mat4!(self.program, "normal_matrix", instance.normal);
-            gl::ActiveTexture(gl::TEXTURE0);
-            let diffuse_id = if mat.diffuse == 0 { self.default_texture } else { mat.diffuse };
-            gl::BindTexture(gl::TEXTURE_2D, diffuse_id);
-            uniform_int!(self.program, "diffuse_map", 0);
-            gl::ActiveTexture(gl::TEXTURE1);
-            let spec_id = if mat.specular == 0 { self.default_texture } else { mat.specular };
-            gl::BindTexture(gl::TEXTURE_2D, spec_id);
-            uniform_int!(self.program, "specular_map", 1);
-            match mat.normal {
-                Some(normal_id) => {
-                    gl::ActiveTexture(gl::TEXTURE2);
-                    gl::BindTexture(gl::TEXTURE_2D, normal_id);
-                    uniform_int!(self.program, "normal_map", 2);
-                    uniform_int!(self.program, "use_normal_map", 1); },
-                None => { uniform_int!(self.program, "use_normal_map", 0); },
-            };
-            uniform_float!(self.program, "specular_coeff", mat.shininess);
-            uniform_vec4!(self.program, "color", color_to_vec!(mat.color));
-
-            gl::DrawElements(gl::TRIANGLES, info.size as i32,
-                    gl::UNSIGNED_INT, uint_size!(info.start, CVoid));
+        let command = render_queue::DrawCommand::new(self.program, instance);
+        let mut bound_textures = [None; MATERIAL_TEXTURE_UNITS];
+        let mut stats = render_queue::RenderStats::default();
+        self.draw_batch(&[command], None, view_proj, &mut bound_textures, &mut stats);
+        if instance.info.mat.transparency.is_blended() {
+            self.apply_transparency(material::Transparency::Opaque);
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,151 @@
+// Defines the typed generational handles that the GameWindow hands out for its cameras and lights
+// (and that the SceneGraph hands out for its nodes) along with the HandleMap that stores the
+// objects they refer to. A handle is an index into the
+// HandleMap and the generation of that index. Indices are reused after an object is removed, but
+// the generation is bumped so that using the handle of a removed object returns Err instead of
+// silently referring to whatever took its place.
+//
+// Brian Ho
+// brian@brkho.com
+
+use gfx::camera::Camera;
+use gfx::light;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter;
+use std::marker::PhantomData;
+use std::slice;
+
+// A generational handle to an object of type T in a HandleMap. Handles to different types of
+// objects cannot be mixed up.
+pub struct Handle<T> {
+    index: usize,
+    generation: u32,
+    marker: PhantomData<T>,
+}
+
+impl<T> Handle<T> {
+    // Gets the index of the handle, which is only unique among live objects.
+    pub fn get_index(&self) -> usize { self.index }
+
+    // Gets the generation of the handle's index.
+    pub fn get_generation(&self) -> u32 { self.generation }
+}
+
+// Handles are Copy regardless of T, so the traits are implemented by hand instead of derived.
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Handle<T> { *self }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Handle<T>) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Handle({}, {})", self.index, self.generation)
+    }
+}
+
+// The handles of the cameras and lights attached to a GameWindow.
+pub type CameraHandle = Handle<Box<Camera>>;
+pub type PointLightHandle = Handle<light::PointLight>;
+pub type DirectionalLightHandle = Handle<light::DirectionalLight>;
+pub type SpotLightHandle = Handle<light::SpotLight>;
+
+// A container of objects that are referred to by generational handles. The name of the objects
+// is used in error messages.
+pub struct HandleMap<T> {
+    name: &'static str,
+    slots: Vec<(u32, Option<T>)>,    // (generation, object)
+    free: Vec<usize>,
+}
+
+impl<T> HandleMap<T> {
+    // Default constructor for an empty HandleMap given the name of its objects.
+    pub fn new(name: &'static str) -> HandleMap<T> {
+        HandleMap { name: name, slots: Vec::new(), free: Vec::new() }
+    }
+
+    // Adds an object and returns its handle.
+    pub fn insert(&mut self, object: T) -> Handle<T> {
+        let index = match self.free.pop() {
+            Some(i) => i,
+            None => {
+                self.slots.push((0, None));
+                self.slots.len() - 1
+            },
+        };
+        self.slots[index].1 = Some(object);
+        Handle { index: index, generation: self.slots[index].0, marker: PhantomData }
+    }
+
+    // Removes an object and returns it. Returns Err if the handle is stale.
+    pub fn remove(&mut self, handle: Handle<T>) -> Result<T, String> {
+        try!(self.get(handle));
+        let slot = &mut self.slots[handle.index];
+        slot.0 = slot.0.wrapping_add(1);
+        self.free.push(handle.index);
+        Ok(slot.1.take().unwrap())
+    }
+
+    // Gets an immutable reference to an object. Returns Err if the handle is stale.
+    pub fn get(&self, handle: Handle<T>) -> Result<&T, String> {
+        match self.slots.get(handle.index) {
+            Some(&(generation, Some(ref object))) if generation == handle.generation => Ok(object),
+            _ => Err(format!("The {} handle {:?} is stale.", self.name, handle)),
+        }
+    }
+
+    // Gets a mutable reference to an object. Returns Err if the handle is stale.
+    pub fn get_mut(&mut self, handle: Handle<T>) -> Result<&mut T, String> {
+        match self.slots.get_mut(handle.index) {
+            Some(&mut (generation, Some(ref mut object))) if generation == handle.generation =>
+                    Ok(object),
+            _ => Err(format!("The {} handle {:?} is stale.", self.name, handle)),
+        }
+    }
+
+    // Whether a handle refers to an object in the HandleMap.
+    pub fn contains(&self, handle: Handle<T>) -> bool { self.get(handle).is_ok() }
+
+    // Gets the number of objects in the HandleMap.
+    pub fn len(&self) -> usize { self.slots.len() - self.free.len() }
+
+    // Whether the HandleMap has no objects.
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    // Iterates over the (handle, object) pairs in order of index.
+    pub fn iter(&self) -> Iter<T> { Iter { slots: self.slots.iter().enumerate() } }
+}
+
+// An iterator over the (handle, object) pairs of a HandleMap.
+pub struct Iter<'a, T: 'a> {
+    slots: iter::Enumerate<slice::Iter<'a, (u32, Option<T>)>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (Handle<T>, &'a T);
+
+    fn next(&mut self) -> Option<(Handle<T>, &'a T)> {
+        while let Some((i, &(generation, ref object))) = self.slots.next() {
+            if let &Some(ref o) = object {
+                return Some((Handle { index: i, generation: generation, marker: PhantomData }, o));
+            }
+        }
+        None
+    }
+}
@@ -2,24 +2,94 @@
 // emanates in all directions from a point. DirectionalLight represents a light which shines in
 // one direction at a constant intensity (kind of like a PointLight from an infinite distance with
 // with no attenuation). SpotLight is like a PointLight except it has a cutoff angle with a dropoff
-// factor.
+// factor. Every light can optionally cast shadows which are rendered by the GameWindow into a depth
+// map (or a depth cube map for PointLights) with the resolution and bias specified on the light.
+// Each light can be packed into a PackedLight which is the layout used in the GameWindow's light
+// uniform buffer.
 //
 // Brian Ho
 // brian@brkho.com
 
+extern crate cgmath;
 extern crate gl;
 
+use self::cgmath::{EuclideanVector, Point};
 use gfx::color;
 use gfx::types::*;
 
-// Light source that emanates from a fixed point with specified intensity and attenuation.
+// Light type identifiers shared with the fragment shader.
+pub const EMPTY_LIGHT: GLint = 0;
+pub const POINT_LIGHT: GLint = 1;
+pub const DIRECTIONAL_LIGHT: GLint = 2;
+pub const SPOT_LIGHT: GLint = 3;
+
+// The default resolution (in texels per side) of a shadow map.
+pub const DEFAULT_SHADOW_RESOLUTION: u32 = 1024;
+
+// The default depth bias applied when comparing against a shadow map to avoid shadow acne.
+pub const DEFAULT_SHADOW_BIAS: f32 = 0.005;
+
+// The default radius in world units out to which a PointLight casts shadows.
+pub const DEFAULT_SHADOW_RADIUS: f32 = 25.0;
+
+// The default half-width in world units of the area covered by a DirectionalLight's shadow map.
+pub const DEFAULT_SHADOW_EXTENT: f32 = 25.0;
+
+// The near plane used when rendering shadow maps for lights with a position.
+const SHADOW_NEAR: f32 = 0.1;
+
+// The farthest distance from a light with a position that a shadow map will cover.
+const MAX_SHADOW_DISTANCE: f32 = 100.0;
+
+// The widest field of view of a SpotLight's shadow map. A perspective projection can't cover a
+// cone of a hemisphere or wider, so wider cones only cast shadows in their center.
+const MAX_SPOT_SHADOW_FOV: f32 = ::std::f32::consts::PI * 0.9;
+
+// Attenuated intensities below this threshold are considered to contribute no light.
+const RANGE_THRESHOLD: f32 = 1.0 / 256.0;
+
+// The layout of a single light in the light uniform buffer. This mirrors the PackedLight struct in
+// std.frag which uses the std140 layout, so every member is padded to a four component vector.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct PackedLight {
+    pub intensity: [GLfloat; 4],    // [R, G, B, -]
+    pub position: [GLfloat; 4],     // [X, Y, Z, -]
+    pub direction: [GLfloat; 4],    // [X, Y, Z, -]
+    pub attenuation: [GLfloat; 4],  // [const, linear, quad, -]
+    pub spot: [GLfloat; 4],         // [cutoff, dropoff, -, -]
+    pub shadow: [GLfloat; 4],       // [bias, radius, -, -]
+    pub info: [GLint; 4],           // [type, shadow_index, -, -]
+}
+
+impl PackedLight {
+    // Creates a PackedLight representing an unused slot in the light uniform buffer.
+    pub fn empty() -> PackedLight {
+        PackedLight { intensity: [0.0; 4], position: [0.0; 4], direction: [0.0; 4],
+                attenuation: [0.0; 4], spot: [0.0; 4], shadow: [0.0; 4],
+                info: [EMPTY_LIGHT, -1, 0, 0] }
+    }
+}
+
+// Helper function that converts an optional shadow map index into the shader representation.
+fn pack_shadow_index(shadow_index: Option<usize>) -> GLint {
+    match shadow_index { Some(i) => i as GLint, None => -1 }
+}
+
+// Light source that emanates from a fixed point with specified intensity and attenuation. If
+// casts_shadows is set, geometry within shadow_radius world units of the light casts shadows.
 pub struct PointLight {
     pub intensity: color::Color,
     pub position: Vector3D,
     pub const_attn: f32,
     pub linear_attn: f32,
     pub quad_attn: f32,
+    pub casts_shadows: bool,
+    pub shadow_resolution: u32,
+    pub shadow_bias: f32,
+    pub shadow_radius: f32,
     pub light_index: Option<usize>,
+    pub shadow_index: Option<usize>,
 }
 
 impl PointLight {
@@ -27,21 +97,89 @@ impl PointLight {
     pub fn new(intensity: color::Color, position: Vector3D, const_attn: f32, linear_attn: f32,
             quad_attn: f32) -> PointLight {
         PointLight { intensity: intensity, position: position, const_attn: const_attn,
-                linear_attn: linear_attn, quad_attn: quad_attn, light_index: None }
+                linear_attn: linear_attn, quad_attn: quad_attn, casts_shadows: false,
+                shadow_resolution: DEFAULT_SHADOW_RESOLUTION, shadow_bias: DEFAULT_SHADOW_BIAS,
+                shadow_radius: DEFAULT_SHADOW_RADIUS, light_index: None, shadow_index: None }
+    }
+
+    // Packs the light into the layout used by the light uniform buffer.
+    pub fn get_packed(&self) -> PackedLight {
+        let mut packed = PackedLight::empty();
+        packed.intensity = [self.intensity.r, self.intensity.g, self.intensity.b, 0.0];
+        packed.position = [self.position.x, self.position.y, self.position.z, 0.0];
+        packed.attenuation = [self.const_attn, self.linear_attn, self.quad_attn, 0.0];
+        packed.shadow = [self.shadow_bias, self.shadow_radius, 0.0, 0.0];
+        packed.info = [POINT_LIGHT, pack_shadow_index(self.shadow_index), 0, 0];
+        packed
+    }
+
+    // Gets the distance at which the light no longer noticeably contributes to the scene.
+    pub fn get_range(&self) -> f32 {
+        let max = self.intensity.r.max(self.intensity.g).max(self.intensity.b);
+        get_attenuation_range(max, self.const_attn, self.linear_attn, self.quad_attn)
+    }
+
+    // Gets the matrices that transform world space into the clip space of each face of the shadow
+    // cube map in the order +X, -X, +Y, -Y, +Z, -Z.
+    pub fn get_shadow_matrices(&self) -> Vec<cgmath::Matrix4<GLfloat>> {
+        let faces = [
+                (Vector3D::new(1.0, 0.0, 0.0), Vector3D::new(0.0, -1.0, 0.0)),
+                (Vector3D::new(-1.0, 0.0, 0.0), Vector3D::new(0.0, -1.0, 0.0)),
+                (Vector3D::new(0.0, 1.0, 0.0), Vector3D::new(0.0, 0.0, 1.0)),
+                (Vector3D::new(0.0, -1.0, 0.0), Vector3D::new(0.0, 0.0, -1.0)),
+                (Vector3D::new(0.0, 0.0, 1.0), Vector3D::new(0.0, -1.0, 0.0)),
+                (Vector3D::new(0.0, 0.0, -1.0), Vector3D::new(0.0, -1.0, 0.0))];
+        let far = self.shadow_radius.max(SHADOW_NEAR * 2.0);
+        let proj = cgmath::perspective(cgmath::deg(90.0), 1.0, SHADOW_NEAR, far);
+        let eye = cgmath::Point3::from_vec(self.position);
+        faces.iter().map(|&(dir, up)| {
+            proj * cgmath::Matrix4::look_at(eye, cgmath::Point3::from_vec(self.position + dir), up)
+        }).collect()
     }
 }
 
-// Light source that shines from an infinite distance from a direction (such as the sun).
+// Light source that shines from an infinite distance from a direction (such as the sun). If
+// casts_shadows is set, the shadow map covers a square of 2 * shadow_extent world units centered
+// on the target of the active camera.
 pub struct DirectionalLight {
     pub intensity: color::Color,
     pub direction: Vector3D,
+    pub casts_shadows: bool,
+    pub shadow_resolution: u32,
+    pub shadow_bias: f32,
+    pub shadow_extent: f32,
     pub light_index: Option<usize>,
+    pub shadow_index: Option<usize>,
 }
 
 impl DirectionalLight {
     // Default constructor for a DirectionalLight.
     pub fn new(intensity: color::Color, direction: Vector3D) -> DirectionalLight {
-        DirectionalLight { intensity: intensity, direction: direction, light_index: None }
+        DirectionalLight { intensity: intensity, direction: direction, casts_shadows: false,
+                shadow_resolution: DEFAULT_SHADOW_RESOLUTION, shadow_bias: DEFAULT_SHADOW_BIAS,
+                shadow_extent: DEFAULT_SHADOW_EXTENT, light_index: None, shadow_index: None }
+    }
+
+    // Packs the light into the layout used by the light uniform buffer.
+    pub fn get_packed(&self) -> PackedLight {
+        let mut packed = PackedLight::empty();
+        packed.intensity = [self.intensity.r, self.intensity.g, self.intensity.b, 0.0];
+        packed.direction = [self.direction.x, self.direction.y, self.direction.z, 0.0];
+        packed.shadow = [self.shadow_bias, 0.0, 0.0, 0.0];
+        packed.info = [DIRECTIONAL_LIGHT, pack_shadow_index(self.shadow_index), 0, 0];
+        packed
+    }
+
+    // Gets the matrix that transforms world space into the clip space of the shadow map given the
+    // center of the area that should receive shadows.
+    pub fn get_shadow_matrix(&self, center: Vector3D) -> cgmath::Matrix4<GLfloat> {
+        let direction = self.direction.normalize();
+        let eye = center - direction * self.shadow_extent * 2.0;
+        let view = cgmath::Matrix4::look_at(cgmath::Point3::from_vec(eye),
+                cgmath::Point3::from_vec(center), get_shadow_up(direction));
+        let extent = self.shadow_extent;
+        let proj = cgmath::ortho(-extent, extent, -extent, extent, 0.0, extent * 4.0);
+        proj * view
     }
 }
 
@@ -56,7 +194,11 @@ pub struct SpotLight {
     pub quad_attn: f32,
     pub cutoff: f32,
     pub dropoff: f32,
+    pub casts_shadows: bool,
+    pub shadow_resolution: u32,
+    pub shadow_bias: f32,
     pub light_index: Option<usize>,
+    pub shadow_index: Option<usize>,
 }
 
 impl SpotLight {
@@ -65,7 +207,123 @@ impl SpotLight {
             linear_attn: f32, quad_attn: f32, cutoff: f32, dropoff: f32) -> SpotLight {
         SpotLight { intensity: intensity, position: position, const_attn: const_attn,
                 direction: direction, linear_attn: linear_attn, quad_attn: quad_attn,
-                cutoff: cutoff, dropoff: dropoff, light_index: None }
+                cutoff: cutoff, dropoff: dropoff, casts_shadows: false,
+                shadow_resolution: DEFAULT_SHADOW_RESOLUTION, shadow_bias: DEFAULT_SHADOW_BIAS,
+                light_index: None, shadow_index: None }
+
+    }
+
+    // Packs the light into the layout used by the light uniform buffer.
+    pub fn get_packed(&self) -> PackedLight {
+        let mut packed = PackedLight::empty();
+        packed.intensity = [self.intensity.r, self.intensity.g, self.intensity.b, 0.0];
+        packed.position = [self.position.x, self.position.y, self.position.z, 0.0];
+        packed.direction = [self.direction.x, self.direction.y, self.direction.z, 0.0];
+        packed.attenuation = [self.const_attn, self.linear_attn, self.quad_attn, 0.0];
+        packed.spot = [self.cutoff, self.dropoff, 0.0, 0.0];
+        packed.shadow = [self.shadow_bias, 0.0, 0.0, 0.0];
+        packed.info = [SPOT_LIGHT, pack_shadow_index(self.shadow_index), 0, 0];
+        packed
+    }
+
+    // Gets the distance at which the light no longer noticeably contributes to the scene.
+    pub fn get_range(&self) -> f32 {
+        let max = self.intensity.r.max(self.intensity.g).max(self.intensity.b);
+        get_attenuation_range(max, self.const_attn, self.linear_attn, self.quad_attn)
+    }
 
+    // Gets the center and radius of the smallest sphere enclosing the cone lit by the light out to
+    // its range. Cones of a hemisphere or wider fall back to a sphere around the light.
+    pub fn get_bounding_sphere(&self) -> (Vector3D, f32) {
+        let direction = self.direction.normalize();
+        let range = self.get_range();
+        if !range.is_finite() || self.cutoff >= ::std::f32::consts::PI / 2.0 {
+            (self.position, range)
+        } else if self.cutoff > ::std::f32::consts::PI / 4.0 {
+            // Wide cones are bounded by the circle at the base of the cone.
+            (self.position + direction * range * self.cutoff.cos(), range * self.cutoff.sin())
+        } else {
+            let radius = range / (2.0 * self.cutoff.cos());
+            (self.position + direction * radius, radius)
+        }
     }
-}
\ No newline at end of file
+
+    // Gets the matrix that transforms world space into the clip space of the shadow map. The
+    // frustum covers the cone of the light out to its range, and the field of view is clamped to
+    // MAX_SPOT_SHADOW_FOV.
+    pub fn get_shadow_matrix(&self) -> cgmath::Matrix4<GLfloat> {
+        let direction = self.direction.normalize();
+        let view = cgmath::Matrix4::look_at(cgmath::Point3::from_vec(self.position),
+                cgmath::Point3::from_vec(self.position + direction), get_shadow_up(direction));
+        let far = self.get_range().max(SHADOW_NEAR * 2.0).min(MAX_SHADOW_DISTANCE);
+        let fov = (self.cutoff * 2.0).min(MAX_SPOT_SHADOW_FOV);
+        let proj = cgmath::perspective(cgmath::rad(fov), 1.0, SHADOW_NEAR, far);
+        proj * view
+    }
+}
+
+// Helper function that solves the attenuation equation for the distance at which a light of the
+// given maximum intensity falls below RANGE_THRESHOLD.
+fn get_attenuation_range(intensity: f32, const_attn: f32, linear_attn: f32, quad_attn: f32)
+        -> f32 {
+    // Solve quad * d^2 + linear * d + (const - intensity / threshold) = 0 for d.
+    let c = const_attn - intensity / RANGE_THRESHOLD;
+    if c >= 0.0 { return 0.0; }
+    if quad_attn > 0.0 {
+        let discriminant = linear_attn * linear_attn - 4.0 * quad_attn * c;
+        (-linear_attn + discriminant.sqrt()) / (2.0 * quad_attn)
+    } else if linear_attn > 0.0 {
+        -c / linear_attn
+    } else {
+        ::std::f32::INFINITY
+    }
+}
+
+// Helper function that picks an up vector for a shadow view matrix which is never parallel to the
+// direction of the light.
+fn get_shadow_up(direction: Vector3D) -> Vector3D {
+    if direction.z.abs() > 0.99 {
+        Vector3D::new(0.0, 1.0, 0.0)
+    } else {
+        Vector3D::new(0.0, 0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gfx::color;
+
+    // Creates a SpotLight at the origin that points down the negative z axis.
+    fn create_spot_light(cutoff: f32) -> SpotLight {
+        SpotLight::new(color::Color::new_rgb(1.0, 1.0, 1.0), Vector3D::new(0.0, 0.0, 0.0),
+                Vector3D::new(0.0, 0.0, -1.0), 1.0, 0.1, 0.01, cutoff, 1.0)
+    }
+
+    #[test]
+    fn wide_spot_shadow_matrix() {
+        // Cones of a hemisphere or wider still project points in front of the light onto the
+        // side of the shadow map they are on.
+        for &cutoff in [1.5, ::std::f32::consts::PI / 2.0, 2.0, ::std::f32::consts::PI].iter() {
+            let matrix = create_spot_light(cutoff).get_shadow_matrix();
+            let clip = matrix * cgmath::Vector4::new(5.0, 0.0, -5.0, 1.0);
+            assert!(clip.w > 0.0 && clip.x / clip.w > 0.01 && clip.x / clip.w < 1.0,
+                    "Cutoff {} projects to {:?}.", cutoff, clip);
+        }
+    }
+
+    #[test]
+    fn spot_shadow_matrix_covers_cone() {
+        // A point on the axis of the light lands in the center of the shadow map.
+        let matrix = create_spot_light(0.5).get_shadow_matrix();
+        let clip = matrix * cgmath::Vector4::new(0.0, 0.0, -5.0, 1.0);
+        assert!((clip.x / clip.w).abs() < 1e-5 && (clip.y / clip.w).abs() < 1e-5);
+        assert!(clip.z / clip.w > -1.0 && clip.z / clip.w < 1.0);
+
+        // Points inside the cone land inside the shadow map and points outside do not.
+        let inside = matrix * cgmath::Vector4::new(0.45f32.tan() * 5.0, 0.0, -5.0, 1.0);
+        assert!((inside.x / inside.w).abs() < 1.0);
+        let outside = matrix * cgmath::Vector4::new(0.55f32.tan() * 5.0, 0.0, -5.0, 1.0);
+        assert!((outside.x / outside.w).abs() > 1.0);
+    }
+}
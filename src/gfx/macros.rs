@@ -29,6 +29,12 @@ macro_rules! uniform_mat4 { ($p:expr, $s:expr, $l: expr) =>
             gl::GetUniformLocation($p, gl_str!($s)), 1,
             gl::FALSE as GLboolean, ($l).as_ptr())) }
 
+#[macro_export]
+// Macro for updating a vec2 uniform.
+macro_rules! uniform_vec2 { ($p:expr, $s:expr, $l: expr) =>
+        (gl::Uniform2fv(
+            gl::GetUniformLocation($p, gl_str!($s)), 1, ($l).as_ptr())) }
+
 #[macro_export]
 // Macro for updating a vec3 uniform.
 macro_rules! uniform_vec3 { ($p:expr, $s:expr, $l: expr) =>
@@ -57,9 +63,10 @@ macro_rules! uniform_int { ($p:expr, $s:expr, $l: expr) =>
         (gl::Uniform1i(gl::GetUniformLocation($p, gl_str!($s)), $l)) }
 
 #[macro_export]
-// Macro for getting a string representation of an index into the lights array
-// uniform.
-macro_rules! lights { [$i:expr, $f:expr] => (format!("lights[{}].{}", $i, $f)) }
+// Macro for updating an ivec3 uniform.
+macro_rules! uniform_ivec3 { ($p:expr, $s:expr, $l: expr) =>
+        (gl::Uniform3iv(
+            gl::GetUniformLocation($p, gl_str!($s)), 1, ($l).as_ptr())) }
 
 #[macro_export]
 // Macro for changing a Vector3D to vector of length 3.
@@ -1,4 +1,8 @@
-// Holds information about and defines a few ways to create a Material.
+// Holds information about and defines a few ways to create a Material. A Material is shaded with
+// either the original Blinn-Phong model (a diffuse map, a specular map, and a shininess factor) or
+// a physically based metallic-roughness model with a Cook-Torrance BRDF. The metallic-roughness
+// textures follow the glTF conventions, so roughness is read from the green channel and metallic
+// from the blue channel of the metallic-roughness map.
 //
 // Brian Ho
 // brian@brkho.com
@@ -8,21 +12,71 @@ extern crate gl;
 use gfx::color;
 use gfx::types::*;
 use std::mem;
-use util::{common, bmp};
+use util::{common, image};
+
+// The lighting model used to shade a Material. The values match the shading model identifiers in
+// std.frag.
+#[derive(Copy, Clone, PartialEq)]
+pub enum ShadingModel {
+    BlinnPhong = 0,
+    MetallicRoughness = 1,
+}
+
+// How a Material is composited with what has already been drawn. AlphaTest discards fragments
+// whose alpha is below the cutoff but is otherwise drawn like an opaque material. AlphaBlend and
+// Additive materials are blended over the scene (with the alpha of the color and diffuse map) and
+// do not write to the depth buffer, so they are drawn after every opaque material.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Transparency {
+    Opaque,
+    AlphaTest(GLfloat),
+    AlphaBlend,
+    Additive,
+}
+
+impl Transparency {
+    // Whether a Material with this mode is blended and must be drawn after opaque Materials.
+    pub fn is_blended(&self) -> bool {
+        match *self {
+            Transparency::Opaque | Transparency::AlphaTest(_) => false,
+            Transparency::AlphaBlend | Transparency::Additive => true,
+        }
+    }
+}
+
+// The default grey ambient color applied when no environment is set.
+pub const DEFAULT_AMBIENT: GLfloat = 0.03;
+
+// The number of textures that a Material binds when drawn.
+pub const MATERIAL_TEXTURE_COUNT: usize = 5;
 
 // Describes a material for a model that contains a color, diffuse map, specular map, and a
-// shininess factor for specular. This can only be created after the window context is set up.
+// shininess factor for specular. For metallic-roughness materials, the color and diffuse map are
+// the base color, and the metallic and roughness factors scale the metallic-roughness map. Every
+// material also has an ambient color, a specular tint (Blinn-Phong only), an emissive color that
+// scales the emissive map, and a transparency mode which defaults to opaque. Texture IDs of 0 are
+// treated as white. This can only be created after the window context is set up.
 pub struct Material {
+    pub shading: ShadingModel,
     pub color: color::Color,
     pub diffuse: GLuint,
     pub specular: GLuint,
     pub normal: Option<GLuint>,
     pub shininess: GLfloat,
+    pub metallic: GLfloat,
+    pub roughness: GLfloat,
+    pub metallic_roughness: GLuint,
+    pub occlusion: GLuint,
+    pub emissive: GLuint,
+    pub emissive_color: color::Color,
+    pub ambient_color: color::Color,
+    pub specular_color: color::Color,
+    pub transparency: Transparency,
 }
 
 impl Material {
     // Default constructor that automatically assigns a white color given a shininess and paths to
-    // the diffuse and specular maps as BMPs.
+    // the diffuse and specular maps as images.
     pub fn new(diffuse_name: Option<&str>, specular_name: Option<&str>,
             normal_name: Option<&str>, shininess: GLfloat) -> Material {
         Material::new_with_color(diffuse_name, specular_name, normal_name,
@@ -45,11 +99,12 @@ impl Material {
         texture_id
     }}
 
-    // Reads and binds a BMP texture given a name and returns the corresponding texture ID. This
-    // method also lets the caller specify if the texture should be in sRGB space or not.
-    fn read_and_bind_bmp(texture_name: Option<&str>, srgb: bool) -> GLuint {
+    // Reads and binds a texture given a name and returns the corresponding texture ID. The decoder
+    // is chosen from the file's signature. This method also lets the caller specify if the texture
+    // should be in sRGB space or not.
+    fn read_and_bind_image(texture_name: Option<&str>, srgb: bool) -> GLuint {
         if let Some(name) = texture_name {
-            let texture = bmp::decode_bmp(name).unwrap().image;
+            let texture = image::decode_image(name).unwrap();
             Material::bind_image(&texture, srgb)
         } else { 0 }
     }
@@ -65,21 +120,76 @@ impl Material {
         let normal_handle = match normal {
             &Some(ref i) => Some(Material::bind_image(i, false)),
             &None => None };
-        Material { color: color, diffuse: diffuse_handle, specular: specular_handle,
-                normal: normal_handle, shininess: shininess }
+        Material { shading: ShadingModel::BlinnPhong, color: color, diffuse: diffuse_handle,
+                specular: specular_handle, normal: normal_handle, shininess: shininess,
+                metallic: 0.0, roughness: 1.0, metallic_roughness: 0, occlusion: 0, emissive: 0,
+                emissive_color: color::Color::new_rgb(0.0, 0.0, 0.0),
+                ambient_color: Material::default_ambient(),
+                specular_color: color::Color::new_rgb(1.0, 1.0, 1.0),
+                transparency: Transparency::Opaque }
     }
 
     // Creates a Material with paths to diffuse and specular maps, shiniess, and color.
     pub fn new_with_color(diffuse_name: Option<&str>, specular_name: Option<&str>,
             normal_name: Option<&str>, color: color::Color, shininess: GLfloat) -> Material {
-        let diffuse = Material::read_and_bind_bmp(diffuse_name, true);
-        let specular = Material::read_and_bind_bmp(specular_name, false);
+        let diffuse = Material::read_and_bind_image(diffuse_name, true);
+        let specular = Material::read_and_bind_image(specular_name, false);
         // TODO: Just use the rgb vec.
         let normal = match normal_name {
-            Some(_) => Some(Material::read_and_bind_bmp(normal_name, false)),
+            Some(_) => Some(Material::read_and_bind_image(normal_name, false)),
             None => None,
         };
-        Material { color: color, diffuse: diffuse, specular: specular, normal: normal,
-                shininess: shininess }
+        Material { shading: ShadingModel::BlinnPhong, color: color, diffuse: diffuse,
+                specular: specular, normal: normal, shininess: shininess, metallic: 0.0,
+                roughness: 1.0, metallic_roughness: 0, occlusion: 0, emissive: 0,
+                emissive_color: color::Color::new_rgb(0.0, 0.0, 0.0),
+                ambient_color: Material::default_ambient(),
+                specular_color: color::Color::new_rgb(1.0, 1.0, 1.0),
+                transparency: Transparency::Opaque }
+    }
+
+    // Creates a metallic-roughness Material given paths to the base color, metallic-roughness,
+    // normal, occlusion, and emissive maps as images along with the base color and the metallic and
+    // roughness factors. If an emissive map is given, the emissive color defaults to white so the
+    // map is shown as is.
+    pub fn new_metallic_roughness(base_color_name: Option<&str>,
+            metallic_roughness_name: Option<&str>, normal_name: Option<&str>,
+            occlusion_name: Option<&str>, emissive_name: Option<&str>, color: color::Color,
+            metallic: GLfloat, roughness: GLfloat) -> Material {
+        let emissive_color = match emissive_name {
+            Some(_) => color::Color::new_rgb(1.0, 1.0, 1.0),
+            None => color::Color::new_rgb(0.0, 0.0, 0.0),
+        };
+        let mut material = Material::new_with_color(base_color_name, None, normal_name, color, 0.0);
+        material.shading = ShadingModel::MetallicRoughness;
+        material.metallic = metallic;
+        material.roughness = roughness;
+        material.metallic_roughness = Material::read_and_bind_image(metallic_roughness_name, false);
+        material.occlusion = Material::read_and_bind_image(occlusion_name, false);
+        material.emissive = Material::read_and_bind_image(emissive_name, true);
+        material.emissive_color = emissive_color;
+        material
+    }
+
+    // Sets the emissive map of the Material given a path to an image along with the color it is
+    // scaled by. This works with both shading models, so a Blinn-Phong material can also glow.
+    pub fn set_emissive(&mut self, emissive_name: Option<&str>, emissive_color: color::Color) {
+        self.emissive = Material::read_and_bind_image(emissive_name, true);
+        self.emissive_color = emissive_color;
+    }
+
+    // Gets the texture IDs sampled by the Material in the order of their texture units: diffuse
+    // (or base color), specular (or metallic-roughness), normal, occlusion, and emissive.
+    pub fn get_textures(&self) -> [GLuint; MATERIAL_TEXTURE_COUNT] {
+        let specular = match self.shading {
+            ShadingModel::BlinnPhong => self.specular,
+            ShadingModel::MetallicRoughness => self.metallic_roughness,
+        };
+        [self.diffuse, specular, self.normal.unwrap_or(0), self.occlusion, self.emissive]
     }
-}
\ No newline at end of file
+
+    // Helper method that gets the default grey ambient color.
+    fn default_ambient() -> color::Color {
+        color::Color::new_rgb(DEFAULT_AMBIENT, DEFAULT_AMBIENT, DEFAULT_AMBIENT)
+    }
+}
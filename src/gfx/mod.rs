@@ -1,10 +1,19 @@
 #[macro_use]
 mod macros;
 
+pub mod allocator;
+pub mod animation;
 pub mod camera;
+pub mod cluster;
 pub mod color;
+pub mod environment;
 pub mod game_window;
+pub mod handle;
 pub mod light;
 pub mod material;
 pub mod model;
-pub mod types;
\ No newline at end of file
+pub mod render_queue;
+pub mod scene;
+pub mod shadow;
+pub mod types;
+pub mod vertex;
\ No newline at end of file
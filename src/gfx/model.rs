@@ -10,22 +10,56 @@
 extern crate cgmath;
 
 use self::cgmath::{Matrix, SquareMatrix};
+use gfx::animation;
 use gfx::color;
 use gfx::material;
 use gfx::types::*;
+use gfx::vertex;
 use std::cell::Cell;
+use std::cmp;
 use std::rc::Rc;
 use util::{common, obj, rmod};
 
+// The maximum number of morph targets that can be blended into a single draw. Instances with more
+// weighted targets only blend the targets with the largest weights.
+pub const MAX_ACTIVE_MORPH_TARGETS: usize = 8;
+
+// The location of a ModelInfo in the engine's managed buffers. The elements start at an offset
+// into the EBO and index vertices relative to the base vertex in the VBO. ModelInfos with morph
+// targets also have a buffer texture holding the deltas of every target (or 0 if they do not).
 #[derive(Copy, Clone)]
 pub struct BufferInfo {
     pub gen: usize,
     pub start: usize,
     pub size: usize,
+    pub base_vertex: usize,
     pub vao: GLuint,
+    pub morph_texture: GLuint,
+}
+
+// A named morph target (blend shape) of a ModelInfo. This stores the offset of every vertex's
+// position and normal from the base mesh when the target is fully applied. The normals may be
+// empty if the target does not change them.
+pub struct MorphTarget {
+    pub name: String,
+    pub positions: Vec<GLfloat>,
+    pub normals: Vec<GLfloat>,
+}
+
+impl MorphTarget {
+    // Default constructor given the position and normal deltas.
+    pub fn new(name: &str, positions: Vec<GLfloat>, normals: Vec<GLfloat>) -> MorphTarget {
+        MorphTarget { name: name.to_string(), positions: positions, normals: normals }
+    }
 }
 
 // Stores information about the model which can be instantiated to create a ModelInstance. 
+// Only the attributes in the layout are uploaded to the GPU. Attributes in the layout without any
+// data (such as colors, which are empty by default) are filled in with their default values.
+// Skinned ModelInfos have a skeleton along with the indices and weights of the four joints that
+// influence each vertex (stored as floats), and they use the skinned layout. Morph targets are
+// blended on the GPU from a separate buffer, so they do not change the layout. They can only be
+// added through add_morph_target() so that every target has a delta for each vertex.
 pub struct ModelInfo {
     pub vertices: Vec<GLfloat>,
     pub normals: Vec<GLfloat>,
@@ -33,6 +67,14 @@ pub struct ModelInfo {
     pub tangents: Vec<GLfloat>,
     pub elements: Vec<GLuint>,
     pub tcoords: Vec<GLfloat>,
+    pub colors: Vec<GLfloat>,
+    pub tcoords2: Vec<GLfloat>,
+    pub joints: Vec<GLfloat>,
+    pub weights: Vec<GLfloat>,
+    pub skeleton: Option<animation::Skeleton>,
+    pub animations: Vec<Rc<animation::AnimationClip>>,
+    morph_targets: Vec<MorphTarget>,
+    pub layout: vertex::VertexLayout,
     pub mat: material::Material,
     pub buffer_info: Cell<Option<BufferInfo>>,
 }
@@ -43,11 +85,13 @@ impl ModelInfo {
             tangents: Vec<GLfloat>, bitangents: Vec<GLfloat>, tcoords: Vec<GLfloat>,
             mat: material::Material) -> ModelInfo {
         ModelInfo { vertices: vertices, normals: normals, tangents: tangents,
-                bitangents: bitangents, elements: elems, tcoords: tcoords, mat: mat,
-                buffer_info: Cell::new(None) }
+                bitangents: bitangents, elements: elems, tcoords: tcoords, colors: Vec::new(),
+                tcoords2: Vec::new(), joints: Vec::new(), weights: Vec::new(), skeleton: None,
+                animations: Vec::new(), morph_targets: Vec::new(),
+                layout: vertex::VertexLayout::standard(), mat: mat, buffer_info: Cell::new(None) }
     }
 
-    // Creates a box with specified size and color.
+    // Creates a box with specified size and color. The box only has positions and normals.
     pub fn new_box(scale_x: f32, scale_y: f32, scale_z: f32,
             mat: material::Material) -> ModelInfo {
         let vertices: Vec<GLfloat> = vec![
@@ -66,10 +110,10 @@ impl ModelInfo {
                 1, 5, 5, 4, 0, 3, 2, 6, 6, 7, 8,
         ];
         let normals: Vec<GLfloat> = vec![0.0; 9 * 3];
-        let tangents: Vec<GLfloat> = vec![0.0; 9 * 3];
-        let bitangents: Vec<GLfloat> = vec![0.0; 9 * 3];
-        let uvs: Vec<GLfloat> = vec![0.0; 9 * 2];
-        ModelInfo::new(vertices, elements, normals, tangents, bitangents, uvs, mat)
+        let mut info = ModelInfo::new(
+                vertices, elements, normals, Vec::new(), Vec::new(), Vec::new(), mat);
+        info.layout = vertex::VertexLayout::simple();
+        info
     }
 
     // Helper method that refactors the lengthy code used to construct the data lists.
@@ -107,7 +151,7 @@ impl ModelInfo {
 
     // Creates a ModelInfo with corresponding Material from the result of a RMOD decoding with a
     // specific color. Does a copy right now despite the inefficiency in order to avoid passing
-    // ownership.
+    // ownership. If the RMOD has a skin section, the ModelInfo is skinned and has its animations.
     pub fn from_rmod_color(rmod: &rmod::DecodedRMOD, color: color::Color) -> ModelInfo {
         let mat =  material::Material::from_images(&rmod.diffuse, &rmod.specular, &rmod.normal,
                 color, rmod.shininess);
@@ -116,7 +160,19 @@ impl ModelInfo {
         for element in &rmod.elements {
             elems.push(element.clone());
         }
-        ModelInfo::new(verts, elems, norms, tans, bitans, tcs, mat)
+        let mut info = ModelInfo::new(verts, elems, norms, tans, bitans, tcs, mat);
+        if let Some(ref skin) = rmod.skin {
+            // The decoder already validates the joint hierarchy and keyframes.
+            info.skeleton = Some(animation::Skeleton::from_rmod(skin).unwrap());
+            info.animations = skin.animations.iter().map(|a|
+                    Rc::new(animation::AnimationClip::from_rmod(a).unwrap())).collect();
+            for (indices, weights) in skin.joint_indices.iter().zip(skin.joint_weights.iter()) {
+                info.joints.extend(indices.iter().map(|&i| i as GLfloat));
+                info.weights.extend(weights.iter());
+            }
+            info.layout = vertex::VertexLayout::skinned();
+        }
+        info
     }
 
     // Creates a ModelInfo from the result of a OBJ decoding.
@@ -131,36 +187,98 @@ impl ModelInfo {
         ModelInfo::new(verts, elems, norms, tans, bitans, tcs, mat)
     }
 
-    // Gets a single vector representing the the ModelInfo in VBO format.
+    // Gets the per-vertex data of an attribute.
+    pub fn get_attribute_data(&self, attribute: vertex::VertexAttribute) -> &[GLfloat] {
+        match attribute {
+            vertex::VertexAttribute::Position => &self.vertices,
+            vertex::VertexAttribute::Normal => &self.normals,
+            vertex::VertexAttribute::Tangent => &self.tangents,
+            vertex::VertexAttribute::Bitangent => &self.bitangents,
+            vertex::VertexAttribute::TCoord => &self.tcoords,
+            vertex::VertexAttribute::Color => &self.colors,
+            vertex::VertexAttribute::TCoord2 => &self.tcoords2,
+            vertex::VertexAttribute::Joints => &self.joints,
+            vertex::VertexAttribute::Weights => &self.weights,
+        }
+    }
+
+    // Finds one of the ModelInfo's animations by name.
+    pub fn find_animation(&self, name: &str) -> Option<Rc<animation::AnimationClip>> {
+        self.animations.iter().find(|a| a.name == name).cloned()
+    }
+
+    // Adds a morph target to the ModelInfo. Returns Err if the target does not have a delta for
+    // every vertex or if a target with the same name already exists. This must be done before the
+    // ModelInfo is first drawn.
+    pub fn add_morph_target(&mut self, target: MorphTarget) -> Result<(), String> {
+        let size = self.vertices.len();
+        if target.positions.len() != size || !(target.normals.is_empty() ||
+                target.normals.len() == size) {
+            return Err(format!("Morph target {} does not match the vertex count.", target.name));
+        }
+        if self.find_morph_target(&target.name).is_some() {
+            return Err(format!("Morph target {} already exists.", target.name));
+        }
+        self.morph_targets.push(target);
+        Ok(())
+    }
+
+    // Gets the morph targets of the ModelInfo.
+    pub fn get_morph_targets(&self) -> &[MorphTarget] { &self.morph_targets }
+
+    // Finds the index of a morph target by name.
+    pub fn find_morph_target(&self, name: &str) -> Option<usize> {
+        self.morph_targets.iter().position(|t| t.name == name)
+    }
+
+    // Gets the deltas of every morph target in the format of the morph buffer. Each target stores
+    // a position texel and a normal texel for every vertex.
+    pub fn get_morph_format(&self) -> Vec<GLfloat> {
+        let count = self.get_vertex_count();
+        let mut deltas: Vec<GLfloat> = Vec::with_capacity(self.morph_targets.len() * count * 8);
+        for target in self.morph_targets.iter() {
+            for x in 0..count {
+                deltas.extend(target.positions[x * 3..(x + 1) * 3].iter());
+                deltas.push(0.0);
+                if target.normals.is_empty() {
+                    deltas.extend([0.0, 0.0, 0.0].iter());
+                } else {
+                    deltas.extend(target.normals[x * 3..(x + 1) * 3].iter());
+                }
+                deltas.push(0.0);
+            }
+        }
+        deltas
+    }
+
+    // Gets the number of vertices in the ModelInfo.
+    pub fn get_vertex_count(&self) -> usize { self.vertices.len() / 3 }
+
+    // Gets a single vector representing the the ModelInfo in VBO format. The attributes of each
+    // vertex are interleaved in the order of the layout.
     pub fn get_vbo_format(&self) -> Vec<GLfloat> {
-        let mut vertices: Vec<GLfloat> = Vec::new();
-        let mut y = 0;
-        for x in 0..self.vertices.len() {
-            if x % 3 != 0 {
-                continue;
+        let count = self.get_vertex_count();
+        let mut vertices: Vec<GLfloat> = Vec::with_capacity(count * self.layout.get_stride());
+        let attributes: Vec<(usize, &[GLfloat], [GLfloat; 4])> =
+                self.layout.get_attributes().iter().map(|a|
+                (a.get_size(), self.get_attribute_data(*a), a.get_default())).collect();
+        for x in 0..count {
+            for &(size, data, default) in attributes.iter() {
+                if data.len() >= (x + 1) * size {
+                    vertices.extend(data[x * size..(x + 1) * size].iter());
+                } else {
+                    vertices.extend(default[..size].iter());
+                }
             }
-            vertices.push(self.vertices[x]);
-            vertices.push(self.vertices[x + 1]);
-            vertices.push(self.vertices[x + 2]);
-            vertices.push(self.normals[x]);
-            vertices.push(self.normals[x + 1]);
-            vertices.push(self.normals[x + 2]);
-            vertices.push(self.tangents[x]);
-            vertices.push(self.tangents[x + 1]);
-            vertices.push(self.tangents[x + 2]);
-            vertices.push(self.bitangents[x]);
-            vertices.push(self.bitangents[x + 1]);
-            vertices.push(self.bitangents[x + 2]);
-            vertices.push(self.tcoords[y]);
-            vertices.push(self.tcoords[y + 1]);
-            y += 2;
         }
         vertices
     }
 }
 
 // An instantiazation of a ModelInfo that represents a model in-game. This has a variety of
-// positional attributes used to render the instance.
+// positional attributes used to render the instance. Instances of skinned ModelInfos also have the
+// skinning matrices of their current pose, and are drawn in the bind pose until one is set. The
+// morph weights are indexed by morph target and missing weights are treated as 0.
 pub struct ModelInstance {
     pub info: Rc<ModelInfo>,
     pub pos: Vector3D,
@@ -168,6 +286,8 @@ pub struct ModelInstance {
     pub scale: f32,
     pub model: cgmath::Matrix4<GLfloat>,
     pub normal: cgmath::Matrix4<GLfloat>,
+    pub joint_matrices: Vec<cgmath::Matrix4<GLfloat>>,
+    pub morph_weights: Vec<GLfloat>,
 }
 
 impl ModelInstance {
@@ -179,16 +299,60 @@ impl ModelInstance {
         let model = cgmath::Matrix4::from(cgmath::Decomposed {
                 scale: scale, rot: rot, disp: pos });
         let norm = model.clone().invert().unwrap().transpose();
-        ModelInstance { info: info, pos: pos, scale: scale, rot: rot, model: model, normal: norm }
+        ModelInstance { info: info, pos: pos, scale: scale, rot: rot, model: model, normal: norm,
+                joint_matrices: Vec::new(), morph_weights: Vec::new() }
     }
 
     // Updates the model and normal matrices. This must be called after any sequence of struct
     // field changes for the changes to appear in-world.
     pub fn update(&mut self) {
-        let model = cgmath::Matrix4::from(cgmath::Decomposed {
+        self.update_with_parent(cgmath::Matrix4::identity());
+    }
+
+    // Updates the model and normal matrices where the position, rotation, and scale are relative
+    // to a parent transform (such as a node of a SceneGraph).
+    pub fn update_with_parent(&mut self, parent: cgmath::Matrix4<GLfloat>) {
+        let model = parent * cgmath::Matrix4::from(cgmath::Decomposed {
                 scale: self.scale, rot: self.rot, disp: self.pos });
         let normal = model.clone().invert().unwrap().transpose();
         self.model = model;
         self.normal = normal;
     }
+
+    // Poses the instance's skeleton by computing its skinning matrices. Returns Err if the
+    // ModelInfo is not skinned or if the Pose does not match its skeleton.
+    pub fn set_pose(&mut self, pose: &animation::Pose) -> Result<(), String> {
+        let matrices = match self.info.skeleton {
+            Some(ref skeleton) => try!(skeleton.get_skinning_matrices(pose)),
+            None => { return Err("ModelInfo does not have a skeleton.".to_string()); },
+        };
+        self.joint_matrices = matrices;
+        Ok(())
+    }
+
+    // Sets the weight of a morph target by name. Returns Err if the ModelInfo does not have a
+    // morph target with that name.
+    pub fn set_morph_weight(&mut self, name: &str, weight: GLfloat) -> Result<(), String> {
+        let index = try!(self.info.find_morph_target(name).ok_or(
+                format!("ModelInfo does not have a morph target named {}.", name)));
+        if self.morph_weights.len() <= index {
+            self.morph_weights.resize(index + 1, 0.0);
+        }
+        self.morph_weights[index] = weight;
+        Ok(())
+    }
+
+    // Gets the (target, weight) pairs of the morph targets blended into the instance. Only the
+    // MAX_ACTIVE_MORPH_TARGETS targets with the largest nonzero weights are returned.
+    pub fn get_active_morph_targets(&self) -> Vec<(usize, GLfloat)> {
+        let count = self.info.get_morph_targets().len();
+        let mut active: Vec<(usize, GLfloat)> = self.morph_weights.iter().cloned().enumerate()
+                .filter(|&(i, w)| i < count && w != 0.0).collect();
+        active.sort_by(|a, b| b.1.abs().partial_cmp(&a.1.abs()).unwrap_or(cmp::Ordering::Equal));
+        active.truncate(MAX_ACTIVE_MORPH_TARGETS);
+        active
+    }
+
+    // Whether the instance is drawn with skinning.
+    pub fn is_skinned(&self) -> bool { !self.joint_matrices.is_empty() }
 }
@@ -0,0 +1,165 @@
+// Defines the RenderQueue which records the draws of a frame so that they can be reordered before
+// any GL state is touched. Opaque draws are sorted by shader program, VAO, and material textures
+// so that consecutive draws share as much state as possible, and then front-to-back so that early
+// depth testing rejects hidden fragments. Blended draws are kept separately and sorted
+// back-to-front so that they composite correctly. Consecutive draws of the same ModelInfo are
+// grouped into batches which the GameWindow draws with a single instanced draw call. This does not
+// touch any GL state, so the GameWindow is responsible for issuing the draws and filling in the
+// RenderStats.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use gfx::material;
+use gfx::model;
+use gfx::types::*;
+use std::cmp;
+use std::ops;
+use std::rc::Rc;
+
+// Counts of the GL state changes made while flushing a RenderQueue. A change is counted as saved
+// whenever a draw reuses the program, VAO, texture, or material uniforms of the previous draw.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RenderStats {
+    pub draw_calls: usize,
+    pub instanced_draw_calls: usize,
+    pub instances: usize,
+    pub program_changes: usize,
+    pub vao_changes: usize,
+    pub texture_changes: usize,
+    pub material_changes: usize,
+    pub changes_saved: usize,
+}
+
+// A single recorded draw of a ModelInfo along with the matrices of the instance it came from. The
+// depth is the view space depth of the instance's position and is filled in when sorted. Skinned
+// and morphed draws also record the skinning matrices and active morph targets of the instance.
+pub struct DrawCommand {
+    pub program: GLuint,
+    pub info: Rc<model::ModelInfo>,
+    pub pos: Vector3D,
+    pub model: cgmath::Matrix4<GLfloat>,
+    pub normal: cgmath::Matrix4<GLfloat>,
+    pub joint_matrices: Vec<cgmath::Matrix4<GLfloat>>,
+    pub morph_targets: Vec<(usize, GLfloat)>,
+    pub depth: GLfloat,
+}
+
+impl DrawCommand {
+    // Default constructor that records a ModelInstance drawn with a program.
+    pub fn new(program: GLuint, instance: &model::ModelInstance) -> DrawCommand {
+        DrawCommand { program: program, info: instance.info.clone(), pos: instance.pos,
+                model: instance.model, normal: instance.normal,
+                joint_matrices: instance.joint_matrices.clone(),
+                morph_targets: instance.get_active_morph_targets(), depth: 0.0 }
+    }
+
+    // Gets the material of the recorded ModelInfo.
+    pub fn get_material(&self) -> &material::Material { &self.info.mat }
+
+    // Gets the VAO of the recorded ModelInfo or 0 if it has not been mapped yet.
+    pub fn get_vao(&self) -> GLuint {
+        match self.info.buffer_info.get() {
+            Some(info) => info.vao,
+            None => 0,
+        }
+    }
+
+    // Whether this command draws the same ModelInfo as another, in which case they share a
+    // material and its uniforms do not have to be uploaded again.
+    pub fn shares_material(&self, other: &DrawCommand) -> bool {
+        self.get_info_id() == other.get_info_id()
+    }
+
+    // Whether this command can be drawn in the same instanced draw call as another. Deformed
+    // commands are always drawn on their own since each has its own skinning matrices or morph
+    // weights.
+    pub fn can_batch_with(&self, other: &DrawCommand) -> bool {
+        self.program == other.program && self.shares_material(other) &&
+                !self.is_deformed() && !other.is_deformed()
+    }
+
+    // Whether the command is drawn with skinning or morph targets.
+    pub fn is_deformed(&self) -> bool {
+        !self.joint_matrices.is_empty() || !self.morph_targets.is_empty()
+    }
+
+    // Helper method that gets a value identifying the recorded ModelInfo.
+    fn get_info_id(&self) -> usize { &*self.info as *const model::ModelInfo as usize }
+
+    // Helper method that orders two opaque commands by their GL state, then by ModelInfo so that
+    // they can be batched, and then front-to-back.
+    fn compare_opaque(&self, other: &DrawCommand) -> cmp::Ordering {
+        let state = (self.program, self.get_vao(), self.get_material().get_textures(),
+                self.get_info_id()).cmp(&(other.program, other.get_vao(),
+                other.get_material().get_textures(), other.get_info_id()));
+        if state != cmp::Ordering::Equal { return state; }
+        self.depth.partial_cmp(&other.depth).unwrap_or(cmp::Ordering::Equal)
+    }
+}
+
+// The draws recorded over a frame, split by whether their materials are blended.
+pub struct RenderQueue {
+    pub opaque: Vec<DrawCommand>,
+    pub blended: Vec<DrawCommand>,
+}
+
+impl RenderQueue {
+    // Default constructor for an empty RenderQueue.
+    pub fn new() -> RenderQueue {
+        RenderQueue { opaque: Vec::new(), blended: Vec::new() }
+    }
+
+    // Records a ModelInstance to be drawn with a program.
+    pub fn submit(&mut self, program: GLuint, instance: &model::ModelInstance) {
+        let command = DrawCommand::new(program, instance);
+        if command.get_material().transparency.is_blended() {
+            self.blended.push(command);
+        } else {
+            self.opaque.push(command);
+        }
+    }
+
+    // Gets the number of recorded draws.
+    pub fn len(&self) -> usize { self.opaque.len() + self.blended.len() }
+
+    // Whether there are no recorded draws.
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    // Removes every recorded draw while keeping the allocated space for the next frame.
+    pub fn clear(&mut self) {
+        self.opaque.clear();
+        self.blended.clear();
+    }
+
+    // Sorts the recorded draws given the view matrix of the camera they will be drawn with. Every
+    // ModelInfo should be mapped to the engine's VBO space beforehand so that draws sharing a VAO
+    // end up next to each other.
+    pub fn sort(&mut self, view: cgmath::Matrix4<GLfloat>) {
+        for command in self.opaque.iter_mut().chain(self.blended.iter_mut()) {
+            command.depth = -(view * command.pos.extend(1.0)).z;
+        }
+        self.opaque.sort_by(|a, b| a.compare_opaque(b));
+        self.blended.sort_by(|a, b| b.depth.partial_cmp(&a.depth).unwrap_or(cmp::Ordering::Equal));
+    }
+}
+
+// Splits a sorted list of commands into batches of consecutive commands that can be drawn with a
+// single instanced draw call, with no more than max_batch commands in a batch. Each batch is
+// returned as the range of its commands in the list.
+pub fn get_batches(commands: &[DrawCommand], max_batch: usize) -> Vec<ops::Range<usize>> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+    while start < commands.len() {
+        let mut end = start + 1;
+        while end < commands.len() && end - start < max_batch &&
+                commands[end].can_batch_with(&commands[start]) {
+            end += 1;
+        }
+        batches.push(start..end);
+        start = end;
+    }
+    batches
+}
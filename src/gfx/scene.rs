@@ -0,0 +1,355 @@
+// Defines the SceneGraph which is a hierarchy of transform nodes. Each node has a position,
+// rotation, and scale relative to its parent, and the graph caches the world and normal matrices
+// of every node. Changing a node only marks it as dirty, and update() recomputes the matrices of
+// the dirty nodes and all of their descendants in a single pass. ModelInstances can be placed
+// under a node, and cameras and lights can be attached to a node so that the GameWindow moves them
+// with it.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+
+use self::cgmath::{Matrix, SquareMatrix};
+use gfx::handle::{CameraHandle, DirectionalLightHandle, Handle, HandleMap, PointLightHandle,
+        SpotLightHandle};
+use gfx::model;
+use gfx::types::*;
+
+// The camera or light that an Attachment moves.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AttachmentKind {
+    Camera(CameraHandle),
+    PointLight(PointLightHandle),
+    DirectionalLight(DirectionalLightHandle),
+    SpotLight(SpotLightHandle),
+}
+
+// A camera or light attached to a node along with its offset and direction in the node's space.
+// The direction of a camera points from its position to its target.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Attachment {
+    pub kind: AttachmentKind,
+    pub offset: Vector3D,
+    pub direction: Vector3D,
+}
+
+impl Attachment {
+    // Attaches a camera with a position and target in the node's space.
+    pub fn camera(handle: CameraHandle, pos: Vector3D, target: Vector3D) -> Attachment {
+        Attachment { kind: AttachmentKind::Camera(handle), offset: pos, direction: target - pos }
+    }
+
+    // Attaches a PointLight with a position in the node's space.
+    pub fn point_light(handle: PointLightHandle, offset: Vector3D) -> Attachment {
+        Attachment { kind: AttachmentKind::PointLight(handle), offset: offset,
+                direction: Vector3D::new(0.0, 0.0, 0.0) }
+    }
+
+    // Attaches a DirectionalLight with a direction in the node's space.
+    pub fn directional_light(handle: DirectionalLightHandle, direction: Vector3D) -> Attachment {
+        Attachment { kind: AttachmentKind::DirectionalLight(handle),
+                offset: Vector3D::new(0.0, 0.0, 0.0), direction: direction }
+    }
+
+    // Attaches a SpotLight with a position and direction in the node's space.
+    pub fn spot_light(handle: SpotLightHandle, offset: Vector3D, direction: Vector3D) ->
+            Attachment {
+        Attachment { kind: AttachmentKind::SpotLight(handle), offset: offset, direction: direction }
+    }
+}
+
+// A single node of the SceneGraph.
+pub struct SceneNode {
+    pos: Vector3D,
+    rot: Quaternion,
+    scale: f32,
+    parent: Option<NodeHandle>,
+    children: Vec<NodeHandle>,
+    world: cgmath::Matrix4<GLfloat>,
+    normal: cgmath::Matrix4<GLfloat>,
+    dirty: bool,
+    pub attachments: Vec<Attachment>,
+}
+
+impl SceneNode {
+    // Gets the position relative to the parent.
+    pub fn get_pos(&self) -> Vector3D { self.pos }
+
+    // Gets the rotation relative to the parent.
+    pub fn get_rot(&self) -> Quaternion { self.rot }
+
+    // Gets the scale relative to the parent.
+    pub fn get_scale(&self) -> f32 { self.scale }
+
+    // Gets the parent of the node or None if it is a root.
+    pub fn get_parent(&self) -> Option<NodeHandle> { self.parent }
+
+    // Gets the children of the node.
+    pub fn get_children(&self) -> &[NodeHandle] { &self.children }
+
+    // Gets the matrix that transforms the node's space into world space as of the last update.
+    pub fn get_world_matrix(&self) -> cgmath::Matrix4<GLfloat> { self.world }
+
+    // Gets the matrix that transforms normals in the node's space into world space as of the last
+    // update.
+    pub fn get_normal_matrix(&self) -> cgmath::Matrix4<GLfloat> { self.normal }
+
+    // Gets the position of the node in world space as of the last update.
+    pub fn get_world_pos(&self) -> Vector3D { self.world.w.truncate() }
+
+    // Whether the node has changed since the last update.
+    pub fn is_dirty(&self) -> bool { self.dirty }
+
+    // Helper method that gets the matrix of the node's transform relative to its parent.
+    fn get_local_matrix(&self) -> cgmath::Matrix4<GLfloat> {
+        cgmath::Matrix4::from(cgmath::Decomposed {
+                scale: self.scale, rot: self.rot, disp: self.pos })
+    }
+}
+
+// The handle of a node in a SceneGraph.
+pub type NodeHandle = Handle<SceneNode>;
+
+// A hierarchy of nodes. Nodes are referred to by generational handles, so the handle of a removed
+// node returns Err instead of referring to a node that later reused its index.
+pub struct SceneGraph {
+    nodes: HandleMap<SceneNode>,
+}
+
+impl SceneGraph {
+    // Default constructor for an empty SceneGraph.
+    pub fn new() -> SceneGraph {
+        SceneGraph { nodes: HandleMap::new("scene node") }
+    }
+
+    // Adds a node at its parent's origin (or the world origin if there is no parent) and returns
+    // its handle. Returns Err if the parent does not exist.
+    pub fn add_node(&mut self, parent: Option<NodeHandle>) -> Result<NodeHandle, String> {
+        if let Some(p) = parent { try!(self.get_node(p)); }
+        let identity = cgmath::Matrix4::identity();
+        let node = SceneNode { pos: Vector3D::new(0.0, 0.0, 0.0),
+                rot: Quaternion::new(1.0, 0.0, 0.0, 0.0), scale: 1.0, parent: parent,
+                children: Vec::new(), world: identity, normal: identity, dirty: true,
+                attachments: Vec::new() };
+        let handle = self.nodes.insert(node);
+        if let Some(p) = parent { self.nodes.get_mut(p).unwrap().children.push(handle); }
+        Ok(handle)
+    }
+
+    // Removes a node along with all of its descendants. Returns Err if the node does not exist.
+    pub fn remove_node(&mut self, handle: NodeHandle) -> Result<(), String> {
+        let parent = try!(self.get_node(handle)).parent;
+        if let Some(p) = parent {
+            self.nodes.get_mut(p).unwrap().children.retain(|&c| c != handle);
+        }
+        let mut stack = vec![handle];
+        while let Some(h) = stack.pop() {
+            let node = self.nodes.remove(h).unwrap();
+            stack.extend(node.children.iter());
+        }
+        Ok(())
+    }
+
+    // Gets an immutable reference to a node given its handle. Returns Err if it does not exist.
+    pub fn get_node(&self, handle: NodeHandle) -> Result<&SceneNode, String> {
+        self.nodes.get(handle)
+    }
+
+    // Helper method that gets a mutable reference to a node and marks it as dirty.
+    fn get_dirty_node(&mut self, handle: NodeHandle) -> Result<&mut SceneNode, String> {
+        let node = try!(self.nodes.get_mut(handle));
+        node.dirty = true;
+        Ok(node)
+    }
+
+    // Moves a node under a new parent (or to the root if None) while keeping its transform
+    // relative to its parent. Returns Err if either node does not exist or if the new parent is
+    // the node itself or one of its descendants.
+    pub fn set_parent(&mut self, handle: NodeHandle, parent: Option<NodeHandle>) ->
+            Result<(), String> {
+        let old_parent = try!(self.get_node(handle)).parent;
+        let mut ancestor = parent;
+        while let Some(a) = ancestor {
+            if a == handle { return Err("A scene node cannot be its own ancestor.".to_string()); }
+            ancestor = try!(self.get_node(a)).parent;
+        }
+        if let Some(p) = old_parent {
+            self.nodes.get_mut(p).unwrap().children.retain(|&c| c != handle);
+        }
+        if let Some(p) = parent { self.nodes.get_mut(p).unwrap().children.push(handle); }
+        try!(self.get_dirty_node(handle)).parent = parent;
+        Ok(())
+    }
+
+    // Sets the position of a node relative to its parent.
+    pub fn set_pos(&mut self, handle: NodeHandle, pos: Vector3D) -> Result<(), String> {
+        try!(self.get_dirty_node(handle)).pos = pos;
+        Ok(())
+    }
+
+    // Sets the rotation of a node relative to its parent.
+    pub fn set_rot(&mut self, handle: NodeHandle, rot: Quaternion) -> Result<(), String> {
+        try!(self.get_dirty_node(handle)).rot = rot;
+        Ok(())
+    }
+
+    // Sets the scale of a node relative to its parent.
+    pub fn set_scale(&mut self, handle: NodeHandle, scale: f32) -> Result<(), String> {
+        try!(self.get_dirty_node(handle)).scale = scale;
+        Ok(())
+    }
+
+    // Attaches a camera or light to a node. The GameWindow moves it with the node the next time
+    // the scene is updated.
+    pub fn attach(&mut self, handle: NodeHandle, attachment: Attachment) -> Result<(), String> {
+        try!(self.get_dirty_node(handle)).attachments.push(attachment);
+        Ok(())
+    }
+
+    // Detaches a camera or light from whichever node it is attached to. The camera or light stays
+    // where it was last moved to.
+    pub fn detach(&mut self, kind: AttachmentKind) {
+        for handle in self.get_handles() {
+            self.nodes.get_mut(handle).unwrap().attachments.retain(|a| a.kind != kind);
+        }
+    }
+
+    // Recomputes the world and normal matrices of every dirty node and its descendants. Parents
+    // are always updated before their children. Returns the handles of the updated nodes.
+    pub fn update(&mut self) -> Vec<NodeHandle> {
+        let mut updated = Vec::new();
+        let mut stack: Vec<(NodeHandle, bool)> = self.nodes.iter()
+                .filter(|&(_, n)| n.parent.is_none()).map(|(h, _)| (h, false)).collect();
+        while let Some((h, parent_dirty)) = stack.pop() {
+            let parent_world = match self.nodes.get(h).unwrap().parent {
+                Some(p) => self.nodes.get(p).unwrap().world,
+                None => cgmath::Matrix4::identity(),
+            };
+            let node = self.nodes.get_mut(h).unwrap();
+            let dirty = parent_dirty || node.dirty;
+            if dirty {
+                node.world = parent_world * node.get_local_matrix();
+                node.normal = node.world.invert().unwrap_or(node.world).transpose();
+                node.dirty = false;
+                updated.push(h);
+            }
+            stack.extend(node.children.iter().map(|&c| (c, dirty)));
+        }
+        updated
+    }
+
+    // Places a ModelInstance under a node by building its model and normal matrices from its own
+    // position, rotation, and scale relative to the node. Like ModelInstance::update(), this must
+    // be called whenever the instance or the node changes.
+    pub fn update_instance(&self, handle: NodeHandle, instance: &mut model::ModelInstance) ->
+            Result<(), String> {
+        let world = try!(self.get_node(handle)).world;
+        instance.update_with_parent(world);
+        Ok(())
+    }
+
+    // Transforms an Attachment from its node's space into world space and returns the world
+    // position and direction. Directions are not normalized.
+    pub fn get_attachment_transform(&self, handle: NodeHandle, attachment: &Attachment) ->
+            Result<(Vector3D, Vector3D), String> {
+        let world = try!(self.get_node(handle)).world;
+        let offset = (world * attachment.offset.extend(1.0)).truncate();
+        let direction = (world * attachment.direction.extend(0.0)).truncate();
+        Ok((offset, direction))
+    }
+
+    // Gets the handles of every node in the graph.
+    pub fn get_handles(&self) -> Vec<NodeHandle> {
+        self.nodes.iter().map(|(h, _)| h).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Builds a root with a child and a grandchild under it and another child on the side.
+    fn create_graph() -> (SceneGraph, [NodeHandle; 4]) {
+        let mut scene = SceneGraph::new();
+        let root = scene.add_node(None).unwrap();
+        let child = scene.add_node(Some(root)).unwrap();
+        let grandchild = scene.add_node(Some(child)).unwrap();
+        let sibling = scene.add_node(Some(root)).unwrap();
+        (scene, [root, child, grandchild, sibling])
+    }
+
+    // Sorts handles by index so that sets of them can be compared.
+    fn sorted(mut handles: Vec<NodeHandle>) -> Vec<usize> {
+        handles.sort_by_key(|h| h.get_index());
+        handles.iter().map(|h| h.get_index()).collect()
+    }
+
+    #[test]
+    fn update_propagates_to_descendants() {
+        let (mut scene, [root, child, grandchild, sibling]) = create_graph();
+        assert_eq!(sorted(scene.update()), sorted(vec![root, child, grandchild, sibling]));
+        assert!(scene.update().is_empty());
+
+        scene.set_pos(root, Vector3D::new(1.0, 0.0, 0.0)).unwrap();
+        scene.set_scale(root, 2.0).unwrap();
+        scene.set_pos(child, Vector3D::new(0.0, 1.0, 0.0)).unwrap();
+        scene.set_pos(grandchild, Vector3D::new(0.0, 0.0, 1.0)).unwrap();
+        assert_eq!(scene.update().len(), 4);
+        let pos = scene.get_node(grandchild).unwrap().get_world_pos();
+        assert_eq!((pos.x, pos.y, pos.z), (1.0, 2.0, 2.0));
+
+        // Only the changed node and its descendants are updated, and they see the new parent.
+        scene.set_pos(child, Vector3D::new(0.0, -1.0, 0.0)).unwrap();
+        assert!(scene.get_node(child).unwrap().is_dirty());
+        assert!(!scene.get_node(grandchild).unwrap().is_dirty());
+        assert_eq!(sorted(scene.update()), sorted(vec![child, grandchild]));
+        let pos = scene.get_node(grandchild).unwrap().get_world_pos();
+        assert_eq!((pos.x, pos.y, pos.z), (1.0, -2.0, 2.0));
+        assert!(!scene.get_node(grandchild).unwrap().is_dirty());
+    }
+
+    #[test]
+    fn set_parent_rejects_cycles() {
+        let (mut scene, [root, child, grandchild, sibling]) = create_graph();
+        assert!(scene.set_parent(root, Some(root)).is_err());
+        assert!(scene.set_parent(root, Some(grandchild)).is_err());
+        assert!(scene.set_parent(child, Some(grandchild)).is_err());
+        assert_eq!(scene.get_node(root).unwrap().get_parent(), None);
+        assert_eq!(scene.get_node(child).unwrap().get_children(), &[grandchild]);
+
+        // Moving a subtree under a sibling updates both parents' children.
+        scene.update();
+        scene.set_parent(child, Some(sibling)).unwrap();
+        assert_eq!(scene.get_node(root).unwrap().get_children(), &[sibling]);
+        assert_eq!(scene.get_node(sibling).unwrap().get_children(), &[child]);
+        assert_eq!(scene.get_node(child).unwrap().get_parent(), Some(sibling));
+        assert_eq!(sorted(scene.update()), sorted(vec![child, grandchild]));
+
+        scene.set_parent(grandchild, None).unwrap();
+        assert!(scene.get_node(child).unwrap().get_children().is_empty());
+        assert!(scene.set_parent(root, Some(grandchild)).is_ok());
+    }
+
+    #[test]
+    fn remove_node_removes_subtree() {
+        let (mut scene, [root, child, grandchild, sibling]) = create_graph();
+        scene.remove_node(child).unwrap();
+        assert!(scene.get_node(child).is_err() && scene.get_node(grandchild).is_err());
+        assert_eq!(scene.get_node(root).unwrap().get_children(), &[sibling]);
+        assert_eq!(sorted(scene.get_handles()), sorted(vec![root, sibling]));
+        assert!(scene.remove_node(child).is_err());
+
+        // A new node reuses a removed index, but the stale handles cannot edit or reparent it.
+        let reused = scene.add_node(None).unwrap();
+        assert!(reused.get_index() == child.get_index() ||
+                reused.get_index() == grandchild.get_index());
+        assert!(scene.set_pos(child, Vector3D::new(1.0, 0.0, 0.0)).is_err());
+        assert!(scene.set_pos(grandchild, Vector3D::new(1.0, 0.0, 0.0)).is_err());
+        assert!(scene.set_parent(reused, Some(child)).is_err());
+        assert!(scene.set_parent(child, Some(root)).is_err());
+        assert!(scene.add_node(Some(grandchild)).is_err());
+        assert_eq!(scene.get_node(reused).unwrap().get_pos(), Vector3D::new(0.0, 0.0, 0.0));
+        assert_eq!(scene.get_node(reused).unwrap().get_parent(), None);
+    }
+}
@@ -0,0 +1,154 @@
+// Defines the GPU resources used for shadow mapping. A ShadowMap is a framebuffer with a single
+// depth texture attachment that the GameWindow renders shadow casters into from the point of view
+// of a light. A CubeShadowMap is the omnidirectional equivalent used by PointLights which stores
+// the distance to the light in each of the six faces of a depth cube map. Both depth textures are
+// set up for hardware depth comparison so they can be sampled with shadow samplers in the
+// fragment shader.
+//
+// Brian Ho
+// brian@brkho.com
+
+extern crate cgmath;
+extern crate gl;
+
+use self::cgmath::SquareMatrix;
+use gfx::types::*;
+
+// Number of faces in a cube map.
+pub const CUBE_FACES: usize = 6;
+
+// Common interface for shadow render targets so the GameWindow can manage pools of them.
+pub trait ShadowTarget {
+    fn create(resolution: u32) -> Self;
+    fn resize(&mut self, resolution: u32);
+    fn delete(&mut self);
+}
+
+// A depth-only render target for a single shadow casting light.
+pub struct ShadowMap {
+    pub fbo: GLuint,
+    pub texture: GLuint,
+    pub resolution: u32,
+    pub matrix: cgmath::Matrix4<GLfloat>,
+}
+
+impl ShadowTarget for ShadowMap {
+    // Creates the framebuffer and depth texture for a shadow map of the given resolution. This
+    // can only be called after the window context is set up.
+    fn create(resolution: u32) -> ShadowMap { unsafe {
+        let mut shadow_map = ShadowMap { fbo: 0, texture: 0, resolution: resolution,
+                matrix: cgmath::Matrix4::identity() };
+        gl::GenFramebuffers(1, &mut shadow_map.fbo);
+        gl::GenTextures(1, &mut shadow_map.texture);
+        shadow_map.allocate();
+        shadow_map
+    }}
+
+    // Resizes the depth texture if the resolution has changed.
+    fn resize(&mut self, resolution: u32) {
+        if resolution == self.resolution { return; }
+        self.resolution = resolution;
+        self.allocate();
+    }
+
+    // Deletes the framebuffer and depth texture. The ShadowMap must not be used afterwards.
+    fn delete(&mut self) { unsafe {
+        gl::DeleteFramebuffers(1, &mut self.fbo);
+        gl::DeleteTextures(1, &mut self.texture);
+    }}
+}
+
+impl ShadowMap {
+    // Helper method that (re)allocates the depth texture storage and attaches it to the
+    // framebuffer.
+    fn allocate(&mut self) { unsafe {
+        let border: Vec<GLfloat> = vec![1.0, 1.0, 1.0, 1.0];
+        gl::BindTexture(gl::TEXTURE_2D, self.texture);
+        gl::TexImage2D(
+                gl::TEXTURE_2D, 0, gl::DEPTH_COMPONENT24 as GLint, self.resolution as GLsizei,
+                self.resolution as GLsizei, 0, gl::DEPTH_COMPONENT, gl::FLOAT, 0 as CVoid);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as GLint);
+        gl::TexParameterfv(gl::TEXTURE_2D, gl::TEXTURE_BORDER_COLOR, border.as_ptr());
+        gl::TexParameteri(
+                gl::TEXTURE_2D, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as GLint);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as GLint);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+        gl::FramebufferTexture2D(
+                gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_2D, self.texture, 0);
+        gl::DrawBuffer(gl::NONE);
+        gl::ReadBuffer(gl::NONE);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+    }}
+}
+
+// A depth-only cube map render target for a single shadow casting PointLight. The position and
+// radius of the light at the time of the last render are cached so that the faces are only
+// re-rendered when the light changes or the map is explicitly marked as dirty.
+pub struct CubeShadowMap {
+    pub fbo: GLuint,
+    pub texture: GLuint,
+    pub resolution: u32,
+    pub position: Vector3D,
+    pub radius: f32,
+    pub dirty: bool,
+}
+
+impl ShadowTarget for CubeShadowMap {
+    // Creates the framebuffer and depth cube map for a shadow map of the given resolution. This
+    // can only be called after the window context is set up.
+    fn create(resolution: u32) -> CubeShadowMap { unsafe {
+        let mut shadow_map = CubeShadowMap { fbo: 0, texture: 0, resolution: resolution,
+                position: Vector3D::new(0.0, 0.0, 0.0), radius: 0.0, dirty: true };
+        gl::GenFramebuffers(1, &mut shadow_map.fbo);
+        gl::GenTextures(1, &mut shadow_map.texture);
+        shadow_map.allocate();
+        shadow_map
+    }}
+
+    // Resizes the faces of the cube map if the resolution has changed.
+    fn resize(&mut self, resolution: u32) {
+        if resolution == self.resolution { return; }
+        self.resolution = resolution;
+        self.dirty = true;
+        self.allocate();
+    }
+
+    // Deletes the framebuffer and cube map. The CubeShadowMap must not be used afterwards.
+    fn delete(&mut self) { unsafe {
+        gl::DeleteFramebuffers(1, &mut self.fbo);
+        gl::DeleteTextures(1, &mut self.texture);
+    }}
+}
+
+impl CubeShadowMap {
+    // Helper method that (re)allocates the storage for each face of the cube map. Faces are
+    // attached to the framebuffer one at a time while rendering.
+    fn allocate(&mut self) { unsafe {
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.texture);
+        for face in 0..CUBE_FACES {
+            gl::TexImage2D(
+                    gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as GLuint, 0,
+                    gl::DEPTH_COMPONENT24 as GLint, self.resolution as GLsizei,
+                    self.resolution as GLsizei, 0, gl::DEPTH_COMPONENT, gl::FLOAT, 0 as CVoid);
+        }
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MIN_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAG_FILTER, gl::LINEAR as GLint);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as GLint);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_COMPARE_MODE,
+                gl::COMPARE_REF_TO_TEXTURE as GLint);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as GLint);
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+        gl::DrawBuffer(gl::NONE);
+        gl::ReadBuffer(gl::NONE);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+    }}
+}
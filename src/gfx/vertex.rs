@@ -0,0 +1,137 @@
+// Defines the VertexLayout which describes which attributes are interleaved in the VBO data of a
+// ModelInfo and in what order. Every attribute is stored as floats and is bound to a fixed
+// location in every program, so VAOs can be shared across programs regardless of the layout. The
+// GameWindow keeps separate VBOs for each layout since a VBO can only have a single stride.
+//
+// Brian Ho
+// brian@brkho.com
+
+use gfx::types::*;
+
+// A vertex attribute that can be part of a VertexLayout.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum VertexAttribute {
+    Position,
+    Normal,
+    Tangent,
+    Bitangent,
+    TCoord,
+    Color,
+    TCoord2,
+    Joints,
+    Weights,
+}
+
+// Every vertex attribute in the order of their bound locations.
+pub const VERTEX_ATTRIBUTES: [VertexAttribute; 9] = [VertexAttribute::Position,
+        VertexAttribute::Normal, VertexAttribute::Tangent, VertexAttribute::Bitangent,
+        VertexAttribute::TCoord, VertexAttribute::Color, VertexAttribute::TCoord2,
+        VertexAttribute::Joints, VertexAttribute::Weights];
+
+impl VertexAttribute {
+    // Gets the number of floats in the attribute.
+    pub fn get_size(&self) -> usize {
+        match *self {
+            VertexAttribute::Position | VertexAttribute::Normal | VertexAttribute::Tangent |
+                    VertexAttribute::Bitangent => 3,
+            VertexAttribute::TCoord | VertexAttribute::TCoord2 => 2,
+            VertexAttribute::Color | VertexAttribute::Joints | VertexAttribute::Weights => 4,
+        }
+    }
+
+    // Gets the name of the attribute's input variable in the vertex shaders.
+    pub fn get_name(&self) -> &'static str {
+        match *self {
+            VertexAttribute::Position => "position",
+            VertexAttribute::Normal => "normal",
+            VertexAttribute::Tangent => "tangent",
+            VertexAttribute::Bitangent => "bitangent",
+            VertexAttribute::TCoord => "tcoord",
+            VertexAttribute::Color => "vertex_color",
+            VertexAttribute::TCoord2 => "tcoord2",
+            VertexAttribute::Joints => "joints",
+            VertexAttribute::Weights => "weights",
+        }
+    }
+
+    // Gets the location that the attribute is bound to in every program.
+    pub fn get_location(&self) -> GLuint {
+        VERTEX_ATTRIBUTES.iter().position(|a| a == self).unwrap() as GLuint
+    }
+
+    // Gets the value that the attribute takes on for vertices that do not supply it. Vertex
+    // colors default to white so that they do not tint the material, and joint weights default to
+    // zero so that the vertex is not skinned.
+    pub fn get_default(&self) -> [GLfloat; 4] {
+        match *self {
+            VertexAttribute::Color => [1.0, 1.0, 1.0, 1.0],
+            VertexAttribute::Joints | VertexAttribute::Weights => [0.0, 0.0, 0.0, 0.0],
+            _ => [0.0, 0.0, 0.0, 1.0],
+        }
+    }
+}
+
+// An ordered list of the attributes interleaved in each vertex.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VertexLayout {
+    attributes: Vec<VertexAttribute>,
+}
+
+impl VertexLayout {
+    // Creates a VertexLayout from a list of attributes. Returns Err if the layout does not start
+    // with a position or if an attribute appears more than once.
+    pub fn new(attributes: Vec<VertexAttribute>) -> Result<VertexLayout, String> {
+        if attributes.first() != Some(&VertexAttribute::Position) {
+            return Err("Vertex layouts must start with a position.".to_string());
+        }
+        for (i, attribute) in attributes.iter().enumerate() {
+            if attributes[..i].contains(attribute) {
+                return Err(format!("Vertex layout has a duplicate {:?} attribute.", attribute));
+            }
+        }
+        Ok(VertexLayout { attributes: attributes })
+    }
+
+    // The standard layout with a position, normal, tangent, bitangent, and texture coordinate.
+    pub fn standard() -> VertexLayout {
+        VertexLayout { attributes: vec![VertexAttribute::Position, VertexAttribute::Normal,
+                VertexAttribute::Tangent, VertexAttribute::Bitangent, VertexAttribute::TCoord] }
+    }
+
+    // The standard layout followed by the indices and weights of the joints influencing each
+    // vertex for skinned meshes.
+    pub fn skinned() -> VertexLayout {
+        let mut layout = VertexLayout::standard();
+        layout.attributes.extend([VertexAttribute::Joints, VertexAttribute::Weights].iter());
+        layout
+    }
+
+    // A minimal layout with only a position and a normal for debug meshes.
+    pub fn simple() -> VertexLayout {
+        VertexLayout { attributes: vec![VertexAttribute::Position, VertexAttribute::Normal] }
+    }
+
+    // Gets the attributes in the order they are interleaved.
+    pub fn get_attributes(&self) -> &[VertexAttribute] { &self.attributes }
+
+    // Whether the layout contains an attribute.
+    pub fn contains(&self, attribute: VertexAttribute) -> bool {
+        self.attributes.contains(&attribute)
+    }
+
+    // Gets the number of floats in a single vertex.
+    pub fn get_stride(&self) -> usize {
+        self.attributes.iter().map(|a| a.get_size()).sum()
+    }
+
+    // Gets the offset (in floats) of an attribute from the start of a vertex or None if the
+    // layout does not contain the attribute.
+    pub fn get_offset(&self, attribute: VertexAttribute) -> Option<usize> {
+        let mut offset = 0;
+        for a in self.attributes.iter() {
+            if *a == attribute { return Some(offset); }
+            offset += a.get_size();
+        }
+        None
+    }
+}
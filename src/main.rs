@@ -6,9 +6,13 @@
 extern crate mmo;
 extern crate time;
 
+use mmo::ecs::components::{Camera, Light, MeshRenderer, Transform};
+use mmo::ecs::entity::Entity;
+use mmo::ecs::render::RenderSystem;
+use mmo::ecs::system::Scheduler;
+use mmo::ecs::world::World;
 use mmo::gfx::color;
 use mmo::gfx::camera;
-use mmo::gfx::camera::Camera;
 use mmo::gfx::camera::EuclideanVector;
 use mmo::gfx::game_window::*;
 use mmo::gfx::light;
@@ -16,6 +20,7 @@ use mmo::gfx::material;
 use mmo::gfx::model;
 use mmo::gfx::types::*;
 use mmo::util::{rmod, obj};
+// use mmo::util::hdr;
 
 use std::path;
 use std::process;
@@ -29,6 +34,16 @@ macro_rules! asset { ($s:expr) => {{
     path.push($s);
     &path.to_str().unwrap().to_string() }};}
 
+// The state of the keys used to control the camera, which is stored as a World resource.
+#[derive(Default)]
+struct Input {
+    left: i32,
+    right: i32,
+    up: i32,
+    down: i32,
+    shift: i32,
+}
+
 // Driver test program.
 fn main() {
     let ground = obj::decode_obj(asset!("ground.obj")).unwrap();
@@ -37,82 +52,147 @@ fn main() {
     // let dragon = obj::decode_obj("dragon.obj").unwrap();
     let mut window = GameWindow::new(800, 600, "Engine Test".to_string()).unwrap();
     window.bg_color = color::Color::new_rgb(0.2, 0.2, 0.2);
+    let mut world = World::new();
 
     let camera1 = camera::PerspectiveCamera::new(
             Vector3D::new(17.0, 17.0, 17.0), Vector3D::new(0.0, 0.0, 0.0),
             window.get_aspect_ratio(), 45.0, 0.1, 100.0);
-    let camera2 = camera::PerspectiveCamera::new(
+    let camera2 = camera::OrthographicCamera::new_centered(
             Vector3D::new(0.0001, 0.0, 30.0), Vector3D::new(0.0, 0.0, 0.0),
-            window.get_aspect_ratio(), 45.0, 0.1, 100.0);
-    let main_camera = window.attach_camera(camera1);
-    let secondary_camera = window.attach_camera(camera2);
-    window.set_active_camera(main_camera).unwrap();
+            40.0, window.get_aspect_ratio(), 0.1, 100.0);
+    let main_camera = world.create_entity();
+    world.add_component(main_camera, Transform::new(camera1.pos)).unwrap();
+    world.add_component(main_camera, Camera::new(camera1, true)).unwrap();
+    let secondary_camera = world.create_entity();
+    world.add_component(secondary_camera, Transform::new(camera2.pos)).unwrap();
+    world.add_component(secondary_camera, Camera::new(camera2, false)).unwrap();
+
+    // let sky = hdr::decode_hdr(asset!("environment.hdr")).unwrap();
+    // window.set_environment(&sky.image).unwrap();
+    // window.set_skybox_from_equirect(&sky.image).unwrap();
 
     // let bunny_mat = material::Material::new_with_color(Some(asset!("stone_diffuse.bmp")),
     //         Some(asset!("stone_specular.bmp")), Some(asset!("stone_normal.bmp")),
     //         color::Color::new_rgb(1.0, 1.0, 1.0), 75.0);
     let bunny = rmod::decode_rmod(asset!("bunny.rmod")).unwrap();
     let bunny_info = Rc::new(model::ModelInfo::from_rmod(&bunny));
-    let mut bunny_inst = model::ModelInstance::from(bunny_info.clone());
-    bunny_inst.scale = 30.0;
-    bunny_inst.pos = Vector3D::new(0.0, 0.0, 0.0);
-    bunny_inst.update();
+    let bunny_entity = world.create_entity();
+    let mut bunny_transform = Transform::new(Vector3D::new(0.0, 0.0, 0.0));
+    bunny_transform.scale = 30.0;
+    world.add_component(bunny_entity, bunny_transform).unwrap();
+    world.add_component(bunny_entity, MeshRenderer::new(bunny_info.clone())).unwrap();
 
     let ground = rmod::decode_rmod(asset!("plane.rmod")).unwrap();
     let ground_info = Rc::new(model::ModelInfo::from_rmod(&ground));
-    let mut ground_inst = model::ModelInstance::from(ground_info.clone());
-    ground_inst.scale = 20.0;
-    ground_inst.update();
+    let ground_entity = world.create_entity();
+    let mut ground_transform = Transform::new(Vector3D::new(0.0, 0.0, 0.0));
+    ground_transform.scale = 20.0;
+    world.add_component(ground_entity, ground_transform).unwrap();
+    world.add_component(ground_entity, MeshRenderer::new(ground_info.clone())).unwrap();
 
     // let dragon_mat = material::Material::new_with_color(Some(asset!("uvs.bmp")),
     //     None, None,
     //     color::Color::new_rgb(1.0, 1.0, 1.0), 175.0);
     // let dragon_info = Rc::new(model::ModelInfo::from_obj(&dragon, dragon_mat));
-    // let mut dragon_inst = model::ModelInstance::from(dragon_info.clone());
-    // dragon_inst.scale = 0.6;
-    // dragon_inst.pos = Vector3D::new(4.0, -4.0, 0.0);
-    // dragon_inst.update();
+    // let dragon = world.create_entity();
+    // let mut dragon_transform = Transform::new(Vector3D::new(4.0, -4.0, 0.0));
+    // dragon_transform.scale = 0.6;
+    // world.add_component(dragon, dragon_transform).unwrap();
+    // world.add_component(dragon, MeshRenderer::new(dragon_info.clone())).unwrap();
 
     // let budda_mat = material::Material::new_with_color(Some(asset!("brian.bmp")),
     //         None, None,
     //         color::Color::new_rgb(1.0, 1.0, 1.0), 175.0);
     // let budda_info = Rc::new(model::ModelInfo::from_obj(&budda, budda_mat));
-    // let mut budda_inst = model::ModelInstance::from(budda_info.clone());
-    // budda_inst.pos = Vector3D::new(3.5, 3.5, 1.0);
-    // budda_inst.update();
+    // let budda = world.create_entity();
+    // world.add_component(budda, Transform::new(Vector3D::new(3.5, 3.5, 1.0))).unwrap();
+    // world.add_component(budda, MeshRenderer::new(budda_info.clone())).unwrap();
+
+    // let gold_mat = material::Material::new_metallic_roughness(None, None, None, None, None,
+    //         color::Color::new_rgb(1.0, 0.77, 0.34), 1.0, 0.35);
+    // let gold_info = Rc::new(model::ModelInfo::from_obj(&budda, gold_mat));
 
-    let lb_mat = material::Material::new_with_color(None,
+    let mut lb_mat = material::Material::new_with_color(None,
             None, None,
             color::Color::new_rgb(0.0, 0.0, 0.0), 75.0);
+    lb_mat.set_emissive(None, color::Color::new_rgb(1.0, 1.0, 1.0));
     let lb = Rc::new(model::ModelInfo::new_box(1.0, 1.0, 1.0, lb_mat));
-    let mut lb1_inst = model::ModelInstance::from(lb.clone());
-    lb1_inst.update();
 
-    let mut lb2_inst = model::ModelInstance::from(lb.clone());
-    lb2_inst.update();
-
-    // let spot_obj = light::SpotLight::new(color::Color::new_rgb(0.3, 0.3, 0.3),
+    // The first light box carries the point light along with it.
+    let mut pl1_obj = light::PointLight::new(color::Color::new_rgb(1.0, 1.0, 1.0),
+            Vector3D::new(3.0, 3.0, 1.0), 1.0, 0.03, 0.004);
+    pl1_obj.casts_shadows = true;
+    let lb1 = world.create_entity();
+    let mut lb1_renderer = MeshRenderer::new(lb.clone());
+    lb1_renderer.casts_shadows = false;
+    world.add_component(lb1, Transform::new(Vector3D::new(3.0, 3.0, 1.0))).unwrap();
+    world.add_component(lb1, lb1_renderer).unwrap();
+    world.add_component(lb1, Light::point(pl1_obj)).unwrap();
+
+    let lb2 = world.create_entity();
+    let mut lb2_renderer = MeshRenderer::new(lb.clone());
+    lb2_renderer.casts_shadows = false;
+    lb2_renderer.visible = false;
+    world.add_component(lb2, Transform::new(Vector3D::new(0.0, 0.0, 0.0))).unwrap();
+    world.add_component(lb2, lb2_renderer).unwrap();
+
+    // let mut spot_obj = light::SpotLight::new(color::Color::new_rgb(0.3, 0.3, 0.3),
     //         Vector3D::new(0.0, 15.0, 15.0), Vector3D::new(0.0, -1.0, -1.0), 1.0, 0.0, 0.0, 0.4,
     //         42.0);
-    // let spot_handle = window.attach_spot_light(spot_obj);
+    // spot_obj.casts_shadows = true;
+    // let spot = world.create_entity();
+    // world.add_component(spot, Transform::new(spot_obj.position)).unwrap();
+    // world.add_component(spot, Light::spot(spot_obj)).unwrap();
 
-    // let dir_obj = light::DirectionalLight::new(color::Color::new_rgb(0.5, 0.5, 0.5),
+    // let mut dir_obj = light::DirectionalLight::new(color::Color::new_rgb(0.5, 0.5, 0.5),
     //         Vector3D::new(0.0, 0.0, -1.0));
-    // let dir_handle = window.attach_directional_light(dir_obj);
-
-    let pl1_obj = light::PointLight::new(color::Color::new_rgb(1.0, 1.0, 1.0),
-            Vector3D::new(3.0, 3.0, 1.0), 1.0, 0.03, 0.004);
-    let pl1_handle = window.attach_point_light(pl1_obj);
-
-    // let point_light2 = window.attach_point_light(
-    //         color::Color::new_rgb(1.0, 1.0, 1.0),
-    //         Vector3D::new(3.0, 3.0, 1.0), 1.0, 0.06, 0.008).unwrap();
+    // dir_obj.casts_shadows = true;
+    // let sun = world.create_entity();
+    // world.add_component(sun, Transform::new(Vector3D::new(0.0, 0.0, 0.0))).unwrap();
+    // world.add_component(sun, Light::directional(dir_obj)).unwrap();
+
+    world.insert_resource(window);
+    world.insert_resource(Input::default());
+
+    let mut scheduler = Scheduler::new();
+    scheduler.add("camera", Box::new(move |world: &mut World, dt: f32| {
+        let input = try!(world.read_resource::<Input>());
+        let mut cameras = try!(world.write::<Camera>());
+        let mut transforms = try!(world.write::<Transform>());
+        let (active, inactive) = if input.shift == 0 { (main_camera, secondary_camera) }
+                else { (secondary_camera, main_camera) };
+        try!(cameras.get_mut(inactive).ok_or("Missing camera.".to_string())).active = false;
+        let camera = try!(cameras.get_mut(active).ok_or("Missing camera.".to_string()));
+        camera.active = true;
+        let x_dir = (input.right - input.left) as f32 * 5.0 * dt;
+        let y_dir = (input.up - input.down) as f32 * 5.0 * dt;
+        // The direction points from the camera to its target, so unlike Camera::get_fwd() (which
+        // points backwards out of the screen) it does not need to be negated.
+        let fwd = Vector3D::new(camera.direction[0], camera.direction[1], 0.0).normalize();
+        let right = fwd.cross(Vector3D::new(0.0, 0.0, 1.0));
+        let transform = try!(transforms.get_mut(active).ok_or("Missing transform.".to_string()));
+        // Keep the camera looking at the same target as it moves.
+        let target = transform.pos + camera.direction;
+        transform.pos = transform.pos + right * x_dir + fwd * y_dir;
+        camera.direction = target - transform.pos;
+        Ok(())
+    })).unwrap();
+    let mut orbit_time = 0.0;
+    scheduler.add("orbit", Box::new(move |world: &mut World, dt: f32| {
+        orbit_time += dt;
+        let mut transforms = try!(world.write::<Transform>());
+        let positions: [(Entity, Vector3D); 2] = [
+            (lb1, Vector3D::new(10.0 * orbit_time.cos(), 10.0 * orbit_time.sin(), 1.0)),
+            (lb2, Vector3D::new(0.0, 10.0 * (1.43 * orbit_time).sin(),
+                    10.0 * (1.43 * orbit_time).cos())),
+        ];
+        for &(entity, pos) in positions.iter() {
+            try!(transforms.get_mut(entity).ok_or("Missing transform.".to_string())).pos = pos;
+        }
+        Ok(())
+    })).unwrap();
+    scheduler.add("render", Box::new(RenderSystem::new())).unwrap();
 
-    let mut left_pressed = 0;
-    let mut right_pressed = 0;
-    let mut up_pressed = 0;
-    let mut down_pressed = 0;
-    let mut shift_pressed = 0;
     let mut last_time = time::now().to_timespec();
     let mut elapsed_time = 0.0;
     let mut frame_count = 0;
@@ -127,73 +207,21 @@ fn main() {
             println!("AVERAGE FPS: {}", frame_count as f32 / elapsed_time);
         }
 
-        // Update Camera.
-        {   if shift_pressed == 0 {
-                window.set_active_camera(main_camera).unwrap();
-            } else {
-                window.set_active_camera(secondary_camera).unwrap();
-                // window.clear_vertex_buffers();
-            }
-            let x_dir = (right_pressed - left_pressed) as f32 * 5.0 * dt;
-            let y_dir = (up_pressed - down_pressed) as f32 * 5.0 * dt;
-            let mut camera = window.get_active_camera_mut().unwrap();
-            let cam_dir = camera.get_fwd();
-            let fwd = Vector3D::new(cam_dir[0], cam_dir[1], 0.0).normalize();
-            let right = camera.get_right();
-            let dir = right * x_dir + fwd * -y_dir;
-            camera.pos = camera.pos + dir;
-            camera.target = camera.target; }
-        window.update_active_camera();
-
-        // Update Objects.
-        lb1_inst.pos = Vector3D::new(10.0 * elapsed_time.cos(), 10.0 * elapsed_time.sin(), 1.0);
-        lb1_inst.update();
-        lb2_inst.pos = Vector3D::new(0.0, 10.0 * (1.43 * elapsed_time).sin(),
-                10.0 * (1.43 * elapsed_time).cos());
-        lb2_inst.update();
-
-        // {   let mut light = window.get_spot_light_mut(spot_handle);
-        //     let intensity = ((elapsed_time * 10.0).sin() + 1.0) / 2.0;
-        //     light.intensity = color::Color::new_rgb(intensity, intensity, intensity); }
-        // window.update_spot_light(spot_handle);
-
-        // {   let mut light = window.get_directional_light_mut(dir_handle);
-        //     let intensity = ((elapsed_time * 5.0).sin() + 1.0) / 2.0;
-        //     light.intensity = color::Color::new_rgb(intensity, intensity, intensity); }
-        // window.update_directional_light(dir_handle);
-
-        {   let mut light = window.get_point_light_mut(pl1_handle);
-            let lpos = Vector3D::new(10.0 * elapsed_time.cos(), 10.0 * elapsed_time.sin(), 1.0);
-            light.position = lpos; }
-        window.update_point_light(pl1_handle);
-
-        // {   let mut light = window.get_point_light(point_light2);
-        //     let lpos = Vector3D::new(0.0, 10.0 * (1.43 * elapsed_time).sin(),
-        //             10.0 * (1.43 * elapsed_time).cos());
-        //     light.position = lpos;
-        //     light.update(program); }
-        // window.update_point_light(pl2_handle);
-
-        // Draw Objects.
-        window.clear();
-        window.draw_instance(&bunny_inst);
-        window.draw_instance(&lb1_inst);
-        // window.draw_instance(&lb2_inst);
-        window.draw_instance(&ground_inst);
-        // window.draw_instance(&budda_inst);
-        // window.draw_instance(&dragon_inst);
-        window.swap_buffers();
+        // Update and draw the world.
+        scheduler.run(&mut world, dt).unwrap();
 
+        let window = world.read_resource::<GameWindow>().unwrap();
+        let mut input = world.write_resource::<Input>().unwrap();
         for event in window.poll_events() {
             match event {
                 Event::KeyboardInput(state, _, Some(key)) => {
                     let pressed = if state == ElementState::Pressed { 1 } else { 0 };
                     match key {
-                        VirtualKeyCode::Left => left_pressed = pressed,
-                        VirtualKeyCode::Right => right_pressed = pressed,
-                        VirtualKeyCode::Up => up_pressed = pressed,
-                        VirtualKeyCode::Down => down_pressed = pressed,
-                        VirtualKeyCode::LShift => shift_pressed = pressed,
+                        VirtualKeyCode::Left => input.left = pressed,
+                        VirtualKeyCode::Right => input.right = pressed,
+                        VirtualKeyCode::Up => input.up = pressed,
+                        VirtualKeyCode::Down => input.down = pressed,
+                        VirtualKeyCode::LShift => input.shift = pressed,
                         _ => (),
                     }
                 }
@@ -203,4 +231,4 @@ fn main() {
         }
         // sleep(Duration::from_millis(500));
     }
-}
\ No newline at end of file
+}
@@ -1,21 +1,37 @@
-// Utility module that allows for decoding of a BMP given a path to the file. This is only
-// implemented for a very strict subset of possible BMP formats (BITMAPINFOHEADER) without
-// compression. This is the format output by GIMP when exporting as BMP.
+// Utility module that allows for decoding and encoding of a BMP given a path to the file. This
+// decodes the BITMAPCOREHEADER and every version of the BITMAPINFOHEADER at all of the standard bit
+// depths: 1, 4, and 8-bit palettized images (optionally RLE compressed), 16 and 32-bit images with
+// default or BITFIELDS masks (including an alpha mask), and 24-bit images. Both bottom-up and
+// top-down row orders are handled. Embedded JPEG and PNG images are not supported. Images are
+// encoded as 24-bit if they are opaque and as 32-bit with an alpha mask otherwise.
 //
 // Brian Ho
 // brian@brkho.com
 
 
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::mem;
 use util::common;
 
-// Data structure representation of the DIBHeader fields we care about.
+// The compression methods of the DIB header.
+const BI_RGB: u32 = 0;
+const BI_RLE8: u32 = 1;
+const BI_RLE4: u32 = 2;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+// Data structure representation of the DIBHeader fields we care about. The masks of 16 and 32-bit
+// images are in the order red, green, blue, and alpha, and a mask of 0 means the channel is absent.
 struct DIBHeader {
     width: u32,
     height: u32,
+    top_down: bool,
     depth: u16,
+    compression: u32,
+    colors: u32,
+    masks: [u32; 4],
+    core: bool,
 }
 
 // Return value for a decoded BMP file. This contains a width, height, and an array of pixels with
@@ -76,58 +92,258 @@ fn read_byte(data: &Vec<u8>, cursor: &mut usize) -> Result<u8, String> {
     Ok(data[orig])
 }
 
-// Reads and consumes the initial BMP file header. This also performs the bare minimum amount of
-// error checking by verifying that the first two bytes correspond to 'BM' in ASCII.
-// TODO: Perform actual validation.
-fn read_bmp_header(data: &Vec<u8>, cursor: &mut usize) -> Result<(), String> {
-    let orig = *cursor;
-    try!(consume_n(data, cursor, 14));
-    if data[orig] != ('B' as u8) || data[orig + 1] != ('M' as u8) {
+// Reads and consumes the initial BMP file header and returns the offset of the pixel data. This
+// verifies that the first two bytes correspond to 'BM' in ASCII and that the offset is in the file.
+fn read_bmp_header(data: &Vec<u8>, cursor: &mut usize) -> Result<usize, String> {
+    let magic = try!(read_n_bytes(data, cursor, 2));
+    if magic != b"BM" {
         return Err("BMP file header has incorrect magic values.".to_string())
     }
-    Ok(())
+    try!(consume_n(data, cursor, 8));
+    let offset = try!(read_dword(data, cursor)) as usize;
+    if offset > data.len() {
+        return Err(format!("BMP pixel data offset of {} is past the end of the file.", offset));
+    }
+    Ok(offset)
+}
+
+// Checks that a BITFIELDS mask is a single run of set bits.
+fn is_contiguous(mask: u32) -> bool {
+    if mask == 0 { return true; }
+    let bits = mask >> mask.trailing_zeros();
+    bits & bits.wrapping_add(1) == 0
 }
 
 // Reads and consumes the DIB header following the initial BMP file header. This uses helper
-// functions to consume and read values from the DIB header to build a DIBHeader struct. We then
-// return the constructed DIBHeader.
+// functions to consume and read values from the DIB header to build a DIBHeader struct. Masks that
+// follow a BITMAPINFOHEADER are consumed as well. We then return the constructed DIBHeader.
 fn read_dib_header(data: &Vec<u8>, cursor: &mut usize) -> Result<DIBHeader, String> {
-    let length = match try!(read_dword(data, cursor)) {
-        l @ 40 | l @ 52 | l @ 56 | l @ 108 | l @ 124 => l, // Various BITMAPINFOHEADER versions.
-        _ => return Err("Unsupported DIB header type.".to_string()),
+    let length = try!(read_dword(data, cursor));
+    let core = length == 12;
+    let (width, height) = match length {
+        12 => (try!(read_word(data, cursor)) as i32, try!(read_word(data, cursor)) as i32),
+        40 | 52 | 56 | 108 | 124 => // Various BITMAPINFOHEADER versions.
+                (try!(read_dword(data, cursor)) as i32, try!(read_dword(data, cursor)) as i32),
+        _ => return Err(format!("BMP DIB header with a size of {} is not supported.", length)),
     };
-    let width = try!(read_dword(data, cursor));
-    let height = try!(read_dword(data, cursor));
-    try!(consume_n(data, cursor, 2));
-    let depth = match try!(read_word(data, cursor)) {
-        d @ 24 | d @ 32 => d, // Only support bit depths of 24 and 36.
-        _ => return Err("Unsupported bit depth.".to_string()),
+    if width <= 0 || height == 0 || height == i32::min_value() {
+        return Err(format!("BMP image has invalid dimensions of {}x{}.", width, height));
+    }
+    if try!(read_word(data, cursor)) != 1 {
+        return Err("BMP image must have exactly one color plane.".to_string());
+    }
+    let depth = try!(read_word(data, cursor));
+    let (compression, colors) = if core { (BI_RGB, 0) } else {
+        let compression = try!(read_dword(data, cursor));
+        try!(consume_n(data, cursor, 12));
+        let colors = try!(read_dword(data, cursor));
+        try!(consume_n(data, cursor, 4));
+        (compression, colors)
+    };
+
+    // Make sure the bit depth is valid for the compression method.
+    let valid = match compression {
+        BI_RGB => [1, 4, 8, 16, 24, 32].contains(&depth) && !(core && (depth == 16 || depth == 32)),
+        BI_RLE8 => depth == 8,
+        BI_RLE4 => depth == 4,
+        BI_BITFIELDS | BI_ALPHABITFIELDS => depth == 16 || depth == 32,
+        _ => return Err(format!("BMP compression method {} is not supported.", compression)),
     };
-    try!(consume_n(data, cursor, length as usize - 16));
-    Ok(DIBHeader {width: width, height: height, depth: depth})
+    if !valid {
+        return Err(format!("BMP compression method {} cannot have a bit depth of {}.",
+                compression, depth));
+    }
+    if (compression == BI_RLE8 || compression == BI_RLE4) && height < 0 {
+        return Err("BMP image cannot be both RLE compressed and top-down.".to_string());
+    }
+    if depth <= 8 && colors > 1 << depth {
+        return Err(format!("BMP palette has {} colors, which is too many for a bit depth of {}.",
+                colors, depth));
+    }
+
+    // Read the masks, which either are part of the header or follow a BITMAPINFOHEADER.
+    let mut masks = [0; 4];
+    let mask_count = match (length, compression) {
+        (40, BI_BITFIELDS) => 3,
+        (40, BI_ALPHABITFIELDS) => 4,
+        (40, _) | (12, _) => 0,
+        (52, _) => 3,
+        _ => 4,
+    };
+    for i in 0..mask_count { masks[i] = try!(read_dword(data, cursor)); }
+    let header_end = if length > 40 { length as usize - 40 - mask_count * 4 } else { 0 };
+    try!(consume_n(data, cursor, header_end));
+    masks = match (compression, depth) {
+        (BI_BITFIELDS, _) => [masks[0], masks[1], masks[2], if length > 52 { masks[3] } else { 0 }],
+        (BI_ALPHABITFIELDS, _) => masks,
+        (_, 16) => [0x7C00, 0x03E0, 0x001F, 0],
+        (_, 32) => [0xFF0000, 0x00FF00, 0x0000FF, 0],
+        _ => [0; 4],
+    };
+    if !masks.iter().all(|&mask| is_contiguous(mask)) {
+        return Err("BMP BITFIELDS masks must be contiguous runs of bits.".to_string());
+    }
+    Ok(DIBHeader { width: width as u32, height: height.abs() as u32, top_down: height < 0,
+            depth: depth, compression: compression, colors: colors, masks: masks, core: core })
+}
+
+// Reads the color table following the DIB header. Entries are BGR triples for BITMAPCOREHEADERs
+// and BGRX quads otherwise. Only the entries that fit before the pixel data are read.
+fn read_palette(data: &Vec<u8>, cursor: &mut usize, info: &DIBHeader, offset: usize)
+        -> Result<Vec<[u8; 3]>, String> {
+    if offset < *cursor {
+        return Err(format!("BMP pixel data offset of {} overlaps the headers.", offset));
+    }
+    if info.depth > 8 { return Ok(Vec::new()); }
+    let entry_size = if info.core { 3 } else { 4 };
+    let count = if info.colors == 0 { 1 << info.depth } else { info.colors as usize };
+    let count = count.min((offset - *cursor) / entry_size);
+    let mut palette = Vec::new();
+    for _ in 0..count {
+        let entry = try!(read_n_bytes(data, cursor, entry_size));
+        palette.push([entry[2], entry[1], entry[0]]);
+    }
+    Ok(palette)
+}
+
+// Gets the pixel of a palette index. Returns Err if the index is outside of the palette.
+fn get_palette_pixel(palette: &[[u8; 3]], index: u8) -> Result<common::Pixel, String> {
+    match palette.get(index as usize) {
+        Some(color) => Ok(common::Pixel { red: color[0], green: color[1], blue: color[2],
+                alpha: 255 }),
+        None => Err(format!("BMP pixel refers to color {} outside of the palette.", index)),
+    }
+}
+
+// Extracts a channel from a 16 or 32-bit value given its mask and scales it to the range 0-255.
+// Absent channels are given a default value.
+fn read_channel(value: u32, mask: u32, default: u8) -> u8 {
+    if mask == 0 { return default; }
+    let shift = mask.trailing_zeros();
+    let max = (mask >> shift) as u64;
+    ((((value & mask) >> shift) as u64 * 255 + max / 2) / max) as u8
+}
+
+// Gets the pixel of a 16 or 32-bit value given the red, green, blue, and alpha masks.
+fn get_masked_pixel(value: u32, masks: &[u32; 4]) -> common::Pixel {
+    common::Pixel { red: read_channel(value, masks[0], 0),
+            green: read_channel(value, masks[1], 0), blue: read_channel(value, masks[2], 0),
+            alpha: read_channel(value, masks[3], 255) }
 }
 
-// Reads in the pixel array from the data vector and returns a vector of Pixels.
-fn read_pixel_array(data: &Vec<u8>, cursor: &mut usize, info: &DIBHeader)
-        -> Result<Vec<common::Pixel>, String> {
-    let pad_bytes = info.width % 4;
-    let mut pixel_arr: Vec<common::Pixel> = Vec::new();
+// Reads in the uncompressed pixel array from the data vector and returns a vector of rows of
+// Pixels in the order they are stored.
+fn read_pixel_array(data: &Vec<u8>, cursor: &mut usize, info: &DIBHeader, palette: &[[u8; 3]])
+        -> Result<Vec<Vec<common::Pixel>>, String> {
+    let width = info.width as usize;
+    let depth = info.depth as usize;
+    let stride = (width * depth + 31) / 32 * 4;
+    if (data.len() - *cursor) / stride < info.height as usize {
+        return Err("BMP pixel data is smaller than the image.".to_string());
+    }
+    let mut rows = Vec::new();
     for _ in 0..(info.height) {
-        let mut row_vec = Vec::new();
-        for _ in 0..(info.width) {
-            let a = if info.depth == 24 { 0 } else { try!(read_byte(data, cursor)) };
-            let b = try!(read_byte(data, cursor));
-            let g = try!(read_byte(data, cursor));
-            let r = try!(read_byte(data, cursor));
-            let pixel = common::Pixel { red: r, green: g, blue: b, alpha: a };
+        let row = try!(read_n_bytes(data, cursor, stride));
+        let mut row_vec = Vec::with_capacity(width);
+        for x in 0..width {
+            let pixel = match depth {
+                1 | 4 | 8 => {
+                    let per_byte = 8 / depth;
+                    let shift = 8 - depth * (x % per_byte + 1);
+                    let index = (row[x / per_byte] >> shift) & ((1 << depth) - 1) as u8;
+                    try!(get_palette_pixel(palette, index))
+                },
+                16 => get_masked_pixel(row[x * 2] as u32 | (row[x * 2 + 1] as u32) << 8,
+                        &info.masks),
+                24 => common::Pixel { red: row[x * 3 + 2], green: row[x * 3 + 1],
+                        blue: row[x * 3], alpha: 255 },
+                _ => {
+                    let bytes = &row[(x * 4)..(x * 4 + 4)];
+                    let value = bytes[0] as u32 | (bytes[1] as u32) << 8 |
+                            (bytes[2] as u32) << 16 | (bytes[3] as u32) << 24;
+                    get_masked_pixel(value, &info.masks)
+                },
+            };
             row_vec.push(pixel);
         }
-        row_vec.reverse();
-        pixel_arr.extend(row_vec);
-        try!(consume_n(data, cursor, pad_bytes as usize));
+        rows.push(row_vec);
     }
-    pixel_arr.reverse();
-    Ok(pixel_arr)
+    Ok(rows)
+}
+
+// Sets the palette index at a position of an RLE compressed image. Returns Err if the position is
+// outside of the image.
+fn set_rle_index(indices: &mut Vec<Option<u8>>, info: &DIBHeader, x: usize, y: usize, index: u8)
+        -> Result<(), String> {
+    if x >= info.width as usize || y >= info.height as usize {
+        return Err("BMP RLE data runs past the edge of the image.".to_string());
+    }
+    indices[y * info.width as usize + x] = Some(index);
+    Ok(())
+}
+
+// Reads in the RLE8 or RLE4 compressed pixel array from the data vector and returns a vector of
+// rows of Pixels in the order they are stored. Pixels skipped by the end of line and delta escapes
+// are transparent. Since every 2 bytes of the data can encode a run of at most 255 pixels, images
+// with more pixels than the data could encode that way are rejected before anything is allocated.
+// This means images that are mostly skipped pixels cannot be read.
+fn read_rle_pixel_array(data: &Vec<u8>, cursor: &mut usize, info: &DIBHeader,
+        palette: &[[u8; 3]]) -> Result<Vec<Vec<common::Pixel>>, String> {
+    let (width, height) = (info.width as usize, info.height as usize);
+    let max_pixels = (data.len() - *cursor) / 2 * 255;
+    match width.checked_mul(height) {
+        Some(pixels) if pixels <= max_pixels => {},
+        _ => {
+            return Err(format!("BMP RLE data is too small for a {}x{} image.", width, height));
+        },
+    }
+    let mut indices = vec![None; width * height];
+    let nibble = |byte: u8, i: usize| if info.depth == 8 { byte } else if i % 2 == 0 { byte >> 4 }
+            else { byte & 15 };
+    let (mut x, mut y) = (0, 0);
+    loop {
+        let count = try!(read_byte(data, cursor)) as usize;
+        let value = try!(read_byte(data, cursor));
+        match (count, value) {
+            // Encoded mode repeats a byte, which alternates between two indices for RLE4.
+            (0, 0) => { x = 0; y += 1; },
+            (0, 1) => { break; },
+            (0, 2) => {
+                x += try!(read_byte(data, cursor)) as usize;
+                y += try!(read_byte(data, cursor)) as usize;
+            },
+            // Absolute mode copies a run of indices, which is padded to an even number of bytes.
+            (0, n) => {
+                let n = n as usize;
+                let size = if info.depth == 8 { n } else { (n + 1) / 2 };
+                let bytes = try!(read_n_bytes(data, cursor, size));
+                for i in 0..n {
+                    let byte = bytes[if info.depth == 8 { i } else { i / 2 }];
+                    try!(set_rle_index(&mut indices, info, x, y, nibble(byte, i)));
+                    x += 1;
+                }
+                try!(consume_n(data, cursor, size % 2));
+            },
+            (count, _) => {
+                for i in 0..count {
+                    try!(set_rle_index(&mut indices, info, x, y, nibble(value, i)));
+                    x += 1;
+                }
+            },
+        }
+    }
+    let mut rows = Vec::new();
+    for row in indices.chunks(width) {
+        let mut row_vec = Vec::with_capacity(width);
+        for index in row {
+            row_vec.push(match *index {
+                Some(index) => try!(get_palette_pixel(palette, index)),
+                None => common::Pixel { red: 0, green: 0, blue: 0, alpha: 0 },
+            });
+        }
+        rows.push(row_vec);
+    }
+    Ok(rows)
 }
 
 // Decodes a BMP given a path to the file and returns a DecodedBMP struct containing the pixel
@@ -138,10 +354,291 @@ pub fn decode_bmp(fpath: &str) -> Result<DecodedBMP, String> {
     try!(fd.read_to_end(&mut data).map_err(|e| e.to_string()));
 
     let mut cursor = 0;
-    try!(read_bmp_header(&data, &mut cursor));
+    let offset = try!(read_bmp_header(&data, &mut cursor));
     let info = try!(read_dib_header(&data, &mut cursor));
-    let pixel_arr = try!(read_pixel_array(&data, &mut cursor, &info));
+    let palette = try!(read_palette(&data, &mut cursor, &info, offset));
+    if info.depth <= 8 && palette.is_empty() {
+        return Err("BMP palettized image is missing its palette.".to_string());
+    }
+    cursor = offset;
+    let mut rows = if info.compression == BI_RLE8 || info.compression == BI_RLE4 {
+        try!(read_rle_pixel_array(&data, &mut cursor, &info, &palette))
+    } else {
+        try!(read_pixel_array(&data, &mut cursor, &info, &palette))
+    };
+    if !info.top_down { rows.reverse(); }
+    let pixel_arr = rows.into_iter().flat_map(|row| row).collect();
     let image = common::Image { width: info.width, height: info.height, data: pixel_arr };
     Ok(DecodedBMP { image: image })
 }
 
+// Appends a u16 to the data vector in little endian order.
+fn write_word(data: &mut Vec<u8>, value: u16) {
+    data.extend_from_slice(&[value as u8, (value >> 8) as u8]);
+}
+
+// Appends a u32 to the data vector in little endian order.
+fn write_dword(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8,
+            (value >> 24) as u8]);
+}
+
+// Encodes an image as a BMP and writes it to a path. Opaque images are written as 24-bit with a
+// BITMAPINFOHEADER, and other images are written as 32-bit with a BITMAPV4HEADER whose masks
+// include alpha. Rows are stored bottom-up, which every reader supports.
+pub fn encode_bmp(image: &common::Image, fpath: &str) -> Result<(), String> {
+    try!(image.validate());
+    let opaque = image.is_opaque();
+    let (depth, header_size) = if opaque { (24, 40) } else { (32, 108) };
+    let width = image.width as usize;
+    let stride = (width * depth + 31) / 32 * 4;
+    let offset = 14 + header_size;
+    let size = offset as u64 + stride as u64 * image.height as u64;
+    if image.width > i32::max_value() as u32 || image.height > i32::max_value() as u32 ||
+            size > u32::max_value() as u64 {
+        return Err("Image is too large to be encoded as a BMP.".to_string());
+    }
+
+    let mut data = Vec::with_capacity(size as usize);
+    data.extend_from_slice(b"BM");
+    write_dword(&mut data, size as u32);
+    write_dword(&mut data, 0);
+    write_dword(&mut data, offset as u32);
+    write_dword(&mut data, header_size as u32);
+    write_dword(&mut data, image.width);
+    write_dword(&mut data, image.height);
+    write_word(&mut data, 1);
+    write_word(&mut data, depth as u16);
+    write_dword(&mut data, if opaque { BI_RGB } else { BI_BITFIELDS });
+    write_dword(&mut data, (stride * image.height as usize) as u32);
+    write_dword(&mut data, 2835);    // 72 DPI in pixels per meter.
+    write_dword(&mut data, 2835);
+    write_dword(&mut data, 0);
+    write_dword(&mut data, 0);
+    if !opaque {
+        for &mask in [0x00FF0000, 0x0000FF00, 0x000000FF, 0xFF000000].iter() {
+            write_dword(&mut data, mask);
+        }
+        data.extend_from_slice(b"BGRs");    // The sRGB color space written little endian.
+        data.extend_from_slice(&[0; 48]);    // The unused endpoints and gamma.
+    }
+
+    for row in image.data.chunks(width).rev() {
+        for pixel in row {
+            data.extend_from_slice(&[pixel.blue, pixel.green, pixel.red]);
+            if !opaque { data.push(pixel.alpha); }
+        }
+        let padding = stride - width * depth / 8;
+        data.extend_from_slice(&[0; 3][..padding]);
+    }
+
+    let mut fd = try!(File::create(fpath).map_err(|e| e.to_string()));
+    fd.write_all(&data).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use util::common::{self, Image, Pixel};
+
+    // Encodes an image to a temporary file and decodes it again.
+    fn round_trip(image: &Image, name: &str) -> Image {
+        common::round_trip_file(image, name, super::encode_bmp,
+                |path| super::decode_bmp(path).map(|decoded| decoded.image))
+    }
+
+    #[test]
+    fn round_trip_opaque() {
+        // An odd width makes the 24-bit rows padded.
+        let image = common::create_test_image(67, 45, true);
+        let decoded = round_trip(&image, "mmo_bmp_round_trip_opaque.bmp");
+        assert_eq!((decoded.width, decoded.height), (67, 45));
+        assert!(decoded.data == image.data);
+    }
+
+    #[test]
+    fn round_trip_translucent() {
+        // Images with any transparency are written as 32-bit with an alpha mask.
+        let image = common::create_test_image(31, 70, false);
+        let decoded = round_trip(&image, "mmo_bmp_round_trip_translucent.bmp");
+        assert_eq!((decoded.width, decoded.height), (31, 70));
+        assert!(decoded.data == image.data);
+    }
+
+    #[test]
+    fn round_trip_asset() {
+        let image = super::decode_bmp("assets/uvs.bmp").unwrap().image;
+        let decoded = round_trip(&image, "mmo_bmp_round_trip_asset.bmp");
+        assert_eq!((decoded.width, decoded.height), (image.width, image.height));
+        assert!(decoded.data == image.data);
+    }
+
+    // Builds a BITMAPINFOHEADER of the given size. Only the first 40 bytes are written, so larger
+    // headers need their masks and remaining fields appended.
+    fn info_header(size: u32, width: i32, height: i32, depth: u16, compression: u32, colors: u32)
+            -> Vec<u8> {
+        let mut data = Vec::new();
+        for &value in [size, width as u32, height as u32].iter() {
+            super::write_dword(&mut data, value);
+        }
+        super::write_word(&mut data, 1);
+        super::write_word(&mut data, depth);
+        for &value in [compression, 0, 0, 0, colors, 0].iter() {
+            super::write_dword(&mut data, value);
+        }
+        data
+    }
+
+    // Writes a BMP made of the DIB header, palette, and pixel data to a temporary file and decodes
+    // it.
+    fn decode_fixture(header: &[u8], palette: &[u8], pixels: &[u8], name: &str)
+            -> Result<super::DecodedBMP, String> {
+        let offset = 14 + header.len() + palette.len();
+        let mut data = b"BM".to_vec();
+        for &value in [(offset + pixels.len()) as u32, 0, offset as u32].iter() {
+            super::write_dword(&mut data, value);
+        }
+        data.extend_from_slice(header);
+        data.extend_from_slice(palette);
+        data.extend_from_slice(pixels);
+        let path = env::temp_dir().join(name);
+        let path = path.to_str().unwrap();
+        fs::File::create(path).unwrap().write_all(&data).unwrap();
+        let decoded = super::decode_bmp(path);
+        fs::remove_file(path).unwrap();
+        decoded
+    }
+
+    // Writes an RLE8 BMP with a two color palette to a temporary file and decodes it.
+    fn decode_rle8(width: u32, height: u32, rle: &[u8], name: &str)
+            -> Result<super::DecodedBMP, String> {
+        let header = info_header(40, width as i32, height as i32, 8, super::BI_RLE8, 2);
+        decode_fixture(&header, &[255, 0, 0, 0, 0, 255, 0, 0], rle, name)
+    }
+
+    // Shorthand for an opaque pixel.
+    fn rgb(red: u8, green: u8, blue: u8) -> Pixel {
+        Pixel { red: red, green: green, blue: blue, alpha: 255 }
+    }
+
+    #[test]
+    fn decode_rle8_skipped_pixels() {
+        let rle = [4, 1, 0, 0, 2, 0, 0, 1];
+        let image = decode_rle8(4, 2, &rle, "mmo_bmp_rle8.bmp").unwrap().image;
+        let (blue, green) = (Pixel { red: 0, green: 0, blue: 255, alpha: 255 },
+                Pixel { red: 0, green: 255, blue: 0, alpha: 255 });
+        let clear = Pixel { red: 0, green: 0, blue: 0, alpha: 0 };
+        assert!(image.data == vec![blue, blue, clear, clear, green, green, green, green]);
+    }
+
+    #[test]
+    fn decode_rle8_huge_dimensions() {
+        let rle = [4, 1, 0, 0, 2, 0, 0, 1];
+        assert!(decode_rle8(0x7fffffff, 0x7fffffff, &rle, "mmo_bmp_rle8_huge.bmp").is_err());
+        assert!(decode_rle8(0x7fffffff, 1, &rle, "mmo_bmp_rle8_wide.bmp").is_err());
+    }
+
+    #[test]
+    fn decode_1_bit_palette() {
+        // Rows are stored bottom-up and padded to 4 bytes, with the first pixel in the high bit.
+        let header = info_header(40, 10, 2, 1, super::BI_RGB, 2);
+        let palette = [0, 0, 255, 0, 0, 255, 0, 0];
+        let pixels = [0b10110000, 0b01000000, 0, 0, 0xFF, 0xC0, 0, 0];
+        let image = decode_fixture(&header, &palette, &pixels, "mmo_bmp_1_bit.bmp").unwrap().image;
+        let (red, green) = (rgb(255, 0, 0), rgb(0, 255, 0));
+        let mut expected = vec![green; 10];
+        expected.extend_from_slice(&[green, red, green, green, red, red, red, red, red, green]);
+        assert_eq!((image.width, image.height), (10, 2));
+        assert!(image.data == expected);
+    }
+
+    #[test]
+    fn decode_4_bit_palette() {
+        let header = info_header(40, 3, 1, 4, super::BI_RGB, 3);
+        let palette = [0, 0, 255, 0, 0, 255, 0, 0, 255, 0, 0, 0];
+        let image = decode_fixture(&header, &palette, &[0x21, 0x00, 0, 0], "mmo_bmp_4_bit.bmp")
+                .unwrap().image;
+        assert!(image.data == vec![rgb(0, 0, 255), rgb(0, 255, 0), rgb(255, 0, 0)]);
+    }
+
+    #[test]
+    fn decode_rle4() {
+        // Encoded runs alternate between the two nibbles, and an absolute run of 5 indices takes 3
+        // bytes, so it is followed by a padding byte.
+        let header = info_header(40, 8, 2, 4, super::BI_RLE4, 3);
+        let palette = [0, 0, 255, 0, 0, 255, 0, 0, 255, 0, 0, 0];
+        let rle = [5, 0x12, 0, 0, 0, 5, 0x01, 0x20, 0x10, 0, 3, 0x22, 0, 1];
+        let image = decode_fixture(&header, &palette, &rle, "mmo_bmp_rle4.bmp").unwrap().image;
+        let (red, green, blue) = (rgb(255, 0, 0), rgb(0, 255, 0), rgb(0, 0, 255));
+        let clear = Pixel { red: 0, green: 0, blue: 0, alpha: 0 };
+        assert!(image.data == vec![red, green, blue, red, green, blue, blue, blue,
+                green, blue, green, blue, green, clear, clear, clear]);
+    }
+
+    #[test]
+    fn decode_16_bit_defaults() {
+        // Uncompressed 16-bit images are 5-5-5 with the top bit ignored.
+        let header = info_header(40, 2, 1, 16, super::BI_RGB, 0);
+        let image = decode_fixture(&header, &[], &[0x00, 0xFC, 0xF0, 0x03], "mmo_bmp_16_bit.bmp")
+                .unwrap().image;
+        assert!(image.data == vec![rgb(255, 0, 0), rgb(0, 255, 132)]);
+    }
+
+    #[test]
+    fn decode_bitfields() {
+        // A 5-6-5 mask scales each channel by its own maximum.
+        let mut header = info_header(40, 2, 1, 16, super::BI_BITFIELDS, 0);
+        for &mask in [0xF800, 0x07E0, 0x001F].iter() { super::write_dword(&mut header, mask); }
+        let image = decode_fixture(&header, &[], &[0x20, 0x80, 0xFF, 0xFF],
+                "mmo_bmp_bitfields.bmp").unwrap().image;
+        assert!(image.data == vec![rgb(132, 4, 0), rgb(255, 255, 255)]);
+    }
+
+    #[test]
+    fn decode_alpha_bitfields() {
+        // The alpha mask follows the color masks of a BITMAPINFOHEADER with BI_ALPHABITFIELDS.
+        let mut header = info_header(40, 1, 1, 32, super::BI_ALPHABITFIELDS, 0);
+        for &mask in [0x000000FF, 0x0000FF00, 0x00FF0000, 0xF0000000].iter() {
+            super::write_dword(&mut header, mask);
+        }
+        let image = decode_fixture(&header, &[], &[0x10, 0x20, 0x30, 0x80],
+                "mmo_bmp_alpha_bitfields.bmp").unwrap().image;
+        assert!(image.data == vec![Pixel { red: 0x10, green: 0x20, blue: 0x30, alpha: 136 }]);
+
+        // A BITMAPV4HEADER always has an alpha mask, which BI_BITFIELDS uses as well.
+        let mut header = info_header(108, 1, 1, 32, super::BI_BITFIELDS, 0);
+        for &mask in [0x000000FF, 0x0000FF00, 0x00FF0000, 0xF0000000].iter() {
+            super::write_dword(&mut header, mask);
+        }
+        header.extend_from_slice(&[0; 52]);
+        let image = decode_fixture(&header, &[], &[0x10, 0x20, 0x30, 0x80],
+                "mmo_bmp_v4_bitfields.bmp").unwrap().image;
+        assert!(image.data == vec![Pixel { red: 0x10, green: 0x20, blue: 0x30, alpha: 136 }]);
+    }
+
+    #[test]
+    fn decode_top_down() {
+        // A negative height stores the rows from top to bottom.
+        let header = info_header(40, 1, -2, 24, super::BI_RGB, 0);
+        let pixels = [255, 0, 0, 0, 0, 0, 255, 0];
+        let image = decode_fixture(&header, &[], &pixels, "mmo_bmp_top_down.bmp").unwrap().image;
+        assert_eq!((image.width, image.height), (1, 2));
+        assert!(image.data == vec![rgb(0, 0, 255), rgb(255, 0, 0)]);
+    }
+
+    #[test]
+    fn decode_core_header() {
+        // BITMAPCOREHEADERs have 16-bit dimensions and a palette of BGR triples. The palette is
+        // cut short by the pixel data offset.
+        let mut header = Vec::new();
+        super::write_dword(&mut header, 12);
+        for &value in [3, 1, 1, 8].iter() { super::write_word(&mut header, value); }
+        let palette = [0, 0, 255, 255, 0, 0];
+        let image = decode_fixture(&header, &palette, &[1, 0, 1, 0], "mmo_bmp_core.bmp")
+                .unwrap().image;
+        let (red, blue) = (rgb(255, 0, 0), rgb(0, 0, 255));
+        assert!(image.data == vec![blue, red, blue]);
+    }
+}
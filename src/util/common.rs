@@ -20,6 +20,7 @@ pub struct Vertex {
 }
 
 // A pixel with color and alpha information in the range 0-255.
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Pixel {
     pub red: u8,
     pub green: u8,
@@ -27,7 +28,8 @@ pub struct Pixel {
     pub alpha: u8,
 }
 
-// Defines what is in an image.
+// Defines what is in an image. The pixels are stored starting from the top left of the image.
+#[derive(Clone)]
 pub struct Image {
     pub width: u32,
     pub height: u32,
@@ -58,4 +60,94 @@ impl Image {
     pub fn get_rgba_vec(&self) -> Vec<u8> {
         self.get_vec_helper(true)
     }
-}
\ No newline at end of file
+
+    // Checks that the image is not empty and that its data has a pixel for every position. This
+    // should be called before encoding an image since the fields can be set freely.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 {
+            return Err("Image has no pixels.".to_string());
+        }
+        if self.data.len() as u64 != self.width as u64 * self.height as u64 {
+            return Err(format!("Image has {} pixels but is {}x{}.", self.data.len(), self.width,
+                    self.height));
+        }
+        Ok(())
+    }
+
+    // Whether every pixel of the image is fully opaque.
+    pub fn is_opaque(&self) -> bool {
+        self.data.iter().all(|pixel| pixel.alpha == 255)
+    }
+}
+
+// Defines what is in a high dynamic range image. The data is stored as linear floating point RGB
+// triples starting from the top left of the image.
+pub struct HDRImage {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<f32>,
+}
+
+impl HDRImage {
+    // Creates an HDRImage from an Image by converting the sRGB encoded colors to linear floats. The
+    // alpha channel is dropped.
+    pub fn from_image(image: &Image) -> HDRImage {
+        let mut data = Vec::with_capacity(image.data.len() * 3);
+        for pixel in &image.data {
+            for &channel in [pixel.red, pixel.green, pixel.blue].iter() {
+                let value = channel as f32 / 255.0;
+                data.push(if value <= 0.04045 {
+                    value / 12.92
+                } else {
+                    ((value + 0.055) / 1.055).powf(2.4)
+                });
+            }
+        }
+        HDRImage { width: image.width, height: image.height, data: data }
+    }
+}
+
+// Creates an image with a gradient of colors (and of alphas unless it is opaque) for testing the
+// image codecs. The gradient produces every byte value after PNG filtering.
+#[cfg(test)]
+pub fn create_test_image(width: u32, height: u32, opaque: bool) -> Image {
+    let mut data = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            data.push(Pixel { red: (x * 37 + y * 11) as u8, green: (x * y) as u8,
+                    blue: (255 - x * 3) as u8,
+                    alpha: if opaque { 255 } else { (x * 5 + y * 17) as u8 } });
+        }
+    }
+    Image { width: width, height: height, data: data }
+}
+
+// Encodes an image to a temporary file with the given name and decodes it again for testing the
+// image codecs.
+#[cfg(test)]
+pub fn round_trip_file<E, D>(image: &Image, name: &str, encode: E, decode: D) -> Image
+        where E: Fn(&Image, &str) -> Result<(), String>, D: Fn(&str) -> Result<Image, String> {
+    let path = ::std::env::temp_dir().join(name);
+    let path = path.to_str().unwrap();
+    encode(image, path).unwrap();
+    let decoded = decode(path);
+    ::std::fs::remove_file(path).unwrap();
+    decoded.unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use util::{bmp, png, tga};
+    use super::*;
+
+    #[test]
+    fn encoders_reject_invalid_images() {
+        let image = Image { width: 4, height: 4, data: Vec::new() };
+        let path = env::temp_dir().join("mmo_invalid_image");
+        let path = path.to_str().unwrap();
+        assert!(bmp::encode_bmp(&image, path).is_err());
+        assert!(png::encode_png(&image, path).is_err());
+        assert!(tga::encode_tga(&image, path).is_err());
+    }
+}
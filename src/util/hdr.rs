@@ -0,0 +1,289 @@
+// Utility module that allows for decoding of a Radiance HDR (.hdr) file given a path to the file.
+// This supports the 32-bit RGBE pixel format with flat, old-style run length encoded, and
+// new-style run length encoded scanlines. These are the formats used by most HDR environment maps
+// found online. Images stored with a flipped or transposed resolution string are not supported.
+//
+// Brian Ho
+// brian@brkho.com
+
+
+use std::fs::File;
+use std::io::Read;
+use std::str;
+use util::common;
+
+// Return value for a decoded HDR file. This contains a width, height, and an array of linear RGB
+// floats.
+pub struct DecodedHDR {
+    pub image: common::HDRImage,
+}
+
+// Scanlines of new-style run length encoded files must have a width in this range.
+const MIN_RLE_WIDTH: u32 = 8;
+const MAX_RLE_WIDTH: u32 = 0x7fff;
+
+// The largest width or height of an image that can be decoded.
+const MAX_DIMENSION: u32 = 0x7fff;
+
+// Reads a single byte from the data vector and advances the cursor.
+fn read_byte(data: &Vec<u8>, cursor: &mut usize) -> Result<u8, String> {
+    match data.get(*cursor) {
+        Some(b) => { *cursor += 1; Ok(*b) },
+        None => Err("HDR file is too small.".to_string()),
+    }
+}
+
+// Reads a single line of the header (without the trailing newline) and advances the cursor.
+fn read_line<'a>(data: &'a Vec<u8>, cursor: &mut usize) -> Result<&'a str, String> {
+    let start = *cursor;
+    loop {
+        if try!(read_byte(data, cursor)) == b'\n' { break; }
+    }
+    str::from_utf8(&data[start..(*cursor - 1)]).map_err(|e| e.to_string())
+}
+
+// Reads and validates the header lines up to and including the resolution string. This returns
+// the width and height of the image.
+fn read_header(data: &Vec<u8>, cursor: &mut usize) -> Result<(u32, u32), String> {
+    let magic = try!(read_line(data, cursor));
+    if magic != "#?RADIANCE" && magic != "#?RGBE" {
+        return Err("HDR file header has incorrect magic values.".to_string());
+    }
+    loop {
+        let line = try!(read_line(data, cursor));
+        if line.is_empty() { break; }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err("Unsupported HDR pixel format.".to_string());
+        }
+    }
+    let resolution: Vec<&str> = try!(read_line(data, cursor)).split_whitespace().collect();
+    if resolution.len() != 4 || resolution[0] != "-Y" || resolution[2] != "+X" {
+        return Err("Unsupported HDR resolution string.".to_string());
+    }
+    let height = try!(resolution[1].parse::<u32>().map_err(|e| e.to_string()));
+    let width = try!(resolution[3].parse::<u32>().map_err(|e| e.to_string()));
+    Ok((width, height))
+}
+
+// Reads a scanline stored as flat or old-style run length encoded RGBE pixels into the scanline
+// buffer. Old-style runs are marked by a pixel of (1, 1, 1, count) which repeats the previous
+// pixel, with consecutive run markers shifting the count by a further 8 bits. Returns Err if more
+// than four markers are chained together since the count would no longer fit in 32 bits.
+fn read_flat_scanline(data: &Vec<u8>, cursor: &mut usize, scanline: &mut Vec<[u8; 4]>)
+        -> Result<(), String> {
+    let width = scanline.len();
+    let mut x = 0;
+    let mut shift = 0;
+    while x < width {
+        let mut pixel = [0; 4];
+        for i in 0..4 { pixel[i] = try!(read_byte(data, cursor)); }
+        if pixel[0] == 1 && pixel[1] == 1 && pixel[2] == 1 {
+            if x == 0 { return Err("HDR run has no pixel to repeat.".to_string()); }
+            if shift >= 32 { return Err("HDR run is too long.".to_string()); }
+            let count = (pixel[3] as usize) << shift;
+            if x + count > width { return Err("HDR run overflows the scanline.".to_string()); }
+            let previous = scanline[x - 1];
+            for _ in 0..count {
+                scanline[x] = previous;
+                x += 1;
+            }
+            shift += 8;
+        } else {
+            scanline[x] = pixel;
+            x += 1;
+            shift = 0;
+        }
+    }
+    Ok(())
+}
+
+// Reads a new-style run length encoded scanline into the scanline buffer. The marker bytes have
+// already been consumed. Each of the four components is stored separately as a sequence of runs
+// (a count above 128 followed by a value to repeat) and dumps (a count followed by that many
+// literal values).
+fn read_rle_scanline(data: &Vec<u8>, cursor: &mut usize, scanline: &mut Vec<[u8; 4]>)
+        -> Result<(), String> {
+    let width = scanline.len();
+    for component in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = try!(read_byte(data, cursor)) as usize;
+            if count > 128 {
+                let count = count - 128;
+                if x + count > width { return Err("HDR run overflows the scanline.".to_string()); }
+                let value = try!(read_byte(data, cursor));
+                for _ in 0..count {
+                    scanline[x][component] = value;
+                    x += 1;
+                }
+            } else {
+                if count == 0 || x + count > width {
+                    return Err("HDR dump has an invalid length.".to_string());
+                }
+                for _ in 0..count {
+                    scanline[x][component] = try!(read_byte(data, cursor));
+                    x += 1;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Reads in every scanline from the data vector and returns the pixels converted from RGBE to
+// linear floats. Returns Err if either dimension is above MAX_DIMENSION or if the data does not
+// have the 4 bytes every scanline needs at the very least.
+fn read_pixel_array(data: &Vec<u8>, cursor: &mut usize, width: u32, height: u32)
+        -> Result<Vec<f32>, String> {
+    if width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(format!("HDR image dimensions of {}x{} are too large.", width, height));
+    }
+    if width > 0 && height as usize > (data.len() - *cursor) / 4 {
+        return Err("HDR image data is too small.".to_string());
+    }
+    let size = width as usize * height as usize * 3;
+    // Flat scanlines take 4 bytes per pixel, so only reserve what the data could hold unless it
+    // is run length encoded.
+    let mut pixels = Vec::with_capacity(size.min((data.len() - *cursor) / 4 * 3));
+    let mut scanline = vec![[0u8; 4]; width as usize];
+    for _ in 0..height {
+        let is_rle = width >= MIN_RLE_WIDTH && width <= MAX_RLE_WIDTH &&
+                data.len() >= *cursor + 4 && data[*cursor] == 2 && data[*cursor + 1] == 2 &&
+                data[*cursor + 2] & 0x80 == 0;
+        if is_rle {
+            let encoded_width = ((data[*cursor + 2] as u32) << 8) | data[*cursor + 3] as u32;
+            if encoded_width != width {
+                return Err("HDR scanline width does not match the image.".to_string());
+            }
+            *cursor += 4;
+            try!(read_rle_scanline(data, cursor, &mut scanline));
+        } else {
+            try!(read_flat_scanline(data, cursor, &mut scanline));
+        }
+        for pixel in scanline.iter() {
+            let (r, g, b) = rgbe_to_float(pixel);
+            pixels.push(r);
+            pixels.push(g);
+            pixels.push(b);
+        }
+    }
+    Ok(pixels)
+}
+
+// Converts an RGBE pixel (three mantissas sharing an exponent) to linear floating point RGB.
+fn rgbe_to_float(pixel: &[u8; 4]) -> (f32, f32, f32) {
+    if pixel[3] == 0 { return (0.0, 0.0, 0.0); }
+    let scale = (2.0 as f32).powi(pixel[3] as i32 - (128 + 8));
+    (pixel[0] as f32 * scale, pixel[1] as f32 * scale, pixel[2] as f32 * scale)
+}
+
+// Decodes an HDR given a path to the file and returns a DecodedHDR struct containing the pixel
+// information, width, and height of the image.
+pub fn decode_hdr(fpath: &str) -> Result<DecodedHDR, String> {
+    let mut data = Vec::new();
+    let mut fd = try!(File::open(fpath).map_err(|e| e.to_string()));
+    try!(fd.read_to_end(&mut data).map_err(|e| e.to_string()));
+
+    let mut cursor = 0;
+    let (width, height) = try!(read_header(&data, &mut cursor));
+    let pixels = try!(read_pixel_array(&data, &mut cursor, width, height));
+    let image = common::HDRImage { width: width, height: height, data: pixels };
+    Ok(DecodedHDR { image: image })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_header, read_pixel_array};
+
+    // Decodes the scanlines of an image from the pixel data alone.
+    fn decode(width: u32, height: u32, data: Vec<u8>) -> Result<Vec<f32>, String> {
+        let mut cursor = 0;
+        read_pixel_array(&data, &mut cursor, width, height)
+    }
+
+    // Reads the header of a file made from the given lines.
+    fn decode_header(lines: &[&str]) -> Result<(u32, u32), String> {
+        let data = (lines.join("\n") + "\n").into_bytes();
+        let mut cursor = 0;
+        read_header(&data, &mut cursor)
+    }
+
+    #[test]
+    fn flat_scanline() {
+        // Exponents of 128 + 8 give a scale of 1, and an exponent of 0 is black.
+        let data = vec![128, 64, 1, 137, 10, 20, 30, 0, 4, 2, 0, 136];
+        let pixels = decode(3, 1, data).unwrap();
+        assert_eq!(pixels, vec![256.0, 128.0, 2.0, 0.0, 0.0, 0.0, 4.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn old_style_run_scanline() {
+        // The run marker repeats the first pixel twice, and then a chained marker repeats it
+        // another 1 << 8 times.
+        let mut data = vec![3, 2, 1, 136, 1, 1, 1, 2, 1, 1, 1, 1, 9, 9, 9, 136];
+        data.extend(vec![1, 1, 1, 2]);
+        let pixels = decode(262, 1, data).unwrap();
+        assert_eq!(pixels.len(), 262 * 3);
+        for pixel in pixels.chunks(3).take(259) { assert_eq!(pixel, &[3.0, 2.0, 1.0]); }
+        for pixel in pixels.chunks(3).skip(259) { assert_eq!(pixel, &[9.0, 9.0, 9.0]); }
+    }
+
+    #[test]
+    fn new_style_rle_scanline() {
+        // Each component is a run, a dump, or a mix of both.
+        let mut data = vec![2, 2, 0, 8];
+        data.extend(vec![136, 5]);
+        data.extend(vec![8, 0, 1, 2, 3, 4, 5, 6, 7]);
+        data.extend(vec![131, 1, 5, 2, 2, 2, 2, 2]);
+        data.extend(vec![136, 136]);
+        let pixels = decode(8, 1, data).unwrap();
+        for x in 0..8 {
+            let blue = if x < 3 { 1.0 } else { 2.0 };
+            assert_eq!(&pixels[(x * 3)..(x * 3 + 3)], &[5.0, x as f32, blue]);
+        }
+    }
+
+    #[test]
+    fn run_overflows_scanline() {
+        assert!(decode(2, 1, vec![1, 1, 1, 136, 1, 1, 1, 2]).is_err());
+        assert!(decode(8, 1, vec![2, 2, 0, 8, 137, 5]).is_err());
+        assert!(decode(8, 1, vec![2, 2, 0, 8, 9, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+
+        // A run marker needs a pixel before it to repeat.
+        assert!(decode(2, 1, vec![1, 1, 1, 1, 1, 1, 1, 1]).is_err());
+    }
+
+    #[test]
+    fn chained_runs_overflow_count() {
+        // Runs of zero pixels do not advance the scanline, so chaining nine of them would shift
+        // the count past 64 bits.
+        let mut data = vec![5, 5, 5, 136];
+        for _ in 0..9 { data.extend(vec![1, 1, 1, 0]); }
+        assert!(decode(2, 1, data).is_err());
+    }
+
+    #[test]
+    fn huge_dimensions() {
+        assert!(decode(::std::u32::MAX, 1, vec![5, 5, 5, 136]).is_err());
+        assert!(decode(1, ::std::u32::MAX, vec![5, 5, 5, 136]).is_err());
+        assert!(decode(1, 2, vec![5, 5, 5, 136]).is_err());
+        assert!(decode(1, 1, vec![5, 5, 5, 136]).is_ok());
+    }
+
+    #[test]
+    fn header() {
+        let lines = ["#?RADIANCE", "FORMAT=32-bit_rle_rgbe", "EXPOSURE=1.0", "", "-Y 4 +X 6"];
+        assert_eq!(decode_header(&lines), Ok((6, 4)));
+        assert_eq!(decode_header(&["#?RGBE", "", "-Y 1 +X 2"]), Ok((2, 1)));
+    }
+
+    #[test]
+    fn unsupported_header() {
+        assert!(decode_header(&["#?RADIANCE", "FORMAT=32-bit_rle_xyze", "", "-Y 4 +X 6"])
+                .is_err());
+        assert!(decode_header(&["#?RADIANCE", "", "+X 6 -Y 4"]).is_err());
+        assert!(decode_header(&["#?RADIANCE", "", "-Y 4 -X 6"]).is_err());
+        assert!(decode_header(&["#?RADIANCE", "", "-Y 4 +X six"]).is_err());
+        assert!(decode_header(&["#?PNG", "", "-Y 4 +X 6"]).is_err());
+    }
+}
@@ -0,0 +1,43 @@
+// Utility module that decodes or encodes an image file of any supported format given a path to
+// the file. When decoding, the format is determined by the signature at the start of the file
+// rather than by its extension. When encoding, the format is determined by the extension.
+//
+// Brian Ho
+// brian@brkho.com
+
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use util::{bmp, common, jpeg, png, tga};
+
+// Decodes a BMP, JPEG, or PNG given a path to the file and returns the image. Returns Err if the
+// file cannot be read, is not in a supported format, or fails to decode.
+pub fn decode_image(fpath: &str) -> Result<common::Image, String> {
+    let mut signature = Vec::new();
+    let fd = try!(File::open(fpath).map_err(|e| e.to_string()));
+    try!(fd.take(8).read_to_end(&mut signature).map_err(|e| e.to_string()));
+    if signature[..] == png::SIGNATURE {
+        Ok(try!(png::decode_png(fpath)).image)
+    } else if signature.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Ok(try!(jpeg::decode_jpeg(fpath)).image)
+    } else if signature.starts_with(b"BM") {
+        Ok(try!(bmp::decode_bmp(fpath)).image)
+    } else {
+        Err(format!("{} is not a supported image format.", fpath))
+    }
+}
+
+// Encodes an image as a BMP, PNG, or TGA and writes it to a path. The format is chosen from the
+// path's extension, ignoring case. Returns Err if the extension is not supported or if the file
+// cannot be written.
+pub fn encode_image(image: &common::Image, fpath: &str) -> Result<(), String> {
+    let extension = Path::new(fpath).extension().and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+    match extension.as_ref().map(|e| &e[..]) {
+        Some("bmp") => bmp::encode_bmp(image, fpath),
+        Some("png") => png::encode_png(image, fpath),
+        Some("tga") => tga::encode_tga(image, fpath),
+        _ => Err(format!("{} does not have a supported image extension.", fpath)),
+    }
+}
@@ -0,0 +1,520 @@
+// Utility module of CPU image operations for asset tooling. This resizes images with box,
+// bilinear, or Lanczos filters, flips, rotates, crops, and blits them, swizzles and packs their
+// channels, converts between sRGB and linear colors, premultiplies alpha, and generates mipmap
+// chains. Every operation returns a new Image except for blitting, which draws into an existing
+// one. Filtering weights colors by alpha so that transparent pixels do not bleed into their
+// neighbors.
+//
+// Brian Ho
+// brian@brkho.com
+
+
+use std::f32::consts::PI;
+use util::common::{Image, Pixel};
+
+// The filter used to resample an image. Box averages the pixels under each destination pixel (or
+// picks the nearest one when enlarging), Bilinear uses a triangle filter, and Lanczos uses a
+// 3-lobed windowed sinc filter which is the sharpest but can ring around hard edges.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Filter {
+    Box,
+    Bilinear,
+    Lanczos,
+}
+
+impl Filter {
+    // Gets the distance from the center in pixels past which the filter is zero.
+    fn get_radius(&self) -> f32 {
+        match *self {
+            Filter::Box => 0.5,
+            Filter::Bilinear => 1.0,
+            Filter::Lanczos => 3.0,
+        }
+    }
+
+    // Evaluates the filter at a distance from the center in pixels.
+    fn evaluate(&self, x: f32) -> f32 {
+        match *self {
+            Filter::Box => if x >= -0.5 && x < 0.5 { 1.0 } else { 0.0 },
+            Filter::Bilinear => (1.0 - x.abs()).max(0.0),
+            Filter::Lanczos => {
+                if x == 0.0 { return 1.0; }
+                if x.abs() >= 3.0 { return 0.0; }
+                let x = x * PI;
+                3.0 * x.sin() * (x / 3.0).sin() / (x * x)
+            },
+        }
+    }
+}
+
+// A channel of a pixel.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+impl Channel {
+    // Gets the value of the channel of a pixel.
+    fn get(&self, pixel: &Pixel) -> u8 {
+        match *self {
+            Channel::Red => pixel.red,
+            Channel::Green => pixel.green,
+            Channel::Blue => pixel.blue,
+            Channel::Alpha => pixel.alpha,
+        }
+    }
+}
+
+// Where a channel of a packed image comes from: either a channel of another image or a constant
+// value.
+pub enum Source<'a> {
+    Channel(&'a Image, Channel),
+    Constant(u8),
+}
+
+// Converts an sRGB encoded color in the range 0-1 to linear.
+pub fn srgb_to_linear_value(value: f32) -> f32 {
+    if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+// Converts a linear color in the range 0-1 to sRGB encoding.
+pub fn linear_to_srgb_value(value: f32) -> f32 {
+    if value <= 0.0031308 { value * 12.92 } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 }
+}
+
+// Converts a value in the range 0-1 to a byte.
+fn to_byte(value: f32) -> u8 {
+    (value * 255.0).round().max(0.0).min(255.0) as u8
+}
+
+// Helper function that creates an image by evaluating a function at every position.
+fn from_fn<F: FnMut(u32, u32) -> Pixel>(width: u32, height: u32, mut f: F) -> Image {
+    let mut data = Vec::with_capacity(width as usize * height as usize);
+    for y in 0..height {
+        for x in 0..width {
+            data.push(f(x, y));
+        }
+    }
+    Image { width: width, height: height, data: data }
+}
+
+// Helper function that gets the pixel at a position of an image.
+fn get_pixel(image: &Image, x: u32, y: u32) -> Pixel {
+    image.data[y as usize * image.width as usize + x as usize]
+}
+
+// Converts an image to RGBA floats in the range 0-1 with the colors premultiplied by alpha. sRGB
+// colors are first converted to linear if requested.
+fn to_floats(image: &Image, srgb: bool) -> Vec<[f32; 4]> {
+    image.data.iter().map(|pixel| {
+        let alpha = pixel.alpha as f32 / 255.0;
+        let color = |value: u8| {
+            let value = value as f32 / 255.0;
+            (if srgb { srgb_to_linear_value(value) } else { value }) * alpha
+        };
+        [color(pixel.red), color(pixel.green), color(pixel.blue), alpha]
+    }).collect()
+}
+
+// Converts premultiplied RGBA floats back to an image, converting linear colors to sRGB if
+// requested.
+fn from_floats(floats: &[[f32; 4]], width: u32, height: u32, srgb: bool) -> Image {
+    let data = floats.iter().map(|value| {
+        let alpha = value[3].max(0.0).min(1.0);
+        let color = |value: f32| {
+            let value = if alpha > 0.0 { (value / alpha).max(0.0).min(1.0) } else { 0.0 };
+            to_byte(if srgb { linear_to_srgb_value(value) } else { value })
+        };
+        Pixel { red: color(value[0]), green: color(value[1]), blue: color(value[2]),
+                alpha: to_byte(alpha) }
+    }).collect();
+    Image { width: width, height: height, data: data }
+}
+
+// Computes the source pixels that contribute to each destination pixel along one axis as the
+// index of the first source pixel and the normalized weights of it and the pixels after it. When
+// shrinking, the filter is stretched to cover every source pixel under the destination pixel.
+fn get_weights(source: u32, destination: u32, filter: Filter) -> Vec<(usize, Vec<f32>)> {
+    let scale = source as f32 / destination as f32;
+    let stretch = scale.max(1.0);
+    let radius = filter.get_radius() * stretch;
+    (0..destination).map(|i| {
+        let center = (i as f32 + 0.5) * scale;
+        let start = (center - radius).floor().max(0.0) as usize;
+        let end = ((center + radius).ceil() as usize).min(source as usize);
+        let mut weights: Vec<f32> = (start..end)
+                .map(|j| filter.evaluate((j as f32 + 0.5 - center) / stretch)).collect();
+        let total = weights.iter().fold(0.0, |sum, &weight| sum + weight);
+        if total > 0.0 {
+            for weight in weights.iter_mut() { *weight /= total; }
+            (start, weights)
+        } else {
+            // Fall back to the nearest pixel if the filter misses every source pixel.
+            ((center as usize).min(source as usize - 1), vec![1.0])
+        }
+    }).collect()
+}
+
+// Resamples premultiplied RGBA floats to a new size with a separable filter, first along the rows
+// and then along the columns.
+fn resize_floats(floats: &[[f32; 4]], width: u32, height: u32, new_width: u32, new_height: u32,
+        filter: Filter) -> Vec<[f32; 4]> {
+    let weights = get_weights(width, new_width, filter);
+    let (width, new_width) = (width as usize, new_width as usize);
+    let mut rows = vec![[0.0; 4]; new_width * height as usize];
+    for y in 0..(height as usize) {
+        for (x, &(start, ref weights)) in weights.iter().enumerate() {
+            let value = &mut rows[y * new_width + x];
+            for (i, weight) in weights.iter().enumerate() {
+                let source = floats[y * width + start + i];
+                for c in 0..4 { value[c] += source[c] * weight; }
+            }
+        }
+    }
+
+    let mut resized = vec![[0.0; 4]; new_width * new_height as usize];
+    let weights = get_weights(height, new_height, filter);
+    for (y, &(start, ref weights)) in weights.iter().enumerate() {
+        for x in 0..new_width {
+            let value = &mut resized[y * new_width + x];
+            for (i, weight) in weights.iter().enumerate() {
+                let source = rows[(start + i) * new_width + x];
+                for c in 0..4 { value[c] += source[c] * weight; }
+            }
+        }
+    }
+    resized
+}
+
+// Resizes an image with a filter. The colors are filtered as they are stored, so use
+// generate_mipmaps or convert to linear first when a physically correct average of sRGB colors
+// matters. Returns Err if the image or the new size is empty.
+pub fn resize(image: &Image, width: u32, height: u32, filter: Filter) -> Result<Image, String> {
+    try!(image.validate());
+    if width == 0 || height == 0 {
+        return Err("Cannot resize an image to zero pixels.".to_string());
+    }
+    let floats = to_floats(image, false);
+    let resized = resize_floats(&floats, image.width, image.height, width, height, filter);
+    Ok(from_floats(&resized, width, height, false))
+}
+
+// Generates a chain of mipmaps starting from the image itself, where each level is half the size
+// of the previous one rounded down until the last level is 1x1. If the image is sRGB encoded, the
+// levels are filtered in linear space so that they do not darken.
+pub fn generate_mipmaps(image: &Image, filter: Filter, srgb: bool) -> Result<Vec<Image>, String> {
+    try!(image.validate());
+    let mut levels = vec![image.clone()];
+    let (mut width, mut height) = (image.width, image.height);
+    let mut floats = to_floats(image, srgb);
+    while width > 1 || height > 1 {
+        let (new_width, new_height) = ((width / 2).max(1), (height / 2).max(1));
+        floats = resize_floats(&floats, width, height, new_width, new_height, filter);
+        levels.push(from_floats(&floats, new_width, new_height, srgb));
+        width = new_width;
+        height = new_height;
+    }
+    Ok(levels)
+}
+
+// Mirrors an image so that its left and right sides are swapped. Returns Err if the image is
+// invalid.
+pub fn flip_horizontal(image: &Image) -> Result<Image, String> {
+    try!(image.validate());
+    Ok(from_fn(image.width, image.height, |x, y| get_pixel(image, image.width - 1 - x, y)))
+}
+
+// Mirrors an image so that its top and bottom are swapped. Returns Err if the image is invalid.
+pub fn flip_vertical(image: &Image) -> Result<Image, String> {
+    try!(image.validate());
+    Ok(from_fn(image.width, image.height, |x, y| get_pixel(image, x, image.height - 1 - y)))
+}
+
+// Rotates an image 90 degrees clockwise. Returns Err if the image is invalid.
+pub fn rotate_90(image: &Image) -> Result<Image, String> {
+    try!(image.validate());
+    Ok(from_fn(image.height, image.width, |x, y| get_pixel(image, y, image.height - 1 - x)))
+}
+
+// Rotates an image 180 degrees. Returns Err if the image is invalid.
+pub fn rotate_180(image: &Image) -> Result<Image, String> {
+    try!(image.validate());
+    Ok(from_fn(image.width, image.height,
+            |x, y| get_pixel(image, image.width - 1 - x, image.height - 1 - y)))
+}
+
+// Rotates an image 270 degrees clockwise. Returns Err if the image is invalid.
+pub fn rotate_270(image: &Image) -> Result<Image, String> {
+    try!(image.validate());
+    Ok(from_fn(image.height, image.width, |x, y| get_pixel(image, image.width - 1 - y, x)))
+}
+
+// Copies a rectangle of an image given its top left corner and size. Returns Err if the rectangle
+// is empty or does not fit in the image.
+pub fn crop(image: &Image, x: u32, y: u32, width: u32, height: u32) -> Result<Image, String> {
+    try!(image.validate());
+    if width == 0 || height == 0 || x as u64 + width as u64 > image.width as u64 ||
+            y as u64 + height as u64 > image.height as u64 {
+        return Err(format!("Cannot crop a {}x{} rectangle at ({}, {}) from a {}x{} image.", width,
+                height, x, y, image.width, image.height));
+    }
+    Ok(from_fn(width, height, |i, j| get_pixel(image, x + i, y + j)))
+}
+
+// Copies a source image into a destination image with the source's top left corner at a position
+// of the destination. Pixels are replaced rather than blended, and the parts of the source that
+// fall outside of the destination are clipped. Returns Err if either image is invalid.
+pub fn blit(destination: &mut Image, source: &Image, x: u32, y: u32) -> Result<(), String> {
+    try!(destination.validate());
+    try!(source.validate());
+    let width = source.width.min(destination.width.saturating_sub(x));
+    let height = source.height.min(destination.height.saturating_sub(y));
+    for j in 0..height {
+        for i in 0..width {
+            let index = (y + j) as usize * destination.width as usize + (x + i) as usize;
+            destination.data[index] = get_pixel(source, i, j);
+        }
+    }
+    Ok(())
+}
+
+// Rearranges the channels of an image. Each channel of the new image is taken from the given
+// channel of the image, e.g. [Blue, Green, Red, Alpha] swaps red and blue. Returns Err if the
+// image is invalid.
+pub fn swizzle(image: &Image, channels: [Channel; 4]) -> Result<Image, String> {
+    try!(image.validate());
+    let data = image.data.iter().map(|pixel| Pixel { red: channels[0].get(pixel),
+            green: channels[1].get(pixel), blue: channels[2].get(pixel),
+            alpha: channels[3].get(pixel) }).collect();
+    Ok(Image { width: image.width, height: image.height, data: data })
+}
+
+// Packs channels of several images into the red, green, blue, and alpha channels of one image.
+// For example, a metallic-roughness map can be packed from separate grayscale maps with
+// [Channel(&occlusion, Red), Channel(&roughness, Red), Channel(&metallic, Red), Constant(255)].
+// Returns Err if no channel comes from an image or if the images are not all the same size.
+pub fn pack_channels(sources: [Source; 4]) -> Result<Image, String> {
+    let mut images = sources.iter().filter_map(|source| match *source {
+        Source::Channel(image, _) => Some(image),
+        Source::Constant(_) => None,
+    });
+    let first = try!(images.next().ok_or(
+            "At least one packed channel must come from an image.".to_string()));
+    try!(first.validate());
+    if images.any(|image| image.width != first.width || image.height != first.height ||
+            image.data.len() != first.data.len()) {
+        return Err("Cannot pack channels from images of different sizes.".to_string());
+    }
+    let get = |source: &Source, i: usize| match *source {
+        Source::Channel(image, channel) => channel.get(&image.data[i]),
+        Source::Constant(value) => value,
+    };
+    let data = (0..first.data.len()).map(|i| Pixel { red: get(&sources[0], i),
+            green: get(&sources[1], i), blue: get(&sources[2], i), alpha: get(&sources[3], i) })
+            .collect();
+    Ok(Image { width: first.width, height: first.height, data: data })
+}
+
+// Helper function that applies a function to the red, green, and blue channels of every pixel.
+fn map_colors<F: Fn(u8, u8) -> u8>(image: &Image, f: F) -> Image {
+    let data = image.data.iter().map(|pixel| Pixel { red: f(pixel.red, pixel.alpha),
+            green: f(pixel.green, pixel.alpha), blue: f(pixel.blue, pixel.alpha),
+            alpha: pixel.alpha }).collect();
+    Image { width: image.width, height: image.height, data: data }
+}
+
+// Converts the colors of an sRGB encoded image to linear. Alpha is left as is. Dark colors lose
+// precision when stored as bytes, so prefer HDRImage::from_image when the result is not saved.
+pub fn srgb_to_linear(image: &Image) -> Image {
+    map_colors(image, |value, _| to_byte(srgb_to_linear_value(value as f32 / 255.0)))
+}
+
+// Converts the colors of a linear image to sRGB encoding. Alpha is left as is.
+pub fn linear_to_srgb(image: &Image) -> Image {
+    map_colors(image, |value, _| to_byte(linear_to_srgb_value(value as f32 / 255.0)))
+}
+
+// Multiplies the colors of an image by its alpha.
+pub fn premultiply_alpha(image: &Image) -> Image {
+    map_colors(image, |value, alpha| ((value as u32 * alpha as u32 + 127) / 255) as u8)
+}
+
+// Divides the colors of a premultiplied image by its alpha. Fully transparent pixels become
+// black.
+pub fn unpremultiply_alpha(image: &Image) -> Image {
+    map_colors(image, |value, alpha| {
+        if alpha == 0 { 0 } else {
+            ((value as u32 * 255 + alpha as u32 / 2) / alpha as u32).min(255) as u8
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::common::{Image, Pixel};
+
+    // Creates an image where every pixel is different. Alpha is never zero so that colors survive
+    // being premultiplied.
+    fn create_image(width: u32, height: u32) -> Image {
+        from_fn(width, height, |x, y| Pixel { red: (x * 40 + y) as u8, green: (y * 40 + x) as u8,
+                blue: (x * y) as u8, alpha: (255 - x * 7 - y * 3) as u8 })
+    }
+
+    // Creates an image of a single color.
+    fn create_solid(width: u32, height: u32, value: u8) -> Image {
+        from_fn(width, height, |_, _| Pixel { red: value, green: value, blue: value, alpha: 255 })
+    }
+
+    #[test]
+    fn rotate_four_times() {
+        let image = create_image(5, 3);
+        let mut rotated = image.clone();
+        for _ in 0..4 { rotated = rotate_90(&rotated).unwrap(); }
+        assert_eq!((rotated.width, rotated.height), (5, 3));
+        assert_eq!(rotated.data, image.data);
+        assert_eq!(rotate_90(&rotate_90(&image).unwrap()).unwrap().data,
+                rotate_180(&image).unwrap().data);
+        assert_eq!(rotate_270(&rotate_90(&image).unwrap()).unwrap().data, image.data);
+    }
+
+    #[test]
+    fn rotate_corner() {
+        let image = create_image(5, 3);
+        let rotated = rotate_90(&image).unwrap();
+        assert_eq!((rotated.width, rotated.height), (3, 5));
+        // The bottom left corner ends up at the top left.
+        assert_eq!(get_pixel(&rotated, 0, 0), get_pixel(&image, 0, 2));
+    }
+
+    #[test]
+    fn flip_twice() {
+        let image = create_image(4, 7);
+        assert_eq!(flip_horizontal(&flip_horizontal(&image).unwrap()).unwrap().data, image.data);
+        assert_eq!(flip_vertical(&flip_vertical(&image).unwrap()).unwrap().data, image.data);
+        assert_eq!(get_pixel(&flip_horizontal(&image).unwrap(), 0, 1), get_pixel(&image, 3, 1));
+        assert_eq!(get_pixel(&flip_vertical(&image).unwrap(), 2, 0), get_pixel(&image, 2, 6));
+    }
+
+    #[test]
+    fn resize_same_size() {
+        let image = create_image(6, 5);
+        for &filter in [Filter::Box, Filter::Bilinear].iter() {
+            let resized = resize(&image, 6, 5, filter).unwrap();
+            assert_eq!(resized.data, image.data);
+        }
+    }
+
+    #[test]
+    fn resize_solid() {
+        let image = create_solid(9, 4, 100);
+        for &filter in [Filter::Box, Filter::Bilinear, Filter::Lanczos].iter() {
+            let resized = resize(&image, 4, 13, filter).unwrap();
+            assert_eq!((resized.width, resized.height), (4, 13));
+            assert!(resized.data.iter().all(|pixel| *pixel == image.data[0]));
+        }
+        assert!(resize(&image, 0, 4, Filter::Box).is_err());
+    }
+
+    #[test]
+    fn mipmap_levels() {
+        let levels = generate_mipmaps(&create_solid(512, 512, 50), Filter::Box, true).unwrap();
+        assert_eq!(levels.len(), 10);
+        assert_eq!((levels[9].width, levels[9].height), (1, 1));
+        assert_eq!(levels[9].data[0], levels[0].data[0]);
+
+        let levels = generate_mipmaps(&create_image(7, 2), Filter::Bilinear, false).unwrap();
+        let sizes: Vec<(u32, u32)> = levels.iter().map(|level| (level.width, level.height))
+                .collect();
+        assert_eq!(sizes, vec![(7, 2), (3, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn mipmap_srgb_average() {
+        // Averaging black and white in linear space gives a lighter gray than averaging the
+        // stored values.
+        let image = from_fn(2, 1, |x, _| {
+            let value = if x == 0 { 0 } else { 255 };
+            Pixel { red: value, green: value, blue: value, alpha: 255 }
+        });
+        let linear = generate_mipmaps(&image, Filter::Box, true).unwrap();
+        let stored = generate_mipmaps(&image, Filter::Box, false).unwrap();
+        assert_eq!(stored[1].data[0].red, 128);
+        assert_eq!(linear[1].data[0].red, 188);
+    }
+
+    #[test]
+    fn premultiply_round_trip() {
+        let image = from_fn(16, 16, |x, y| Pixel { red: (x * 16 + y) as u8,
+                green: (y * 16 + x) as u8, blue: (x * y) as u8, alpha: 255 });
+        assert_eq!(premultiply_alpha(&image).data, image.data);
+        assert_eq!(unpremultiply_alpha(&premultiply_alpha(&image)).data, image.data);
+        let half = Image { width: 1, height: 1,
+                data: vec![Pixel { red: 200, green: 100, blue: 0, alpha: 128 }] };
+        assert_eq!(premultiply_alpha(&half).data[0],
+                Pixel { red: 100, green: 50, blue: 0, alpha: 128 });
+    }
+
+    #[test]
+    fn crop_edges() {
+        let image = create_image(6, 4);
+        let cropped = crop(&image, 4, 1, 2, 3).unwrap();
+        assert_eq!((cropped.width, cropped.height), (2, 3));
+        assert_eq!(get_pixel(&cropped, 0, 0), get_pixel(&image, 4, 1));
+        assert_eq!(get_pixel(&cropped, 1, 2), get_pixel(&image, 5, 3));
+        assert!(crop(&image, 5, 0, 2, 1).is_err());
+        assert!(crop(&image, 0, 3, 1, 2).is_err());
+        assert!(crop(&image, 0, 0, 0, 1).is_err());
+        assert!(crop(&image, u32::max_value(), 0, 2, 1).is_err());
+    }
+
+    #[test]
+    fn blit_edges() {
+        let source = create_solid(3, 3, 255);
+        let mut destination = create_solid(4, 4, 0);
+        blit(&mut destination, &source, 2, 3).unwrap();
+        for y in 0..4 {
+            for x in 0..4 {
+                let expected = if x >= 2 && y >= 3 { 255 } else { 0 };
+                assert_eq!(get_pixel(&destination, x, y).red, expected);
+            }
+        }
+        let before = destination.data.clone();
+        blit(&mut destination, &source, 4, 0).unwrap();
+        blit(&mut destination, &source, 0, u32::max_value()).unwrap();
+        assert_eq!(destination.data, before);
+    }
+
+    #[test]
+    fn invalid_images() {
+        let image = Image { width: 3, height: 3, data: vec![] };
+        let mut valid = create_solid(2, 2, 0);
+        assert!(flip_horizontal(&image).is_err());
+        assert!(flip_vertical(&image).is_err());
+        assert!(rotate_90(&image).is_err());
+        assert!(rotate_180(&image).is_err());
+        assert!(rotate_270(&image).is_err());
+        assert!(swizzle(&image, [Channel::Red, Channel::Red, Channel::Red, Channel::Red])
+                .is_err());
+        assert!(blit(&mut valid, &image, 0, 0).is_err());
+        assert!(generate_mipmaps(&image, Filter::Box, false).is_err());
+    }
+
+    #[test]
+    fn swizzle_and_pack() {
+        let image = create_image(3, 2);
+        let swapped = swizzle(&image, [Channel::Blue, Channel::Green, Channel::Red,
+                Channel::Alpha]).unwrap();
+        assert_eq!(swapped.data[4].red, image.data[4].blue);
+        assert_eq!(swapped.data[4].blue, image.data[4].red);
+        let packed = pack_channels([Source::Channel(&image, Channel::Alpha), Source::Constant(7),
+                Source::Channel(&swapped, Channel::Red), Source::Constant(255)]).unwrap();
+        assert_eq!(packed.data[5], Pixel { red: image.data[5].alpha, green: 7,
+                blue: image.data[5].blue, alpha: 255 });
+        assert!(pack_channels([Source::Constant(0), Source::Constant(0), Source::Constant(0),
+                Source::Constant(0)]).is_err());
+    }
+}
@@ -0,0 +1,736 @@
+// Utility module that allows for decoding of a JPEG given a path to the file. This supports 8-bit
+// baseline, extended, and progressive Huffman coded images with one (grayscale) or three (YCbCr or
+// Adobe RGB) components at any chroma subsampling along with restart intervals. Subsampled
+// components are upsampled with linear interpolation, and the EXIF orientation is applied so the
+// image comes out the way cameras intended it to be viewed. Arithmetic coded, lossless,
+// hierarchical, 12-bit, and CMYK images are not supported.
+//
+// Brian Ho
+// brian@brkho.com
+
+
+use std::fs::File;
+use std::io::Read;
+use util::common;
+
+// The index into a block in natural (row major) order of each coefficient in zigzag order.
+const ZIGZAG: [usize; 64] = [
+        0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27,
+        20, 13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51,
+        58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63];
+
+// Return value for a decoded JPEG file. This contains a width, height, and an array of pixels with
+// color and alpha information. JPEGs have no transparency, so every alpha is 255.
+pub struct DecodedJPEG {
+    pub image: common::Image,
+}
+
+// Reads the entropy coded data of a scan a bit at a time starting from the most significant bit.
+// Stuffed zero bytes after 0xFF are skipped, and zeros are returned once a marker is reached.
+struct BitReader<'a> {
+    data: &'a [u8],
+    cursor: usize,
+    bits: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    // Creates a BitReader that starts reading at a position of the data.
+    fn new(data: &'a [u8], cursor: usize) -> BitReader<'a> {
+        BitReader { data: data, cursor: cursor, bits: 0, count: 0 }
+    }
+
+    // Reads a single bit.
+    fn read_bit(&mut self) -> u32 {
+        if self.count == 0 {
+            self.bits = match (self.data.get(self.cursor), self.data.get(self.cursor + 1)) {
+                (Some(&0xFF), Some(&0x00)) => { self.cursor += 2; 0xFF },
+                (Some(&0xFF), _) | (None, _) => 0,
+                (Some(&byte), _) => { self.cursor += 1; byte as u32 },
+            };
+            self.count = 8;
+        }
+        self.count -= 1;
+        (self.bits >> self.count) & 1
+    }
+
+    // Reads n bits as an unsigned integer.
+    fn read_bits(&mut self, n: u32) -> u32 {
+        let mut value = 0;
+        for _ in 0..n { value = (value << 1) | self.read_bit(); }
+        value
+    }
+
+    // Reads an n bit magnitude and extends it to the signed value it represents.
+    fn receive_extend(&mut self, n: u32) -> i32 {
+        if n == 0 { return 0; }
+        let value = self.read_bits(n) as i32;
+        if value < 1 << (n - 1) { value - (1 << n) + 1 } else { value }
+    }
+
+    // Discards the remaining bits of the current byte and consumes the restart marker that must
+    // come next.
+    fn restart(&mut self) -> Result<(), String> {
+        self.count = 0;
+        while self.data.get(self.cursor) == Some(&0xFF) &&
+                self.data.get(self.cursor + 1) == Some(&0xFF) {
+            self.cursor += 1;
+        }
+        match (self.data.get(self.cursor), self.data.get(self.cursor + 1)) {
+            (Some(&0xFF), Some(&(0xD0...0xD7))) => { self.cursor += 2; Ok(()) },
+            _ => Err("JPEG scan is missing a restart marker.".to_string()),
+        }
+    }
+}
+
+// A Huffman table from a DHT segment. Codes are decoded a bit at a time using the number of codes
+// of each length, which is enough to find the symbol of a canonical code.
+struct HuffmanTable {
+    counts: [u16; 17],
+    symbols: Vec<u8>,
+}
+
+impl HuffmanTable {
+    // Decodes a symbol.
+    fn decode(&self, reader: &mut BitReader) -> Result<u8, String> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0usize);
+        for length in 1..17 {
+            code |= reader.read_bit() as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count { return Ok(self.symbols[index + (code - first) as usize]); }
+            index += count as usize;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("JPEG data has an invalid Huffman code.".to_string())
+    }
+}
+
+// A color component of the frame along with its decoded coefficients. The coefficients are stored
+// block by block in natural order and are padded out to a whole number of MCUs.
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    quantization: usize,
+    blocks_w: usize,
+    blocks_h: usize,
+    coefficients: Vec<i32>,
+}
+
+// Data structure representation of an SOF segment.
+struct Frame {
+    progressive: bool,
+    width: usize,
+    height: usize,
+    h_max: usize,
+    v_max: usize,
+    mcus_w: usize,
+    mcus_h: usize,
+    components: Vec<Component>,
+}
+
+impl Frame {
+    // Gets the width and height in samples of a component before padding.
+    fn get_component_size(&self, component: &Component) -> (usize, usize) {
+        ((self.width * component.h + self.h_max - 1) / self.h_max,
+                (self.height * component.v + self.v_max - 1) / self.v_max)
+    }
+}
+
+// Data structure representation of an SOS segment. Each component is given as its index in the
+// frame and its DC and AC table IDs.
+struct Scan {
+    components: Vec<(usize, usize, usize)>,
+    start: usize,
+    end: usize,
+    high: u32,
+    low: u32,
+}
+
+// The tables and settings that can change between scans.
+struct Tables {
+    dc: [Option<HuffmanTable>; 4],
+    ac: [Option<HuffmanTable>; 4],
+    quantization: [[u16; 64]; 4],
+    restart_interval: usize,
+}
+
+// Reads and consumes 2 bytes from the data vector as a big endian u16.
+fn read_u16(data: &[u8], cursor: &mut usize) -> Result<u16, String> {
+    if *cursor + 2 > data.len() { return Err("JPEG file is too small.".to_string()); }
+    let value = (data[*cursor] as u16) << 8 | data[*cursor + 1] as u16;
+    *cursor += 2;
+    Ok(value)
+}
+
+// Reads the next marker, skipping any fill bytes before it.
+fn read_marker(data: &[u8], cursor: &mut usize) -> Result<u8, String> {
+    if data.get(*cursor) != Some(&0xFF) {
+        return Err("JPEG file is missing a marker where one was expected.".to_string());
+    }
+    while data.get(*cursor) == Some(&0xFF) { *cursor += 1; }
+    match data.get(*cursor) {
+        Some(&marker) => { *cursor += 1; Ok(marker) },
+        None => Err("JPEG file ended before its EOI marker.".to_string()),
+    }
+}
+
+// Reads a DHT segment, which can define several tables.
+fn read_huffman_tables(segment: &[u8], tables: &mut Tables) -> Result<(), String> {
+    let mut cursor = 0;
+    while cursor < segment.len() {
+        if cursor + 17 > segment.len() { return Err("JPEG DHT segment is too small.".to_string()); }
+        let (class, id) = (segment[cursor] >> 4, (segment[cursor] & 15) as usize);
+        let mut counts = [0u16; 17];
+        for length in 1..17 { counts[length] = segment[cursor + length] as u16; }
+        let total = counts.iter().fold(0, |sum, &count| sum + count as usize);
+        cursor += 17;
+        if class > 1 || id > 3 || total > 256 || cursor + total > segment.len() {
+            return Err("JPEG DHT segment is invalid.".to_string());
+        }
+        let symbols = segment[cursor..(cursor + total)].to_vec();
+        cursor += total;
+        let table = HuffmanTable { counts: counts, symbols: symbols };
+        if class == 0 { tables.dc[id] = Some(table); } else { tables.ac[id] = Some(table); }
+    }
+    Ok(())
+}
+
+// Reads a DQT segment, which can define several tables. The tables are stored in natural order.
+fn read_quantization_tables(segment: &[u8], tables: &mut Tables) -> Result<(), String> {
+    let mut cursor = 0;
+    while cursor < segment.len() {
+        let (precision, id) = (segment[cursor] >> 4, (segment[cursor] & 15) as usize);
+        let size = if precision == 0 { 64 } else { 128 };
+        cursor += 1;
+        if precision > 1 || id > 3 || cursor + size > segment.len() {
+            return Err("JPEG DQT segment is invalid.".to_string());
+        }
+        for i in 0..64 {
+            let bytes = &segment[cursor..];
+            tables.quantization[id][ZIGZAG[i]] = if precision == 0 { bytes[i] as u16 }
+                    else { (bytes[i * 2] as u16) << 8 | bytes[i * 2 + 1] as u16 };
+        }
+        cursor += size;
+    }
+    Ok(())
+}
+
+// Reads and validates an SOF segment.
+fn read_frame(segment: &[u8], progressive: bool) -> Result<Frame, String> {
+    if segment.len() < 6 { return Err("JPEG SOF segment is too small.".to_string()); }
+    if segment[0] != 8 {
+        return Err(format!("JPEG images with {}-bit samples are not supported.", segment[0]));
+    }
+    let mut cursor = 1;
+    let height = try!(read_u16(segment, &mut cursor)) as usize;
+    let width = try!(read_u16(segment, &mut cursor)) as usize;
+    let count = segment[5] as usize;
+    if width == 0 || height == 0 { return Err("JPEG image has invalid dimensions.".to_string()); }
+    if count != 1 && count != 3 {
+        return Err(format!("JPEG images with {} components are not supported.", count));
+    }
+    if segment.len() != 6 + count * 3 { return Err("JPEG SOF segment is invalid.".to_string()); }
+
+    let mut components = Vec::new();
+    for i in 0..count {
+        let bytes = &segment[(6 + i * 3)..(9 + i * 3)];
+        let (h, v) = ((bytes[1] >> 4) as usize, (bytes[1] & 15) as usize);
+        if h < 1 || h > 4 || v < 1 || v > 4 || bytes[2] > 3 {
+            return Err("JPEG SOF segment has an invalid component.".to_string());
+        }
+        components.push(Component { id: bytes[0], h: h, v: v, quantization: bytes[2] as usize,
+                blocks_w: 0, blocks_h: 0, coefficients: Vec::new() });
+    }
+    let h_max = components.iter().map(|c| c.h).max().unwrap();
+    let v_max = components.iter().map(|c| c.v).max().unwrap();
+    let mcus_w = (width + h_max * 8 - 1) / (h_max * 8);
+    let mcus_h = (height + v_max * 8 - 1) / (v_max * 8);
+    for component in components.iter_mut() {
+        component.blocks_w = mcus_w * component.h;
+        component.blocks_h = mcus_h * component.v;
+        component.coefficients = vec![0; component.blocks_w * component.blocks_h * 64];
+    }
+    Ok(Frame { progressive: progressive, width: width, height: height, h_max: h_max,
+            v_max: v_max, mcus_w: mcus_w, mcus_h: mcus_h, components: components })
+}
+
+// Reads and validates an SOS segment.
+fn read_scan(segment: &[u8], frame: &Frame) -> Result<Scan, String> {
+    let count = *segment.get(0).unwrap_or(&0) as usize;
+    if count < 1 || count > 4 || segment.len() != 4 + count * 2 {
+        return Err("JPEG SOS segment is invalid.".to_string());
+    }
+    let mut components = Vec::new();
+    for i in 0..count {
+        let (id, selectors) = (segment[1 + i * 2], segment[2 + i * 2]);
+        let index = try!(frame.components.iter().position(|c| c.id == id).ok_or(
+                format!("JPEG scan refers to an unknown component {}.", id)));
+        let (dc, ac) = ((selectors >> 4) as usize, (selectors & 15) as usize);
+        if dc > 3 || ac > 3 { return Err("JPEG SOS segment is invalid.".to_string()); }
+        components.push((index, dc, ac));
+    }
+    let bytes = &segment[(1 + count * 2)..];
+    let scan = Scan { components: components, start: bytes[0] as usize, end: bytes[1] as usize,
+            high: (bytes[2] >> 4) as u32, low: (bytes[2] & 15) as u32 };
+    let valid = if frame.progressive {
+        scan.start <= scan.end && scan.end < 64 && (scan.start == 0) == (scan.end == 0) &&
+                (scan.start == 0 || count == 1) && scan.low < 14 && scan.high < 14
+    } else {
+        scan.start == 0 && scan.end == 63 && scan.high == 0 && scan.low == 0
+    };
+    if !valid {
+        return Err("JPEG scan has an invalid spectral selection or approximation.".to_string());
+    }
+    Ok(scan)
+}
+
+// Reads the EXIF orientation from an APP1 segment. Returns None if the segment does not contain
+// one. Malformed EXIF data is ignored since it does not affect the image data.
+fn read_orientation(segment: &[u8]) -> Option<u16> {
+    if !segment.starts_with(b"Exif\0\0") { return None; }
+    let tiff = &segment[6..];
+    let big_endian = if tiff.starts_with(b"MM") { true }
+            else if tiff.starts_with(b"II") { false }
+            else { return None; };
+    let read = |offset: usize, size: usize| -> Option<usize> {
+        tiff.get(offset..(offset + size)).map(|bytes| {
+            let mut value = 0;
+            for i in 0..size {
+                let byte = bytes[if big_endian { i } else { size - 1 - i }];
+                value = (value << 8) | byte as usize;
+            }
+            value
+        })
+    };
+    let ifd = match read(4, 4) {
+        Some(ifd) => ifd,
+        None => { return None; },
+    };
+    let entries = read(ifd, 2).unwrap_or(0);
+    (0..entries).map(|i| ifd + 2 + i * 12).find(|&entry| read(entry, 2) == Some(0x0112))
+            .and_then(|entry| read(entry + 8, 2)).map(|orientation| orientation as u16)
+}
+
+// Gets a Huffman table that a scan needs. Returns Err if it was never defined.
+fn get_table(tables: &[Option<HuffmanTable>; 4], id: usize) -> Result<&HuffmanTable, String> {
+    tables[id].as_ref().ok_or(format!("JPEG scan uses an undefined Huffman table {}.", id))
+}
+
+// Decodes the DC coefficient of a block for baseline scans and the first scan of a progressive DC
+// coefficient.
+fn decode_dc_first(reader: &mut BitReader, table: &HuffmanTable, low: u32, prediction: &mut i32,
+        block: &mut [i32]) -> Result<(), String> {
+    let size = try!(table.decode(reader)) as u32;
+    if size > 11 { return Err("JPEG DC coefficient is too large.".to_string()); }
+    *prediction += reader.receive_extend(size);
+    block[0] = *prediction << low;
+    Ok(())
+}
+
+// Decodes the AC coefficients in the spectral selection of a block for baseline scans and the
+// first scan of progressive AC coefficients. An end of band run skips the following blocks.
+fn decode_ac_first(reader: &mut BitReader, table: &HuffmanTable, scan: &Scan, eob_run: &mut u32,
+        block: &mut [i32]) -> Result<(), String> {
+    if *eob_run > 0 {
+        *eob_run -= 1;
+        return Ok(());
+    }
+    let mut k = scan.start.max(1);
+    while k <= scan.end {
+        let symbol = try!(table.decode(reader));
+        let (run, size) = ((symbol >> 4) as u32, (symbol & 15) as u32);
+        if size == 0 {
+            if run < 15 {
+                *eob_run = (1 << run) - 1 + reader.read_bits(run);
+                break;
+            }
+            k += 16;
+            continue;
+        }
+        k += run as usize;
+        if k > scan.end { return Err("JPEG AC coefficients overflow their block.".to_string()); }
+        block[ZIGZAG[k]] = reader.receive_extend(size) << scan.low;
+        k += 1;
+    }
+    Ok(())
+}
+
+// Decodes another bit of the DC coefficient of a block for progressive refinement scans.
+fn decode_dc_refine(reader: &mut BitReader, low: u32, block: &mut [i32]) {
+    block[0] |= (reader.read_bit() as i32) << low;
+}
+
+// Refines a coefficient that is already nonzero with another bit of its magnitude.
+fn refine_nonzero(reader: &mut BitReader, bit: i32, coefficient: &mut i32) {
+    if reader.read_bit() == 1 && *coefficient & bit == 0 {
+        *coefficient += if *coefficient >= 0 { bit } else { -bit };
+    }
+}
+
+// Decodes another bit of the AC coefficients in the spectral selection of a block for progressive
+// refinement scans. Coefficients that are already nonzero get a correction bit, and coefficients
+// that become nonzero are placed after skipping a run of coefficients that are still zero.
+fn decode_ac_refine(reader: &mut BitReader, table: &HuffmanTable, scan: &Scan, eob_run: &mut u32,
+        block: &mut [i32]) -> Result<(), String> {
+    let bit = 1 << scan.low;
+    let mut k = scan.start;
+    if *eob_run == 0 {
+        while k <= scan.end {
+            let symbol = try!(table.decode(reader));
+            let (mut run, size) = ((symbol >> 4) as u32, symbol & 15);
+            let mut value = 0;
+            if size == 1 {
+                value = if reader.read_bit() == 1 { bit } else { -bit };
+            } else if size != 0 {
+                return Err("JPEG refinement scan has an invalid coefficient size.".to_string());
+            } else if run < 15 {
+                *eob_run = (1 << run) + reader.read_bits(run);
+                break;
+            }
+            while k <= scan.end {
+                let coefficient = &mut block[ZIGZAG[k]];
+                if *coefficient != 0 {
+                    refine_nonzero(reader, bit, coefficient);
+                } else if run == 0 {
+                    break;
+                } else {
+                    run -= 1;
+                }
+                k += 1;
+            }
+            if value != 0 {
+                if k > scan.end {
+                    return Err("JPEG AC coefficients overflow their block.".to_string());
+                }
+                block[ZIGZAG[k]] = value;
+            }
+            k += 1;
+        }
+    }
+    if *eob_run > 0 {
+        while k <= scan.end {
+            let coefficient = &mut block[ZIGZAG[k]];
+            if *coefficient != 0 { refine_nonzero(reader, bit, coefficient); }
+            k += 1;
+        }
+        *eob_run -= 1;
+    }
+    Ok(())
+}
+
+// Decodes the entropy coded data of a scan starting at the cursor into the coefficients of its
+// components. The cursor is advanced to the marker after the scan. Scans of a single component
+// cover only its blocks inside the image, while interleaved scans cover every MCU.
+fn decode_scan(data: &[u8], cursor: &mut usize, frame: &mut Frame, tables: &Tables, scan: &Scan) ->
+        Result<(), String> {
+    let mut reader = BitReader::new(data, *cursor);
+    let mut predictions = vec![0; scan.components.len()];
+    let mut eob_run = 0;
+    let mut blocks = Vec::new();
+    let units = if scan.components.len() == 1 {
+        let (width, height) = frame.get_component_size(&frame.components[scan.components[0].0]);
+        ((width + 7) / 8) * ((height + 7) / 8)
+    } else {
+        frame.mcus_w * frame.mcus_h
+    };
+    for unit in 0..units {
+        if tables.restart_interval > 0 && unit > 0 && unit % tables.restart_interval == 0 {
+            try!(reader.restart());
+            for prediction in predictions.iter_mut() { *prediction = 0; }
+            eob_run = 0;
+        }
+
+        // Find the component and block index of every block in the unit.
+        blocks.clear();
+        if scan.components.len() == 1 {
+            let component = &frame.components[scan.components[0].0];
+            let (width, _) = frame.get_component_size(component);
+            let row_blocks = (width + 7) / 8;
+            blocks.push((0, (unit / row_blocks) * component.blocks_w + unit % row_blocks));
+        } else {
+            let (mcu_y, mcu_x) = (unit / frame.mcus_w, unit % frame.mcus_w);
+            for (i, &(index, _, _)) in scan.components.iter().enumerate() {
+                let component = &frame.components[index];
+                for y in 0..component.v {
+                    for x in 0..component.h {
+                        blocks.push((i, (mcu_y * component.v + y) * component.blocks_w +
+                                mcu_x * component.h + x));
+                    }
+                }
+            }
+        }
+
+        for &(i, block_index) in blocks.iter() {
+            let (index, dc, ac) = scan.components[i];
+            let block = &mut frame.components[index].coefficients[
+                    (block_index * 64)..(block_index * 64 + 64)];
+            if scan.start == 0 && scan.high == 0 {
+                let table = try!(get_table(&tables.dc, dc));
+                try!(decode_dc_first(&mut reader, table, scan.low, &mut predictions[i], block));
+            } else if scan.start == 0 {
+                decode_dc_refine(&mut reader, scan.low, block);
+            }
+            if scan.end > 0 && scan.high == 0 {
+                let table = try!(get_table(&tables.ac, ac));
+                try!(decode_ac_first(&mut reader, table, scan, &mut eob_run, block));
+            } else if scan.end > 0 {
+                let table = try!(get_table(&tables.ac, ac));
+                try!(decode_ac_refine(&mut reader, table, scan, &mut eob_run, block));
+            }
+        }
+    }
+
+    // Skip to the next marker that is not a restart marker or a stuffed byte.
+    *cursor = reader.cursor;
+    while *cursor + 1 < data.len() && !(data[*cursor] == 0xFF && data[*cursor + 1] != 0 &&
+            data[*cursor + 1] != 0xFF && (data[*cursor + 1] < 0xD0 || data[*cursor + 1] > 0xD7)) {
+        *cursor += 1;
+    }
+    Ok(())
+}
+
+// Dequantizes and transforms the coefficients of a component into a plane of samples with one
+// byte per sample and the padded width of the component.
+fn transform_component(component: &Component, quantization: &[u16; 64]) -> Vec<u8> {
+    let mut basis = [0.0f32; 64];
+    for x in 0..8 {
+        for u in 0..8 {
+            let scale = if u == 0 { 0.5f32.sqrt() } else { 1.0 };
+            let angle = ((2 * x + 1) * u) as f32 * ::std::f32::consts::PI / 16.0;
+            basis[x * 8 + u] = scale * angle.cos() / 2.0;
+        }
+    }
+
+    let stride = component.blocks_w * 8;
+    let mut samples = vec![0; stride * component.blocks_h * 8];
+    let mut rows = [0.0f32; 64];
+    for (i, block) in component.coefficients.chunks(64).enumerate() {
+        // Transform the rows and then the columns of the block.
+        for v in 0..8 {
+            for x in 0..8 {
+                rows[v * 8 + x] = (0..8).fold(0.0, |sum, u| {
+                    let coefficient = block[v * 8 + u] * quantization[v * 8 + u] as i32;
+                    sum + basis[x * 8 + u] * coefficient as f32
+                });
+            }
+        }
+        let (block_x, block_y) = ((i % component.blocks_w) * 8, (i / component.blocks_w) * 8);
+        for y in 0..8 {
+            for x in 0..8 {
+                let value = (0..8).fold(0.0, |sum, v| sum + basis[y * 8 + v] * rows[v * 8 + x]);
+                samples[(block_y + y) * stride + block_x + x] =
+                        (value + 128.0).round().max(0.0).min(255.0) as u8;
+            }
+        }
+    }
+    samples
+}
+
+// Samples a component plane at a pixel of the image. Subsampled components are linearly
+// interpolated between the centers of their samples.
+fn sample_component(frame: &Frame, component: &Component, samples: &[u8], x: usize, y: usize) ->
+        f32 {
+    let (width, height) = frame.get_component_size(component);
+    let stride = component.blocks_w * 8;
+    let position = |pixel: usize, factor: usize, max: usize, size: usize| {
+        let position = ((pixel as f32 + 0.5) * factor as f32 / max as f32 - 0.5).max(0.0);
+        let low = (position as usize).min(size - 1);
+        (low, (low + 1).min(size - 1), position - low as f32)
+    };
+    let (x0, x1, fx) = position(x, component.h, frame.h_max, width);
+    let (y0, y1, fy) = position(y, component.v, frame.v_max, height);
+    let lerp = |a: u8, b: u8, t: f32| a as f32 + (b as f32 - a as f32) * t;
+    lerp(samples[y0 * stride + x0], samples[y0 * stride + x1], fx) * (1.0 - fy) +
+            lerp(samples[y1 * stride + x0], samples[y1 * stride + x1], fx) * fy
+}
+
+// Converts the decoded coefficients of a frame to an array of Pixels starting from the top left of
+// the image. Three component images are converted from YCbCr unless an Adobe segment says they
+// are RGB.
+fn read_pixel_array(frame: &Frame, tables: &Tables, ycbcr: bool) -> Vec<common::Pixel> {
+    let planes: Vec<Vec<u8>> = frame.components.iter()
+            .map(|c| transform_component(c, &tables.quantization[c.quantization])).collect();
+    let mut pixels = Vec::with_capacity(frame.width * frame.height);
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let mut samples = [0.0f32; 3];
+            for (sample, (c, plane)) in samples.iter_mut()
+                    .zip(frame.components.iter().zip(planes.iter())) {
+                *sample = sample_component(frame, c, plane, x, y);
+            }
+            let (red, green, blue) = if frame.components.len() == 1 {
+                (samples[0], samples[0], samples[0])
+            } else if ycbcr {
+                let (luma, cb, cr) = (samples[0], samples[1] - 128.0, samples[2] - 128.0);
+                (luma + 1.402 * cr, luma - 0.344136 * cb - 0.714136 * cr, luma + 1.772 * cb)
+            } else {
+                (samples[0], samples[1], samples[2])
+            };
+            let to_byte = |value: f32| value.round().max(0.0).min(255.0) as u8;
+            pixels.push(common::Pixel { red: to_byte(red), green: to_byte(green),
+                    blue: to_byte(blue), alpha: 255 });
+        }
+    }
+    pixels
+}
+
+// Applies an EXIF orientation to an image so that it is upright. Orientations 5 through 8 swap
+// the width and height.
+fn orient_image(image: common::Image, orientation: u16) -> common::Image {
+    if orientation < 2 || orientation > 8 { return image; }
+    let (width, height) = (image.width as usize, image.height as usize);
+    let (new_width, new_height) = if orientation >= 5 { (height, width) } else { (width, height) };
+    let mut data = Vec::with_capacity(image.data.len());
+    for y in 0..new_height {
+        for x in 0..new_width {
+            // Find the pixel of the stored image that is shown at (x, y).
+            let (source_x, source_y) = match orientation {
+                2 => (width - 1 - x, y),
+                3 => (width - 1 - x, height - 1 - y),
+                4 => (x, height - 1 - y),
+                5 => (y, x),
+                6 => (y, height - 1 - x),
+                7 => (width - 1 - y, height - 1 - x),
+                _ => (width - 1 - y, x),
+            };
+            let pixel = &image.data[source_y * width + source_x];
+            data.push(common::Pixel { red: pixel.red, green: pixel.green, blue: pixel.blue,
+                    alpha: pixel.alpha });
+        }
+    }
+    common::Image { width: new_width as u32, height: new_height as u32, data: data }
+}
+
+// Decodes a JPEG given a path to the file and returns a DecodedJPEG struct containing the pixel
+// information, width, and height of the image.
+pub fn decode_jpeg(fpath: &str) -> Result<DecodedJPEG, String> {
+    let mut data = Vec::new();
+    let mut fd = try!(File::open(fpath).map_err(|e| e.to_string()));
+    try!(fd.read_to_end(&mut data).map_err(|e| e.to_string()));
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err("JPEG file has an incorrect signature.".to_string());
+    }
+
+    let mut cursor = 2;
+    let mut frame: Option<Frame> = None;
+    let mut tables = Tables { dc: [None, None, None, None], ac: [None, None, None, None],
+            quantization: [[0; 64]; 4], restart_interval: 0 };
+    let mut orientation = 1;
+    let mut ycbcr = true;
+    loop {
+        let marker = try!(read_marker(&data, &mut cursor));
+        match marker {
+            0xD9 => { break; },
+            0x01 | 0xD0...0xD7 => { continue; },
+            _ => (),
+        }
+        let length = try!(read_u16(&data, &mut cursor)) as usize;
+        if length < 2 || cursor + length - 2 > data.len() {
+            return Err("JPEG segment extends past the end of the file.".to_string());
+        }
+        let segment = &data[cursor..(cursor + length - 2)];
+        cursor += length - 2;
+        match marker {
+            0xC0 | 0xC1 | 0xC2 => {
+                if frame.is_some() { return Err("JPEG file has more than one frame.".to_string()); }
+                frame = Some(try!(read_frame(segment, marker == 0xC2)));
+            },
+            0xC3 | 0xC5...0xC7 | 0xC9...0xCB | 0xCD...0xCF => {
+                return Err("JPEG file uses an unsupported lossless, hierarchical, or arithmetic \
+                        coding process.".to_string());
+            },
+            0xC4 => { try!(read_huffman_tables(segment, &mut tables)); },
+            0xDB => { try!(read_quantization_tables(segment, &mut tables)); },
+            0xDD => {
+                if segment.len() != 2 { return Err("JPEG DRI segment is invalid.".to_string()); }
+                tables.restart_interval = (segment[0] as usize) << 8 | segment[1] as usize;
+            },
+            0xDA => {
+                let frame = try!(frame.as_mut().ok_or(
+                        "JPEG file has a scan before its frame.".to_string()));
+                let scan = try!(read_scan(segment, frame));
+                try!(decode_scan(&data, &mut cursor, frame, &tables, &scan));
+            },
+            0xE1 => { orientation = read_orientation(segment).unwrap_or(orientation); },
+            // An Adobe segment with a transform of 0 means that three components are RGB.
+            0xEE => {
+                if segment.starts_with(b"Adobe") && segment.len() >= 12 {
+                    ycbcr = segment[11] != 0;
+                }
+            },
+            _ => (),
+        }
+    }
+
+    let frame = try!(frame.ok_or("JPEG file is missing its frame.".to_string()));
+    let pixel_arr = read_pixel_array(&frame, &tables, ycbcr);
+    let image = common::Image { width: frame.width as u32, height: frame.height as u32,
+            data: pixel_arr };
+    Ok(DecodedJPEG { image: orient_image(image, orientation) })
+}
+
+#[cfg(test)]
+mod tests {
+    use util::common::{Image, Pixel};
+
+    // Decodes a 32x32 test image whose quadrants are filled with red, green, blue, and a gradient.
+    // The chroma is subsampled and the baseline version has restart markers every two MCUs.
+    fn decode_quadrants(name: &str) -> Image {
+        let path = format!("assets/test/quadrants_{}.jpg", name);
+        let image = super::decode_jpeg(&path).unwrap().image;
+        assert_eq!((image.width, image.height), (32, 32));
+        image
+    }
+
+    // Checks that the pixel at a location is within a tolerance of an expected color.
+    fn assert_near(image: &Image, x: usize, y: usize, expected: (u8, u8, u8), tolerance: i32) {
+        let Pixel { red, green, blue, alpha } = image.data[y * image.width as usize + x];
+        let actual = (red, green, blue);
+        let near = |a: u8, e: u8| (a as i32 - e as i32).abs() <= tolerance;
+        assert!(near(red, expected.0) && near(green, expected.1) && near(blue, expected.2),
+                "Pixel ({}, {}) is {:?} instead of {:?}.", x, y, actual, expected);
+        assert_eq!(alpha, 255);
+    }
+
+    // Checks the centers of the solid quadrants and a point on the gradient.
+    fn check_quadrants(image: &Image) {
+        assert_near(image, 8, 8, (200, 30, 40), 3);
+        assert_near(image, 24, 8, (20, 180, 60), 3);
+        assert_near(image, 8, 24, (40, 50, 220), 3);
+        assert_near(image, 28, 28, (180, 180, 128), 8);
+    }
+
+    #[test]
+    fn decode_baseline() {
+        check_quadrants(&decode_quadrants("baseline"));
+    }
+
+    #[test]
+    fn decode_progressive() {
+        check_quadrants(&decode_quadrants("progressive"));
+    }
+
+    #[test]
+    fn progressive_matches_baseline() {
+        // Both files hold the same quantized coefficients, so they should decode identically.
+        let baseline = decode_quadrants("baseline");
+        let progressive = decode_quadrants("progressive");
+        for (b, p) in baseline.data.iter().zip(progressive.data.iter()) {
+            assert_eq!((b.red, b.green, b.blue), (p.red, p.green, p.blue));
+        }
+    }
+
+    #[test]
+    fn decode_asset() {
+        let image = super::decode_jpeg("assets/wood.jpg").unwrap().image;
+        assert_eq!((image.width, image.height), (1000, 1000));
+        assert_eq!(image.data.len(), 1000 * 1000);
+    }
+
+    #[test]
+    fn decode_missing_file() {
+        assert!(super::decode_jpeg("assets/test/missing.jpg").is_err());
+    }
+}
@@ -1,5 +1,12 @@
 pub mod bmp;
 pub mod common;
+pub mod hdr;
+pub mod image;
+pub mod imageops;
+pub mod jpeg;
 pub mod obj;
+pub mod png;
 pub mod rmod;
 pub mod shader;
+pub mod tga;
+pub mod zlib;
@@ -0,0 +1,610 @@
+// Utility module that allows for decoding and encoding of a PNG given a path to the file. This
+// decodes every color type (grayscale, truecolor, indexed, and either with alpha) at every bit
+// depth allowed for it, including 16-bit channels which are reduced to 8 bits. Transparency from a
+// tRNS chunk is applied, and Adam7 interlaced images are deinterlaced. Ancillary chunks such as
+// gamma and color profiles are ignored. Images are encoded as 8-bit truecolor, with alpha only if
+// some pixel is not opaque.
+//
+// Brian Ho
+// brian@brkho.com
+
+
+use std::fs::File;
+use std::io::{Read, Write};
+use util::common;
+use util::zlib;
+
+// The 8 bytes that every PNG file starts with.
+pub const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+// The color types of the IHDR chunk.
+const GRAYSCALE: u8 = 0;
+const TRUECOLOR: u8 = 2;
+const INDEXED: u8 = 3;
+const GRAYSCALE_ALPHA: u8 = 4;
+const TRUECOLOR_ALPHA: u8 = 6;
+
+// The (x start, y start, x step, y step) of each of the 7 passes of an Adam7 interlaced image.
+const ADAM7_PASSES: [(u32, u32, u32, u32); 7] = [
+        (0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2),
+        (0, 1, 1, 2)];
+
+// Return value for a decoded PNG file. This contains a width, height, and an array of pixels with
+// color and alpha information.
+pub struct DecodedPNG {
+    pub image: common::Image,
+}
+
+// Data structure representation of the IHDR chunk.
+struct Header {
+    width: u32,
+    height: u32,
+    depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    // Gets the number of samples in each pixel.
+    fn get_channels(&self) -> usize {
+        match self.color_type {
+            TRUECOLOR => 3,
+            GRAYSCALE_ALPHA => 2,
+            TRUECOLOR_ALPHA => 4,
+            _ => 1,
+        }
+    }
+
+    // Gets the number of bytes in a row of a given width, not including the filter type byte.
+    fn get_stride(&self, width: u32) -> usize {
+        (width as usize * self.get_channels() * self.depth as usize + 7) / 8
+    }
+
+    // Gets the number of bytes between a byte and the matching byte of the previous pixel, which
+    // is used by the filters. This is rounded up to 1 for bit depths below 8.
+    fn get_filter_distance(&self) -> usize {
+        (self.get_channels() * self.depth as usize + 7) / 8
+    }
+}
+
+// The transparency given by a tRNS chunk. Grayscale and truecolor images have a single color that
+// is fully transparent, and indexed images have an alpha for each palette entry.
+enum Transparency {
+    None,
+    Gray(u16),
+    Color(u16, u16, u16),
+}
+
+// Reads and consumes 4 bytes from the data vector as a big endian u32.
+fn read_u32(data: &[u8], cursor: &mut usize) -> Result<u32, String> {
+    if *cursor + 4 > data.len() { return Err("PNG file is too small.".to_string()); }
+    let bytes = &data[*cursor..(*cursor + 4)];
+    *cursor += 4;
+    Ok((bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 |
+            bytes[3] as u32)
+}
+
+// Computes the CRC-32 of a sequence of bytes as used by PNG chunks.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xedb88320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+// Reads and consumes a chunk and returns its type and data after checking its CRC.
+fn read_chunk<'a>(data: &'a [u8], cursor: &mut usize) -> Result<(&'a [u8], &'a [u8]), String> {
+    let length = try!(read_u32(data, cursor)) as usize;
+    if length > 0x7fffffff || *cursor + length + 8 > data.len() {
+        return Err("PNG chunk extends past the end of the file.".to_string());
+    }
+    let kind = &data[*cursor..(*cursor + 4)];
+    let chunk_data = &data[(*cursor + 4)..(*cursor + 4 + length)];
+    let crc = crc32(&data[*cursor..(*cursor + 4 + length)]);
+    *cursor += 4 + length;
+    if try!(read_u32(data, cursor)) != crc {
+        return Err(format!("PNG {} chunk has an incorrect CRC.", String::from_utf8_lossy(kind)));
+    }
+    Ok((kind, chunk_data))
+}
+
+// Reads and validates the IHDR chunk.
+fn read_header(chunk: &[u8]) -> Result<Header, String> {
+    if chunk.len() != 13 { return Err("PNG IHDR chunk has the wrong length.".to_string()); }
+    let mut cursor = 0;
+    let width = try!(read_u32(chunk, &mut cursor));
+    let height = try!(read_u32(chunk, &mut cursor));
+    let (depth, color_type) = (chunk[8], chunk[9]);
+    if width == 0 || height == 0 || width > 0x7fffffff || height > 0x7fffffff {
+        return Err("PNG image has invalid dimensions.".to_string());
+    }
+    let valid_depth = match color_type {
+        GRAYSCALE => [1, 2, 4, 8, 16].contains(&depth),
+        INDEXED => [1, 2, 4, 8].contains(&depth),
+        TRUECOLOR | GRAYSCALE_ALPHA | TRUECOLOR_ALPHA => depth == 8 || depth == 16,
+        _ => { return Err(format!("PNG image has unknown color type {}.", color_type)); },
+    };
+    if !valid_depth {
+        return Err(format!("PNG color type {} cannot have a bit depth of {}.", color_type, depth));
+    }
+    if chunk[10] != 0 || chunk[11] != 0 {
+        return Err("PNG image uses an unknown compression or filter method.".to_string());
+    }
+    let interlaced = match chunk[12] {
+        0 => false,
+        1 => true,
+        _ => { return Err("PNG image uses an unknown interlace method.".to_string()); },
+    };
+    Ok(Header { width: width, height: height, depth: depth, color_type: color_type,
+            interlaced: interlaced })
+}
+
+// Reads the PLTE chunk into a list of opaque RGBA colors.
+fn read_palette(chunk: &[u8]) -> Result<Vec<[u8; 4]>, String> {
+    if chunk.is_empty() || chunk.len() % 3 != 0 || chunk.len() > 256 * 3 {
+        return Err("PNG PLTE chunk has an invalid length.".to_string());
+    }
+    Ok(chunk.chunks(3).map(|c| [c[0], c[1], c[2], 255]).collect())
+}
+
+// Reads the tRNS chunk. The alphas of indexed images are written into the palette.
+fn read_transparency(chunk: &[u8], header: &Header, palette: &mut Vec<[u8; 4]>) ->
+        Result<Transparency, String> {
+    let read_u16 = |i: usize| (chunk[i * 2] as u16) << 8 | chunk[i * 2 + 1] as u16;
+    match header.color_type {
+        GRAYSCALE if chunk.len() == 2 => Ok(Transparency::Gray(read_u16(0))),
+        TRUECOLOR if chunk.len() == 6 =>
+                Ok(Transparency::Color(read_u16(0), read_u16(1), read_u16(2))),
+        INDEXED if !palette.is_empty() && chunk.len() <= palette.len() => {
+            for (color, &alpha) in palette.iter_mut().zip(chunk.iter()) { color[3] = alpha; }
+            Ok(Transparency::None)
+        },
+        _ => Err("PNG tRNS chunk is invalid for the image.".to_string()),
+    }
+}
+
+// Predicts a byte from its left, upper, and upper left neighbors for the Paeth filter.
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+// Reverses the filters of the rows of a (sub)image starting at the cursor and returns the raw
+// rows back to back. The cursor is advanced past the filtered rows.
+fn unfilter(data: &[u8], cursor: &mut usize, header: &Header, width: u32, height: u32) ->
+        Result<Vec<u8>, String> {
+    let stride = header.get_stride(width);
+    let distance = header.get_filter_distance();
+    if *cursor + (stride + 1) * height as usize > data.len() {
+        return Err("PNG image data is too small.".to_string());
+    }
+    let mut rows = vec![0u8; stride * height as usize];
+    for y in 0..(height as usize) {
+        let filter = data[*cursor];
+        let filtered = &data[(*cursor + 1)..(*cursor + 1 + stride)];
+        *cursor += stride + 1;
+        let (previous, current) = rows.split_at_mut(y * stride);
+        let current = &mut current[..stride];
+        let above = if y > 0 { &previous[((y - 1) * stride)..] } else { &[][..] };
+        for x in 0..stride {
+            let a = if x >= distance { current[x - distance] } else { 0 };
+            let b = if y > 0 { above[x] } else { 0 };
+            let c = if x >= distance && y > 0 { above[x - distance] } else { 0 };
+            let prediction = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => { return Err(format!("PNG row has unknown filter type {}.", filter)); },
+            };
+            current[x] = filtered[x].wrapping_add(prediction);
+        }
+    }
+    Ok(rows)
+}
+
+// Reads the sample at an index of a raw row.
+fn read_sample(row: &[u8], index: usize, depth: u8) -> u16 {
+    match depth {
+        16 => (row[index * 2] as u16) << 8 | row[index * 2 + 1] as u16,
+        8 => row[index] as u16,
+        _ => {
+            let per_byte = 8 / depth as usize;
+            let shift = 8 - depth as usize * (index % per_byte + 1);
+            ((row[index / per_byte] >> shift) & ((1 << depth) - 1)) as u16
+        },
+    }
+}
+
+// Scales a sample of a bit depth to the range 0-255.
+fn scale_sample(sample: u16, depth: u8) -> u8 {
+    let max = (1u32 << depth) - 1;
+    ((sample as u32 * 255 + max / 2) / max) as u8
+}
+
+// Converts the pixel at a column of a raw row to a Pixel.
+fn read_pixel(row: &[u8], x: usize, header: &Header, palette: &[[u8; 4]],
+        transparency: &Transparency) -> Result<common::Pixel, String> {
+    let channels = header.get_channels();
+    let depth = header.depth;
+    let sample = |c: usize| read_sample(row, x * channels + c, depth);
+    let scale = |c: usize| scale_sample(sample(c), depth);
+    let pixel = match header.color_type {
+        GRAYSCALE => {
+            let gray = scale(0);
+            let alpha = match transparency {
+                &Transparency::Gray(key) if key == sample(0) => 0,
+                _ => 255,
+            };
+            common::Pixel { red: gray, green: gray, blue: gray, alpha: alpha }
+        },
+        TRUECOLOR => {
+            let alpha = match transparency {
+                &Transparency::Color(r, g, b) if (r, g, b) == (sample(0), sample(1), sample(2)) =>
+                        0,
+                _ => 255,
+            };
+            common::Pixel { red: scale(0), green: scale(1), blue: scale(2), alpha: alpha }
+        },
+        INDEXED => {
+            let color = try!(palette.get(sample(0) as usize).ok_or(
+                    "PNG pixel refers to a color outside of the palette.".to_string()));
+            common::Pixel { red: color[0], green: color[1], blue: color[2], alpha: color[3] }
+        },
+        GRAYSCALE_ALPHA => {
+            let gray = scale(0);
+            common::Pixel { red: gray, green: gray, blue: gray, alpha: scale(1) }
+        },
+        _ => common::Pixel { red: scale(0), green: scale(1), blue: scale(2), alpha: scale(3) },
+    };
+    Ok(pixel)
+}
+
+// Gets the number of bytes of filtered rows in the given passes of an image, or None if it does
+// not fit in a usize.
+fn get_data_size(header: &Header, passes: &[(u32, u32, u32, u32)]) -> Option<usize> {
+    let bits_per_pixel = header.get_channels() * header.depth as usize;
+    let mut size = 0usize;
+    for &(x_start, y_start, x_step, y_step) in passes.iter() {
+        if x_start >= header.width || y_start >= header.height { continue; }
+        let width = (header.width - x_start + x_step - 1) / x_step;
+        let height = (header.height - y_start + y_step - 1) / y_step;
+        // Each row is its stride rounded up to whole bytes plus the filter type byte.
+        let total = (width as usize).checked_mul(bits_per_pixel)
+                .and_then(|bits| (bits / 8 + if bits % 8 == 0 { 1 } else { 2 })
+                        .checked_mul(height as usize))
+                .and_then(|pass_size| size.checked_add(pass_size));
+        size = match total {
+            Some(total) => total,
+            None => { return None; },
+        };
+    }
+    Some(size)
+}
+
+// Unfilters the decompressed image data and converts it to an array of Pixels starting from the
+// top left of the image. Interlaced images are unfiltered one pass at a time and each pass is
+// scattered into its pixels of the image.
+fn read_pixel_array(data: &[u8], header: &Header, palette: &[[u8; 4]],
+        transparency: &Transparency) -> Result<Vec<common::Pixel>, String> {
+    let passes = if header.interlaced { ADAM7_PASSES.to_vec() } else { vec![(0, 0, 1, 1)] };
+    // Check that the data covers every row before allocating the pixels so that a header with
+    // huge dimensions cannot cause a huge allocation.
+    match get_data_size(header, &passes) {
+        Some(size) if size <= data.len() => {},
+        _ => { return Err("PNG image data is too small.".to_string()); },
+    }
+    let mut pixels: Vec<common::Pixel> = (0..(header.width as usize * header.height as usize))
+            .map(|_| common::Pixel { red: 0, green: 0, blue: 0, alpha: 0 }).collect();
+    let mut cursor = 0;
+    for &(x_start, y_start, x_step, y_step) in passes.iter() {
+        if x_start >= header.width || y_start >= header.height { continue; }
+        let width = (header.width - x_start + x_step - 1) / x_step;
+        let height = (header.height - y_start + y_step - 1) / y_step;
+        let rows = try!(unfilter(data, &mut cursor, header, width, height));
+        let stride = header.get_stride(width);
+        for (y, row) in rows.chunks(stride).enumerate() {
+            let image_y = (y_start + y as u32 * y_step) as usize;
+            for x in 0..(width as usize) {
+                let image_x = (x_start + x as u32 * x_step) as usize;
+                pixels[image_y * header.width as usize + image_x] =
+                        try!(read_pixel(row, x, header, palette, transparency));
+            }
+        }
+    }
+    Ok(pixels)
+}
+
+// Decodes a PNG given a path to the file and returns a DecodedPNG struct containing the pixel
+// information, width, and height of the image.
+pub fn decode_png(fpath: &str) -> Result<DecodedPNG, String> {
+    let mut data = Vec::new();
+    let mut fd = try!(File::open(fpath).map_err(|e| e.to_string()));
+    try!(fd.read_to_end(&mut data).map_err(|e| e.to_string()));
+    if data.len() < SIGNATURE.len() || data[..SIGNATURE.len()] != SIGNATURE {
+        return Err("PNG file has an incorrect signature.".to_string());
+    }
+
+    let mut cursor = SIGNATURE.len();
+    let (kind, chunk) = try!(read_chunk(&data, &mut cursor));
+    if kind != b"IHDR" { return Err("PNG file does not start with an IHDR chunk.".to_string()); }
+    let header = try!(read_header(chunk));
+    let mut palette = Vec::new();
+    let mut transparency = Transparency::None;
+    let mut compressed = Vec::new();
+    loop {
+        let (kind, chunk) = try!(read_chunk(&data, &mut cursor));
+        match kind {
+            b"PLTE" => { palette = try!(read_palette(chunk)); },
+            b"tRNS" => { transparency = try!(read_transparency(chunk, &header, &mut palette)); },
+            b"IDAT" => { compressed.extend_from_slice(chunk); },
+            b"IEND" => { break; },
+            // Chunks with an uppercase first letter are critical and cannot be skipped.
+            _ if kind[0] & 0x20 == 0 => {
+                return Err(format!("PNG file has an unsupported critical chunk {}.",
+                        String::from_utf8_lossy(kind)));
+            },
+            _ => (),
+        }
+    }
+    if header.color_type == INDEXED && palette.is_empty() {
+        return Err("PNG indexed image is missing its PLTE chunk.".to_string());
+    }
+    if compressed.is_empty() { return Err("PNG file is missing its IDAT chunks.".to_string()); }
+
+    let decompressed = try!(zlib::decompress(&compressed));
+    let pixel_arr = try!(read_pixel_array(&decompressed, &header, &palette, &transparency));
+    let image = common::Image { width: header.width, height: header.height, data: pixel_arr };
+    Ok(DecodedPNG { image: image })
+}
+
+// Writes a chunk with its length and CRC to the data vector.
+fn write_chunk(data: &mut Vec<u8>, kind: &[u8], chunk: &[u8]) {
+    let length = chunk.len() as u32;
+    data.extend_from_slice(&[(length >> 24) as u8, (length >> 16) as u8, (length >> 8) as u8,
+            length as u8]);
+    let start = data.len();
+    data.extend_from_slice(kind);
+    data.extend_from_slice(chunk);
+    let crc = crc32(&data[start..]);
+    data.extend_from_slice(&[(crc >> 24) as u8, (crc >> 16) as u8, (crc >> 8) as u8, crc as u8]);
+}
+
+// Filters the raw rows of an image for compression. Each row uses whichever filter gives the
+// smallest sum of its bytes taken as signed values, which is the heuristic suggested by the PNG
+// specification.
+fn filter(rows: &[u8], stride: usize, distance: usize) -> Vec<u8> {
+    let mut filtered = Vec::with_capacity(rows.len() + rows.len() / stride);
+    let mut candidate = vec![0u8; stride];
+    let mut best = vec![0u8; stride];
+    for (y, row) in rows.chunks(stride).enumerate() {
+        let above = if y > 0 { &rows[((y - 1) * stride)..(y * stride)] } else { &[][..] };
+        let mut best_filter = (0, ::std::u64::MAX);
+        for filter in 0..5 {
+            for x in 0..stride {
+                let a = if x >= distance { row[x - distance] } else { 0 };
+                let b = if y > 0 { above[x] } else { 0 };
+                let c = if x >= distance && y > 0 { above[x - distance] } else { 0 };
+                let prediction = match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    _ => paeth(a, b, c),
+                };
+                candidate[x] = row[x].wrapping_sub(prediction);
+            }
+            let cost = candidate.iter()
+                    .fold(0, |sum, &byte| sum + (byte as i8 as i32).abs() as u64);
+            if cost < best_filter.1 {
+                best_filter = (filter, cost);
+                best.copy_from_slice(&candidate);
+            }
+        }
+        filtered.push(best_filter.0);
+        filtered.extend_from_slice(&best);
+    }
+    filtered
+}
+
+// Encodes an image as a PNG and writes it to a path. Opaque images are written without an alpha
+// channel.
+pub fn encode_png(image: &common::Image, fpath: &str) -> Result<(), String> {
+    try!(image.validate());
+    let opaque = image.is_opaque();
+    let channels = if opaque { 3 } else { 4 };
+    let rows = if opaque { image.get_rgb_vec() } else { image.get_rgba_vec() };
+
+    let mut data = SIGNATURE.to_vec();
+    let mut header = Vec::new();
+    for &value in [image.width, image.height].iter() {
+        header.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8,
+                value as u8]);
+    }
+    header.extend_from_slice(&[8, if opaque { TRUECOLOR } else { TRUECOLOR_ALPHA }, 0, 0, 0]);
+    write_chunk(&mut data, b"IHDR", &header);
+    let filtered = filter(&rows, image.width as usize * channels, channels);
+    write_chunk(&mut data, b"IDAT", &zlib::compress(&filtered));
+    write_chunk(&mut data, b"IEND", &[]);
+
+    let mut fd = try!(File::create(fpath).map_err(|e| e.to_string()));
+    fd.write_all(&data).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use util::common::{self, Image, Pixel};
+    use util::zlib;
+
+    // Writes a PNG with the given header, chunks before the image data, and filtered rows of
+    // image data to a temporary file and decodes it.
+    fn decode_fixture(name: &str, width: u32, height: u32, depth: u8, color_type: u8,
+            interlaced: bool, chunks: &[(&[u8], &[u8])], rows: &[u8]) -> Result<Image, String> {
+        let mut data = super::SIGNATURE.to_vec();
+        let mut header = Vec::new();
+        for &value in [width, height].iter() {
+            header.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8,
+                    (value >> 8) as u8, value as u8]);
+        }
+        header.extend_from_slice(&[depth, color_type, 0, 0, interlaced as u8]);
+        super::write_chunk(&mut data, b"IHDR", &header);
+        for &(kind, chunk) in chunks.iter() { super::write_chunk(&mut data, kind, chunk); }
+        super::write_chunk(&mut data, b"IDAT", &zlib::compress(rows));
+        super::write_chunk(&mut data, b"IEND", &[]);
+        let path = env::temp_dir().join(format!("mmo_png_{}.png", name));
+        let path = path.to_str().unwrap();
+        fs::File::create(path).unwrap().write_all(&data).unwrap();
+        let result = super::decode_png(path).map(|decoded| decoded.image);
+        fs::remove_file(path).unwrap();
+        result
+    }
+
+    // Encodes an image to a temporary file and decodes it again.
+    fn round_trip(image: &Image, name: &str) -> Image {
+        common::round_trip_file(image, name, super::encode_png,
+                |path| super::decode_png(path).map(|decoded| decoded.image))
+    }
+
+    #[test]
+    fn round_trip_gradients() {
+        // The gradients make the encoder pick every filter type, and the sizes are not a multiple
+        // of the filter distance.
+        for &(width, height, opaque) in [(67, 45, true), (31, 70, false)].iter() {
+            let image = common::create_test_image(width, height, opaque);
+            let name = format!("mmo_png_round_trip_{}x{}.png", width, height);
+            let decoded = round_trip(&image, &name);
+            assert_eq!((decoded.width, decoded.height), (width, height));
+            assert!(decoded.data == image.data);
+        }
+    }
+
+    #[test]
+    fn round_trip_asset() {
+        let image = super::decode_png("assets/uvs.png").unwrap().image;
+        let decoded = round_trip(&image, "mmo_png_round_trip_asset.png");
+        assert_eq!((decoded.width, decoded.height), (image.width, image.height));
+        assert!(decoded.data == image.data);
+    }
+
+    #[test]
+    fn decode_huge_dimensions() {
+        let result = decode_fixture("huge_dimensions", 0x7fffffff, 0x7fffffff, 8,
+                super::TRUECOLOR_ALPHA, false, &[], &[0; 16]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn decode_palette() {
+        // 2-bit indices into a palette of red, green, and blue where red is transparent and green
+        // is translucent.
+        let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+        let chunks: [(&[u8], &[u8]); 2] = [(b"PLTE", &palette), (b"tRNS", &[0, 128])];
+        let rows = [0, 0b00011000, 0, 0b10100100];
+        let image = decode_fixture("palette", 3, 2, 2, super::INDEXED, false, &chunks, &rows)
+                .unwrap();
+        let red = Pixel { red: 255, green: 0, blue: 0, alpha: 0 };
+        let green = Pixel { red: 0, green: 255, blue: 0, alpha: 128 };
+        let blue = Pixel { red: 0, green: 0, blue: 255, alpha: 255 };
+        assert!(image.data == vec![red, green, blue, blue, blue, green]);
+
+        // Index 3 is outside of the palette, and indexed images must have a palette.
+        let rows = [0, 0b11000000, 0, 0];
+        assert!(decode_fixture("palette_outside", 3, 2, 2, super::INDEXED, false, &chunks,
+                &rows).is_err());
+        assert!(decode_fixture("palette_missing", 3, 2, 2, super::INDEXED, false, &[],
+                &[0; 4]).is_err());
+    }
+
+    #[test]
+    fn decode_16_bit() {
+        // Two RGBA pixels where the second is stored with the sub filter, which subtracts the
+        // matching byte of the first pixel 8 bytes back.
+        let first: [u8; 8] = [0xff, 0xff, 0x80, 0x00, 0x00, 0x00, 0x01, 0x01];
+        let second: [u8; 8] = [0x12, 0x34, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff];
+        let mut rows = vec![1];
+        rows.extend_from_slice(&first);
+        rows.extend(second.iter().zip(first.iter()).map(|(&b, &a)| b.wrapping_sub(a)));
+        let image = decode_fixture("16_bit_rgba", 2, 1, 16, super::TRUECOLOR_ALPHA, false, &[],
+                &rows).unwrap();
+        assert!(image.data == vec![Pixel { red: 255, green: 128, blue: 0, alpha: 1 },
+                Pixel { red: 18, green: 0, blue: 255, alpha: 255 }]);
+
+        // Only a gray sample matching all 16 bits of the tRNS key is transparent.
+        let rows = [0, 0x01, 0x02, 0xab, 0xcd, 0x01, 0x03];
+        let chunks: [(&[u8], &[u8]); 1] = [(b"tRNS", &[0x01, 0x02])];
+        let image = decode_fixture("16_bit_gray", 3, 1, 16, super::GRAYSCALE, false, &chunks,
+                &rows).unwrap();
+        assert!(image.data == vec![Pixel { red: 1, green: 1, blue: 1, alpha: 0 },
+                Pixel { red: 171, green: 171, blue: 171, alpha: 255 },
+                Pixel { red: 1, green: 1, blue: 1, alpha: 255 }]);
+    }
+
+    #[test]
+    fn decode_low_bit_grayscale() {
+        // Rows of 5 samples do not fill their last byte, which is padded with zeros.
+        for &depth in [1u8, 2, 4].iter() {
+            let max = (1u16 << depth) - 1;
+            let samples: Vec<u16> = (0..10).map(|i| (i * 7 + i / 5) % (max + 1)).collect();
+            let mut rows = Vec::new();
+            for row in samples.chunks(5) {
+                rows.push(0);
+                let mut packed = vec![0u8; (5 * depth as usize + 7) / 8];
+                for (x, &sample) in row.iter().enumerate() {
+                    let bit = x * depth as usize;
+                    packed[bit / 8] |= (sample as u8) << (8 - depth as usize - bit % 8);
+                }
+                rows.extend(packed);
+            }
+            let name = format!("gray_{}_bit", depth);
+            let image = decode_fixture(&name, 5, 2, depth, super::GRAYSCALE, false, &[], &rows)
+                    .unwrap();
+            for (pixel, &sample) in image.data.iter().zip(samples.iter()) {
+                let gray = (sample as u32 * 255 / max as u32) as u8;
+                assert!(*pixel == Pixel { red: gray, green: gray, blue: gray, alpha: 255 },
+                        "Sample {} of depth {} decoded to {:?}.", sample, depth, pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn decode_gray_alpha() {
+        let rows = [0, 10, 200, 255, 0];
+        let image = decode_fixture("gray_alpha", 2, 1, 8, super::GRAYSCALE_ALPHA, false, &[],
+                &rows).unwrap();
+        assert!(image.data == vec![Pixel { red: 10, green: 10, blue: 10, alpha: 200 },
+                Pixel { red: 255, green: 255, blue: 255, alpha: 0 }]);
+    }
+
+    #[test]
+    fn decode_adam7() {
+        // The 3x3 image leaves some passes empty, and the 9x5 image only partially fills the
+        // first passes.
+        for &(width, height) in [(3, 3), (9, 5), (8, 8)].iter() {
+            let image = common::create_test_image(width, height, true);
+            let mut rows = Vec::new();
+            for &(x_start, y_start, x_step, y_step) in super::ADAM7_PASSES.iter() {
+                if x_start >= width { continue; }
+                for y in (y_start..height).filter(|y| (y - y_start) % y_step == 0) {
+                    rows.push(0);
+                    for x in (x_start..width).filter(|x| (x - x_start) % x_step == 0) {
+                        let pixel = image.data[(y * width + x) as usize];
+                        rows.extend_from_slice(&[pixel.red, pixel.green, pixel.blue]);
+                    }
+                }
+            }
+            let name = format!("adam7_{}x{}", width, height);
+            let decoded = decode_fixture(&name, width, height, 8, super::TRUECOLOR, true, &[],
+                    &rows).unwrap();
+            assert!(decoded.data == image.data, "{}x{} image is different.", width, height);
+        }
+    }
+}
@@ -1,6 +1,8 @@
 // Utility module that allows for decoding of a .rmod file given a path to a file. The .rmod file
 // format is a binary file format native to the Rust game engine and can be created from a FBX file
-// and texture maps using the rmod_converter.py script.
+// and texture maps using the rmod_converter.py script. Files of skinned meshes end with an
+// optional skin section holding the skeleton, the joint influences of every vertex, and the
+// keyframed animations of the skeleton.
 //
 // Brian Ho
 // brian@brkho.com
@@ -23,6 +25,42 @@ pub struct DecodedRMOD {
     pub vertices: Vec<common::Vertex>,
     pub elements: Vec<u32>,
     pub shininess: GLfloat,
+    pub skin: Option<DecodedSkin>,
+}
+
+// A joint of the skeleton. The parent always comes before the joint and the rest transform is
+// relative to the parent.
+pub struct DecodedJoint {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub inverse_bind: Matrix4<GLfloat>,
+    pub translation: Vector3<GLfloat>,
+    pub rotation: Quaternion<GLfloat>,
+    pub scale: Vector3<GLfloat>,
+}
+
+// The (time, value) keyframes of a single joint in an animation.
+pub struct DecodedTrack {
+    pub joint: usize,
+    pub translations: Vec<(GLfloat, Vector3<GLfloat>)>,
+    pub rotations: Vec<(GLfloat, Quaternion<GLfloat>)>,
+    pub scales: Vec<(GLfloat, Vector3<GLfloat>)>,
+}
+
+// A named animation of the skeleton.
+pub struct DecodedAnimation {
+    pub name: String,
+    pub duration: GLfloat,
+    pub tracks: Vec<DecodedTrack>,
+}
+
+// The skeleton of a skinned mesh along with the indices and weights of the (up to) four joints
+// that influence each vertex and the animations of the skeleton.
+pub struct DecodedSkin {
+    pub joints: Vec<DecodedJoint>,
+    pub joint_indices: Vec<[u32; 4]>,
+    pub joint_weights: Vec<[GLfloat; 4]>,
+    pub animations: Vec<DecodedAnimation>,
 }
 
 // Handy constant for the number of bits in a byte (to avoid magic numbers in the code).
@@ -32,6 +70,12 @@ const BITS_PER_BYTE: usize = 8;
 // is like ELFMAGIC, but it stores RUSTGAME instead.
 static RUSTGAME_MAGIC: [u8; 8] = [82, 85, 83, 84, 71, 65, 77, 69];
 
+// Header of the optional skin section that follows the elements, which stores "SKIN".
+static SKIN_MAGIC: [u8; 4] = [83, 75, 73, 78];
+
+// Parent index used by joints at the root of the skeleton.
+const NO_PARENT: u32 = 0xFFFFFFFF;
+
 // Static array used for lookup for masking the nth bit in a byte.
 static BIT_MASK: [u8; BITS_PER_BYTE] = [128, 64, 32, 16, 8, 4, 2, 1];
 
@@ -158,6 +202,128 @@ fn read_vertex(data: &Vec<u8>, cursor: &mut usize) -> Result<common::Vertex, Str
     Ok(vertex)
 }
 
+// Reads in a string stored as its length in bytes followed by its UTF-8 bytes.
+fn read_string(data: &Vec<u8>, cursor: &mut usize) -> Result<String, String> {
+    let length = try!(read_u32(data, cursor));
+    let mut bytes = Vec::new();
+    for _ in 0..length {
+        bytes.push(try!(read_byte(data, cursor)));
+    }
+    String::from_utf8(bytes).map_err(|_| "RMOD string is not valid UTF-8.".to_string())
+}
+
+// Reads in a quaternion stored as w, x, y, z and returns a Quaternion struct.
+fn read_quaternion(data: &Vec<u8>, cursor: &mut usize) -> Result<Quaternion<GLfloat>, String> {
+    let w = try!(read_f32(data, cursor));
+    let v = try!(read_vec3(data, cursor));
+    Ok(Quaternion::from_sv(w, v))
+}
+
+// Reads in a 4x4 matrix stored in column major order and returns a Matrix4 struct.
+fn read_mat4(data: &Vec<u8>, cursor: &mut usize) -> Result<Matrix4<GLfloat>, String> {
+    let mut values = [0.0; 16];
+    for i in 0..16 {
+        values[i] = try!(read_f32(data, cursor));
+    }
+    Ok(Matrix4::new(values[0], values[1], values[2], values[3], values[4], values[5], values[6],
+            values[7], values[8], values[9], values[10], values[11], values[12], values[13],
+            values[14], values[15]))
+}
+
+// Reads in a joint given the number of joints before it and returns a DecodedJoint struct.
+fn read_joint(data: &Vec<u8>, cursor: &mut usize, index: usize) -> Result<DecodedJoint, String> {
+    let name = try!(read_string(data, cursor));
+    let parent = match try!(read_u32(data, cursor)) {
+        NO_PARENT => None,
+        p if (p as usize) < index => Some(p as usize),
+        _ => { return Err(format!("Joint {} must come after its parent.", name)); },
+    };
+    let inverse_bind = try!(read_mat4(data, cursor));
+    let translation = try!(read_vec3(data, cursor));
+    let rotation = try!(read_quaternion(data, cursor));
+    let scale = try!(read_vec3(data, cursor));
+    Ok(DecodedJoint { name: name, parent: parent, inverse_bind: inverse_bind,
+            translation: translation, rotation: rotation, scale: scale })
+}
+
+// Reads in a list of keyframes given a function that reads a single value. The keyframe times
+// must be increasing.
+fn read_keyframes<T, F>(data: &Vec<u8>, cursor: &mut usize, read_value: F) ->
+        Result<Vec<(GLfloat, T)>, String> where F: Fn(&Vec<u8>, &mut usize) -> Result<T, String> {
+    let mut keyframes: Vec<(GLfloat, T)> = Vec::new();
+    let num_keyframes = try!(read_u32(data, cursor));
+    for _ in 0..num_keyframes {
+        let time = try!(read_f32(data, cursor));
+        if keyframes.last().map_or(false, |k| k.0 >= time) {
+            return Err("Keyframe times must be increasing.".to_string());
+        }
+        keyframes.push((time, try!(read_value(data, cursor))));
+    }
+    Ok(keyframes)
+}
+
+// Reads in an animation of a skeleton with a number of joints and returns a DecodedAnimation.
+fn read_animation(data: &Vec<u8>, cursor: &mut usize, num_joints: usize) ->
+        Result<DecodedAnimation, String> {
+    let name = try!(read_string(data, cursor));
+    let duration = try!(read_f32(data, cursor));
+    let mut tracks = Vec::new();
+    let num_tracks = try!(read_u32(data, cursor));
+    for _ in 0..num_tracks {
+        let joint = try!(read_u32(data, cursor)) as usize;
+        if joint >= num_joints {
+            return Err(format!("Animation {} has a track for a missing joint.", name));
+        }
+        let translations = try!(read_keyframes(data, cursor, read_vec3));
+        let rotations = try!(read_keyframes(data, cursor, read_quaternion));
+        let scales = try!(read_keyframes(data, cursor, read_vec3));
+        tracks.push(DecodedTrack { joint: joint, translations: translations,
+                rotations: rotations, scales: scales });
+    }
+    Ok(DecodedAnimation { name: name, duration: duration, tracks: tracks })
+}
+
+// Reads in the skin section given the number of vertices if the file has one. The section starts
+// with the ASCII characters "SKIN" and is followed by the joints, the joint indices and weights of
+// every vertex, and the animations.
+fn read_skin(data: &Vec<u8>, cursor: &mut usize, num_vertices: usize) ->
+        Result<Option<DecodedSkin>, String> {
+    if *cursor == data.len() * BITS_PER_BYTE { return Ok(None); }
+    for i in 0..4 {
+        let byte = try!(read_byte(data, cursor));
+        if byte != SKIN_MAGIC[i] { return Err("Skin header is invalid.".to_string()); }
+    }
+    let mut joints = Vec::new();
+    let num_joints = try!(read_u32(data, cursor)) as usize;
+    for i in 0..num_joints {
+        joints.push(try!(read_joint(data, cursor, i)));
+    }
+    let mut joint_indices = Vec::new();
+    let mut joint_weights = Vec::new();
+    for _ in 0..num_vertices {
+        let mut indices = [0; 4];
+        let mut weights = [0.0; 4];
+        for i in 0..4 {
+            indices[i] = try!(read_u32(data, cursor));
+            if indices[i] as usize >= num_joints {
+                return Err("Vertex is influenced by a missing joint.".to_string());
+            }
+        }
+        for i in 0..4 {
+            weights[i] = try!(read_f32(data, cursor));
+        }
+        joint_indices.push(indices);
+        joint_weights.push(weights);
+    }
+    let mut animations = Vec::new();
+    let num_animations = try!(read_u32(data, cursor));
+    for _ in 0..num_animations {
+        animations.push(try!(read_animation(data, cursor, num_joints)));
+    }
+    Ok(Some(DecodedSkin { joints: joints, joint_indices: joint_indices,
+            joint_weights: joint_weights, animations: animations }))
+}
+
 // Decodes a .rmod file given a path to the file and returns a DecodedRMOD struct containing the
 // material and vertex information.
 pub fn decode_rmod(fpath: &str) -> Result<DecodedRMOD, String> {
@@ -181,11 +347,12 @@ pub fn decode_rmod(fpath: &str) -> Result<DecodedRMOD, String> {
     for _ in 0..num_elements {
         elements.push(try!(read_u32(&data, &mut cursor)));
     }
+    let skin = try!(read_skin(&data, &mut cursor, num_vertices as usize));
     if cursor != data.len() * BITS_PER_BYTE {
         return Err("RMOD file is improperly sized.".to_string());
     }
     let rmod_file = DecodedRMOD { diffuse: diffuse, specular: specular, normal: normal,
-            vertices: vertices, elements: elements, shininess: shininess };
+            vertices: vertices, elements: elements, shininess: shininess, skin: skin };
     Ok(rmod_file)
 }
 
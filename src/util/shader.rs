@@ -53,10 +53,20 @@ pub fn compile_shader(path: &str, ty: GLenum) -> GLuint {
 
 // Link the program given a vertex shader and a fragment shader. This is
 // entirely copied off the triangle.rs example from the gl-rs repo.
-pub fn link_program(vs: GLuint, fs: GLuint) -> GLuint { unsafe {
+pub fn link_program(vs: GLuint, fs: GLuint) -> GLuint {
+    link_program_with_attribs(vs, fs, &[])
+}
+
+// Link the program given a vertex shader, a fragment shader, and a list of vertex attribute names
+// which are bound to locations matching their index in the list. This lets multiple programs share
+// the same VAOs.
+pub fn link_program_with_attribs(vs: GLuint, fs: GLuint, attribs: &[&str]) -> GLuint { unsafe {
     let program = gl::CreateProgram();
     gl::AttachShader(program, vs);
     gl::AttachShader(program, fs);
+    for (i, attrib) in attribs.iter().enumerate() {
+        gl::BindAttribLocation(program, i as GLuint, CString::new(*attrib).unwrap().as_ptr());
+    }
     gl::LinkProgram(program);
     // See if the shader compilation failed.
     let mut status = gl::FALSE as GLint;
@@ -0,0 +1,101 @@
+// Utility module that allows for encoding of a TGA given a path to the file. Images are written
+// uncompressed with the rows starting from the top left, as 24-bit if they are opaque and as 32-bit
+// with 8 bits of alpha otherwise. The TGA 2.0 footer is included so readers can identify the file.
+//
+// Brian Ho
+// brian@brkho.com
+
+
+use std::fs::File;
+use std::io::Write;
+use util::common;
+
+// The image type of an uncompressed truecolor image.
+const UNCOMPRESSED_TRUECOLOR: u8 = 2;
+
+// The bit of the image descriptor that marks the first row as the top of the image.
+const TOP_LEFT_ORIGIN: u8 = 0x20;
+
+// Encodes an image as a TGA and writes it to a path. Returns Err if the image does not fit in the
+// 16-bit dimensions of a TGA.
+pub fn encode_tga(image: &common::Image, fpath: &str) -> Result<(), String> {
+    try!(image.validate());
+    if image.width > 0xFFFF || image.height > 0xFFFF {
+        return Err("Image is too large to be encoded as a TGA.".to_string());
+    }
+    let opaque = image.is_opaque();
+    let (depth, alpha_bits) = if opaque { (24, 0) } else { (32, 8) };
+
+    // The header has no ID or color map, and the origin of the image is (0, 0).
+    let mut data = vec![0, 0, UNCOMPRESSED_TRUECOLOR, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    data.extend_from_slice(&[image.width as u8, (image.width >> 8) as u8, image.height as u8,
+            (image.height >> 8) as u8, depth, TOP_LEFT_ORIGIN | alpha_bits]);
+    for pixel in &image.data {
+        data.extend_from_slice(&[pixel.blue, pixel.green, pixel.red]);
+        if !opaque { data.push(pixel.alpha); }
+    }
+
+    // The footer has no extension or developer areas.
+    data.extend_from_slice(&[0; 8]);
+    data.extend_from_slice(b"TRUEVISION-XFILE.\0");
+
+    let mut fd = try!(File::create(fpath).map_err(|e| e.to_string()));
+    fd.write_all(&data).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
+    use util::common::{Image, Pixel};
+
+    // Encodes an image to a temporary file and returns the bytes of the file.
+    fn encode(image: &Image, name: &str) -> Vec<u8> {
+        let path = env::temp_dir().join(name);
+        let path = path.to_str().unwrap();
+        super::encode_tga(image, path).unwrap();
+        let mut data = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut data).unwrap();
+        fs::remove_file(path).unwrap();
+        data
+    }
+
+    // Checks the header, pixels, and footer of an encoded 258x1 image.
+    fn check_encoded(data: &[u8], depth: u8, descriptor: u8, pixels: &[u8]) {
+        assert_eq!(&data[..18], &[0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 1, 0, depth,
+                descriptor]);
+        assert_eq!(data.len(), 18 + 258 * depth as usize / 8 + 26);
+        assert_eq!(&data[18..(18 + pixels.len())], pixels);
+        let footer = &data[(data.len() - 26)..];
+        assert_eq!(&footer[..8], &[0; 8]);
+        assert_eq!(&footer[8..], b"TRUEVISION-XFILE.\0");
+    }
+
+    #[test]
+    fn encode_opaque() {
+        // The width does not fit in a byte so that both bytes of it are checked.
+        let mut data = vec![Pixel { red: 1, green: 2, blue: 3, alpha: 255 }; 258];
+        data[1] = Pixel { red: 250, green: 128, blue: 0, alpha: 255 };
+        let image = Image { width: 258, height: 1, data: data };
+        let encoded = encode(&image, "mmo_tga_opaque.tga");
+        check_encoded(&encoded, 24, 0x20, &[3, 2, 1, 0, 128, 250]);
+    }
+
+    #[test]
+    fn encode_translucent() {
+        let mut data = vec![Pixel { red: 1, green: 2, blue: 3, alpha: 255 }; 258];
+        data[1] = Pixel { red: 250, green: 128, blue: 0, alpha: 64 };
+        let image = Image { width: 258, height: 1, data: data };
+        let encoded = encode(&image, "mmo_tga_translucent.tga");
+        check_encoded(&encoded, 32, 0x28, &[3, 2, 1, 255, 0, 128, 250, 64]);
+    }
+
+    #[test]
+    fn encode_too_large() {
+        let image = Image { width: 0x10000, height: 1,
+                data: vec![Pixel { red: 0, green: 0, blue: 0, alpha: 255 }; 0x10000] };
+        let path = env::temp_dir().join("mmo_tga_too_large.tga");
+        assert!(super::encode_tga(&image, path.to_str().unwrap()).is_err());
+    }
+}
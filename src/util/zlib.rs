@@ -0,0 +1,523 @@
+// Utility module that decompresses and compresses data in the zlib format (RFC 1950) along with
+// the DEFLATE format (RFC 1951) that it wraps. Decompression supports stored, fixed Huffman, and
+// dynamic Huffman blocks, which covers every valid DEFLATE stream. Preset dictionaries are not
+// supported since PNG files never use them. Huffman codes are decoded a bit at a time with the
+// canonical code counts, which is simple and fast enough for loading textures. Compression finds
+// repeated strings with hash chains and writes them in a single fixed Huffman block.
+//
+// Brian Ho
+// brian@brkho.com
+
+
+// The maximum number of bits in a DEFLATE Huffman code.
+const MAX_BITS: usize = 15;
+
+// The number of literal/length and distance codes that can appear in a block.
+const MAX_LENGTH_CODES: usize = 286;
+const MAX_DISTANCE_CODES: usize = 30;
+
+// The farthest back that a repeated string can be found, and the shortest and longest strings
+// that can be repeated.
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+// The number of bits in the hash of the next 3 bytes that is used to find repeated strings, and
+// the number of earlier strings with the same hash that are compared before giving up.
+const HASH_BITS: usize = 15;
+const MAX_CHAIN: usize = 64;
+
+// The base lengths and extra bits of length codes 257 through 285.
+const LENGTH_BASE: [u16; 29] = [
+        3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+        131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [
+        0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+
+// The base distances and extra bits of distance codes 0 through 29.
+const DISTANCE_BASE: [u16; 30] = [
+        1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+        2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [
+        0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12,
+        13, 13];
+
+// The order in which the code lengths of the code length alphabet are stored in dynamic blocks.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// Reads bits from a byte slice starting with the least significant bit of each byte.
+struct BitReader<'a> {
+    data: &'a [u8],
+    cursor: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    // Reads and consumes n bits (at most 16) and returns them as an integer.
+    fn read_bits(&mut self, n: u32) -> Result<u32, String> {
+        while self.count < n {
+            let byte = try!(self.data.get(self.cursor).ok_or(
+                    "DEFLATE stream is too small.".to_string()));
+            self.cursor += 1;
+            self.buffer |= (*byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1 << n) - 1);
+        self.buffer >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    // Discards the rest of the current byte so that the next read starts on a byte boundary. Bytes
+    // are only buffered as they are needed, so fewer than 8 bits are ever left over.
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+
+    // Reads and consumes n whole bytes. The reader must be aligned.
+    fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.cursor + n > self.data.len() {
+            return Err("DEFLATE stream is too small.".to_string());
+        }
+        let bytes = &self.data[self.cursor..(self.cursor + n)];
+        self.cursor += n;
+        Ok(bytes)
+    }
+}
+
+// A canonical Huffman code given by the number of codes of each length and the symbols sorted by
+// code.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    // Builds the canonical Huffman code from the code length of every symbol. A length of 0 means
+    // the symbol is unused. Returns Err if the lengths describe more codes than can exist.
+    // Incomplete codes are allowed since a block may only use a single distance code.
+    fn new(lengths: &[u8]) -> Result<Huffman, String> {
+        let mut counts = [0; MAX_BITS + 1];
+        for &length in lengths { counts[length as usize] += 1; }
+        let mut left: i32 = 1;
+        for length in 1..(MAX_BITS + 1) {
+            left = (left << 1) - counts[length] as i32;
+            if left < 0 { return Err("DEFLATE Huffman code is over-subscribed.".to_string()); }
+        }
+        let mut offsets = [0; MAX_BITS + 1];
+        for length in 1..MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length] as usize;
+        }
+        let mut symbols = vec![0; offsets[MAX_BITS] + counts[MAX_BITS] as usize];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize]] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts: counts, symbols: symbols })
+    }
+
+    // Reads and consumes a single code and returns its symbol.
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, String> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..(MAX_BITS + 1) {
+            code |= try!(reader.read_bits(1)) as i32;
+            let count = self.counts[length] as i32;
+            if code - count < first { return Ok(self.symbols[(index + code - first) as usize]); }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("DEFLATE stream has an invalid Huffman code.".to_string())
+    }
+}
+
+// Builds the literal/length and distance codes used by fixed Huffman blocks.
+fn get_fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0; 288];
+    for (i, length) in lengths.iter_mut().enumerate() {
+        *length = match i {
+            0...143 => 8,
+            144...255 => 9,
+            256...279 => 7,
+            _ => 8,
+        };
+    }
+    let literals = Huffman::new(&lengths).unwrap();
+    let distances = Huffman::new(&[5; MAX_DISTANCE_CODES]).unwrap();
+    (literals, distances)
+}
+
+// Reads the code lengths at the start of a dynamic Huffman block and builds its literal/length and
+// distance codes.
+fn read_dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let literal_count = try!(reader.read_bits(5)) as usize + 257;
+    let distance_count = try!(reader.read_bits(5)) as usize + 1;
+    let code_length_count = try!(reader.read_bits(4)) as usize + 4;
+    if literal_count > MAX_LENGTH_CODES || distance_count > MAX_DISTANCE_CODES {
+        return Err("DEFLATE dynamic block has too many codes.".to_string());
+    }
+
+    let mut code_lengths = [0; 19];
+    for &i in CODE_LENGTH_ORDER[..code_length_count].iter() {
+        code_lengths[i] = try!(reader.read_bits(3)) as u8;
+    }
+    let code_length_code = try!(Huffman::new(&code_lengths));
+
+    let mut lengths: Vec<u8> = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = try!(code_length_code.decode(reader));
+        let (length, repeat) = match symbol {
+            0...15 => (symbol as u8, 1),
+            16 => {
+                let previous = try!(lengths.last().cloned().ok_or(
+                        "DEFLATE code lengths repeat with no previous length.".to_string()));
+                (previous, 3 + try!(reader.read_bits(2)) as usize)
+            },
+            17 => (0, 3 + try!(reader.read_bits(3)) as usize),
+            _ => (0, 11 + try!(reader.read_bits(7)) as usize),
+        };
+        if lengths.len() + repeat > literal_count + distance_count {
+            return Err("DEFLATE code lengths overflow the number of codes.".to_string());
+        }
+        for _ in 0..repeat { lengths.push(length); }
+    }
+    if lengths[256] == 0 {
+        return Err("DEFLATE dynamic block is missing the end of block code.".to_string());
+    }
+    let literals = try!(Huffman::new(&lengths[..literal_count]));
+    let distances = try!(Huffman::new(&lengths[literal_count..]));
+    Ok((literals, distances))
+}
+
+// Decodes the symbols of a Huffman compressed block into the output until the end of the block.
+fn inflate_codes(reader: &mut BitReader, output: &mut Vec<u8>, literals: &Huffman,
+        distances: &Huffman) -> Result<(), String> {
+    loop {
+        let symbol = try!(literals.decode(reader)) as usize;
+        if symbol < 256 {
+            output.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let index = symbol - 257;
+            if index >= LENGTH_BASE.len() {
+                return Err("DEFLATE stream has an invalid length code.".to_string());
+            }
+            let length = LENGTH_BASE[index] as usize +
+                    try!(reader.read_bits(LENGTH_EXTRA[index] as u32)) as usize;
+            let index = try!(distances.decode(reader)) as usize;
+            if index >= DISTANCE_BASE.len() {
+                return Err("DEFLATE stream has an invalid distance code.".to_string());
+            }
+            let distance = DISTANCE_BASE[index] as usize +
+                    try!(reader.read_bits(DISTANCE_EXTRA[index] as u32)) as usize;
+            if distance > output.len() {
+                return Err("DEFLATE stream refers to data before its start.".to_string());
+            }
+            // The copy may overlap the bytes it produces, so it is done a byte at a time.
+            let start = output.len() - distance;
+            for i in 0..length {
+                let byte = output[start + i];
+                output.push(byte);
+            }
+        }
+    }
+}
+
+// Decompresses a raw DEFLATE stream and returns the decompressed data along with the number of
+// bytes of the input that the stream used.
+pub fn inflate(data: &[u8]) -> Result<(Vec<u8>, usize), String> {
+    let mut reader = BitReader { data: data, cursor: 0, buffer: 0, count: 0 };
+    let mut output = Vec::new();
+    loop {
+        let last = try!(reader.read_bits(1)) == 1;
+        match try!(reader.read_bits(2)) {
+            0 => {
+                reader.align();
+                let header = try!(reader.read_bytes(4));
+                let length = header[0] as u16 | (header[1] as u16) << 8;
+                let complement = header[2] as u16 | (header[3] as u16) << 8;
+                if length != !complement {
+                    return Err("DEFLATE stored block has a corrupt length.".to_string());
+                }
+                output.extend_from_slice(try!(reader.read_bytes(length as usize)));
+            },
+            1 => {
+                let (literals, distances) = get_fixed_codes();
+                try!(inflate_codes(&mut reader, &mut output, &literals, &distances));
+            },
+            2 => {
+                let (literals, distances) = try!(read_dynamic_codes(&mut reader));
+                try!(inflate_codes(&mut reader, &mut output, &literals, &distances));
+            },
+            _ => { return Err("DEFLATE stream has an invalid block type.".to_string()); },
+        }
+        if last { break; }
+    }
+    Ok((output, reader.cursor))
+}
+
+// Computes the Adler-32 checksum used by the zlib format.
+pub fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 is the most bytes that can be summed before b can overflow.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+// Decompresses a zlib stream after validating its header and checking the Adler-32 checksum of the
+// decompressed data.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < 6 { return Err("zlib stream is too small.".to_string()); }
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0f != 8 || cmf >> 4 > 7 {
+        return Err("zlib stream uses an unsupported compression method.".to_string());
+    }
+    if ((cmf as u16) << 8 | flg as u16) % 31 != 0 {
+        return Err("zlib stream has a corrupt header.".to_string());
+    }
+    if flg & 0x20 != 0 {
+        return Err("zlib streams with preset dictionaries are not supported.".to_string());
+    }
+    let (output, used) = try!(inflate(&data[2..]));
+    let checksum = &data[(2 + used)..];
+    if checksum.len() < 4 { return Err("zlib stream is missing its checksum.".to_string()); }
+    let expected = (checksum[0] as u32) << 24 | (checksum[1] as u32) << 16 |
+            (checksum[2] as u32) << 8 | checksum[3] as u32;
+    if adler32(&output) != expected {
+        return Err("zlib stream has an incorrect checksum.".to_string());
+    }
+    Ok(output)
+}
+
+// Writes bits to a byte vector starting from the least significant bit of each byte.
+struct BitWriter {
+    output: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    // Writes the n lowest bits of a value starting from its least significant bit.
+    fn write_bits(&mut self, value: u32, n: u32) {
+        self.bits |= value << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.output.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // Writes a Huffman code of a length, which is packed starting from its most significant bit.
+    fn write_code(&mut self, code: u32, length: u32) {
+        let reversed = (0..length)
+                .fold(0, |reversed, i| reversed | ((code >> i) & 1) << (length - 1 - i));
+        self.write_bits(reversed, length);
+    }
+
+    // Writes a literal/length symbol with the fixed Huffman code.
+    fn write_symbol(&mut self, symbol: u32) {
+        match symbol {
+            0...143 => self.write_code(0x30 + symbol, 8),
+            144...255 => self.write_code(0x190 + symbol - 144, 9),
+            256...279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    // Writes a repeated string given its length and its distance back with the fixed Huffman
+    // codes.
+    fn write_match(&mut self, length: usize, distance: usize) {
+        let code = (0..LENGTH_BASE.len()).rev()
+                .find(|&i| LENGTH_BASE[i] as usize <= length).unwrap();
+        self.write_symbol(257 + code as u32);
+        self.write_bits((length - LENGTH_BASE[code] as usize) as u32, LENGTH_EXTRA[code] as u32);
+        let code = (0..DISTANCE_BASE.len()).rev()
+                .find(|&i| DISTANCE_BASE[i] as usize <= distance).unwrap();
+        self.write_code(code as u32, 5);
+        self.write_bits((distance - DISTANCE_BASE[code] as usize) as u32,
+                DISTANCE_EXTRA[code] as u32);
+    }
+
+    // Writes the remaining bits padded out to a byte and returns the bytes.
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 { self.output.push(self.bits as u8); }
+        self.output
+    }
+}
+
+// Hashes the 3 bytes at a position of the data.
+fn hash(data: &[u8], i: usize) -> usize {
+    ((data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize) &
+            ((1 << HASH_BITS) - 1)
+}
+
+// Compresses data into a raw DEFLATE stream made up of a single fixed Huffman block. At each
+// position, the longest earlier string with the same first 3 bytes is repeated if there is one.
+// The hash chains link every position to the previous position with the same hash.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter { output: Vec::new(), bits: 0, count: 0 };
+    writer.write_bits(1, 1);
+    writer.write_bits(1, 2);
+    let none = ::std::usize::MAX;
+    let mut heads = vec![none; 1 << HASH_BITS];
+    let mut chains = vec![none; WINDOW_SIZE];
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_length, mut best_distance) = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let max_length = (data.len() - i).min(MAX_MATCH);
+            let mut candidate = heads[hash(data, i)];
+            for _ in 0..MAX_CHAIN {
+                if candidate == none || i - candidate > WINDOW_SIZE { break; }
+                let length = (0..max_length).take_while(|&k| data[candidate + k] == data[i + k])
+                        .count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = i - candidate;
+                    if length == max_length { break; }
+                }
+                candidate = chains[candidate % WINDOW_SIZE];
+            }
+        }
+        let advance = if best_length >= MIN_MATCH {
+            writer.write_match(best_length, best_distance);
+            best_length
+        } else {
+            writer.write_symbol(data[i] as u32);
+            1
+        };
+        for j in i..(i + advance) {
+            if j + MIN_MATCH > data.len() { break; }
+            let h = hash(data, j);
+            chains[j % WINDOW_SIZE] = heads[h];
+            heads[h] = j;
+        }
+        i += advance;
+    }
+    writer.write_symbol(256);
+    writer.finish()
+}
+
+// Compresses data into a zlib stream with a header and the Adler-32 checksum of the data.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut output = vec![0x78, 0x9c];
+    output.extend(deflate(data));
+    let checksum = adler32(data);
+    output.extend_from_slice(&[(checksum >> 24) as u8, (checksum >> 16) as u8,
+            (checksum >> 8) as u8, checksum as u8]);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BitWriter, compress, decompress, inflate};
+
+    // Starts a final fixed Huffman block with a literal so that matches have something to copy.
+    fn start_fixed_block() -> BitWriter {
+        let mut writer = BitWriter { output: Vec::new(), bits: 0, count: 0 };
+        writer.write_bits(1, 1);
+        writer.write_bits(1, 2);
+        writer.write_symbol(b'a' as u32);
+        writer
+    }
+
+    #[test]
+    fn inflate_stored() {
+        // A stored block that is not the last followed by an empty stored block that is.
+        let data = [0x00, 0x03, 0x00, 0xfc, 0xff, 1, 2, 3, 0x01, 0x00, 0x00, 0xff, 0xff, 0xaa];
+        assert_eq!(inflate(&data), Ok((vec![1, 2, 3], 13)));
+        let corrupt = [0x01, 0x03, 0x00, 0xfc, 0xfe, 1, 2, 3];
+        assert!(inflate(&corrupt).is_err());
+        assert!(inflate(&data[..7]).is_err());
+    }
+
+    #[test]
+    fn inflate_fixed() {
+        // "abcabcabcabcabcabcX" as a fixed Huffman block from zlib.
+        let data = [0x4b, 0x4c, 0x4a, 0x4e, 0x44, 0x45, 0x11, 0x00];
+        assert_eq!(inflate(&data), Ok((b"abcabcabcabcabcabcX".to_vec(), 8)));
+
+        // A match can overlap the bytes it produces.
+        let mut writer = start_fixed_block();
+        writer.write_match(5, 1);
+        writer.write_symbol(256);
+        assert_eq!(inflate(&writer.finish()).unwrap().0, b"aaaaaa".to_vec());
+    }
+
+    #[test]
+    fn inflate_dynamic() {
+        // Three repeats of a sentence and another sentence as a dynamic Huffman block from zlib.
+        let data = [
+                0xb5, 0xcb, 0xc7, 0x01, 0x80, 0x20, 0x10, 0x05, 0xd1, 0x56, 0x7e, 0x05, 0xd4,
+                0xe2, 0xc1, 0x06, 0x40, 0x49, 0x06, 0x56, 0xb2, 0x50, 0xbd, 0xdb, 0x84, 0xe7,
+                0x79, 0xb3, 0x3a, 0x8d, 0x58, 0xfd, 0x76, 0x42, 0x25, 0xea, 0x01, 0x86, 0x5e,
+                0x1c, 0xf5, 0x7e, 0x32, 0xa8, 0xe9, 0x84, 0xc2, 0xf9, 0x92, 0x73, 0x60, 0x27,
+                0x2b, 0xb0, 0xfe, 0x86, 0x17, 0xc9, 0xee, 0x1e, 0x50, 0x8c, 0xba, 0x2f, 0x0e,
+                0xc6, 0x37, 0xcd, 0x69, 0xea, 0x80, 0xcb, 0xc7, 0x4a, 0x89, 0x5f, 0x9b, 0xc5,
+                0x07];
+        assert_eq!((data[0] >> 1) & 3, 2);
+        let mut expected = b"The quick brown fox jumps over the lazy dog. ".repeat(3);
+        expected.extend_from_slice(b"Pack my box with five dozen liquor jugs.");
+        assert_eq!(inflate(&data), Ok((expected, data.len())));
+        assert!(inflate(&data[..40]).is_err());
+    }
+
+    #[test]
+    fn inflate_invalid_codes() {
+        // A distance that reaches back before the start of the output.
+        let mut writer = start_fixed_block();
+        writer.write_match(3, 2);
+        writer.write_symbol(256);
+        assert!(inflate(&writer.finish()).is_err());
+
+        // Length code 286 and distance code 30 can be written but are never valid.
+        let mut writer = start_fixed_block();
+        writer.write_symbol(286);
+        writer.write_symbol(256);
+        assert!(inflate(&writer.finish()).is_err());
+        let mut writer = start_fixed_block();
+        writer.write_symbol(257);
+        writer.write_code(30, 5);
+        writer.write_symbol(256);
+        assert!(inflate(&writer.finish()).is_err());
+
+        // Block type 3 is reserved.
+        assert!(inflate(&[0x07, 0x00]).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut data: Vec<u8> = (0..70000u64).map(|i| (i * i % 251) as u8).collect();
+        data.extend((0..1000).map(|_| 7));
+        assert_eq!(decompress(&compress(&data)), Ok(data));
+        assert_eq!(decompress(&compress(&[])), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn decompress_invalid_stream() {
+        let mut data = compress(b"checksum");
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert!(decompress(&data).is_err());
+        let mut data = compress(b"header");
+        data[1] += 1;
+        assert!(decompress(&data).is_err());
+        assert!(decompress(&compress(b"truncated")[..8]).is_err());
+    }
+}
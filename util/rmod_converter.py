@@ -16,6 +16,9 @@
 # The '_' character can be used to specify an absensce of a texture map. For example:
 # 'python rmod_converter.py diffuse.bmp _ _ 75.0 model.fbx model.rmod'
 #
+# If the scene has a skeleton, the mesh's skin weights, the joint hierarchy, and every animation
+# stack (sampled at ANIMATION_FRAME_RATE) are appended to the end of the file in a skin section.
+#
 # Note: This has only been tested with Maya 2015, exporting with "Tangents and Binomials" enabled
 # and a LEFT HANDED COORDINATE SYSTEM.
 #
@@ -31,14 +34,44 @@ import time
 # How many decimal places to round floats to.
 FLOAT_DECIMAL_PLACES = 5;
 
+# The maximum number of joints that can influence a single vertex.
+MAX_JOINT_INFLUENCES = 4
+
+# How many times per second animations are sampled into keyframes.
+ANIMATION_FRAME_RATE = 30.0
+
+# Parent index stored for joints at the root of the skeleton.
+NO_PARENT = 0xFFFFFFFF
+
+# Maps each engine axis to the FBX axis it is read from in order to stay consistent with the
+# ordering of the vertex data.
+AXIS_ORDER = (2, 0, 1)
+
 # Data class for holding information about a vertex.
 class Vertex:
-    def __init__(self, pos, normal, tangent, bitangent, tcoord):
+    def __init__(self, pos, normal, tangent, bitangent, tcoord, influences):
         self.pos = pos
         self.normal = normal
         self.tangent = tangent
         self.bitangent = bitangent
         self.tcoord = tcoord
+        self.influences = influences
+
+# Data class for holding information about a joint of the skeleton.
+class Joint:
+    def __init__(self, node, name, parent):
+        self.node = node
+        self.name = name
+        self.parent = parent
+        self.inverse_bind = None
+
+# Data class for holding the keyframes of a joint in an animation.
+class Track:
+    def __init__(self, joint):
+        self.joint = joint
+        self.translations = []
+        self.rotations = []
+        self.scales = []
 
 # Output wrapper that prepends a message with timestamp.
 def output(msg):
@@ -108,7 +141,8 @@ def get_vertex_info(info, size, vertex_index, vertex_count):
         error('Unsupported mapping mode.')
     return fbx_to_tuple(result, size)
 
-# Reads in the specified input file and returns the relevant mesh information.
+# Reads in the specified input file and returns the relevant mesh information along with the
+# skeleton and animations (which are empty if the scene is not skinned).
 def read_input_file(fname):
     sdk_manager, scene = InitializeSdkObjects()
     load_result = LoadScene(sdk_manager, scene, fname)
@@ -117,23 +151,100 @@ def read_input_file(fname):
     root_node = scene.GetRootNode()
     if not root_node:
         error("FBX file is empty.")
+    joints = []
+    for i in xrange(0, root_node.GetChildCount()):
+        get_skeleton_info(root_node.GetChild(i), None, joints)
     result = []
     for i in xrange(0, root_node.GetChildCount()):
-        result.extend(get_child_info(root_node.GetChild(i)))
-    return result
+        result.extend(get_child_info(root_node.GetChild(i), joints))
+    animations = get_animations(scene, joints) if joints else []
+    return result, joints, animations
+
+# Helper function that gets the attribute type of a node or None if it does not have one.
+def get_attribute_type(node):
+    attribute = node.GetNodeAttribute()
+    return attribute.GetAttributeType() if attribute else None
 
 # Recursively gets the child node information.
-def get_child_info(node):
+def get_child_info(node, joints):
     result = []
     # We only care about Mesh attributes right now.
-    if node.GetNodeAttribute().GetAttributeType() == FbxNodeAttribute.eMesh:
-        result.append(get_mesh_info(node.GetNodeAttribute()))
+    if get_attribute_type(node) == FbxNodeAttribute.eMesh:
+        result.append(get_mesh_info(node.GetNodeAttribute(), joints))
     for i in xrange(0, node.GetChildCount()):
-        result.extend(get_child_info(root_node.GetChild(i)))
+        result.extend(get_child_info(node.GetChild(i), joints))
     return result
 
+# Recursively adds the skeleton nodes to the list of joints. Parents are always added before
+# their children.
+def get_skeleton_info(node, parent, joints):
+    if get_attribute_type(node) == FbxNodeAttribute.eSkeleton:
+        joints.append(Joint(node, node.GetName(), parent))
+        parent = len(joints) - 1
+    for i in xrange(0, node.GetChildCount()):
+        get_skeleton_info(node.GetChild(i), parent, joints)
+
+# Gets the (joint, weight) influences of every control point of a mesh from its skin deformers
+# and fills in the inverse bind matrix of each joint.
+def get_skin_info(mesh, joints):
+    influences = [[] for _ in xrange(0, mesh.GetControlPointsCount())]
+    joint_indices = dict((joint.node.GetName(), i) for i, joint in enumerate(joints))
+    for i in xrange(0, mesh.GetDeformerCount(FbxDeformer.eSkin)):
+        skin = mesh.GetDeformer(i, FbxDeformer.eSkin)
+        for j in xrange(0, skin.GetClusterCount()):
+            cluster = skin.GetCluster(j)
+            link = cluster.GetLink()
+            if link is None or link.GetName() not in joint_indices:
+                continue
+            joint = joint_indices[link.GetName()]
+            mesh_matrix = FbxAMatrix()
+            link_matrix = FbxAMatrix()
+            cluster.GetTransformMatrix(mesh_matrix)
+            cluster.GetTransformLinkMatrix(link_matrix)
+            joints[joint].inverse_bind = link_matrix.Inverse() * mesh_matrix
+            indices = cluster.GetControlPointIndices()
+            weights = cluster.GetControlPointWeights()
+            for k in xrange(0, cluster.GetControlPointIndicesCount()):
+                influences[indices[k]].append((joint, weights[k]))
+    return [normalize_influences(influence) for influence in influences]
+
+# Keeps the MAX_JOINT_INFLUENCES heaviest influences and rescales them so that they sum to one.
+# Unused influences are padded with joint 0 and a weight of 0.
+def normalize_influences(influence):
+    influence = sorted(influence, key=lambda x: -x[1])[:MAX_JOINT_INFLUENCES]
+    total = sum(weight for _, weight in influence)
+    if total > 0.0:
+        influence = [(joint, round(weight / total, FLOAT_DECIMAL_PLACES))
+            for joint, weight in influence]
+    influence.extend([(0, 0.0)] * (MAX_JOINT_INFLUENCES - len(influence)))
+    return tuple(influence)
+
+# Samples the local transform of every joint in each animation stack of the scene.
+def get_animations(scene, joints):
+    animations = []
+    for i in xrange(0, scene.GetSrcObjectCount(FbxCriteria.ObjectType(FbxAnimStack.ClassId))):
+        stack = scene.GetSrcObject(FbxCriteria.ObjectType(FbxAnimStack.ClassId), i)
+        scene.SetCurrentAnimationStack(stack)
+        span = stack.GetLocalTimeSpan()
+        start = span.GetStart().GetSecondDouble()
+        duration = span.GetStop().GetSecondDouble() - start
+        frames = int(round(duration * ANIMATION_FRAME_RATE)) + 1
+        tracks = [Track(j) for j in xrange(0, len(joints))]
+        for frame in xrange(0, frames):
+            seconds = frame / ANIMATION_FRAME_RATE
+            time = FbxTime()
+            time.SetSecondDouble(start + seconds)
+            for joint, track in zip(joints, tracks):
+                transform = joint.node.EvaluateLocalTransform(time)
+                track.translations.append((seconds, fbx_to_tuple(transform.GetT(), 3)))
+                track.rotations.append((seconds, fbx_to_tuple(transform.GetQ(), 4)))
+                track.scales.append((seconds, fbx_to_tuple(transform.GetS(), 3)))
+        output('Sampled animation {} with {} frames.'.format(stack.GetName(), frames))
+        animations.append((stack.GetName(), duration, tracks))
+    return animations
+
 # Gets information about a mesh node. Returns (elist, vlist).
-def get_mesh_info(mesh):
+def get_mesh_info(mesh, joints):
     name = 'UNNAMED' if mesh.GetName() == '' else mesh.GetName()
     output('Processing mesh: {}.'.format(name))
     vertex_map = {}
@@ -144,6 +255,7 @@ def get_mesh_info(mesh):
     tangents = mesh.GetElementTangent(0)
     bitangents = mesh.GetElementBinormal(0)
     uvs = mesh.GetElementUV(0)
+    influences = get_skin_info(mesh, joints) if joints else None
     vertex_count = 0
     for i in xrange(0, mesh.GetPolygonCount()):
         if mesh.GetPolygonSize(i) != 3:
@@ -155,10 +267,11 @@ def get_mesh_info(mesh):
             tangent = get_vertex_info(tangents, 3, vertex_index, vertex_count)
             bitangent = get_vertex_info(bitangents, 3, vertex_index, vertex_count)
             tcoord = get_vertex_info(uvs, 2, vertex_index, vertex_count)
-            key = (pos, normal, tangent, bitangent, tcoord)
+            influence = influences[vertex_index] if influences else None
+            key = (pos, normal, tangent, bitangent, tcoord, influence)
             if key not in vertex_map:
                 # print key
-                new_vertex = Vertex(pos, normal, tangent, bitangent, tcoord)
+                new_vertex = Vertex(pos, normal, tangent, bitangent, tcoord, influence)
                 vertex_list.append(new_vertex)
                 vertex_map[key] = len(vertex_map)
             element_list.append(vertex_map[key])
@@ -193,6 +306,69 @@ def serialize_uint32(num, bytes):
     bytes.extend([ord(elem) for elem in packed])
     # print ''.join(format(x, '02x') for x in bytes)
 
+# Serializes a string as its length in bytes followed by its UTF-8 bytes.
+def serialize_string(string, bytes):
+    encoded = string.encode('utf-8')
+    serialize_uint32(len(encoded), bytes)
+    bytes.extend(encoded)
+
+# Serializes a vector from FBX axes into engine axes.
+def serialize_vec3(vec, bytes):
+    for axis in AXIS_ORDER:
+        serialize_float32(vec[axis], bytes)
+
+# Serializes a FBX (x, y, z, w) quaternion as w followed by its vector in engine axes.
+def serialize_quaternion(quat, bytes):
+    serialize_float32(quat[3], bytes)
+    serialize_vec3(quat, bytes)
+
+# Serializes a FBX matrix in column major order with its axes swapped into engine axes. FBX
+# matrices store the translation in their last row, so the rows of a FBX matrix are the columns of
+# the engine's matrix.
+def serialize_matrix(matrix, bytes):
+    for column in AXIS_ORDER + (3,):
+        for row in AXIS_ORDER + (3,):
+            serialize_float32(round(matrix.Get(column, row), FLOAT_DECIMAL_PLACES), bytes)
+
+# Serializes a list of (time, value) keyframes given a function that serializes a single value.
+def serialize_keyframes(keyframes, serialize_value, bytes):
+    serialize_uint32(len(keyframes), bytes)
+    for time, value in keyframes:
+        serialize_float32(time, bytes)
+        serialize_value(value, bytes)
+
+# Serializes the skin section holding the joints, the influences of every vertex, and the
+# animations. This is only written for skinned meshes so that static models stay unchanged.
+def serialize_skin(joints, vertex_list, animations, bytes):
+    # Magic header that represents the string "SKIN".
+    bytes.extend([83, 75, 73, 78])
+    serialize_uint32(len(joints), bytes)
+    for joint in joints:
+        if joint.inverse_bind is None:
+            joint.inverse_bind = joint.node.EvaluateGlobalTransform().Inverse()
+        rest = joint.node.EvaluateLocalTransform()
+        serialize_string(joint.name, bytes)
+        serialize_uint32(NO_PARENT if joint.parent is None else joint.parent, bytes)
+        serialize_matrix(joint.inverse_bind, bytes)
+        serialize_vec3(rest.GetT(), bytes)
+        serialize_quaternion(rest.GetQ(), bytes)
+        serialize_vec3(rest.GetS(), bytes)
+    for vertex in vertex_list:
+        for joint, _ in vertex.influences:
+            serialize_uint32(joint, bytes)
+        for _, weight in vertex.influences:
+            serialize_float32(weight, bytes)
+    serialize_uint32(len(animations), bytes)
+    for name, duration, tracks in animations:
+        serialize_string(name, bytes)
+        serialize_float32(duration, bytes)
+        serialize_uint32(len(tracks), bytes)
+        for track in tracks:
+            serialize_uint32(track.joint, bytes)
+            serialize_keyframes(track.translations, serialize_vec3, bytes)
+            serialize_keyframes(track.rotations, serialize_quaternion, bytes)
+            serialize_keyframes(track.scales, serialize_vec3, bytes)
+
 # Loads and serializes a texture image given a path.
 def serialize_image(path, bytes):
     if path is None:
@@ -231,7 +407,7 @@ def main():
     serialize_float32(shininess, bytes)
 
     # TODO: Don't just take the first element here. Explore all elements.
-    parsed_results = read_input_file(input_name)
+    parsed_results, joints, animations = read_input_file(input_name)
     if len(parsed_results) > 1:
         output("There file contains more than one mesh, however only the first will be converted.")
     element_list, vertex_list = parsed_results[0]
@@ -256,6 +432,10 @@ def main():
     serialize_uint32(len(element_list), bytes)
     for element in element_list:
         serialize_uint32(element, bytes)
+    if joints:
+        output("Writing skin with {} joints and {} animations.".format(
+            len(joints), len(animations)))
+        serialize_skin(joints, vertex_list, animations, bytes)
     with open(output_name, 'wb') as of:
         of.write(bytes)
     output("Saved converted model as as {}.".format(output_name))
@@ -263,7 +443,8 @@ def main():
 if __name__ == '__main__':
     try:
         from FbxCommon import *
-        from fbx import FbxLayerElement
+        from fbx import FbxAMatrix, FbxAnimStack, FbxCriteria, FbxDeformer, FbxLayerElement
+        from fbx import FbxTime
     except ImportError:
         error('Autodesk FBX SDK with Python bindings is required.')
     try: